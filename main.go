@@ -590,13 +590,13 @@ func (ctx *WASMContext) initTypeRegistry() {
 	ctx.TypeRegistry = append(ctx.TypeRegistry, printBytesType)
 	ctx.TypeMap["(i32)->()"] = 1
 
-	// Type 2: read_line function (i32) -> ()
+	// Type 2: read_line function (i32) -> (i32)
 	readLineType := FunctionType{
 		Parameters: []byte{0x7F}, // i32 (destination address for slice)
-		Results:    []byte{},     // void
+		Results:    []byte{0x7F}, // i32 status: 1 line, 0 end of input, -1 error
 	}
 	ctx.TypeRegistry = append(ctx.TypeRegistry, readLineType)
-	ctx.TypeMap["(i32)->()"] = 2
+	ctx.TypeMap["(i32)->(i32)"] = 2
 }
 
 func (ctx *WASMContext) initFunctionRegistry(functions []*ASTNode, symbolTable *SymbolTable) {
@@ -1912,6 +1912,7 @@ func (ctx *WASMContext) EmitExpressionR(buf *bytes.Buffer, node *ASTNode, localC
 			// Call read_line with destination address as parameter
 			// Stack: [return_addr, dest_addr]
 			w.call(2) // function index 2 (read_line import)
+			w.drop()  // the status; end of input reads as an empty line
 
 			// Stack now has: [return_addr] - the address where the slice was stored
 		} else if functionName == "append" {
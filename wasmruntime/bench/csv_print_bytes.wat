@@ -0,0 +1,26 @@
+;; Writes a CSV of 100000 rows of 8 fields, one print_bytes call per field,
+;; as a baseline for csv_print_bytes_n.wat. Run both with
+;;
+;;   wasmruntime bench bench/csv_print_bytes.wat
+;;   wasmruntime bench bench/csv_print_bytes_n.wat
+(module
+  (import "env" "print_bytes" (func $print_bytes (param i32)))
+  (memory (export "memory") 1)
+  (global (export "tstack") (mut i32) (i32.const 0x8000))
+  (data (i32.const 0x100) "field,field\n")
+  ;; Slices of "field," and "field\n"
+  (data (i32.const 0x200) "\00\01\00\00\00\00\00\00\06\00\00\00\00\00\00\00")
+  (data (i32.const 0x210) "\06\01\00\00\00\00\00\00\06\00\00\00\00\00\00\00")
+  (func (export "main") (result i32)
+    (local $row i32)
+    (local $field i32)
+    (loop $rows
+      (local.set $field (i32.const 0))
+      (loop $fields
+        (call $print_bytes (i32.const 0x200))
+        (local.set $field (i32.add (local.get $field) (i32.const 1)))
+        (br_if $fields (i32.lt_u (local.get $field) (i32.const 7))))
+      (call $print_bytes (i32.const 0x210))
+      (local.set $row (i32.add (local.get $row) (i32.const 1)))
+      (br_if $rows (i32.lt_u (local.get $row) (i32.const 100000))))
+    (i32.const 0)))
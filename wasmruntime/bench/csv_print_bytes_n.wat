@@ -0,0 +1,23 @@
+;; Writes the same CSV as csv_print_bytes.wat, one print_bytes_n call per
+;; row of 8 fields.
+(module
+  (import "env" "print_bytes_n" (func $print_bytes_n (param i32 i64)))
+  (memory (export "memory") 1)
+  (global (export "tstack") (mut i32) (i32.const 0x8000))
+  (data (i32.const 0x100) "field,field\n")
+  ;; A row's slices: 7 of "field," and 1 of "field\n"
+  (data (i32.const 0x200) "\00\01\00\00\00\00\00\00\06\00\00\00\00\00\00\00")
+  (data (i32.const 0x210) "\00\01\00\00\00\00\00\00\06\00\00\00\00\00\00\00")
+  (data (i32.const 0x220) "\00\01\00\00\00\00\00\00\06\00\00\00\00\00\00\00")
+  (data (i32.const 0x230) "\00\01\00\00\00\00\00\00\06\00\00\00\00\00\00\00")
+  (data (i32.const 0x240) "\00\01\00\00\00\00\00\00\06\00\00\00\00\00\00\00")
+  (data (i32.const 0x250) "\00\01\00\00\00\00\00\00\06\00\00\00\00\00\00\00")
+  (data (i32.const 0x260) "\00\01\00\00\00\00\00\00\06\00\00\00\00\00\00\00")
+  (data (i32.const 0x270) "\06\01\00\00\00\00\00\00\06\00\00\00\00\00\00\00")
+  (func (export "main") (result i32)
+    (local $row i32)
+    (loop $rows
+      (call $print_bytes_n (i32.const 0x200) (i64.const 8))
+      (local.set $row (i32.add (local.get $row) (i32.const 1)))
+      (br_if $rows (i32.lt_u (local.get $row) (i32.const 100000))))
+    (i32.const 0)))
@@ -0,0 +1,82 @@
+// Generates valid modules that import the host functions taking slices and
+// pointers, export their memory and a main function, and call the host
+// functions with whatever addresses and lengths wasm-smith comes up with.
+// wasm-smith cannot be made to export a memory, so the export is added
+// afterwards.
+
+#![no_main]
+
+use libfuzzer_sys::arbitrary::{Result, Unstructured};
+use libfuzzer_sys::fuzz_target;
+use std::sync::OnceLock;
+use wasm_smith::{Config, Module};
+use zong_runtime::fuzz::{run_bytes, RunResult};
+
+// The host functions generated modules may import.
+const IMPORTS: &str = r#"
+(module
+  (import "env" "print" (func (param i64)))
+  (import "env" "print_bytes" (func (param i32)))
+  (import "env" "print_fmt" (func (param i32 i32 i64)))
+  (import "env" "eprint_bytes" (func (param i32)))
+  (import "env" "read_line" (func (param i32) (result i32)))
+  (import "env" "read_line_utf8" (func (param i32)))
+  (import "env" "read_bytes" (func (param i32 i64) (result i64)))
+  (import "env" "read_all" (func (param i32)))
+  (import "env" "host_alloc" (func (param i64) (result i32)))
+  (import "env" "host_free" (func (param i32)))
+  (import "env" "parse_i64" (func (param i32 i32) (result i32)))
+  (import "env" "i64_to_string" (func (param i64 i32)))
+  (import "env" "get_embedded" (func (param i32 i32)))
+  (import "env" "random_bytes" (func (param i32 i64)))
+  (import "env" "progress_begin" (func (param i32 i64)))
+)
+"#;
+
+// The exports generated modules must have, other than the memory.
+const EXPORTS: &str = r#"
+(module
+  (func (export "main"))
+)
+"#;
+
+fn wasm(text: &'static str, cell: &'static OnceLock<Vec<u8>>) -> Vec<u8> {
+    cell.get_or_init(|| wat::parse_str(text).expect("valid module text"))
+        .clone()
+}
+
+fn generate(u: &mut Unstructured) -> Result<(Vec<u8>, Vec<u8>)> {
+    static IMPORTS_WASM: OnceLock<Vec<u8>> = OnceLock::new();
+    static EXPORTS_WASM: OnceLock<Vec<u8>> = OnceLock::new();
+    let mut config: Config = u.arbitrary()?;
+    config.available_imports = Some(wasm(IMPORTS, &IMPORTS_WASM));
+    config.exports = Some(wasm(EXPORTS, &EXPORTS_WASM));
+    config.min_memories = 1;
+    config.max_memories = 1;
+    config.memory64_enabled = false;
+    config.threads_enabled = false;
+    let module = Module::new(config, u)?;
+    let stdin = u.arbitrary()?;
+    Ok((module.to_bytes(), stdin))
+}
+
+// Exports the module's memory as "memory", where the host functions look for
+// it.
+fn export_memory(wasm_bytes: &[u8]) -> Option<Vec<u8>> {
+    let mut module = walrus::Module::from_buffer(wasm_bytes).ok()?;
+    let memory = module.memories.iter().next()?.id();
+    module.exports.add("memory", memory);
+    Some(module.emit_wasm())
+}
+
+fuzz_target!(|data: &[u8]| {
+    let Ok((wasm_bytes, stdin)) = generate(&mut Unstructured::new(data)) else {
+        return;
+    };
+    let Some(wasm_bytes) = export_memory(&wasm_bytes) else {
+        return;
+    };
+    if let RunResult::Panicked(message) = run_bytes(&wasm_bytes, &stdin) {
+        panic!("host function panicked: {}", message);
+    }
+});
@@ -0,0 +1,15 @@
+// Runs arbitrary bytes as a module with arbitrary stdin. Most inputs are not
+// valid wasm, so this mostly exercises module loading; host_abi generates
+// valid modules that call the host functions.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use zong_runtime::fuzz::{run_bytes, RunResult};
+
+fuzz_target!(|input: (&[u8], &[u8])| {
+    let (wasm_bytes, stdin) = input;
+    if let RunResult::Panicked(message) = run_bytes(wasm_bytes, stdin) {
+        panic!("host function panicked: {}", message);
+    }
+});
@@ -0,0 +1,217 @@
+// The contract between Zong programs and the runtime, beyond what wasm
+// validation checks, for `wasmruntime check`. A program must
+//
+//   * import only functions the runtime provides, with the types it
+//     provides them with;
+//   * export its memory as "memory", which host functions read slices from
+//     and write results to;
+//   * export its tstack pointer as "tstack", a mutable i32 global, or i64
+//     if its memory is a 64-bit one, which host functions that return
+//     slices advance past what they write; and
+//   * export its entry function as "main", with type () -> (), () -> i32,
+//     or () -> i64.
+//
+// Breaking any of these fails at instantiation, or only once a host function
+// is called. WASI commands, which export _start instead of main, are only
+// checked for their imports.
+//
+// The contract is versioned, so that the compiler and the runtime can change
+// it independently. A module says which version it was compiled for with a
+// "zong.abi" custom section holding the version number in decimal, like
+// `(@custom "zong.abi" "1")`; modules without one are version 1. The runtime
+// defines the host functions the way that version expects, and refuses to
+// run modules for versions it does not support. Precompiled code does not
+// keep custom sections, so precompiled modules must be version 1, as must
+// modules loaded with load_module, which get only print and print_bytes.
+
+use crate::{guest, HostState};
+use wasmparser::{Parser, Payload};
+use wasmtime::{
+    Error, Extern, ExternType, FuncType, Linker, Module, Mutability, Result, Store, ValType,
+};
+
+pub const SECTION_NAME: &str = "zong.abi";
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Version {
+    // Slices are passed by the address of a 16-byte structure.
+    #[default]
+    V1,
+    // Slices are passed as (items_ptr: i32, length: i32) pairs of
+    // parameters; see slice_abi.
+    V2,
+}
+
+// Oldest first.
+pub const SUPPORTED: &[Version] = &[Version::V1, Version::V2];
+
+impl Version {
+    pub fn number(self) -> u32 {
+        match self {
+            Version::V1 => 1,
+            Version::V2 => 2,
+        }
+    }
+}
+
+// Returns the version the module was compiled for, or an error saying how to
+// fix the mismatch if the runtime does not support it.
+pub fn version(wasm_bytes: &[u8]) -> Result<Version> {
+    let mut section = None;
+    for payload in Parser::new(0).parse_all(wasm_bytes) {
+        if let Payload::CustomSection(custom) = payload? {
+            if custom.name() == SECTION_NAME {
+                section = Some(custom.data());
+                break;
+            }
+        }
+    }
+    let Some(section) = section else {
+        return Ok(Version::default());
+    };
+    let number: u32 = std::str::from_utf8(section)
+        .ok()
+        .and_then(|text| text.trim().parse().ok())
+        .ok_or_else(|| {
+            Error::msg(format!(
+                "{} section: expected a version number, got {:?}",
+                SECTION_NAME,
+                String::from_utf8_lossy(section)
+            ))
+        })?;
+    if let Some(version) = SUPPORTED.iter().find(|version| version.number() == number) {
+        return Ok(*version);
+    }
+    let supported: Vec<String> = SUPPORTED
+        .iter()
+        .map(|version| version.number().to_string())
+        .collect();
+    let versions = if supported.len() == 1 {
+        "version"
+    } else {
+        "versions"
+    };
+    let newest = SUPPORTED.last().unwrap().number();
+    Err(Error::msg(format!(
+        "the module was compiled for Zong ABI version {}, but this runtime supports {} {}; {}",
+        number,
+        versions,
+        supported.join(", "),
+        if number > newest {
+            "upgrade wasmruntime, or compile the module for an older version"
+        } else {
+            "recompile the module with a newer compiler"
+        }
+    )))
+}
+
+// Returns a message for each way the module breaks the contract, saying how
+// to fix it.
+pub fn violations(
+    linker: &Linker<HostState>,
+    store: &mut Store<HostState>,
+    module: &Module,
+) -> Vec<String> {
+    let mut violations = Vec::new();
+    for import in module.imports() {
+        let name = format!("{}.{}", import.module(), import.name());
+        match (linker.get_by_import(&mut *store, &import), import.ty()) {
+            (None, _) => violations.push(format!(
+                "import {}: the runtime does not provide it; check its name, or the \
+                 sandbox profile and --deny if it is a host function",
+                name
+            )),
+            (Some(Extern::Func(func)), ExternType::Func(ty)) => {
+                let provided = func.ty(&*store);
+                if !provided.matches(&ty) {
+                    violations.push(format!(
+                        "import {}: imported with type {}, but the runtime provides {}",
+                        name,
+                        signature(&ty),
+                        signature(&provided)
+                    ));
+                }
+            }
+            (Some(Extern::Func(_)), ty) => violations.push(format!(
+                "import {}: imported as {}, but the runtime provides a function",
+                name,
+                describe(&ty)
+            )),
+            _ => {}
+        }
+    }
+
+    let export = |name: &str| module.get_export(name);
+    if export("main").is_none() && export("_start").is_some() {
+        return violations;
+    }
+    match export("memory") {
+        None => violations.push(String::from(
+            "no \"memory\" export: host functions need it to read and write slices; \
+             export the module's memory as \"memory\"",
+        )),
+        Some(ExternType::Memory(_)) => {}
+        Some(ty) => violations.push(format!(
+            "export memory: is {}, but must be the module's memory",
+            describe(&ty)
+        )),
+    }
+    let pointer = guest::pointer_type(guest::is_memory64(module));
+    match export("tstack") {
+        None => violations.push(format!(
+            "no \"tstack\" export: host functions that return slices need it to \
+             push them onto the tstack; export the tstack pointer as a mutable {} \
+             global named \"tstack\"",
+            pointer
+        )),
+        Some(ExternType::Global(ty))
+            if ty.content().matches(&pointer) && ty.mutability() == Mutability::Var => {}
+        Some(ty) => violations.push(format!(
+            "export tstack: is {}, but must be a mutable {} global",
+            describe(&ty),
+            pointer
+        )),
+    }
+    match export("main") {
+        None => violations.push(String::from(
+            "no \"main\" export: the runtime calls it to run the program; export the \
+             entry function as \"main\"",
+        )),
+        Some(ExternType::Func(ty))
+            if ty.params().len() == 0
+                && matches!(
+                    ty.results().collect::<Vec<_>>().as_slice(),
+                    [] | [ValType::I32] | [ValType::I64]
+                ) => {}
+        Some(ty) => violations.push(format!(
+            "export main: is {}, but must have type () -> (), () -> i32, or () -> i64",
+            describe(&ty)
+        )),
+    }
+    violations
+}
+
+fn describe(ty: &ExternType) -> String {
+    match ty {
+        ExternType::Func(ty) => format!("a function with type {}", signature(ty)),
+        ExternType::Global(ty) => {
+            let mutability = match ty.mutability() {
+                Mutability::Const => "an immutable",
+                Mutability::Var => "a mutable",
+            };
+            format!("{} {} global", mutability, ty.content())
+        }
+        ExternType::Table(_) => String::from("a table"),
+        ExternType::Memory(_) => String::from("a memory"),
+    }
+}
+
+// A function type as (i32, i64) -> (i32).
+fn signature(ty: &FuncType) -> String {
+    let list = |types: Vec<String>| format!("({})", types.join(", "));
+    format!(
+        "{} -> {}",
+        list(ty.params().map(|ty| ty.to_string()).collect()),
+        list(ty.results().map(|ty| ty.to_string()).collect())
+    )
+}
@@ -0,0 +1,201 @@
+// Host ABI conformance checks for `wasmruntime abi-test`. Each check is a
+// small module using the host functions the Zong compiler relies on, run with
+// given stdin; the check passes if the runtime's stdout matches exactly. The
+// modules are run in a separate process, by default this runtime itself, so
+// another implementation of the host ABI can be checked with --runtime.
+
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use wasmtime::Result;
+
+struct Check {
+    name: &'static str,
+    // Data segments and the body of main, in WAT.
+    data: &'static str,
+    body: &'static str,
+    stdin: &'static [u8],
+    stdout: &'static [u8],
+}
+
+const INITIAL_TSTACK: u32 = 4096;
+
+const CHECKS: &[Check] = &[
+    Check {
+        name: "print writes a decimal i64 and a newline",
+        data: "",
+        body: "
+            (call $print (i64.const 0))
+            (call $print (i64.const 42))
+            (call $print (i64.const -1))
+            (call $print (i64.const 9223372036854775807))
+            (call $print (i64.const -9223372036854775808))",
+        stdin: b"",
+        stdout: b"0\n42\n-1\n9223372036854775807\n-9223372036854775808\n",
+    },
+    Check {
+        name: "print_bytes writes bytes unchanged",
+        data: r#"(data (i32.const 100) "a\00b\ff\fe\n\e2\82\ac")"#,
+        body: "
+            (i32.store (i32.const 0) (i32.const 100))
+            (i64.store (i32.const 8) (i64.const 9))
+            (call $print_bytes (i32.const 0))",
+        stdin: b"",
+        stdout: b"a\0b\xff\xfe\n\xe2\x82\xac",
+    },
+    Check {
+        name: "print_bytes reads the pointer at offset 0 and the i64 length at offset 8",
+        data: r#"(data (i32.const 100) "abcdef")"#,
+        body: "
+            (i32.store (i32.const 0) (i32.const 101))
+            (i32.store (i32.const 4) (i32.const -1))
+            (i64.store (i32.const 8) (i64.const 3))
+            (call $print_bytes (i32.const 0))",
+        stdin: b"",
+        stdout: b"bcd",
+    },
+    Check {
+        name: "print_bytes writes nothing for an empty slice",
+        data: "",
+        body: "
+            (i32.store (i32.const 0) (i32.const 0))
+            (i64.store (i32.const 8) (i64.const 0))
+            (call $print_bytes (i32.const 0))
+            (call $print (i64.const 1))",
+        stdin: b"",
+        stdout: b"1\n",
+    },
+    Check {
+        name: "read_line strips the line's newline",
+        data: "",
+        body: "
+            (call $print (i64.extend_i32_s (call $read_line (i32.const 32))))
+            (call $print_bytes (i32.const 32))
+            (call $print (i64.load (i32.const 40)))",
+        stdin: b"abc\ndef\n",
+        stdout: b"1\nabc3\n",
+    },
+    Check {
+        name: "read_line strips a carriage return before the newline",
+        data: "",
+        body: "
+            (drop (call $read_line (i32.const 32)))
+            (call $print (i64.load (i32.const 40)))
+            (drop (call $read_line (i32.const 32)))
+            (call $print (i64.load (i32.const 40)))",
+        stdin: b"a\r\nb\r",
+        stdout: b"1\n2\n",
+    },
+    Check {
+        name: "read_line returns a last line without a newline",
+        data: "",
+        body: "
+            (call $print (i64.extend_i32_s (call $read_line (i32.const 32))))
+            (call $print_bytes (i32.const 32))
+            (call $print (i64.load (i32.const 40)))",
+        stdin: b"xyz",
+        stdout: b"1\nxyz3\n",
+    },
+    Check {
+        name: "read_line returns 1 and an empty slice for an empty line",
+        data: "",
+        body: "
+            (call $print (i64.extend_i32_s (call $read_line (i32.const 32))))
+            (call $print (i64.load (i32.const 40)))",
+        stdin: b"\nmore\n",
+        stdout: b"1\n0\n",
+    },
+    Check {
+        name: "read_line returns 0 and an empty slice at end of input",
+        data: "",
+        body: "
+            (drop (call $read_line (i32.const 32)))
+            (call $print (i64.extend_i32_s (call $read_line (i32.const 48))))
+            (call $print (i64.load (i32.const 56)))",
+        stdin: b"only\n",
+        stdout: b"0\n0\n",
+    },
+    Check {
+        name: "read_line stores the line on the tstack and advances it",
+        data: "",
+        body: "
+            (drop (call $read_line (i32.const 32)))
+            (call $print (i64.extend_i32_u (i32.load (i32.const 32))))
+            (call $print (i64.extend_i32_u (global.get $tstack)))
+            (drop (call $read_line (i32.const 48)))
+            (call $print (i64.extend_i32_u (i32.load (i32.const 48))))
+            (call $print (i64.extend_i32_u (global.get $tstack)))",
+        stdin: b"hello\nzong\n",
+        stdout: b"4096\n4101\n4101\n4105\n",
+    },
+    Check {
+        name: "read_line grows memory for a line past its end",
+        data: "",
+        body: "
+            (global.set $tstack (i32.const 65534))
+            (drop (call $read_line (i32.const 32)))
+            (call $print (i64.extend_i32_u (global.get $tstack)))
+            (call $print (i64.extend_i32_u (memory.size)))",
+        stdin: b"hello\n",
+        stdout: b"65539\n2\n",
+    },
+];
+
+fn module(check: &Check) -> String {
+    format!(
+        r#"(module
+            (import "env" "print" (func $print (param i64)))
+            (import "env" "print_bytes" (func $print_bytes (param i32)))
+            (import "env" "read_line" (func $read_line (param i32) (result i32)))
+            (memory (export "memory") 1)
+            (global $tstack (export "tstack") (mut i32) (i32.const {}))
+            {}
+            (func (export "main") {}))"#,
+        INITIAL_TSTACK, check.data, check.body
+    )
+}
+
+// Runs every check against the runtime and returns the exit code: 0 if all
+// passed, 1 otherwise.
+pub fn run(runtime: Option<&Path>) -> Result<i32> {
+    let runtime = match runtime {
+        Some(runtime) => runtime.to_path_buf(),
+        None => std::env::current_exe()?,
+    };
+    let mut failed = 0;
+    for (i, check) in CHECKS.iter().enumerate() {
+        let wasm_bytes = wat::parse_str(module(check))?;
+        let wasm_path =
+            std::env::temp_dir().join(format!("zong-abi-test-{}-{}.wasm", std::process::id(), i));
+        fs::write(&wasm_path, wasm_bytes)?;
+        let mut child = Command::new(&runtime)
+            .arg(&wasm_path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        child.stdin.take().unwrap().write_all(check.stdin)?;
+        let output = child.wait_with_output()?;
+        fs::remove_file(&wasm_path)?;
+
+        if output.status.success() && output.stdout == check.stdout {
+            println!("ok    {}", check.name);
+        } else {
+            failed += 1;
+            println!("FAIL  {}", check.name);
+            println!(
+                "      expected stdout {:?}",
+                String::from_utf8_lossy(check.stdout)
+            );
+            println!(
+                "      got stdout      {:?}",
+                String::from_utf8_lossy(&output.stdout)
+            );
+            if !output.status.success() {
+                println!("      and {}", output.status);
+            }
+        }
+    }
+    println!("{} passed, {} failed", CHECKS.len() - failed, failed);
+    Ok(if failed == 0 { 0 } else { 1 })
+}
@@ -0,0 +1,145 @@
+// Allocation profiles for --alloc-profile: how much of the host_alloc heap
+// each call site in the guest asked for, printed to stderr when the run ends,
+// the sites that allocated the most bytes first:
+//
+//   allocations: 102400 bytes in 101 blocks, 4096 bytes live
+//          bytes  blocks        live  site
+//         101376     100        4096  parse_list at list.zong:12:9
+//           1024       1           0  main at wasm offset 0x1a3
+//
+// A site is the call to host_alloc in the innermost guest frame. With a
+// zong.srcmap section or DWARF line table in the module that was compiled,
+// and not an instrumented copy, it is given as a source location. Bytes are
+// those asked for, before rounding to the heap's alignment, and live bytes
+// are those not freed by the end of the run. Threads share the profile, as
+// they share the heap.
+
+use crate::sourcemap::SourceMap;
+use std::collections::HashMap;
+use wasmtime::WasmBacktrace;
+
+// Sites printed, the rest being summed up on one line.
+const TOP_SITES: usize = 20;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct Site {
+    func_index: u32,
+    offset: Option<usize>,
+}
+
+#[derive(Default)]
+struct Totals {
+    func_name: Option<String>,
+    bytes: u64,
+    blocks: u64,
+    live: u64,
+}
+
+pub struct AllocProfile {
+    source_map: Option<SourceMap>,
+    sites: HashMap<Site, Totals>,
+    // Blocks not freed yet, by address, with their sites and sizes.
+    live: HashMap<usize, (Site, u64)>,
+}
+
+impl AllocProfile {
+    pub fn new(source_map: Option<SourceMap>) -> AllocProfile {
+        AllocProfile {
+            source_map,
+            sites: HashMap::new(),
+            live: HashMap::new(),
+        }
+    }
+
+    // Records the block of len bytes at addr, allocated by the guest stack
+    // in backtrace.
+    pub fn record_alloc(&mut self, backtrace: &WasmBacktrace, addr: usize, len: usize) {
+        let frame = backtrace.frames().first();
+        let site = Site {
+            func_index: frame.map_or(u32::MAX, |frame| frame.func_index()),
+            offset: frame.and_then(|frame| frame.module_offset()),
+        };
+        let totals = self.sites.entry(site).or_insert_with(|| Totals {
+            func_name: frame
+                .and_then(|frame| frame.func_name())
+                .map(str::to_string),
+            ..Totals::default()
+        });
+        totals.bytes += len as u64;
+        totals.blocks += 1;
+        totals.live += len as u64;
+        self.live.insert(addr, (site, len as u64));
+    }
+
+    pub fn record_free(&mut self, addr: usize) {
+        if let Some((site, len)) = self.live.remove(&addr) {
+            self.sites.get_mut(&site).unwrap().live -= len;
+        }
+    }
+
+    // Where the block at addr, not freed yet, was allocated.
+    pub fn site(&self, addr: usize) -> Option<String> {
+        let (site, _) = self.live.get(&addr)?;
+        Some(self.describe(site, &self.sites[site]))
+    }
+
+    pub fn print(&self) {
+        let mut sites: Vec<(&Site, &Totals)> = self.sites.iter().collect();
+        sites.sort_by(|(a_site, a), (b_site, b)| {
+            (b.bytes, b.blocks)
+                .cmp(&(a.bytes, a.blocks))
+                .then((a_site.func_index, a_site.offset).cmp(&(b_site.func_index, b_site.offset)))
+        });
+        let bytes: u64 = sites.iter().map(|(_, totals)| totals.bytes).sum();
+        let blocks: u64 = sites.iter().map(|(_, totals)| totals.blocks).sum();
+        let live: u64 = sites.iter().map(|(_, totals)| totals.live).sum();
+        eprintln!(
+            "allocations: {} bytes in {} blocks, {} bytes live",
+            bytes, blocks, live
+        );
+        if sites.is_empty() {
+            return;
+        }
+        eprintln!("  {:>10}  {:>6}  {:>10}  site", "bytes", "blocks", "live");
+        for (site, totals) in sites.iter().take(TOP_SITES) {
+            eprintln!(
+                "  {:>10}  {:>6}  {:>10}  {}",
+                totals.bytes,
+                totals.blocks,
+                totals.live,
+                self.describe(site, totals)
+            );
+        }
+        if sites.len() > TOP_SITES {
+            let rest = &sites[TOP_SITES..];
+            let bytes: u64 = rest.iter().map(|(_, totals)| totals.bytes).sum();
+            let blocks: u64 = rest.iter().map(|(_, totals)| totals.blocks).sum();
+            eprintln!(
+                "  {:>10}  {:>6}  {:>10}  ({} more sites)",
+                bytes,
+                blocks,
+                "",
+                rest.len()
+            );
+        }
+    }
+
+    fn describe(&self, site: &Site, totals: &Totals) -> String {
+        let name = match &totals.func_name {
+            Some(name) => name.clone(),
+            None if site.func_index == u32::MAX => "<host>".to_string(),
+            None => format!("<function {}>", site.func_index),
+        };
+        let Some(offset) = site.offset else {
+            return name;
+        };
+        match self
+            .source_map
+            .as_ref()
+            .and_then(|source_map| source_map.position(offset))
+        {
+            Some(position) => format!("{} at {}", name, position),
+            None => format!("{} at wasm offset {:#x}", name, offset),
+        }
+    }
+}
@@ -0,0 +1,138 @@
+// Capability audit: describes what a module could do to the host, judged
+// only from its imports, without running it. Also the audit log, which
+// records what a running module actually did.
+
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use wasmtime::{Module, Result};
+
+// Maps an import to the host capabilities it grants. Returns None for imports
+// the runtime does not provide itself, e.g. ones satisfied by --link-instance.
+pub fn capability(module: &str, name: &str) -> Option<&'static str> {
+    match (module, name) {
+        ("env", "print" | "print_bytes" | "print_bytes_n" | "print_fmt" | "flush") => {
+            Some("stdout")
+        }
+        ("env", "print_i32" | "print_f32" | "print_f64" | "print_bool") => Some("stdout"),
+        ("env", "eprint" | "eprint_bytes" | "panic" | "log") => Some("stderr"),
+        ("env", "progress_begin" | "progress_update" | "progress_end") => Some("stderr"),
+        ("env", "term_is_tty" | "term_size") => Some("stdout"),
+        ("env", "const_str_register" | "const_str_print") => Some("stdout"),
+        ("env", "read_line" | "read_line_utf8" | "read_bytes" | "read_all") => Some("stdin"),
+        ("env", "read_key") => Some("stdin"),
+        ("env", "wait_event") => Some("stdin, files"),
+        ("env", "getcwd" | "chdir" | "watch_path" | "next_event") => Some("files"),
+        ("env", "file_open" | "file_read" | "file_write" | "write_file") => Some("files"),
+        // Closes sockets as well as files
+        ("env", "file_close") => Some("files, network"),
+        ("env", "tcp_connect" | "tcp_listen" | "tcp_accept" | "tcp_read" | "tcp_write") => {
+            Some("network")
+        }
+        ("env", "http_get") => Some("network"),
+        ("env", "exec") => Some("subprocess"),
+        ("env", "spawn" | "join") => Some("threads"),
+        ("env", "parse_i64" | "i64_to_string") => Some("conversion"),
+        ("env", "host_alloc" | "host_free") => Some("memory"),
+        ("env", "get_embedded") => Some("embedded data"),
+        ("env", "host_intern_string" | "host_string_len" | "host_string_get") => Some("references"),
+        ("env", "host_string_eq" | "host_ref_store" | "host_ref_load" | "host_ref_drop") => {
+            Some("references")
+        }
+        ("env", "sysinfo") => Some("system info"),
+        ("env", "last_error_message") => Some("process"),
+        ("env", "args_count" | "args_get") => Some("arguments"),
+        ("env", "env_get" | "env_count" | "env_at") => Some("environment"),
+        ("env", "clock_monotonic_ns" | "clock_realtime_ns") => Some("clock"),
+        ("env", "random_i64" | "random_bytes") => Some("random"),
+        ("env", "set_timeout" | "set_interval" | "clear_timer" | "wait_timers") => Some("timers"),
+        ("env", "sleep_ms") => Some("timers"),
+        ("env", "on_signal") => Some("signals"),
+        ("env", "shared_open" | "shared_size" | "shared_write" | "shared_read") => {
+            Some("shared memory")
+        }
+        ("env", "load_module" | "module_call") => Some("code loading"),
+        (crate::math::IMPORT_MODULE, "sqrt" | "sin" | "cos" | "pow" | "log" | "floor" | "ceil") => {
+            Some("math")
+        }
+        (crate::int128::IMPORT_MODULE, "i128_mul" | "i128_divmod") => Some("math"),
+        (crate::int128::IMPORT_MODULE, "i128_to_string") => Some("conversion"),
+        (crate::WASI_MODULE, name) => Some(wasi_capability(name)),
+        _ => None,
+    }
+}
+
+fn wasi_capability(name: &str) -> &'static str {
+    match name {
+        "fd_write" => "stdout, files",
+        "fd_read" => "stdin, files",
+        "args_get" | "args_sizes_get" => "arguments",
+        "environ_get" | "environ_sizes_get" => "environment",
+        "clock_res_get" | "clock_time_get" => "clock",
+        "random_get" => "random",
+        "proc_exit" | "proc_raise" | "sched_yield" | "poll_oneoff" => "process",
+        name if name.starts_with("sock_") => "network",
+        _ => "files",
+    }
+}
+
+// Prints each capability the module's imports grant, with the imports that
+// grant it.
+pub fn print_report(module: &Module) {
+    let mut granted: BTreeMap<&str, Vec<String>> = BTreeMap::new();
+    let mut other = Vec::new();
+    for import in module.imports() {
+        let name = format!("{}.{}", import.module(), import.name());
+        match capability(import.module(), import.name()) {
+            Some(capabilities) => {
+                for capability in capabilities.split(", ") {
+                    granted.entry(capability).or_default().push(name.clone());
+                }
+            }
+            None => other.push(name),
+        }
+    }
+
+    if granted.is_empty() {
+        println!("no host capabilities");
+    }
+    for (capability, imports) in &granted {
+        println!("{}: {}", capability, imports.join(", "));
+    }
+    if !other.is_empty() {
+        println!("other imports: {}", other.join(", "));
+    }
+}
+
+// Appends one JSON object per line for each sandbox-relevant operation:
+// {"time_ms", "operation", "target", "ok", "detail"}.
+pub struct AuditLog {
+    file: File,
+}
+
+impl AuditLog {
+    pub fn open(path: &Path) -> Result<AuditLog> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(AuditLog { file })
+    }
+
+    // Records an operation on target (e.g. a path) and its outcome. detail
+    // describes the failure, or anything else worth knowing.
+    pub fn record(&mut self, operation: &str, target: &str, ok: bool, detail: &str) {
+        let time_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_millis() as u64);
+        let entry = serde_json::json!({
+            "time_ms": time_ms,
+            "operation": operation,
+            "target": target,
+            "ok": ok,
+            "detail": detail,
+        });
+        // The log is best-effort; failing to write it must not change how
+        // the guest runs
+        let _ = writeln!(self.file, "{}", entry);
+    }
+}
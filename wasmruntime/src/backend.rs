@@ -0,0 +1,61 @@
+// Code generators for --backend. Cranelift is the optimizing compiler that
+// runs everything by default. Winch is wasmtime's baseline compiler: it
+// compiles quickly, emitting naive code, which makes it useful for telling a
+// miscompile in Cranelift from a bug in the module. Pulley is wasmtime's
+// portable interpreter, for hosts Cranelift cannot generate code for, but
+// the wasmtime this runtime is built with cannot execute its bytecode yet,
+// so choosing it is an error.
+//
+// Winch does not support everything Cranelift does. It rejects modules that
+// use threads, SIMD, or GC types, and cannot consume fuel, so --fuel and the
+// strict sandbox need Cranelift.
+
+use wasmtime::{Config, Error, Result, Strategy};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, clap::ValueEnum)]
+pub enum Backend {
+    /// Compile with Cranelift, the optimizing compiler
+    #[default]
+    Cranelift,
+    /// Compile with Winch, the baseline compiler
+    Winch,
+    /// Interpret with Pulley
+    Pulley,
+}
+
+impl Backend {
+    pub fn name(self) -> &'static str {
+        match self {
+            Backend::Cranelift => "cranelift",
+            Backend::Winch => "winch",
+            Backend::Pulley => "pulley",
+        }
+    }
+
+    pub fn configure(self, config: &mut Config, consume_fuel: bool) -> Result<()> {
+        match self {
+            Backend::Cranelift => {
+                config.strategy(Strategy::Cranelift);
+            }
+            Backend::Winch => {
+                if consume_fuel {
+                    return Err(Error::msg(
+                        "the winch backend cannot consume fuel; use --backend cranelift with \
+                         --fuel and the strict sandbox",
+                    ));
+                }
+                config.strategy(Strategy::Winch);
+                config.wasm_threads(false);
+                config.wasm_function_references(false);
+                config.wasm_gc(false);
+            }
+            Backend::Pulley => {
+                return Err(Error::msg(
+                    "the pulley backend needs wasmtime 28 or newer, which can run its \
+                     bytecode; this runtime is built with wasmtime 26",
+                ));
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,39 @@
+// Readable reports of errors that stopped the guest: the error, then the
+// guest's stack when it stopped.
+
+use crate::diagnostics;
+use crate::panic::GuestPanic;
+use wasmtime::{Error, WasmBacktrace};
+
+// Prints an error that happened while the guest was running, whether a trap
+// or a host function failing, with the guest's backtrace. A trap's raw
+// description is included if verbose. Returns false, printing nothing, if the
+// error has no backtrace.
+pub fn print_error(error: &Error, verbose: bool) -> bool {
+    let Some(backtrace) = error.downcast_ref::<WasmBacktrace>() else {
+        return false;
+    };
+    // A panic's message says what it is
+    match error.downcast_ref::<GuestPanic>() {
+        Some(panic) => eprintln!("{}", panic),
+        None => eprintln!("Error: {}", diagnostics::describe(error, verbose)),
+    }
+    print_backtrace(backtrace);
+    true
+}
+
+// Prints the guest's stack, innermost frame first, with function names from
+// the module's name section.
+pub fn print_backtrace(backtrace: &WasmBacktrace) {
+    eprintln!("wasm backtrace:");
+    for (i, frame) in backtrace.frames().iter().enumerate() {
+        let name = match frame.func_name() {
+            Some(name) => name.to_string(),
+            None => format!("<function {}>", frame.func_index()),
+        };
+        match frame.module_offset() {
+            Some(offset) => eprintln!("  {:>3}: {} at wasm offset {:#x}", i, name, offset),
+            None => eprintln!("  {:>3}: {}", i, name),
+        }
+    }
+}
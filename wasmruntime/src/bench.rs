@@ -0,0 +1,266 @@
+// `wasmruntime bench`: runs a program many times in one process and reports
+// how long its runs took, for comparing builds from different compilers
+// without timing process startup and compilation. The module is compiled
+// once; each run gets a fresh instance and the same stdin and arguments, and
+// is timed from instantiation to the entry function's return, like the
+// time_secs of --output json. The first --warmup runs are not counted.
+//
+//   prog.wasm: 10 iterations after 3 warmup runs
+//     time:  min 1.204 ms  median 1.250 ms  mean 1.262 ms  stddev 0.041 ms
+//     fuel:  min 48211  median 48211  mean 48211  stddev 0
+//
+// Fuel is only counted with --fuel, since consuming it slows the runs down.
+// With --output json, the same statistics are printed as one JSON object:
+//
+//   {"iterations":10,"warmup":3,"exit_code":0,
+//    "time_secs":{"min":0.001204,"median":0.00125,"mean":0.001262,"stddev":0.000041},
+//    "fuel_consumed":null}
+//
+// A run that traps or fails stops the benchmark.
+
+use crate::hostio::HostIo;
+use crate::report::OutputFormat;
+use crate::sandbox::Profile;
+use crate::{
+    call_entry, check_resolved, engine_config, exitcode, host_linker, read_module, HostState,
+};
+use serde::Serialize;
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use wasmtime::{Engine, Error, Linker, Module, Result, Store};
+use wasmtime_wasi::I32Exit;
+
+#[derive(Serialize)]
+struct Summary {
+    min: f64,
+    median: f64,
+    mean: f64,
+    stddev: f64,
+}
+
+impl Summary {
+    fn new(samples: &[f64]) -> Summary {
+        let mut sorted = samples.to_vec();
+        sorted.sort_by(f64::total_cmp);
+        let count = sorted.len();
+        let median = if count.is_multiple_of(2) {
+            (sorted[count / 2 - 1] + sorted[count / 2]) / 2.0
+        } else {
+            sorted[count / 2]
+        };
+        let mean = sorted.iter().sum::<f64>() / count as f64;
+        // The sample standard deviation, which is 0 for a single run
+        let variance = if count > 1 {
+            sorted.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / (count - 1) as f64
+        } else {
+            0.0
+        };
+        Summary {
+            min: sorted[0],
+            median,
+            mean,
+            stddev: variance.sqrt(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Report {
+    iterations: usize,
+    warmup: usize,
+    exit_code: i32,
+    time_secs: Summary,
+    fuel_consumed: Option<Summary>,
+}
+
+// One counted run.
+struct Sample {
+    exit_code: i32,
+    elapsed: Duration,
+    fuel_consumed: Option<u64>,
+}
+
+// Runs the program warmup + iterations times and returns the exit code: 0,
+// unless a run failed.
+pub fn run(
+    wasm_path: &Path,
+    iterations: usize,
+    warmup: usize,
+    fuel: bool,
+    stdin: Option<&Path>,
+    output: OutputFormat,
+    args: &[String],
+) -> Result<i32> {
+    if iterations == 0 {
+        return Err(exitcode::usage("--iterations must be at least 1"));
+    }
+    let stdin = match stdin {
+        Some(path) => {
+            fs::read(path).map_err(|error| exitcode::unreadable(path.display(), error))?
+        }
+        None => Vec::new(),
+    };
+    let engine = Engine::new(&engine_config(fuel, false))?;
+    let wasm_bytes = read_module(wasm_path)?;
+    let module = Module::new(&engine, &wasm_bytes)?;
+    let profile = Profile::default();
+    profile.check_imports_denying(&module, &[String::from("threads")])?;
+    let argv: Vec<String> = std::iter::once(wasm_path.display().to_string())
+        .chain(args.iter().cloned())
+        .collect();
+    let bench = Bench {
+        engine: &engine,
+        module: &module,
+        wasm_bytes: &wasm_bytes,
+        argv: &argv,
+        stdin: &stdin,
+        fuel,
+    };
+    let linker = host_linker(&engine, &bench.state()?)?;
+
+    let mut samples = Vec::with_capacity(iterations);
+    for index in 0..warmup + iterations {
+        let sample = bench.run_once(&linker).map_err(|error| {
+            let run = if index < warmup {
+                format!("warmup run {}", index + 1)
+            } else {
+                format!("iteration {}", index - warmup + 1)
+            };
+            error.context(run)
+        })?;
+        if index >= warmup {
+            samples.push(sample);
+        }
+    }
+
+    let times: Vec<f64> = samples
+        .iter()
+        .map(|sample| sample.elapsed.as_secs_f64())
+        .collect();
+    let fuel_consumed: Option<Vec<f64>> = samples
+        .iter()
+        .map(|sample| sample.fuel_consumed.map(|fuel| fuel as f64))
+        .collect();
+    let report = Report {
+        iterations,
+        warmup,
+        exit_code: samples.last().map_or(0, |sample| sample.exit_code),
+        time_secs: Summary::new(&times),
+        fuel_consumed: fuel_consumed.as_deref().map(Summary::new),
+    };
+    match output {
+        OutputFormat::Json => println!(
+            "{}",
+            serde_json::to_string(&report).expect("reports serialize to JSON")
+        ),
+        OutputFormat::Text => print_report(&argv[0], &report),
+    }
+    Ok(0)
+}
+
+fn print_report(name: &str, report: &Report) {
+    println!(
+        "{}: {} {} after {} warmup {}",
+        name,
+        report.iterations,
+        if report.iterations == 1 {
+            "iteration"
+        } else {
+            "iterations"
+        },
+        report.warmup,
+        if report.warmup == 1 { "run" } else { "runs" }
+    );
+    let time = &report.time_secs;
+    let ms = |secs: f64| format!("{:.3} ms", secs * 1000.0);
+    println!(
+        "  time:  min {}  median {}  mean {}  stddev {}",
+        ms(time.min),
+        ms(time.median),
+        ms(time.mean),
+        ms(time.stddev)
+    );
+    if let Some(fuel) = &report.fuel_consumed {
+        println!(
+            "  fuel:  min {:.0}  median {:.0}  mean {:.0}  stddev {:.0}",
+            fuel.min, fuel.median, fuel.mean, fuel.stddev
+        );
+    }
+    if report.exit_code != 0 {
+        println!("  exit code: {}", report.exit_code);
+    }
+}
+
+// What every run shares.
+struct Bench<'a> {
+    engine: &'a Engine,
+    module: &'a Module,
+    wasm_bytes: &'a [u8],
+    argv: &'a [String],
+    stdin: &'a [u8],
+    fuel: bool,
+}
+
+impl Bench<'_> {
+    // State for a run the way `wasmruntime run` would set it up with default
+    // options, but with its output discarded.
+    fn state(&self) -> Result<HostState> {
+        let mut io = HostIo::default();
+        io.set_stdin(self.stdin.to_vec());
+        io.capture_stdout();
+        io.capture_stderr();
+        HostState::for_module(
+            self.module,
+            self.wasm_bytes,
+            None,
+            &[],
+            self.argv,
+            io,
+            Profile::default(),
+        )
+    }
+
+    fn run_once(&self, linker: &Linker<HostState>) -> Result<Sample> {
+        let mut store = Store::new(self.engine, self.state()?);
+        store.limiter(|state| &mut state.limits);
+        store.set_epoch_deadline(1);
+        if self.fuel {
+            store.set_fuel(u64::MAX)?;
+        }
+        check_resolved(linker, &mut store, self.module, &self.argv[0])?;
+
+        let started = Instant::now();
+        let result = linker
+            .instantiate(&mut store, self.module)
+            .and_then(|instance| {
+                let entry = match instance.get_func(&mut store, "main") {
+                    Some(main) => Some((main, "main")),
+                    None => instance
+                        .get_func(&mut store, "_start")
+                        .map(|start| (start, "_start")),
+                };
+                let (entry_func, entry) =
+                    entry.ok_or_else(|| Error::msg("entry export 'main' not found"))?;
+                call_entry(&mut store, entry_func, entry)
+            });
+        let elapsed = started.elapsed();
+        let exit_code = match result {
+            Ok(exit_code) => exit_code.unwrap_or(0),
+            Err(error) => match error.downcast_ref::<I32Exit>() {
+                Some(exit) => exit.0,
+                None => return Err(error),
+            },
+        };
+        let fuel_consumed = if self.fuel {
+            Some(u64::MAX - store.get_fuel()?)
+        } else {
+            None
+        };
+        Ok(Sample {
+            exit_code,
+            elapsed,
+            fuel_consumed,
+        })
+    }
+}
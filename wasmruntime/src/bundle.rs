@@ -0,0 +1,169 @@
+// Zong program bundles: a zip archive holding `prog.wasm` plus an optional
+// `assets/` directory of data files the program reads at run time. The
+// assets are extracted to a new directory with a random name under the
+// temporary directory, readable only by the user on Unix, so that another
+// user of a shared /tmp cannot plant symlinks to redirect the writes.
+//
+// Entries are read no further than MAX_ENTRY_SIZE bytes, and the bundle no
+// further than MAX_TOTAL_SIZE, whatever sizes the archive claims, so that a
+// zip bomb cannot fill the disk. The directory is removed when the Bundle is
+// dropped, or by remove_extracted on the paths that exit the process
+// without unwinding.
+
+use rand::Rng;
+use std::fs::{self, DirBuilder, OpenOptions};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use wasmtime::{Error, Result};
+
+const BUNDLE_WASM: &str = "prog.wasm";
+const BUNDLE_ASSETS: &str = "assets";
+
+// Names tried for the extraction directory before giving up.
+const EXTRACT_DIR_ATTEMPTS: usize = 16;
+
+const MAX_ENTRY_SIZE: u64 = 256 << 20;
+const MAX_TOTAL_SIZE: u64 = 1 << 30;
+
+// Extraction directories of the bundles not dropped yet.
+static EXTRACTED: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+pub struct Bundle {
+    pub wasm_bytes: Vec<u8>,
+    // Extracted copy of the bundle's assets/ directory, if it has one.
+    pub assets_dir: Option<PathBuf>,
+    extract_dir: PathBuf,
+}
+
+pub fn is_bundle(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension == "zip")
+}
+
+impl Bundle {
+    pub fn open(path: &Path) -> Result<Bundle> {
+        let file = fs::File::open(path)?;
+        let mut archive = zip::ZipArchive::new(file).map_err(|error| {
+            Error::msg(format!("{}: invalid bundle: {}", path.display(), error))
+        })?;
+
+        let extract_dir = create_extract_dir()?;
+        EXTRACTED.lock().unwrap().push(extract_dir.clone());
+        let mut bundle = Bundle {
+            wasm_bytes: Vec::new(),
+            assets_dir: None,
+            extract_dir,
+        };
+
+        let mut found_wasm = false;
+        let mut total: u64 = 0;
+        for i in 0..archive.len() {
+            let mut entry = archive
+                .by_index(i)
+                .map_err(|error| Error::msg(error.to_string()))?;
+            // Reject entries like "../x" or "/etc/x" that would escape the
+            // extraction directory.
+            let name = entry.enclosed_name().ok_or_else(|| {
+                Error::msg(format!("bundle entry has unsafe path: {}", entry.name()))
+            })?;
+
+            let is_dir = entry.is_dir();
+            // Past the limit by one byte is enough to know it is too big
+            let limit = MAX_ENTRY_SIZE.min(MAX_TOTAL_SIZE - total);
+            let mut contents = (&mut entry).take(limit + 1);
+            let read = if name == Path::new(BUNDLE_WASM) {
+                found_wasm = true;
+                contents.read_to_end(&mut bundle.wasm_bytes)? as u64
+            } else if name.starts_with(BUNDLE_ASSETS) {
+                // The assets must be a directory for assets_dir to name one
+                if name == Path::new(BUNDLE_ASSETS) && !is_dir {
+                    return Err(Error::msg(format!(
+                        "{}: bundle entry {} is a file, not a directory",
+                        path.display(),
+                        BUNDLE_ASSETS
+                    )));
+                }
+                bundle.assets_dir = Some(bundle.extract_dir.join(BUNDLE_ASSETS));
+                let dest = bundle.extract_dir.join(&name);
+                if is_dir {
+                    fs::create_dir_all(&dest)?;
+                    0
+                } else {
+                    if let Some(parent) = dest.parent() {
+                        fs::create_dir_all(parent)?;
+                    }
+                    let mut file = OpenOptions::new()
+                        .write(true)
+                        .create_new(true)
+                        .open(&dest)
+                        .map_err(|error| Error::new(error).context(dest.display().to_string()))?;
+                    io::copy(&mut contents, &mut file)?
+                }
+            } else {
+                0
+            };
+            if read > limit {
+                let what = if limit == MAX_ENTRY_SIZE {
+                    format!("entry {} is", name.display())
+                } else {
+                    String::from("contents are")
+                };
+                return Err(Error::msg(format!(
+                    "{}: bundle {} larger than {} bytes",
+                    path.display(),
+                    what,
+                    limit
+                )));
+            }
+            total += read;
+        }
+
+        if !found_wasm {
+            return Err(Error::msg(format!(
+                "{}: bundle has no {}",
+                path.display(),
+                BUNDLE_WASM
+            )));
+        }
+        Ok(bundle)
+    }
+}
+
+// Creates a directory for the bundle's assets that did not exist before.
+fn create_extract_dir() -> Result<PathBuf> {
+    let mut builder = DirBuilder::new();
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+    for _ in 0..EXTRACT_DIR_ATTEMPTS {
+        let name = format!(
+            "wasmruntime-bundle-{:016x}",
+            rand::thread_rng().gen::<u64>()
+        );
+        let dir = std::env::temp_dir().join(name);
+        match builder.create(&dir) {
+            Ok(()) => return Ok(dir),
+            Err(error) if error.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(error) => return Err(Error::new(error).context(dir.display().to_string())),
+        }
+    }
+    Err(Error::msg(
+        "cannot create a directory to extract the bundle to",
+    ))
+}
+
+// Removes the directories of the bundles not dropped yet, before exiting the
+// process.
+pub fn remove_extracted() {
+    let mut extracted = EXTRACTED.lock().unwrap_or_else(|error| error.into_inner());
+    for dir in extracted.drain(..) {
+        let _ = fs::remove_dir_all(dir);
+    }
+}
+
+impl Drop for Bundle {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.extract_dir);
+        let mut extracted = EXTRACTED.lock().unwrap_or_else(|error| error.into_inner());
+        extracted.retain(|dir| *dir != self.extract_dir);
+    }
+}
@@ -0,0 +1,89 @@
+// Loading compiled code for `run`. A precompiled .cwasm file, as written by
+// `wasmruntime compile`, is loaded without compiling it, but only with
+// --allow-precompiled, since nothing checks what its native code does. With
+// --cache-dir, each module's compiled code is kept in the directory, named by
+// a hash of the module and of the engine settings it was compiled for, so
+// running the same module again skips compiling it.
+//
+// Compiled code is loaded without validation. Like the module given on the
+// command line, the cache directory must only be writable by users trusted
+// to run native code as this one.
+
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::Path;
+use wasmtime::{Engine, Error, Module, Precompiled, Result};
+
+pub fn is_precompiled(engine: &Engine, bytes: &[u8]) -> bool {
+    engine.detect_precompiled(bytes) == Some(Precompiled::Module)
+}
+
+// Compiles a module, or loads it if it is cached, or precompiled and
+// allow_precompiled is set.
+pub fn load(
+    engine: &Engine,
+    bytes: &[u8],
+    cache_dir: Option<&Path>,
+    allow_precompiled: bool,
+) -> Result<Module> {
+    if is_precompiled(engine, bytes) {
+        if !allow_precompiled {
+            return Err(crate::exitcode::usage(
+                "precompiled code runs natively without being validated, so running it needs \
+                 --allow-precompiled",
+            ));
+        }
+        // SAFETY: wasmtime only checks that the file was compiled for this
+        // engine, not what its code does, so a precompiled file can do
+        // anything native code can. --allow-precompiled is the user's word
+        // that the file is as trusted as the runtime itself; the strict
+        // sandbox refuses it.
+        return unsafe { Module::deserialize(engine, bytes) };
+    }
+    let Some(cache_dir) = cache_dir else {
+        return Module::new(engine, bytes);
+    };
+    let path = cache_dir.join(format!("{}.cwasm", cache_key(engine, bytes)));
+    // SAFETY: Only this function writes the cache, and the file name covers
+    // the engine settings. A file that fails to load is recompiled.
+    if let Ok(module) = unsafe { Module::deserialize_file(engine, &path) } {
+        return Ok(module);
+    }
+    let module = Module::new(engine, bytes)?;
+    let context = |error| Error::new(error).context(cache_dir.display().to_string());
+    fs::create_dir_all(cache_dir).map_err(context)?;
+    // Other runs may be reading or writing the same entry, so it is written
+    // under a temporary name and moved into place in one step
+    let temp_path = path.with_extension(format!("{}.tmp", std::process::id()));
+    fs::write(&temp_path, module.serialize()?).map_err(context)?;
+    fs::rename(&temp_path, &path).map_err(context)?;
+    Ok(module)
+}
+
+// A hash of the module and the engine's code generation settings.
+fn cache_key(engine: &Engine, bytes: &[u8]) -> String {
+    let mut hasher = Sha256Hasher(Sha256::new());
+    engine.precompile_compatibility_hash().hash(&mut hasher);
+    hasher.0.update(bytes);
+    hasher
+        .0
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+// Feeds Hash implementations into SHA-256, which, unlike DefaultHasher, is
+// the same in every build of the runtime.
+struct Sha256Hasher(Sha256);
+
+impl Hasher for Sha256Hasher {
+    fn write(&mut self, bytes: &[u8]) {
+        self.0.update(bytes);
+    }
+
+    fn finish(&self) -> u64 {
+        unreachable!("only the SHA-256 digest is used")
+    }
+}
@@ -0,0 +1,279 @@
+// Call counting for --pgo-out, --callgraph, --coverage, and --stats. Before compiling, the module
+// is instrumented with one exported i64 counter global per function, bumped
+// on entry, and one per direct call edge, bumped before the call. After the
+// run the counters are written out as a profile for the Zong compiler or as
+// a call graph. Calls through tables only show up in the callee's count.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+use walrus::ir::{
+    BinaryOp, Binop, Const, GlobalGet, GlobalSet, Instr, InstrSeq, Value, VisitorMut,
+};
+use walrus::{ConstExpr, FunctionId, FunctionKind, GlobalId, ValType};
+use wasmtime::{AsContextMut, Error, Instance, Result};
+
+pub struct Instrumented {
+    pub wasm_bytes: Vec<u8>,
+    // Names of all functions, by index: the name section's name for local
+    // functions, if any, and "module.name" for imports.
+    names: Vec<Option<String>>,
+    // (function index, counter export)
+    functions: Vec<(usize, String)>,
+    // (caller index, callee index, counter export)
+    edges: Vec<(usize, usize, String)>,
+}
+
+// Counter values read back after a run.
+pub struct Counts {
+    // (function index, calls)
+    functions: Vec<(usize, i64)>,
+    // (caller index, callee index, calls)
+    edges: Vec<(usize, usize, i64)>,
+}
+
+// Collects the direct callees of a function.
+struct Callees(BTreeSet<FunctionId>);
+
+impl VisitorMut for Callees {
+    fn start_instr_seq_mut(&mut self, seq: &mut InstrSeq) {
+        for (instr, _) in &seq.instrs {
+            if let Some(callee) = direct_callee(instr) {
+                self.0.insert(callee);
+            }
+        }
+    }
+}
+
+// Bumps a call edge's counter before each direct call.
+struct CountCalls<'a>(&'a BTreeMap<FunctionId, GlobalId>);
+
+impl VisitorMut for CountCalls<'_> {
+    fn start_instr_seq_mut(&mut self, seq: &mut InstrSeq) {
+        let instrs = std::mem::take(&mut seq.instrs);
+        for (instr, loc) in instrs {
+            if let Some(counter) = direct_callee(&instr).and_then(|callee| self.0.get(&callee)) {
+                seq.instrs.extend(increment(*counter));
+            }
+            seq.instrs.push((instr, loc));
+        }
+    }
+}
+
+fn direct_callee(instr: &Instr) -> Option<FunctionId> {
+    match instr {
+        Instr::Call(call) => Some(call.func),
+        Instr::ReturnCall(call) => Some(call.func),
+        _ => None,
+    }
+}
+
+fn increment(counter: GlobalId) -> Vec<(Instr, walrus::InstrLocId)> {
+    [
+        Instr::GlobalGet(GlobalGet { global: counter }),
+        Instr::Const(Const {
+            value: Value::I64(1),
+        }),
+        Instr::Binop(Binop {
+            op: BinaryOp::I64Add,
+        }),
+        Instr::GlobalSet(GlobalSet { global: counter }),
+    ]
+    .into_iter()
+    .map(|instr| (instr, Default::default()))
+    .collect()
+}
+
+pub fn instrument(wasm_bytes: &[u8]) -> Result<Instrumented> {
+    let wasm_bytes = wat::parse_bytes(wasm_bytes)?;
+    let mut module = walrus::Module::from_buffer(&wasm_bytes)?;
+    let names = module
+        .funcs
+        .iter()
+        .map(|func| match &func.kind {
+            FunctionKind::Import(import) => {
+                let import = module.imports.get(import.import);
+                Some(format!("{}.{}", import.module, import.name))
+            }
+            _ => func.name.clone(),
+        })
+        .collect();
+    let mut instrumented = Instrumented {
+        wasm_bytes: Vec::new(),
+        names,
+        functions: Vec::new(),
+        edges: Vec::new(),
+    };
+
+    let local_funcs: Vec<FunctionId> = module.funcs.iter_local().map(|(id, _)| id).collect();
+    for caller in local_funcs {
+        let mut callees = Callees(BTreeSet::new());
+        let func = module.funcs.get_mut(caller).kind.unwrap_local_mut();
+        walrus::ir::dfs_pre_order_mut(&mut callees, func, func.entry_block());
+
+        let mut edge_counters = BTreeMap::new();
+        for callee in callees.0 {
+            let export = format!("__zong_calls_{}_{}", caller.index(), callee.index());
+            let counter = add_counter(&mut module, &export);
+            edge_counters.insert(callee, counter);
+            instrumented
+                .edges
+                .push((caller.index(), callee.index(), export));
+        }
+        let export = format!("__zong_calls_{}", caller.index());
+        let entry_counter = add_counter(&mut module, &export);
+        instrumented.functions.push((caller.index(), export));
+
+        let func = module.funcs.get_mut(caller).kind.unwrap_local_mut();
+        walrus::ir::dfs_pre_order_mut(&mut CountCalls(&edge_counters), func, func.entry_block());
+        let entry = func.entry_block();
+        func.block_mut(entry)
+            .instrs
+            .splice(0..0, increment(entry_counter));
+    }
+
+    instrumented.wasm_bytes = module.emit_wasm();
+    Ok(instrumented)
+}
+
+fn add_counter(module: &mut walrus::Module, export: &str) -> GlobalId {
+    let counter =
+        module
+            .globals
+            .add_local(ValType::I64, true, false, ConstExpr::Value(Value::I64(0)));
+    module.exports.add(export, counter);
+    counter
+}
+
+impl Instrumented {
+    pub fn counts(&self, mut store: impl AsContextMut, instance: &Instance) -> Result<Counts> {
+        let mut count = |export: &str| -> Result<i64> {
+            let global = instance
+                .get_global(&mut store, export)
+                .ok_or_else(|| Error::msg(format!("missing call counter {}", export)))?;
+            Ok(global.get(&mut store).unwrap_i64())
+        };
+        Ok(Counts {
+            functions: self
+                .functions
+                .iter()
+                .map(|(index, export)| Ok((*index, count(export)?)))
+                .collect::<Result<_>>()?,
+            edges: self
+                .edges
+                .iter()
+                .map(|(caller, callee, export)| Ok((*caller, *callee, count(export)?)))
+                .collect::<Result<_>>()?,
+        })
+    }
+
+    // Calls to each import, by "module.name", summed over call sites. Imports
+    // that were never called are left out.
+    pub fn import_calls(&self, counts: &Counts) -> BTreeMap<String, i64> {
+        let mut calls = BTreeMap::new();
+        for (_, callee, count) in &counts.edges {
+            let is_local = counts.functions.iter().any(|(index, _)| index == callee);
+            if !is_local && *count > 0 {
+                let name = self.names[*callee].clone().unwrap_or_default();
+                *calls.entry(name).or_insert(0) += count;
+            }
+        }
+        calls
+    }
+
+    // Writes a profile in the text format the Zong compiler reads:
+    //
+    //   zpgo 1
+    //   func <index> <name> <calls>
+    //   edge <caller index> <callee index> <calls>
+    //
+    // Functions without a name are written as "-".
+    pub fn write_profile(&self, counts: &Counts, path: &Path) -> Result<()> {
+        let mut profile = String::from("zpgo 1\n");
+        for (index, calls) in &counts.functions {
+            let name = self.names[*index].as_deref().unwrap_or("-");
+            writeln!(profile, "func {} {} {}", index, name, calls)?;
+        }
+        for (caller, callee, calls) in &counts.edges {
+            writeln!(profile, "edge {} {} {}", caller, callee, calls)?;
+        }
+        fs::write(path, profile)?;
+        Ok(())
+    }
+
+    // Writes a JSON object mapping each local function's name to the number
+    // of times it was called, including functions that were never called.
+    pub fn write_coverage(&self, counts: &Counts, path: &Path) -> Result<()> {
+        let coverage: serde_json::Map<String, serde_json::Value> = counts
+            .functions
+            .iter()
+            .map(|(index, calls)| {
+                let name = self.names[*index]
+                    .clone()
+                    .unwrap_or_else(|| format!("func{}", index));
+                (name, (*calls).into())
+            })
+            .collect();
+        fs::write(path, serde_json::to_string_pretty(&coverage)? + "\n")?;
+        Ok(())
+    }
+
+    // Writes the call graph as JSON if path ends in ".json", otherwise as a
+    // Graphviz digraph with call counts on the edges. Only functions that
+    // were called, and edges that were taken, are included.
+    pub fn write_callgraph(&self, counts: &Counts, path: &Path) -> Result<()> {
+        let name = |index: usize| {
+            self.names[index]
+                .clone()
+                .unwrap_or_else(|| format!("func{}", index))
+        };
+        let edges = counts.edges.iter().filter(|(_, _, calls)| *calls > 0);
+        let graph = if path
+            .extension()
+            .is_some_and(|extension| extension == "json")
+        {
+            let functions: Vec<_> = counts
+                .functions
+                .iter()
+                .filter(|(_, calls)| *calls > 0)
+                .map(|(index, calls)| {
+                    serde_json::json!({"index": index, "name": name(*index), "calls": calls})
+                })
+                .collect();
+            let edges: Vec<_> = edges
+                .map(|(caller, callee, calls)| {
+                    serde_json::json!({"caller": caller, "callee": callee, "calls": calls})
+                })
+                .collect();
+            serde_json::to_string_pretty(&serde_json::json!({
+                "functions": functions,
+                "edges": edges,
+            }))? + "\n"
+        } else {
+            let mut dot = String::from("digraph calls {\n");
+            for (index, calls) in &counts.functions {
+                if *calls > 0 {
+                    writeln!(
+                        dot,
+                        "  f{} [label={:?}];",
+                        index,
+                        format!("{}\n{} calls", name(*index), calls)
+                    )?;
+                }
+            }
+            // Imports have no counter of their own; draw them as boxes
+            let mut imports = BTreeSet::new();
+            for (caller, callee, calls) in edges {
+                let is_local = counts.functions.iter().any(|(index, _)| index == callee);
+                if !is_local && imports.insert(*callee) {
+                    writeln!(dot, "  f{} [label={:?}, shape=box];", callee, name(*callee))?;
+                }
+                writeln!(dot, "  f{} -> f{} [label=\"{}\"];", caller, callee, calls)?;
+            }
+            dot + "}\n"
+        };
+        fs::write(path, graph)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,129 @@
+// Guest call tracing for --trace-calls. Before compiling, each function's body
+// is wrapped in a block between calls to zong_calltrace.enter and
+// zong_calltrace.exit, with its returns turned into branches out of the block
+// so that every way out passes the exit call. Tail calls become plain calls
+// followed by a branch. The host logs each entry and exit to stderr, indented
+// by call depth, with the function's name from the name section or else its
+// export name. A trap skips the exits of the functions it unwinds.
+
+use crate::HostState;
+use walrus::ir::{
+    Block, Br, Call, CallIndirect, Instr, InstrSeq, InstrSeqId, InstrSeqType, VisitorMut,
+};
+use walrus::{FunctionId, FunctionKind, ValType};
+use wasmtime::{Caller, Result};
+
+pub const IMPORT_MODULE: &str = "zong_calltrace";
+
+pub struct CallTracer {
+    // Function names by index, from the name section or else the exports.
+    names: Vec<Option<String>>,
+    depth: usize,
+}
+
+// Turns returns into branches out of the block wrapping the body.
+struct BranchOnReturn(InstrSeqId);
+
+impl VisitorMut for BranchOnReturn {
+    fn start_instr_seq_mut(&mut self, seq: &mut InstrSeq) {
+        let body = self.0;
+        let instrs = std::mem::take(&mut seq.instrs);
+        for (instr, loc) in instrs {
+            let call = match instr {
+                Instr::Return(_) => None,
+                Instr::ReturnCall(call) => Some(Instr::Call(Call { func: call.func })),
+                Instr::ReturnCallIndirect(call) => Some(Instr::CallIndirect(CallIndirect {
+                    ty: call.ty,
+                    table: call.table,
+                })),
+                instr => {
+                    seq.instrs.push((instr, loc));
+                    continue;
+                }
+            };
+            if let Some(call) = call {
+                seq.instrs.push((call, loc));
+            }
+            seq.instrs.push((Instr::Br(Br { block: body }), loc));
+        }
+    }
+}
+
+// Returns the instrumented module and a tracer for it.
+pub fn instrument(wasm_bytes: &[u8]) -> Result<(Vec<u8>, CallTracer)> {
+    let wasm_bytes = wat::parse_bytes(wasm_bytes)?;
+    let mut module = walrus::Module::from_buffer(&wasm_bytes)?;
+    let mut names: Vec<Option<String>> =
+        module.funcs.iter().map(|func| func.name.clone()).collect();
+    for export in module.exports.iter() {
+        if let walrus::ExportItem::Function(func) = export.item {
+            names[func.index()].get_or_insert_with(|| export.name.clone());
+        }
+    }
+
+    let hook_type = module.types.add(&[ValType::I32], &[]);
+    let (enter, _) = module.add_import_func(IMPORT_MODULE, "enter", hook_type);
+    let (exit, _) = module.add_import_func(IMPORT_MODULE, "exit", hook_type);
+
+    let local_funcs: Vec<FunctionId> = module
+        .funcs
+        .iter()
+        .filter(|func| matches!(func.kind, FunctionKind::Local(_)))
+        .map(|func| func.id())
+        .collect();
+    for id in local_funcs {
+        let results = module.types.results(module.funcs.get(id).ty()).to_vec();
+        let ty = InstrSeqType::new(&mut module.types, &[], &results);
+        let func = module.funcs.get_mut(id).kind.unwrap_local_mut();
+        let entry = func.entry_block();
+        let instrs = std::mem::take(&mut func.block_mut(entry).instrs);
+        let builder = func.builder_mut();
+        let mut body = builder.dangling_instr_seq(ty);
+        *body.instrs_mut() = instrs;
+        let body = body.id();
+        walrus::ir::dfs_pre_order_mut(&mut BranchOnReturn(body), func, body);
+
+        let index = id.index() as i32;
+        func.builder_mut()
+            .func_body()
+            .i32_const(index)
+            .call(enter)
+            .instr(Block { seq: body })
+            .i32_const(index)
+            .call(exit);
+    }
+
+    let tracer = CallTracer { names, depth: 0 };
+    Ok((module.emit_wasm(), tracer))
+}
+
+impl CallTracer {
+    fn name(&self, index: usize) -> String {
+        match self.names.get(index).cloned().flatten() {
+            Some(name) => name,
+            None => format!("<function {}>", index),
+        }
+    }
+}
+
+pub fn enter(mut caller: Caller<'_, HostState>, index: i32) {
+    if let Some(tracer) = &mut caller.data_mut().call_trace {
+        eprintln!(
+            "{}-> {}",
+            "  ".repeat(tracer.depth),
+            tracer.name(index as usize)
+        );
+        tracer.depth += 1;
+    }
+}
+
+pub fn exit(mut caller: Caller<'_, HostState>, index: i32) {
+    if let Some(tracer) = &mut caller.data_mut().call_trace {
+        tracer.depth = tracer.depth.saturating_sub(1);
+        eprintln!(
+            "{}<- {}",
+            "  ".repeat(tracer.depth),
+            tracer.name(index as usize)
+        );
+    }
+}
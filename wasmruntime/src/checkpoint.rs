@@ -0,0 +1,105 @@
+// Checkpoints for --checkpoint-every, so that long batch jobs give a sign of
+// life. A background thread marks a checkpoint due every interval and bumps
+// the engine's epoch; at its next epoch check the guest pauses while the
+// runtime flushes its output and updates a progress line on stderr:
+//
+//   checkpoint 12: 1m 02s elapsed, 48211000 fuel consumed
+//
+// The fuel is only shown with --fuel, and is as of the guest's last call or
+// return, since compiled code keeps its count in a register in between. On a
+// terminal the line redraws in place; otherwise each checkpoint logs a line.
+//
+// With --checkpoint-out FILE, SIGTERM also writes a snapshot of the guest's
+// memory and exported globals to FILE, as --snapshot-out does, before the
+// guest's on_signal handler runs and the runtime exits, so that a job
+// stopped by a reboot can be started again with --restore FILE. The call
+// stack is not saved: the entry point starts over, and a program that keeps
+// its progress in memory picks up from there.
+//
+// Guests only check the epoch in wasm code, so a checkpoint due during a
+// host call waits for it to return.
+
+use crate::hostio::HostIo;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use wasmtime::Engine;
+
+pub struct Checkpointer {
+    started: Instant,
+    checkpoints: u64,
+    is_tty: bool,
+    due: Arc<AtomicBool>,
+    stopped: Arc<AtomicBool>,
+}
+
+impl Checkpointer {
+    pub fn start(engine: &Engine, interval: Duration, io: &HostIo) -> Checkpointer {
+        let due = Arc::new(AtomicBool::new(false));
+        let stopped = Arc::new(AtomicBool::new(false));
+        let engine = engine.clone();
+        let (thread_due, thread_stopped) = (due.clone(), stopped.clone());
+        std::thread::spawn(move || {
+            while !thread_stopped.load(Ordering::SeqCst) {
+                std::thread::sleep(interval);
+                thread_due.store(true, Ordering::SeqCst);
+                engine.increment_epoch();
+            }
+        });
+        Checkpointer {
+            started: Instant::now(),
+            checkpoints: 0,
+            is_tty: io.stderr_is_terminal(),
+            due,
+            stopped,
+        }
+    }
+
+    // Whether a checkpoint is due, clearing it. The epoch also advances for
+    // signals, timeouts, and profiling samples.
+    pub fn take_due(&self) -> bool {
+        self.due.swap(false, Ordering::SeqCst)
+    }
+
+    // Reports a checkpoint, given the fuel consumed so far with --fuel.
+    pub fn checkpoint(&mut self, fuel_consumed: Option<u64>, io: &mut HostIo) {
+        self.checkpoints += 1;
+        let mut status = format!(
+            "checkpoint {}: {} elapsed",
+            self.checkpoints,
+            elapsed(self.started.elapsed())
+        );
+        if let Some(fuel_consumed) = fuel_consumed {
+            status += &format!(", {} fuel consumed", fuel_consumed);
+        }
+        let line = if self.is_tty {
+            // Redraw the line in place and clear any leftover characters
+            format!("\r{}\x1b[K", status)
+        } else {
+            format!("{}\n", status)
+        };
+        let _ = io.write_stderr(line.as_bytes());
+    }
+
+    // Ends the progress line once the guest has stopped.
+    pub fn finish(&self, io: &mut HostIo) {
+        if self.is_tty && self.checkpoints > 0 {
+            let _ = io.write_stderr(b"\n");
+        }
+    }
+}
+
+impl Drop for Checkpointer {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::SeqCst);
+    }
+}
+
+fn elapsed(elapsed: Duration) -> String {
+    let seconds = elapsed.as_secs();
+    match (seconds / 3600, seconds / 60 % 60, seconds % 60) {
+        (0, 0, seconds) => format!("{}.{}s", seconds, elapsed.subsec_millis() / 100),
+        (0, minutes, seconds) => format!("{}m {:02}s", minutes, seconds),
+        (hours, minutes, seconds) => format!("{}h {:02}m {:02}s", hours, minutes, seconds),
+    }
+}
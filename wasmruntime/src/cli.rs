@@ -0,0 +1,723 @@
+// Command-line interface. Running a module is the `run` subcommand, which is
+// also what a bare `wasmruntime <wasm-file>` means, since that is how the
+// Zong compiler invokes the runtime. ZONG_RUNTIME_* environment variables
+// give defaults for some flags, which the command line overrides.
+
+use crate::backend::Backend;
+use crate::features::Feature;
+use crate::logging::LogLevel;
+use crate::memdump;
+use crate::profile::Profiler;
+use crate::report::{self, OutputFormat};
+use crate::sandbox::{Preopen, Profile};
+use crate::watchpoint::WatchRange;
+use crate::{
+    abitest, bench, commands, config, coverage, diffrun, exitcode, expect, inspect, repl, rerun,
+    testsuite, wasttest, RunOutcome,
+};
+use clap::{CommandFactory, Parser, Subcommand};
+use clap_complete::Shell;
+use std::ffi::OsString;
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+#[derive(Parser)]
+#[command(
+    name = "wasmruntime",
+    version,
+    about = "Runs WebAssembly modules compiled by the Zong compiler",
+    args_override_self = true
+)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Run a module or bundle
+    Run(Box<Options>),
+    /// Run a module under a debugger that stops on entry to functions and
+    /// reads commands from stdin
+    Debug(Box<DebugOptions>),
+    /// Check that a module is valid and follows the Zong ABI: that the
+    /// runtime provides its imports, and that it exports memory, tstack, and
+    /// main with the right types
+    Check {
+        wasm_file: PathBuf,
+        /// Sandbox profile the module must be allowed to run under
+        #[arg(
+            long,
+            env = "ZONG_RUNTIME_SANDBOX",
+            default_value = "default",
+            value_parser = parse_profile,
+        )]
+        sandbox: Profile,
+    },
+    /// Compile a module ahead of time to a .cwasm file
+    Compile {
+        wasm_file: PathBuf,
+        /// Output file. Defaults to the module's path with a .cwasm extension.
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<PathBuf>,
+        /// Sandbox profile the compiled module will be run under
+        #[arg(
+            long,
+            env = "ZONG_RUNTIME_SANDBOX",
+            default_value = "default",
+            value_parser = parse_profile,
+        )]
+        sandbox: Profile,
+    },
+    /// Call an exported function and print its results
+    Invoke {
+        wasm_file: PathBuf,
+        export: String,
+        /// Arguments, parsed according to the function's parameter types
+        #[arg(allow_negative_numbers = true)]
+        args: Vec<String>,
+    },
+    /// Call exports interactively, one `EXPORT ARGS...` line at a time, or
+    /// evaluate Zong code
+    Repl {
+        wasm_file: PathBuf,
+        /// Zong compiler to build the code typed in with
+        #[arg(
+            long,
+            env = "ZONG_COMPILER",
+            default_value = "zong",
+            value_name = "EXECUTABLE"
+        )]
+        compiler: String,
+        /// What to do with the instance after a call traps
+        #[arg(long, value_enum, default_value = "keep")]
+        on_trap: repl::OnTrap,
+    },
+    /// Run each .wasm file in a directory with the .stdin file beside it
+    /// and compare its stdout with the .expected file beside it
+    Test {
+        dir: PathBuf,
+        /// Tests to run at once. Defaults to the number of cores.
+        #[arg(long, short = 'j', value_name = "N")]
+        jobs: Option<NonZeroUsize>,
+        /// How to print the results. json and junit also give each test's
+        /// host calls, output bytes, and fuel consumed.
+        #[arg(long, value_enum, default_value = "text")]
+        output: testsuite::ReportFormat,
+    },
+    /// Run two builds of a program with the same input and report where
+    /// their stdout, stderr, or exit codes first differ
+    DiffRun {
+        old: PathBuf,
+        new: PathBuf,
+        /// Give both programs this file as stdin. Defaults to the process's
+        /// stdin, read to its end.
+        #[arg(long, value_name = "FILE")]
+        stdin: Option<PathBuf>,
+        /// Seed for the random number generator. Defaults to 0.
+        #[arg(long, value_name = "N")]
+        seed: Option<u64>,
+        /// Arguments passed to both programs
+        #[arg(last = true, value_name = "ARGS")]
+        args: Vec<String>,
+    },
+    /// Run a program many times in one process and report how long its runs
+    /// took
+    Bench {
+        wasm_file: PathBuf,
+        /// Runs to time
+        #[arg(long, short = 'n', value_name = "N", default_value_t = 10)]
+        iterations: usize,
+        /// Runs before the timed ones, which are not counted
+        #[arg(long, value_name = "K", default_value_t = 3)]
+        warmup: usize,
+        /// Count the fuel each run uses, about one unit per instruction
+        #[arg(long)]
+        fuel: bool,
+        /// Give each run this file as stdin. Defaults to no input.
+        #[arg(long, value_name = "FILE")]
+        stdin: Option<PathBuf>,
+        /// Print the statistics as text or as one JSON object
+        #[arg(long, value_enum, default_value = "text")]
+        output: OutputFormat,
+        /// Arguments passed to the program
+        #[arg(last = true, value_name = "ARGS")]
+        args: Vec<String>,
+    },
+    /// Check that a runtime implements the Zong host ABI
+    AbiTest {
+        /// Runtime to check instead of this one. It is run with a module
+        /// path as its only argument.
+        #[arg(long, value_name = "EXECUTABLE")]
+        runtime: Option<String>,
+    },
+    /// Run wast scripts against the engine with the Zong host functions
+    Wast {
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+    },
+    /// Print a module's imports, exports, memories, globals, data segments,
+    /// custom sections, and function sizes. Given a --dump-memory file
+    /// instead, print the slice structure at an address in it, or the dump's
+    /// globals without an address.
+    Inspect {
+        /// Module, as a wasm binary or WAT text, or memory dump
+        file: PathBuf,
+        /// Address of the slice, in decimal or 0x-prefixed hex
+        #[arg(value_parser = parse_address)]
+        address: Option<usize>,
+    },
+    /// Merge --coverage files, or export one as lcov or HTML
+    Coverage {
+        #[command(subcommand)]
+        command: coverage::Command,
+    },
+    /// Print a shell completion script
+    Completions { shell: Shell },
+}
+
+#[derive(clap::Args)]
+pub struct DebugOptions {
+    /// Stop on entry to this function; can be given more than once. Without
+    /// any, the program stops on entry to its first function.
+    #[arg(long = "break", value_name = "FUNCTION")]
+    pub breakpoints: Vec<String>,
+    #[command(flatten)]
+    pub run: Options,
+}
+
+#[derive(clap::Args)]
+pub struct Options {
+    /// Module, as a wasm binary or WAT text, bundle, or precompiled .cwasm
+    /// file (with --allow-precompiled) to run, or `-` to read a module from
+    /// stdin, leaving the program no input. A component, rather than a core
+    /// module, is run with the host interface of wit/zong.wit. Optional when
+    /// replaying a repro manifest, which records the module path.
+    #[arg(
+        value_name = "WASM_FILE | BUNDLE.ZIP | FILE.CWASM | -",
+        required_unless_present = "repro"
+    )]
+    pub wasm_file: Option<String>,
+    /// Library modules to link with the main module, instantiated in order
+    /// before it. Each one's exports satisfy imports from its file name
+    /// without the extension, and modules share a memory imported as
+    /// env.memory.
+    #[arg(value_name = "LIBRARY.WASM", requires = "wasm_file")]
+    pub libraries: Vec<String>,
+    /// Export called as the program entry point. Defaults to `main`, or
+    /// `_start` for WASI-style modules without a `main`.
+    #[arg(long, value_name = "EXPORT")]
+    pub entry: Option<String>,
+    /// Call this export instead of the entry point, with arguments parsed
+    /// according to its parameter types, and print its results
+    #[arg(
+        long,
+        value_name = "EXPORT [ARGS]",
+        num_args = 1..,
+        allow_negative_numbers = true,
+        conflicts_with = "entry",
+    )]
+    pub invoke: Vec<String>,
+    /// Read settings from this file, for the flags not given on the command
+    /// line or by environment variable
+    #[arg(long, value_name = "FILE.TOML")]
+    pub config: Option<String>,
+    /// Read the program's stdin from this file
+    #[arg(long, value_name = "FILE", conflicts_with = "repro")]
+    pub stdin: Option<String>,
+    /// Write the program's stdout to this file
+    #[arg(long, value_name = "FILE")]
+    pub stdout: Option<String>,
+    /// Append to the --stdout file instead of replacing it
+    #[arg(long, requires = "stdout")]
+    pub append: bool,
+    /// Write a repro manifest capturing this run's inputs
+    #[arg(long, value_name = "FILE")]
+    pub emit_repro: Option<String>,
+    /// Re-run the configuration recorded in a repro manifest
+    #[arg(long, value_name = "FILE")]
+    pub repro: Option<String>,
+    /// Log what the program reads from stdin and every clock reading and
+    /// random value it gets, for --replay
+    #[arg(long, value_name = "FILE")]
+    pub record: Option<String>,
+    /// Re-run a program with the stdin, clock readings, and random values
+    /// logged by --record
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = ["record", "stdin", "repro", "emit_repro"],
+    )]
+    pub replay: Option<String>,
+    /// Freeze the realtime clock at this many nanoseconds since the Unix
+    /// epoch, or at the epoch itself, for deterministic runs
+    #[arg(
+        long,
+        value_name = "UNIX_NS",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "0",
+    )]
+    pub fixed_time: Option<u64>,
+    /// Seed the random number generator, so that random values are the same
+    /// in every run
+    #[arg(long, value_name = "N")]
+    pub seed: Option<u64>,
+    /// Make identical inputs give identical output: canonicalize NaNs, make
+    /// relaxed SIMD give the same results on every host, freeze the clocks,
+    /// with the realtime clock at --fixed-time or the epoch, seed the random
+    /// number generator with --seed or 0, and refuse to start threads
+    #[arg(long)]
+    pub deterministic: bool,
+    /// Load host functions from this shared library, which exports
+    /// zong_runtime_plugin. The plugin runs as native code outside the
+    /// sandbox.
+    #[arg(long = "plugin", value_name = "FILE.SO")]
+    pub plugins: Vec<String>,
+    /// Instantiate a module before the main module. Its exports satisfy the
+    /// main module's imports from NAME.
+    #[arg(
+        long = "link-instance",
+        value_name = "NAME=WASM_FILE",
+        value_parser = parse_link_instance,
+    )]
+    pub link_instances: Vec<(String, String)>,
+    /// Print the host capabilities the module's imports grant instead of
+    /// running it
+    #[arg(long)]
+    pub audit: bool,
+    /// Sandbox profile limiting the host capabilities the module may use
+    #[arg(
+        long,
+        env = "ZONG_RUNTIME_SANDBOX",
+        default_value = "default",
+        value_parser = parse_profile,
+    )]
+    pub sandbox: Profile,
+    /// Refuse modules that need this capability, as named by --audit, even
+    /// if the sandbox profile grants it
+    #[arg(long, value_name = "CAPABILITY")]
+    pub deny: Vec<String>,
+    /// Let the program open TCP connections and listen for them, make HTTP
+    /// requests, and use WASI sockets
+    #[arg(long)]
+    pub allow_net: bool,
+    /// Let the program run shell commands with exec. Commands run as native
+    /// processes outside the sandbox.
+    #[arg(long)]
+    pub allow_exec: bool,
+    /// Keep at most this much of each exec command's stdout, 1M by default
+    #[arg(
+        long,
+        value_name = "BYTES",
+        value_parser = parse_bytes,
+        requires = "allow_exec",
+    )]
+    pub exec_output_limit: Option<usize>,
+    /// Let the program read and write files in a host directory, seen at
+    /// GUEST_DIR or else at the same path
+    #[arg(
+        long = "dir",
+        value_name = "HOST_DIR[::GUEST_DIR]",
+        value_parser = |value: &str| Preopen::parse(value, true),
+    )]
+    pub dirs: Vec<Preopen>,
+    /// Like --dir, but read-only
+    #[arg(
+        long = "ro-dir",
+        value_name = "HOST_DIR[::GUEST_DIR]",
+        value_parser = |value: &str| Preopen::parse(value, false),
+    )]
+    pub ro_dirs: Vec<Preopen>,
+    /// Restrict the process's syscalls once setup is done. Commands cannot
+    /// be run, so it cannot be used with --allow-exec.
+    #[arg(long, env = "ZONG_RUNTIME_HARDEN", conflicts_with = "allow_exec")]
+    pub harden: bool,
+    /// Append a record of each sandbox-relevant host operation to this file
+    #[arg(long, env = "ZONG_RUNTIME_AUDIT_LOG", value_name = "FILE")]
+    pub audit_log: Option<String>,
+    /// Public key that every module must be signed with
+    #[arg(long, env = "ZONG_RUNTIME_VERIFY_SIGNATURE", value_name = "KEY.PUB")]
+    pub verify_signature: Option<String>,
+    /// Write per-function call counts and call edge counts to this file
+    #[arg(long, value_name = "FILE")]
+    pub pgo_out: Option<String>,
+    /// Write the call graph, with call counts, as Graphviz or JSON
+    #[arg(long, value_name = "FILE.DOT | FILE.JSON")]
+    pub callgraph: Option<String>,
+    /// Write how many times each function was called, by name, to this
+    /// JSON file, including functions that were never called
+    #[arg(long, value_name = "FILE.JSON")]
+    pub coverage: Option<String>,
+    /// Log every host function call, with its arguments and result, to
+    /// stderr
+    #[arg(long)]
+    pub trace: bool,
+    /// Log entering and leaving every guest function to stderr
+    #[arg(long)]
+    pub trace_calls: bool,
+    /// Print memory, tstack, host call, stdio, and time statistics to stderr
+    /// when the run ends
+    #[arg(long)]
+    pub stats: bool,
+    /// Describe the compiled code to an external profiler
+    #[arg(long, value_name = "PROFILER")]
+    pub profile: Option<Profiler>,
+    /// Sample the guest's call stack every millisecond and write the
+    /// samples to this file as collapsed stacks, for flamegraph tools
+    #[arg(long, value_name = "FILE")]
+    pub sample_profile: Option<String>,
+    /// Print the bytes and blocks each call site allocated with host_alloc
+    /// to stderr when the run ends, the largest first
+    #[arg(long)]
+    pub alloc_profile: bool,
+    /// Write the guest's memory and exported globals to this file when the
+    /// run ends, whether it finishes or traps, for `wasmruntime inspect`
+    #[arg(long, value_name = "FILE")]
+    pub dump_memory: Option<String>,
+    /// Print a view of guest memory to stderr when the run ends, given as
+    /// [LAYOUT@]ADDRESS[:LEN]: a hexdump, or with the slice layout the
+    /// slice structure at ADDRESS. Repeatable
+    #[arg(long = "memdump", value_name = "VIEW", value_parser = memdump::Spec::parse)]
+    pub memdumps: Vec<memdump::Spec>,
+    /// When the guest traps or a host function fails or panics, write an
+    /// archive to this directory with the module, the run's session log, a
+    /// memory dump, the backtrace, and the versions, for a bug report that
+    /// replays the crash
+    #[arg(long, value_name = "DIR")]
+    pub crash_report: Option<String>,
+    /// Call this export, which takes no arguments, before the entry point,
+    /// and write the guest's memory and exported globals to --snapshot-out
+    /// once it returns
+    #[arg(long, value_name = "EXPORT", requires = "snapshot_out")]
+    pub snapshot_at: Option<String>,
+    /// File to write the snapshot taken after --snapshot-at's export to
+    #[arg(long, value_name = "FILE", requires = "snapshot_at")]
+    pub snapshot_out: Option<String>,
+    /// Put back the memory and exported globals saved in a --snapshot-out
+    /// or --checkpoint-out file before calling the entry point, to skip the
+    /// program's setup or pick up a stopped job's progress
+    #[arg(long, value_name = "FILE", conflicts_with = "snapshot_at")]
+    pub restore: Option<String>,
+    /// Pause the guest this often to flush its output and update a progress
+    /// line on stderr with the time elapsed and, with --fuel, the fuel
+    /// consumed
+    #[arg(long, value_name = "MS", value_parser = parse_millis)]
+    pub checkpoint_every: Option<Duration>,
+    /// On SIGTERM, write the guest's memory and exported globals to this
+    /// file before exiting, for --restore to put back
+    #[arg(long, value_name = "FILE")]
+    pub checkpoint_out: Option<String>,
+    /// Write a report of memory accesses by address range to this file
+    #[arg(long, value_name = "FILE")]
+    pub heatmap: Option<String>,
+    /// Report each change to this range of guest memory, checked at every
+    /// host call, given in decimal or 0x-prefixed hex (LEN defaults to 8)
+    #[arg(long, value_name = "ADDR[:LEN]", value_parser = WatchRange::parse)]
+    pub watch_addr: Option<WatchRange>,
+    /// Check the --watch-addr range after every store as well
+    #[arg(long, requires = "watch_addr")]
+    pub watch_stores: bool,
+    /// Run the program again whenever the module, or the --source file,
+    /// changes
+    #[arg(long, conflicts_with = "expect")]
+    pub watch: bool,
+    /// Source file to watch along with the module
+    #[arg(long, value_name = "FILE", requires = "watch")]
+    pub source: Option<String>,
+    /// Drive the program with this interaction script instead of running it
+    /// directly
+    #[arg(long, value_name = "SCRIPT")]
+    pub expect: Option<String>,
+    /// Run the program on a pseudo-terminal when driven by --expect
+    #[arg(long, requires = "expect")]
+    pub pty: bool,
+    /// Keep compiled modules in this directory, so that running the same
+    /// module again skips compiling it
+    #[arg(long, env = "ZONG_RUNTIME_CACHE_DIR", value_name = "DIR")]
+    pub cache_dir: Option<String>,
+    /// Run precompiled .cwasm files. Their code runs natively without being
+    /// validated, so only use it for files as trusted as the runtime itself.
+    #[arg(long)]
+    pub allow_precompiled: bool,
+    /// Stop the program once it has used this much fuel, about one unit per
+    /// instruction, and report the fuel used if it finishes
+    #[arg(long, env = "ZONG_RUNTIME_FUEL", value_name = "UNITS")]
+    pub fuel: Option<u64>,
+    /// Stop the program if a linear memory grows past this size. Accepts K,
+    /// M, and G suffixes.
+    #[arg(
+        long,
+        env = "ZONG_RUNTIME_MAX_MEMORY",
+        value_name = "BYTES",
+        value_parser = parse_bytes,
+    )]
+    pub max_memory: Option<usize>,
+    /// Refuse to create more than this many tables
+    #[arg(long, env = "ZONG_RUNTIME_MAX_TABLES", value_name = "COUNT")]
+    pub max_tables: Option<usize>,
+    /// Size of the region of memory that host_alloc allocates from, 64M by
+    /// default
+    #[arg(long, value_name = "BYTES", value_parser = parse_bytes)]
+    pub heap_size: Option<usize>,
+    /// Stop the program if it runs for longer than this
+    #[arg(
+        long,
+        env = "ZONG_RUNTIME_TIMEOUT",
+        value_name = "SECONDS",
+        value_parser = parse_seconds,
+    )]
+    pub timeout: Option<Duration>,
+    /// Also reject slice structures passed to host functions that are not
+    /// 8-byte aligned
+    #[arg(long, env = "ZONG_RUNTIME_STRICT_ABI")]
+    pub strict_abi: bool,
+    /// Drop the program's log messages below this level
+    #[arg(
+        long,
+        env = "ZONG_RUNTIME_LOG_LEVEL",
+        value_name = "LEVEL",
+        default_value = "info"
+    )]
+    pub log_level: LogLevel,
+    /// Start each log message with the seconds since the run started
+    #[arg(long)]
+    pub log_timestamps: bool,
+    /// How to report the end of the run: errors as text, or a JSON summary
+    /// on stderr
+    #[arg(long, value_name = "FORMAT", default_value = "text")]
+    pub output: OutputFormat,
+    /// Directory of the program's Zong sources, for showing them in trap
+    /// reports
+    #[arg(long, env = "ZONG_RUNTIME_SOURCE_DIR", value_name = "DIR")]
+    pub source_dir: Option<String>,
+    /// Show wasmtime's description of a trap as well as the Zong one
+    #[arg(short, long)]
+    pub verbose: bool,
+    /// Let gdb or lldb step through the program's code with the DWARF debug
+    /// info in the module
+    ///
+    /// Compiles the module with Cranelift optimizations off and DWARF
+    /// translated for the compiled code, which is registered with native
+    /// debuggers through the GDB JIT interface. Run the runtime under the
+    /// debugger, for example `gdb --args wasmruntime --debug-info prog.wasm`;
+    /// lldb also needs `settings set plugin.jit-loader.gdb.enable on`. Set
+    /// breakpoints on Zong source lines or function names once the module
+    /// has been compiled. Instrumenting options such as --trace rewrite the
+    /// module, which leaves its debug info out of date.
+    #[arg(long)]
+    pub debug_info: bool,
+    /// Code generator: cranelift, the optimizing compiler; winch, the
+    /// baseline compiler, to compare against when Cranelift may be
+    /// miscompiling; or pulley, the interpreter
+    #[arg(
+        long,
+        env = "ZONG_RUNTIME_BACKEND",
+        value_name = "BACKEND",
+        default_value = "cranelift"
+    )]
+    pub backend: Backend,
+    /// Let modules use these wasm proposals, such as tail-call or memory64,
+    /// given as a comma-separated list or one at a time
+    #[arg(long, value_name = "FEATURES", value_delimiter = ',')]
+    pub enable_feature: Vec<Feature>,
+    /// Reject modules that use these wasm proposals
+    #[arg(long, value_name = "FEATURES", value_delimiter = ',')]
+    pub disable_feature: Vec<Feature>,
+    /// Arguments for the program, which it reads with args_count and
+    /// args_get
+    #[arg(last = true, value_name = "ARGS")]
+    pub program_args: Vec<String>,
+    /// Functions for the debugger to stop at, set by `wasmruntime debug`
+    #[arg(skip)]
+    pub breakpoints: Option<Vec<String>>,
+}
+
+fn parse_link_instance(value: &str) -> Result<(String, String), String> {
+    let (name, file) = value
+        .split_once('=')
+        .ok_or_else(|| String::from("expected NAME=WASM_FILE"))?;
+    Ok((name.to_string(), file.to_string()))
+}
+
+fn parse_profile(value: &str) -> Result<Profile, String> {
+    Profile::parse(value).ok_or_else(|| String::from("expected strict, default, or permissive"))
+}
+
+fn parse_seconds(value: &str) -> Result<Duration, String> {
+    value
+        .parse()
+        .ok()
+        .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+        .ok_or_else(|| String::from("expected a number of seconds"))
+}
+
+fn parse_millis(value: &str) -> Result<Duration, String> {
+    value
+        .parse()
+        .ok()
+        .filter(|&millis| millis > 0)
+        .map(Duration::from_millis)
+        .ok_or_else(|| String::from("expected a positive number of milliseconds"))
+}
+
+fn parse_bytes(value: &str) -> Result<usize, String> {
+    let (digits, unit) = match value.char_indices().last() {
+        Some((i, 'K' | 'k')) => (&value[..i], 1 << 10),
+        Some((i, 'M' | 'm')) => (&value[..i], 1 << 20),
+        Some((i, 'G' | 'g')) => (&value[..i], 1 << 30),
+        _ => (value, 1),
+    };
+    digits
+        .parse::<usize>()
+        .ok()
+        .and_then(|count| count.checked_mul(unit))
+        .ok_or_else(|| String::from("expected a number of bytes, such as 65536 or 64M"))
+}
+
+fn parse_address(value: &str) -> Result<usize, String> {
+    let parsed = match value.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => value.parse(),
+    };
+    parsed.map_err(|_| String::from("expected an address, such as 4096 or 0x1000"))
+}
+
+// Parses the command line, exiting with a usage message on errors. Arguments
+// that do not start with a subcommand are for `run`. The flags from a config
+// file go before those given, for settings they do not make.
+pub fn parse(args: &[String]) -> Cli {
+    let mut args: Vec<OsString> = args.iter().map(OsString::from).collect();
+    let is_subcommand = |arg: &OsString| {
+        let arg = arg.to_string_lossy();
+        matches!(arg.as_ref(), "-h" | "--help" | "-V" | "--version" | "help")
+            || Cli::command().find_subcommand(arg.as_ref()).is_some()
+    };
+    if !args.get(1).is_some_and(is_subcommand) {
+        args.insert(1.min(args.len()), OsString::from("run"));
+    }
+    let takes_config = matches!(
+        args.get(1).and_then(|arg| arg.to_str()),
+        Some("run" | "debug")
+    );
+    // The settings the arguments make decide which the file can. Arguments
+    // that do not parse are reported by parse_from.
+    let command = Cli::command();
+    let given = takes_config
+        .then(|| command.clone().try_get_matches_from(&args).ok())
+        .flatten();
+    if let Some((name, matches)) = given.as_ref().and_then(|given| given.subcommand()) {
+        match config::flags(command.find_subcommand(name).unwrap(), matches) {
+            Ok(flags) => drop(args.splice(2..2, flags)),
+            Err(error) => {
+                eprintln!("error: {}", error);
+                std::process::exit(exitcode::USAGE);
+            }
+        }
+    }
+    Cli::parse_from(args)
+}
+
+pub fn print_completions(shell: Shell) {
+    clap_complete::generate(
+        shell,
+        &mut Cli::command(),
+        "wasmruntime",
+        &mut std::io::stdout(),
+    );
+}
+
+// Runs the command line and returns how it ended, which main maps to the
+// process exit code.
+pub fn main(args: &[String]) -> RunOutcome {
+    let result = match parse(args).command {
+        Command::Run(options) if options.watch => rerun::run(
+            args,
+            options.wasm_file.as_deref(),
+            options.source.as_deref(),
+        )
+        .map(RunOutcome::Exited),
+        Command::Run(options) => match &options.expect {
+            Some(script) => expect::run(Path::new(script), &expect::child_args(args), options.pty)
+                .map(RunOutcome::Exited),
+            None => match crate::run(&options) {
+                Err(error) if options.output == OutputFormat::Json => {
+                    Ok(report::print_error(&error))
+                }
+                result => result,
+            },
+        },
+        Command::Debug(debug) => {
+            let DebugOptions {
+                breakpoints,
+                mut run,
+            } = *debug;
+            run.breakpoints = Some(breakpoints);
+            crate::run(&run)
+        }
+        Command::Check { wasm_file, sandbox } => {
+            commands::check(&wasm_file, sandbox).map(RunOutcome::Exited)
+        }
+        Command::Compile {
+            wasm_file,
+            output,
+            sandbox,
+        } => commands::compile(&wasm_file, output.as_deref(), sandbox).map(RunOutcome::Exited),
+        Command::Invoke {
+            wasm_file,
+            export,
+            args,
+        } => commands::invoke(&wasm_file, &export, &args).map(RunOutcome::Exited),
+        Command::Repl {
+            wasm_file,
+            compiler,
+            on_trap,
+        } => repl::run(&wasm_file, &compiler, on_trap).map(RunOutcome::Exited),
+        Command::Test { dir, jobs, output } => {
+            testsuite::run(&dir, jobs.map(NonZeroUsize::get), output).map(RunOutcome::Exited)
+        }
+        Command::DiffRun {
+            old,
+            new,
+            stdin,
+            seed,
+            args,
+        } => diffrun::run(&old, &new, stdin.as_deref(), seed, &args).map(RunOutcome::Exited),
+        Command::Bench {
+            wasm_file,
+            iterations,
+            warmup,
+            fuel,
+            stdin,
+            output,
+            args,
+        } => bench::run(
+            &wasm_file,
+            iterations,
+            warmup,
+            fuel,
+            stdin.as_deref(),
+            output,
+            &args,
+        )
+        .map(RunOutcome::Exited),
+        Command::AbiTest { runtime } => {
+            abitest::run(runtime.as_deref().map(Path::new)).map(RunOutcome::Exited)
+        }
+        Command::Wast { files } => wasttest::run(&files).map(RunOutcome::Exited),
+        Command::Inspect { file, address } => inspect::run(&file, address).map(RunOutcome::Exited),
+        Command::Coverage { command } => coverage::run(command).map(RunOutcome::Exited),
+        Command::Completions { shell } => {
+            print_completions(shell);
+            Ok(RunOutcome::Exited(0))
+        }
+    };
+    match result {
+        Ok(outcome) => outcome,
+        Err(error) => {
+            eprintln!("Error: {:?}", error);
+            RunOutcome::for_error(&error)
+        }
+    }
+}
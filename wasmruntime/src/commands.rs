@@ -0,0 +1,144 @@
+// Subcommands that work on a single module without running it as a
+// program: `check` validates it and checks it against the Zong ABI, `compile`
+// precompiles it to a .cwasm file, and `invoke` calls one of its exports with
+// arguments from the command line.
+// `run --invoke` calls an export the same way, but set up as a program run.
+
+use crate::hostio::HostIo;
+use crate::sandbox::Profile;
+use crate::{
+    abi, define_shared_memory, engine_config, host_linker, read_module, HostState, Runtime,
+};
+use std::fs;
+use std::path::Path;
+use wasmtime::{Engine, Error, FuncType, Instance, Module, Result, Store, Val, ValType};
+
+// Checks that the module is valid, that the sandbox profile allows its
+// imports, and that it follows the Zong ABI, reporting every violation.
+pub fn check(wasm_file: &Path, profile: Profile) -> Result<i32> {
+    let wasm_bytes = read_module(wasm_file)?;
+    let engine = Engine::new(&engine_config(profile.fuel().is_some(), false))?;
+    let module = Module::new(&engine, &wasm_bytes)?;
+    profile.check_imports(&module)?;
+    let state = HostState::for_module(
+        &module,
+        &wasm_bytes,
+        None,
+        &[],
+        &[],
+        HostIo::default(),
+        profile,
+    )?;
+    let mut linker = host_linker(&engine, &state)?;
+    let mut store = Store::new(&engine, state);
+    define_shared_memory(&mut linker, &mut store, [&module])?;
+    let name = wasm_file.display().to_string();
+    let violations = abi::violations(&linker, &mut store, &module);
+    if !violations.is_empty() {
+        return Err(Error::msg(format!(
+            "{} does not follow the Zong ABI:\n  {}",
+            name,
+            violations.join("\n  ")
+        )));
+    }
+    println!(
+        "{}: ok (Zong ABI version {})",
+        name,
+        store.data().abi.number()
+    );
+    Ok(0)
+}
+
+// Writes the module compiled for this host to output, by default the module's
+// path with a .cwasm extension. The code is compiled for the sandbox
+// profile's engine configuration, so it must be run with the same profile.
+pub fn compile(wasm_file: &Path, output: Option<&Path>, profile: Profile) -> Result<i32> {
+    let wasm_bytes = read_module(wasm_file)?;
+    // The compiled code does not keep the zong.abi section, so it can only
+    // be run as version 1
+    if abi::version(&wasm_bytes)? != abi::Version::V1 {
+        return Err(Error::msg(
+            "only modules for Zong ABI version 1 can be precompiled",
+        ));
+    }
+    let engine = Engine::new(&engine_config(profile.fuel().is_some(), false))?;
+    let compiled = engine.precompile_module(&wasm_bytes)?;
+    let output = match output {
+        Some(output) => output.to_path_buf(),
+        None => wasm_file.with_extension("cwasm"),
+    };
+    fs::write(&output, compiled)
+        .map_err(|error| Error::new(error).context(output.display().to_string()))?;
+    Ok(0)
+}
+
+// Calls an export with arguments parsed according to its parameter types and
+// prints each result on its own line.
+pub fn invoke(wasm_file: &Path, export: &str, args: &[String]) -> Result<i32> {
+    let mut runtime = Runtime::from_file(wasm_file)?;
+    let ty = runtime
+        .module()
+        .get_export(export)
+        .and_then(|ty| ty.func().cloned())
+        .ok_or_else(|| Error::msg(format!("no function export '{}'", export)))?;
+    let args = parse_args(&ty, export, args)?;
+    print_results(&runtime.invoke(export, &args)?);
+    Ok(0)
+}
+
+// Calls an export of a program's instance like invoke does.
+pub fn invoke_instance(
+    store: &mut Store<HostState>,
+    instance: &Instance,
+    export: &str,
+    args: &[String],
+) -> Result<()> {
+    let func = instance
+        .get_func(&mut *store, export)
+        .ok_or_else(|| Error::msg(format!("no function export '{}'", export)))?;
+    let ty = func.ty(&*store);
+    let args = parse_args(&ty, export, args)?;
+    let mut results = vec![Val::I32(0); ty.results().len()];
+    func.call(&mut *store, &args, &mut results)?;
+    store.data_mut().flush_stdout()?;
+    print_results(&results);
+    Ok(())
+}
+
+pub fn parse_args(ty: &FuncType, export: &str, args: &[String]) -> Result<Vec<Val>> {
+    if ty.params().len() != args.len() {
+        return Err(Error::msg(format!(
+            "'{}' takes {} arguments, got {}",
+            export,
+            ty.params().len(),
+            args.len()
+        )));
+    }
+    ty.params()
+        .zip(args)
+        .map(|(ty, arg)| parse_val(&ty, arg))
+        .collect()
+}
+
+pub fn print_results(results: &[Val]) {
+    for result in results {
+        match result {
+            Val::I32(value) => println!("{}", value),
+            Val::I64(value) => println!("{}", value),
+            Val::F32(bits) => println!("{}", f32::from_bits(*bits)),
+            Val::F64(bits) => println!("{}", f64::from_bits(*bits)),
+            other => println!("{:?}", other),
+        }
+    }
+}
+
+fn parse_val(ty: &ValType, arg: &str) -> Result<Val> {
+    let val = match ty {
+        ValType::I32 => arg.parse().ok().map(Val::I32),
+        ValType::I64 => arg.parse().ok().map(Val::I64),
+        ValType::F32 => arg.parse().ok().map(|value: f32| Val::F32(value.to_bits())),
+        ValType::F64 => arg.parse().ok().map(|value: f64| Val::F64(value.to_bits())),
+        _ => return Err(Error::msg(format!("cannot pass a {} argument", ty))),
+    };
+    val.ok_or_else(|| Error::msg(format!("'{}' is not a valid {}", arg, ty)))
+}
@@ -0,0 +1,207 @@
+// Running wasm components, for when the Zong toolchain emits them instead of
+// core modules. A component imports the zong:runtime/host interface of
+// wit/zong.wit and exports main, which returns the exit code. The canonical
+// ABI passes its strings and lists, so a component needs no memory, tstack,
+// or slice structures, unlike a core module.
+//
+// Only the host interface is provided: not WASI, nor the rest of the env
+// functions. --stdin, --stdout, --fuel, --max-memory, and --timeout apply to
+// components as they do to modules, and SIGINT and SIGTERM stop them; the
+// options that instrument a module, read its memory, or link it with others
+// cannot be used.
+
+use crate::cli::Options;
+use crate::hostio::HostIo;
+use crate::limits::Limits;
+use crate::panic::GuestPanic;
+use crate::signals::{self, PendingSignal, SignalInterrupt};
+use crate::timeout::Deadline;
+use crate::{exitcode, strip_newline, RunOutcome};
+use std::path::Path;
+use wasmparser::Parser;
+use wasmtime::component::{Component, Linker};
+use wasmtime::{Engine, Result, Store, UpdateDeadline};
+
+wasmtime::component::bindgen!({
+    path: "wit/zong.wit",
+    world: "zong",
+    trappable_imports: true,
+});
+
+use zong::runtime::host;
+
+// Whether the binary is a component rather than a core module.
+pub fn is_component(wasm_bytes: &[u8]) -> bool {
+    Parser::is_component(wasm_bytes)
+}
+
+struct ComponentState {
+    io: HostIo,
+    args: Vec<String>,
+    limits: Limits,
+}
+
+impl host::Host for ComponentState {
+    fn print(&mut self, n: i64) -> Result<()> {
+        self.io.write_stdout(format!("{}\n", n).as_bytes())?;
+        Ok(())
+    }
+
+    fn print_bytes(&mut self, bytes: Vec<u8>) -> Result<()> {
+        self.io.write_stdout(&bytes)?;
+        Ok(())
+    }
+
+    fn eprint_bytes(&mut self, bytes: Vec<u8>) -> Result<()> {
+        self.io.write_stderr(&bytes)?;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.io.flush_stdout()?;
+        Ok(())
+    }
+
+    fn read_line(&mut self) -> Result<Option<String>> {
+        let mut bytes = Vec::new();
+        let result = self
+            .io
+            .read_stdin(|stdin| stdin.read_until(b'\n', &mut bytes));
+        match result {
+            Ok(0) | Err(_) => Ok(None),
+            Ok(_) => Ok(Some(
+                String::from_utf8_lossy(strip_newline(&bytes)).into_owned(),
+            )),
+        }
+    }
+
+    fn args(&mut self) -> Result<Vec<String>> {
+        Ok(self.args.clone())
+    }
+
+    fn panic(&mut self, message: String) -> Result<()> {
+        Err(GuestPanic(message).into())
+    }
+}
+
+// The first option given that components do not support.
+fn unsupported_option(options: &Options) -> Option<&'static str> {
+    [
+        (options.entry.is_some(), "--entry"),
+        (!options.invoke.is_empty(), "--invoke"),
+        (!options.libraries.is_empty(), "--library"),
+        (!options.link_instances.is_empty(), "--link"),
+        (!options.plugins.is_empty(), "--plugin"),
+        (options.record.is_some(), "--record"),
+        (options.replay.is_some(), "--replay"),
+        (options.audit, "--audit"),
+        (options.audit_log.is_some(), "--audit-log"),
+        (!options.dirs.is_empty(), "--dir"),
+        (!options.ro_dirs.is_empty(), "--ro-dir"),
+        (options.pgo_out.is_some(), "--pgo-out"),
+        (options.callgraph.is_some(), "--callgraph"),
+        (options.coverage.is_some(), "--coverage"),
+        (options.trace, "--trace"),
+        (options.trace_calls, "--trace-calls"),
+        (options.stats, "--stats"),
+        (options.heatmap.is_some(), "--heatmap"),
+        (options.watch_addr.is_some(), "--watch-addr"),
+        (options.breakpoints.is_some(), "the debugger"),
+        (options.sample_profile.is_some(), "--sample-profile"),
+        (options.alloc_profile, "--alloc-profile"),
+        (options.dump_memory.is_some(), "--dump-memory"),
+        (!options.memdumps.is_empty(), "--memdump"),
+        (options.crash_report.is_some(), "--crash-report"),
+        (options.snapshot_at.is_some(), "--snapshot-at"),
+        (options.restore.is_some(), "--restore"),
+        (options.checkpoint_every.is_some(), "--checkpoint-every"),
+        (options.checkpoint_out.is_some(), "--checkpoint-out"),
+        (options.heap_size.is_some(), "--heap-size"),
+    ]
+    .into_iter()
+    .find(|(given, _)| *given)
+    .map(|(_, option)| option)
+}
+
+// Runs the component's main with the program arguments, reading stdin from
+// the given bytes if any.
+pub fn run(
+    engine: &Engine,
+    wasm_bytes: &[u8],
+    args: Vec<String>,
+    stdin: Option<Vec<u8>>,
+    fuel: Option<u64>,
+    options: &Options,
+) -> Result<RunOutcome> {
+    if let Some(option) = unsupported_option(options) {
+        return Err(exitcode::usage(format!(
+            "{} cannot be used with a component",
+            option
+        )));
+    }
+    let component = Component::new(engine, wasm_bytes)?;
+    let mut linker = Linker::new(engine);
+    Zong::add_to_linker(&mut linker, |state: &mut ComponentState| state)?;
+
+    let mut io = HostIo::default();
+    if let Some(stdin) = stdin {
+        io.set_stdin(stdin);
+    }
+    if let Some(stdout) = &options.stdout {
+        io.stdout_to_file(Path::new(stdout), options.append)?;
+    }
+    let pending_signal = signals::install(engine).ok();
+    let deadline = options
+        .timeout
+        .map(|timeout| Deadline::start(engine, timeout));
+    // Reading stdin makes no epoch checks, so it checks for the signal or
+    // the timeout itself
+    io.interrupt_stdin({
+        let pending_signal = pending_signal.clone();
+        let deadline = deadline.clone();
+        move || {
+            pending_signal
+                .as_ref()
+                .is_some_and(PendingSignal::is_pending)
+                || deadline
+                    .as_ref()
+                    .is_some_and(|deadline| deadline.check().is_some())
+        }
+    });
+    let mut store = Store::new(
+        engine,
+        ComponentState {
+            io,
+            args,
+            limits: Limits::new(
+                options.max_memory.or(options.sandbox.memory_limit()),
+                options.max_tables,
+            ),
+        },
+    );
+    store.limiter(|state| &mut state.limits);
+    if let Some(fuel) = fuel {
+        store.set_fuel(fuel)?;
+    }
+    store.set_epoch_deadline(1);
+    store.epoch_deadline_callback(move |_| {
+        if let Some(signal) = pending_signal.as_ref().and_then(PendingSignal::take) {
+            return Err(SignalInterrupt(signal).into());
+        }
+        if let Some(timed_out) = deadline.as_ref().and_then(Deadline::check) {
+            return Err(timed_out.into());
+        }
+        Ok(UpdateDeadline::Continue(1))
+    });
+
+    let result = Zong::instantiate(&mut store, &component, &linker)
+        .and_then(|zong| zong.call_main(&mut store));
+    store.data_mut().io.flush_stdout()?;
+    match result {
+        Ok(exit_code) => Ok(RunOutcome::Exited(exit_code)),
+        Err(error) => match error.downcast_ref::<SignalInterrupt>() {
+            Some(SignalInterrupt(signal)) => Ok(RunOutcome::Signaled(*signal)),
+            None => Err(error),
+        },
+    }
+}
@@ -0,0 +1,196 @@
+// Config files for `run` and `debug`, so that classroom and CI setups need
+// not pass the same flags every time. The file is the one given with
+// --config; none is read otherwise, since settings such as allow-exec grant
+// capabilities, and a file in the working directory could come from anyone:
+//
+//   [limits]
+//   fuel = 100000000
+//   max-memory = "64M"
+//   max-tables = 4
+//   timeout = 10          # seconds
+//
+//   [host]
+//   sandbox = "strict"
+//   deny = ["random"]     # capabilities, as named by --audit
+//   allow-net = false
+//   allow-exec = false
+//
+//   [[dirs]]
+//   host = "data"         # relative to the config file
+//   guest = "/data"
+//   read-only = true
+//
+//   [stdio]
+//   stdin = "input.txt"
+//   stdout = "output.txt"
+//   append = false
+//
+// Each setting becomes the flag of the same name, unless the command line or
+// the flag's ZONG_RUNTIME_ environment variable already sets it, so that the
+// environment overrides the file and the command line overrides both. The
+// directories and denied capabilities from all of them add up.
+
+use clap::parser::ValueSource;
+use clap::{ArgMatches, Command};
+use serde::Deserialize;
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+use wasmtime::{Error, Result};
+
+// The flags that can be given more than once, which the file adds to.
+const REPEATED_FLAGS: &[&str] = &["deny", "dir", "ro-dir"];
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+struct Config {
+    limits: Limits,
+    host: Host,
+    dirs: Vec<Dir>,
+    stdio: Stdio,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+struct Limits {
+    fuel: Option<u64>,
+    max_memory: Option<Bytes>,
+    max_tables: Option<usize>,
+    heap_size: Option<Bytes>,
+    timeout: Option<f64>,
+}
+
+// A size as a number of bytes or as a string with a K, M, or G suffix.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Bytes {
+    Count(u64),
+    Text(String),
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+struct Host {
+    sandbox: Option<String>,
+    deny: Vec<String>,
+    allow_net: bool,
+    allow_exec: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct Dir {
+    host: PathBuf,
+    guest: Option<String>,
+    #[serde(default)]
+    read_only: bool,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+struct Stdio {
+    stdin: Option<PathBuf>,
+    stdout: Option<PathBuf>,
+    append: bool,
+}
+
+impl Bytes {
+    fn to_arg(&self) -> String {
+        match self {
+            Bytes::Count(count) => count.to_string(),
+            Bytes::Text(text) => text.clone(),
+        }
+    }
+}
+
+// Returns the flags for the config file named with --config, if any, given
+// command, the subcommand, and given, its matches for the arguments without
+// the file.
+pub fn flags(command: &Command, given: &ArgMatches) -> Result<Vec<OsString>> {
+    let Some(path) = given.get_one::<String>("config").map(PathBuf::from) else {
+        return Ok(Vec::new());
+    };
+    let context = |message: String| Error::msg(format!("{}: {}", path.display(), message));
+    let text = fs::read_to_string(&path).map_err(|error| context(error.to_string()))?;
+    let config: Config = toml::from_str(&text).map_err(|error| context(error.to_string()))?;
+    let base = path.parent().unwrap_or(Path::new(""));
+    let mut flags = Vec::new();
+    for (name, value) in config.settings(base) {
+        if !REPEATED_FLAGS.contains(&name) && is_given(command, given, name) {
+            continue;
+        }
+        flags.push(OsString::from(format!("--{}", name)));
+        flags.extend(value);
+    }
+    Ok(flags)
+}
+
+// Whether the flag with the given long name was on the command line or set
+// by its environment variable.
+fn is_given(command: &Command, given: &ArgMatches, name: &str) -> bool {
+    let Some(arg) = command
+        .get_arguments()
+        .find(|arg| arg.get_long() == Some(name))
+    else {
+        return false;
+    };
+    matches!(
+        given.value_source(arg.get_id().as_str()),
+        Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+    )
+}
+
+impl Config {
+    // The file's settings as flag names and their values, if they take one.
+    // Paths are relative to base, the config file's directory.
+    fn settings(&self, base: &Path) -> Vec<(&'static str, Option<OsString>)> {
+        let mut flags = Vec::new();
+        let mut flag = |name: &'static str, value: OsString| flags.push((name, Some(value)));
+        let limits = &self.limits;
+        if let Some(fuel) = limits.fuel {
+            flag("fuel", fuel.to_string().into());
+        }
+        if let Some(max_memory) = &limits.max_memory {
+            flag("max-memory", max_memory.to_arg().into());
+        }
+        if let Some(max_tables) = limits.max_tables {
+            flag("max-tables", max_tables.to_string().into());
+        }
+        if let Some(heap_size) = &limits.heap_size {
+            flag("heap-size", heap_size.to_arg().into());
+        }
+        if let Some(timeout) = limits.timeout {
+            flag("timeout", timeout.to_string().into());
+        }
+        if let Some(sandbox) = &self.host.sandbox {
+            flag("sandbox", sandbox.into());
+        }
+        for capability in &self.host.deny {
+            flag("deny", capability.into());
+        }
+        for dir in &self.dirs {
+            let mut value = base.join(&dir.host).into_os_string();
+            if let Some(guest) = &dir.guest {
+                value.push("::");
+                value.push(guest);
+            }
+            flag(if dir.read_only { "ro-dir" } else { "dir" }, value);
+        }
+        if let Some(stdin) = &self.stdio.stdin {
+            flag("stdin", base.join(stdin).into_os_string());
+        }
+        if let Some(stdout) = &self.stdio.stdout {
+            flag("stdout", base.join(stdout).into_os_string());
+        }
+        if self.stdio.append {
+            flags.push(("append", None));
+        }
+        if self.host.allow_net {
+            flags.push(("allow-net", None));
+        }
+        if self.host.allow_exec {
+            flags.push(("allow-exec", None));
+        }
+        flags
+    }
+}
@@ -0,0 +1,86 @@
+// Constant strings kept in the host, so that a program printing the same
+// literal in a hot loop need not build its slice on the tstack each time:
+//
+//   const_str_register(slice_ptr) -> i32 copies the slice into the table
+//     and returns its id. Registering the same bytes again returns the same
+//     id, so a program can register its literals up front or as it first
+//     meets them.
+//   const_str_print(id) writes the string to stdout, as print_bytes does.
+//
+// Ids count up from 0. The table lasts for the life of the instance, up to
+// MAX_BYTES of strings in all; an unknown id is an error.
+
+use crate::abi::Version;
+use crate::{guest, slice_abi, HostState};
+use std::collections::HashMap;
+use std::sync::Arc;
+use wasmtime::{Caller, Error, Linker, Result, ValType};
+
+const MAX_BYTES: usize = 64 << 20;
+
+#[derive(Default)]
+pub struct ConstStrings {
+    strings: Vec<Arc<[u8]>>,
+    ids: HashMap<Arc<[u8]>, i32>,
+    bytes: usize,
+}
+
+impl ConstStrings {
+    fn register(&mut self, bytes: &[u8]) -> Result<i32> {
+        if let Some(&id) = self.ids.get(bytes) {
+            return Ok(id);
+        }
+        if self.bytes + bytes.len() > MAX_BYTES {
+            return Err(Error::msg(format!(
+                "const_str_register: constant strings would exceed {} bytes",
+                MAX_BYTES
+            )));
+        }
+        let id = i32::try_from(self.strings.len())
+            .map_err(|_| Error::msg("const_str_register: too many constant strings"))?;
+        let string: Arc<[u8]> = Arc::from(bytes);
+        self.bytes += string.len();
+        self.strings.push(string.clone());
+        self.ids.insert(string, id);
+        Ok(id)
+    }
+
+    fn get(&self, id: i32) -> Result<Arc<[u8]>> {
+        usize::try_from(id)
+            .ok()
+            .and_then(|index| self.strings.get(index))
+            .cloned()
+            .ok_or_else(|| Error::msg(format!("const_str_print: invalid string id {}", id)))
+    }
+}
+
+pub fn add_to_linker(
+    linker: &mut Linker<HostState>,
+    abi: Version,
+    pointer: &ValType,
+) -> Result<()> {
+    slice_abi::define(
+        linker,
+        abi,
+        "const_str_register",
+        std::slice::from_ref(pointer),
+        |mut caller, params| -> Result<i32> {
+            let slice_ptr = guest::pointer(&params[0]);
+            let memory = guest::memory(&mut caller, "const_str_register")?;
+            let layout = caller.data().slice_layout();
+            let (data, state) = memory.data_and_store_mut(&mut caller);
+            let data = data.as_data();
+            let bytes = slice_abi::read_slice(data, "const_str_register", slice_ptr, layout)?;
+            state.const_strings.register(&bytes)
+        },
+    )?;
+    linker.func_wrap(
+        "env",
+        "const_str_print",
+        |mut caller: Caller<'_, HostState>, id: i32| -> Result<()> {
+            let string = caller.data().const_strings.get(id)?;
+            caller.data_mut().write_stdout(&string)
+        },
+    )?;
+    Ok(())
+}
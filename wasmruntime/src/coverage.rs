@@ -0,0 +1,160 @@
+// `wasmruntime coverage`: combines the JSON files --coverage writes, one per
+// run, and exports them for coverage tools. Each file maps a function's name
+// to the number of times it was called, so merging sums the counts, and a
+// function is covered if any run called it.
+//
+// The exports are function-level, since the coverage files have no lines:
+//
+//   lcov: one record, with FN and FNDA lines for each function, which
+//         genhtml and most CI coverage services read
+//   html: a standalone page listing each function, uncalled ones first
+
+use crate::exitcode;
+use crate::testsuite::escape_xml;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+use wasmtime::Result;
+
+// Function name to calls, sorted by name so the output is stable.
+type Coverage = BTreeMap<String, u64>;
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+pub enum ExportFormat {
+    Lcov,
+    Html,
+}
+
+#[derive(clap::Subcommand)]
+pub enum Command {
+    /// Sum the call counts in --coverage files, from runs of the same
+    /// module, into one file
+    Merge {
+        #[arg(required = true, value_name = "FILE.JSON")]
+        files: Vec<PathBuf>,
+        /// Where to write the merged file
+        #[arg(short, long, value_name = "FILE.JSON")]
+        output: PathBuf,
+    },
+    /// Convert a --coverage file to lcov or an HTML page
+    Export {
+        #[arg(value_name = "FILE.JSON")]
+        file: PathBuf,
+        #[arg(long, value_enum)]
+        format: ExportFormat,
+        /// Source file to attribute the functions to in lcov's SF line.
+        /// Defaults to the coverage file's name.
+        #[arg(long, value_name = "PATH")]
+        source: Option<String>,
+        /// Where to write the export. Defaults to stdout.
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<PathBuf>,
+    },
+}
+
+pub fn run(command: Command) -> Result<i32> {
+    match command {
+        Command::Merge { files, output } => {
+            let mut total = Coverage::new();
+            for file in &files {
+                for (name, calls) in read(file)? {
+                    *total.entry(name).or_default() += calls;
+                }
+            }
+            fs::write(&output, serde_json::to_string_pretty(&total)? + "\n")?;
+        }
+        Command::Export {
+            file,
+            format,
+            source,
+            output,
+        } => {
+            let coverage = read(&file)?;
+            let text = match format {
+                ExportFormat::Lcov => {
+                    let source = source.unwrap_or_else(|| file.display().to_string());
+                    lcov(&coverage, &source)
+                }
+                ExportFormat::Html => html(&coverage, &file.display().to_string()),
+            };
+            match output {
+                Some(path) => fs::write(path, text)?,
+                None => print!("{}", text),
+            }
+        }
+    }
+    Ok(0)
+}
+
+fn read(path: &Path) -> Result<Coverage> {
+    let text =
+        fs::read_to_string(path).map_err(|error| exitcode::unreadable(path.display(), error))?;
+    serde_json::from_str(&text).map_err(|error| {
+        exitcode::usage(format!(
+            "{} is not a --coverage file, which maps function names to call counts: {}",
+            path.display(),
+            error
+        ))
+    })
+}
+
+fn lcov(coverage: &Coverage, source: &str) -> String {
+    let mut text = format!("TN:\nSF:{}\n", source);
+    // lcov wants the line each function starts on, which is unknown
+    for name in coverage.keys() {
+        writeln!(text, "FN:0,{}", name).unwrap();
+    }
+    for (name, calls) in coverage {
+        writeln!(text, "FNDA:{},{}", calls, name).unwrap();
+    }
+    let hit = coverage.values().filter(|&&calls| calls > 0).count();
+    writeln!(text, "FNF:{}\nFNH:{}\nend_of_record", coverage.len(), hit).unwrap();
+    text
+}
+
+fn html(coverage: &Coverage, title: &str) -> String {
+    let hit = coverage.values().filter(|&&calls| calls > 0).count();
+    let percent = if coverage.is_empty() {
+        100.0
+    } else {
+        hit as f64 * 100.0 / coverage.len() as f64
+    };
+    let mut functions: Vec<(&String, &u64)> = coverage.iter().collect();
+    functions.sort_by_key(|&(name, calls)| (*calls > 0, name));
+
+    let title = escape_xml(title);
+    let mut page = String::from("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n");
+    writeln!(page, "<title>Coverage of {}</title>", title).unwrap();
+    page.push_str(concat!(
+        "<style>\n",
+        "body { font-family: sans-serif; }\n",
+        "td { padding: 0 1em; font-family: monospace; }\n",
+        ".hit { background: #dfd; }\n",
+        ".miss { background: #fdd; }\n",
+        "</style>\n</head>\n<body>\n",
+    ));
+    writeln!(page, "<h1>Coverage of {}</h1>", title).unwrap();
+    writeln!(
+        page,
+        "<p>{} of {} functions called ({:.1}%)</p>",
+        hit,
+        coverage.len(),
+        percent
+    )
+    .unwrap();
+    page.push_str("<table>\n<tr><th>Function</th><th>Calls</th></tr>\n");
+    for (name, calls) in functions {
+        let class = if *calls > 0 { "hit" } else { "miss" };
+        writeln!(
+            page,
+            "<tr class=\"{}\"><td>{}</td><td>{}</td></tr>",
+            class,
+            escape_xml(name),
+            calls
+        )
+        .unwrap();
+    }
+    page.push_str("</table>\n</body>\n</html>\n");
+    page
+}
@@ -0,0 +1,177 @@
+// Crash reports for --crash-report DIR. When the guest traps or runs out of
+// a resource, a host function fails, or the runtime itself panics, a zip
+// archive is written to DIR with everything a compiler bug report needs for
+// the maintainers to replay the crash:
+//
+//   prog.wasm       the module as given, before any instrumentation
+//   session.jsonl   the --record log of what the program read from stdin,
+//                   the clocks, and the random number generator
+//   memory.dump     the guest's memory and exported globals when it stopped,
+//                   for `wasmruntime inspect`
+//   error.txt       the error and the wasm backtrace
+//   crash.json      the runtime and wasmtime versions, the command line,
+//                   the --output json report of how the run ended, and the
+//                   arguments that replay it from the unpacked archive
+//
+// The run is recorded to a session log in DIR as it goes, unless --record
+// or --replay already names one, and the log is removed once the run ends.
+// Exits, signals, timeouts, and the program's own panics are not crashes.
+
+use crate::exitcode::RunOutcome;
+use crate::report::{Report, WASMTIME_VERSION};
+use crate::{dump, HostState};
+use serde::Serialize;
+use std::any::Any;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use wasmtime::{Error, Instance, Result, Store};
+use zip::write::SimpleFileOptions;
+use zip::ZipWriter;
+
+pub struct CrashReporter {
+    dir: PathBuf,
+    wasm_bytes: Vec<u8>,
+    replay_args: Vec<String>,
+    session_log: PathBuf,
+    // Whether the session log is the reporter's own, to remove at the end.
+    owns_session_log: bool,
+}
+
+#[derive(Serialize)]
+struct Summary<'a> {
+    runtime_version: &'static str,
+    wasmtime_version: &'static str,
+    command_line: Vec<String>,
+    report: &'a Report,
+    replay_args: Vec<String>,
+}
+
+// Whether the error that stopped the guest is a crash.
+pub fn is_crash(error: &Error) -> bool {
+    matches!(
+        RunOutcome::for_error(error),
+        RunOutcome::Trapped | RunOutcome::ResourceLimit | RunOutcome::HostError
+    )
+}
+
+// The message a panic was raised with.
+pub fn panic_message(payload: &(dyn Any + Send)) -> String {
+    match payload.downcast_ref::<&str>() {
+        Some(message) => message.to_string(),
+        None => payload
+            .downcast_ref::<String>()
+            .cloned()
+            .unwrap_or_else(|| String::from("non-string panic payload")),
+    }
+}
+
+impl CrashReporter {
+    // Creates DIR, for reports of runs of the module with the given --entry
+    // and program arguments. The session log is session_log if given, and
+    // otherwise one in DIR for the run to record to.
+    pub fn new(
+        dir: &Path,
+        session_log: Option<&Path>,
+        wasm_bytes: &[u8],
+        entry: Option<&str>,
+        args: &[String],
+    ) -> Result<CrashReporter> {
+        fs::create_dir_all(dir)
+            .map_err(|error| Error::new(error).context(dir.display().to_string()))?;
+        let (session_log, owns_session_log) = match session_log {
+            Some(session_log) => (session_log.to_path_buf(), false),
+            None => (
+                dir.join(format!("session-{}.jsonl", std::process::id())),
+                true,
+            ),
+        };
+        let mut replay_args: Vec<String> = ["run", "--replay", "session.jsonl"]
+            .into_iter()
+            .map(String::from)
+            .collect();
+        if let Some(entry) = entry {
+            replay_args.extend([String::from("--entry"), entry.to_string()]);
+        }
+        replay_args.extend([String::from("prog.wasm"), String::from("--")]);
+        replay_args.extend(args.iter().cloned());
+        Ok(CrashReporter {
+            dir: dir.to_path_buf(),
+            wasm_bytes: wasm_bytes.to_vec(),
+            replay_args,
+            session_log,
+            owns_session_log,
+        })
+    }
+
+    // The log to record the run to, if the reporter is to record it.
+    pub fn session_log_to_record(&self) -> Option<&Path> {
+        self.owns_session_log.then_some(self.session_log.as_path())
+    }
+
+    // Writes the report of a crash, printing where it went. Failing to write
+    // it is only a warning, so that the crash itself is still reported.
+    pub fn write(
+        &self,
+        store: &mut Store<HostState>,
+        instance: &Instance,
+        error: &Error,
+        report: &Report,
+    ) {
+        match self.write_archive(store, instance, error, report) {
+            Ok(path) => eprintln!("crash report written to {}", path.display()),
+            Err(error) => eprintln!("warning: cannot write crash report: {:#}", error),
+        }
+    }
+
+    fn write_archive(
+        &self,
+        store: &mut Store<HostState>,
+        instance: &Instance,
+        error: &Error,
+        report: &Report,
+    ) -> Result<PathBuf> {
+        let seconds = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+        let path = self
+            .dir
+            .join(format!("crash-{}-{}.zip", seconds, std::process::id()));
+        let summary = Summary {
+            runtime_version: env!("CARGO_PKG_VERSION"),
+            wasmtime_version: WASMTIME_VERSION,
+            command_line: std::env::args().collect(),
+            report,
+            replay_args: self.replay_args.clone(),
+        };
+        let session_log = fs::read(&self.session_log).unwrap_or_default();
+        let memory_dump = dump::to_bytes(store, instance)?;
+        let error_text = format!("{:?}\n", error);
+        let summary = serde_json::to_string_pretty(&summary)? + "\n";
+        let files: [(&str, &[u8]); 5] = [
+            ("prog.wasm", &self.wasm_bytes),
+            ("session.jsonl", &session_log),
+            ("memory.dump", &memory_dump),
+            ("error.txt", error_text.as_bytes()),
+            ("crash.json", summary.as_bytes()),
+        ];
+        let file = File::create(&path)
+            .map_err(|error| Error::new(error).context(path.display().to_string()))?;
+        let mut archive = ZipWriter::new(file);
+        for (name, contents) in files {
+            archive.start_file(name, SimpleFileOptions::default())?;
+            archive.write_all(contents)?;
+        }
+        archive.finish()?;
+        Ok(path)
+    }
+}
+
+impl Drop for CrashReporter {
+    fn drop(&mut self) {
+        if self.owns_session_log {
+            let _ = fs::remove_file(&self.session_log);
+        }
+    }
+}
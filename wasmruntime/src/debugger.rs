@@ -0,0 +1,346 @@
+// The interactive debugger behind `wasmruntime debug`. Before compiling, each
+// function's body starts with a call to zong_debug.enter with its index. When
+// the function is a breakpoint, or the debugger is stepping, the host stops
+// the guest there and reads commands from stdin until told to go on; the
+// program's own stdin comes from --stdin, or is empty. Functions are named as
+// in --trace-calls: by the name section, or else by their export name.
+
+use crate::heap::Block;
+use crate::{memdump, HostState};
+use std::collections::BTreeSet;
+use std::io::{self, BufRead, Write};
+use walrus::ir::{Call, Const, Instr, Value};
+use walrus::{ExportItem, FunctionKind, ValType};
+use wasmtime::{Caller, Error, Extern, Result, Val, WasmBacktrace};
+
+pub const IMPORT_MODULE: &str = "zong_debug";
+
+const HELP: &str = "\
+commands:
+  c, continue         run to the next breakpoint
+  s, step             run to the next function entry
+  b, break FUNCTION   break on entry to FUNCTION
+  d, delete FUNCTION  remove the breakpoint on FUNCTION
+  i, info             list breakpoints
+  bt, backtrace       show the guest's call stack
+  g, globals          show the exported globals
+  x ADDRESS LEN       show LEN bytes of memory at ADDRESS
+  slice ADDRESS       decode the slice structure at ADDRESS
+  m, memdump VIEW     show memory as [LAYOUT@]ADDRESS[:LEN], e.g. slice@0x1000
+  snap                snapshot the host_alloc blocks not freed yet
+  diff [A B]          show the blocks in snapshot B that are not in snapshot A,
+                      by default the last two
+  q, quit             stop the program
+  h, help             show this list";
+
+pub struct Debugger {
+    // Function names by index, from the name section or else the exports.
+    names: Vec<Option<String>>,
+    // Names of exported globals other than instrumentation counters.
+    globals: Vec<String>,
+    breakpoints: BTreeSet<String>,
+    stepping: bool,
+    // Set once stdin ends, after which the program runs to completion.
+    detached: bool,
+    // Taken with snap, numbered from 1: the blocks, and where they were
+    // allocated if there is an --alloc-profile.
+    snapshots: Vec<Vec<(Block, Option<String>)>>,
+}
+
+// The guest was stopped with the quit command.
+#[derive(Debug)]
+pub struct DebuggerQuit;
+
+impl std::fmt::Display for DebuggerQuit {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "stopped by the debugger")
+    }
+}
+
+impl std::error::Error for DebuggerQuit {}
+
+// Returns the instrumented module and a debugger for it, breaking on entry
+// to the given functions, or on entry to the first function called if
+// there are none.
+pub fn instrument(wasm_bytes: &[u8], breakpoints: &[String]) -> Result<(Vec<u8>, Debugger)> {
+    let wasm_bytes = wat::parse_bytes(wasm_bytes)?;
+    let mut module = walrus::Module::from_buffer(&wasm_bytes)?;
+    // Exported functions without a name get their export name in the name
+    // section, which then also shows it in backtraces
+    let mut globals = Vec::new();
+    let mut export_names = Vec::new();
+    for export in module.exports.iter() {
+        match export.item {
+            ExportItem::Function(func) => export_names.push((func, export.name.clone())),
+            ExportItem::Global(_) if !export.name.starts_with("__zong_") => {
+                globals.push(export.name.clone());
+            }
+            _ => {}
+        }
+    }
+    for (func, name) in export_names {
+        module.funcs.get_mut(func).name.get_or_insert(name);
+    }
+    let names: Vec<Option<String>> = module.funcs.iter().map(|func| func.name.clone()).collect();
+    for breakpoint in breakpoints {
+        if !names.iter().any(|name| name.as_ref() == Some(breakpoint)) {
+            eprintln!("warning: no function named {}", breakpoint);
+        }
+    }
+
+    let hook_type = module.types.add(&[ValType::I32], &[]);
+    let (enter, _) = module.add_import_func(IMPORT_MODULE, "enter", hook_type);
+    let local_funcs: Vec<_> = module
+        .funcs
+        .iter()
+        .filter(|func| matches!(func.kind, FunctionKind::Local(_)))
+        .map(|func| func.id())
+        .collect();
+    for id in local_funcs {
+        let func = module.funcs.get_mut(id).kind.unwrap_local_mut();
+        let entry = func.entry_block();
+        let call = [
+            Instr::Const(Const {
+                value: Value::I32(id.index() as i32),
+            }),
+            Instr::Call(Call { func: enter }),
+        ];
+        func.block_mut(entry)
+            .instrs
+            .splice(0..0, call.map(|instr| (instr, Default::default())));
+    }
+
+    let debugger = Debugger {
+        names,
+        globals,
+        breakpoints: breakpoints.iter().cloned().collect(),
+        stepping: breakpoints.is_empty(),
+        detached: false,
+        snapshots: Vec::new(),
+    };
+    Ok((module.emit_wasm(), debugger))
+}
+
+impl Debugger {
+    fn name(&self, index: usize) -> String {
+        match self.names.get(index).cloned().flatten() {
+            Some(name) => name,
+            None => format!("<function {}>", index),
+        }
+    }
+
+    fn should_break(&self, index: usize) -> bool {
+        !self.detached
+            && (self.stepping
+                || self.names[index]
+                    .as_ref()
+                    .is_some_and(|name| self.breakpoints.contains(name)))
+    }
+}
+
+pub fn enter(mut caller: Caller<'_, HostState>, index: i32) -> Result<()> {
+    let index = index as usize;
+    let Some(debugger) = &caller.data().debugger else {
+        return Ok(());
+    };
+    if !debugger.should_break(index) {
+        return Ok(());
+    }
+    let name = debugger.name(index);
+    caller.data_mut().flush_stdout()?;
+    println!("break at {}", name);
+    loop {
+        print!("(zdb) ");
+        io::stdout().flush()?;
+        let mut line = String::new();
+        if io::stdin().lock().read_line(&mut line)? == 0 {
+            println!();
+            debugger_mut(&mut caller).detached = true;
+            return Ok(());
+        }
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let result = match words.as_slice() {
+            [] => Ok(()),
+            ["c" | "continue"] => {
+                debugger_mut(&mut caller).stepping = false;
+                return Ok(());
+            }
+            ["s" | "step"] => {
+                debugger_mut(&mut caller).stepping = true;
+                return Ok(());
+            }
+            ["q" | "quit"] => return Err(DebuggerQuit.into()),
+            ["b" | "break", function] => {
+                debugger_mut(&mut caller)
+                    .breakpoints
+                    .insert(function.to_string());
+                Ok(())
+            }
+            ["d" | "delete", function] => {
+                if !debugger_mut(&mut caller).breakpoints.remove(*function) {
+                    println!("no breakpoint on {}", function);
+                }
+                Ok(())
+            }
+            ["i" | "info"] => {
+                for breakpoint in &debugger_mut(&mut caller).breakpoints {
+                    println!("breakpoint on {}", breakpoint);
+                }
+                Ok(())
+            }
+            ["bt" | "backtrace"] => {
+                print_backtrace(&caller);
+                Ok(())
+            }
+            ["g" | "globals"] => print_globals(&mut caller),
+            ["x", address, len] => parse_address(address).and_then(|address| {
+                let len = parse_address(len)?;
+                memdump_view(&mut caller, &format!("{:#x}:{}", address, len))
+            }),
+            ["slice", address] => parse_address(address)
+                .and_then(|address| memdump_view(&mut caller, &format!("slice@{:#x}", address))),
+            ["m" | "memdump", view] => memdump_view(&mut caller, view),
+            ["snap"] => {
+                snapshot(&mut caller);
+                Ok(())
+            }
+            ["diff"] => {
+                let taken = debugger_mut(&mut caller).snapshots.len();
+                match taken {
+                    2.. => print_diff(&caller, taken - 1, taken),
+                    _ => Err(Error::msg("diff needs two snapshots; take them with snap")),
+                }
+            }
+            ["diff", a, b] => {
+                parse_address(a).and_then(|a| print_diff(&caller, a, parse_address(b)?))
+            }
+            ["h" | "help"] => {
+                println!("{}", HELP);
+                Ok(())
+            }
+            _ => Err(Error::msg(format!(
+                "unknown command {:?}; try help",
+                line.trim()
+            ))),
+        };
+        if let Err(error) = result {
+            println!("error: {}", error);
+        }
+    }
+}
+
+fn debugger_mut<'a>(caller: &'a mut Caller<'_, HostState>) -> &'a mut Debugger {
+    caller.data_mut().debugger.as_mut().unwrap()
+}
+
+fn parse_address(value: &str) -> Result<usize> {
+    let parsed = match value.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => value.parse(),
+    };
+    parsed.map_err(|_| Error::msg(format!("invalid number {}", value)))
+}
+
+// The backtrace's innermost frame is the function that hit the breakpoint.
+fn print_backtrace(caller: &Caller<'_, HostState>) {
+    let backtrace = WasmBacktrace::force_capture(caller);
+    for (i, frame) in backtrace.frames().iter().enumerate() {
+        let name = match frame.func_name() {
+            Some(name) => name.to_string(),
+            None => format!("<function {}>", frame.func_index()),
+        };
+        println!("  {:>3}: {}", i, name);
+    }
+}
+
+// Prints the memdump view, marking where the tstack pointer is.
+fn memdump_view(caller: &mut Caller<'_, HostState>, view: &str) -> Result<()> {
+    let spec = memdump::Spec::parse(view).map_err(Error::msg)?;
+    let tstack = match caller.get_export("tstack") {
+        Some(Extern::Global(tstack)) => Some(crate::guest::pointer(&tstack.get(&mut *caller))),
+        _ => None,
+    };
+    let layout = caller.data().slice_layout();
+    let memory = crate::guest::memory(caller, "memdump")?;
+    memdump::print_view(&memory.data(&*caller).contents(), spec, layout, tstack)
+}
+
+fn snapshot(caller: &mut Caller<'_, HostState>) {
+    let state = caller.data();
+    let blocks = match &state.heap {
+        Some(heap) => heap.lock().unwrap().outstanding(),
+        None => Vec::new(),
+    };
+    let profile = state
+        .alloc_profile
+        .as_ref()
+        .map(|profile| profile.lock().unwrap());
+    let bytes: usize = blocks.iter().map(|block| block.len).sum();
+    let count = blocks.len();
+    let blocks = blocks
+        .into_iter()
+        .map(|block| {
+            let site = profile
+                .as_ref()
+                .and_then(|profile| profile.site(block.addr));
+            (block, site)
+        })
+        .collect();
+    drop(profile);
+    let debugger = debugger_mut(caller);
+    debugger.snapshots.push(blocks);
+    println!(
+        "snapshot {}: {} blocks, {} bytes",
+        debugger.snapshots.len(),
+        count,
+        bytes
+    );
+}
+
+// Prints the blocks in snapshot b that snapshot a does not have.
+fn print_diff(caller: &Caller<'_, HostState>, a: usize, b: usize) -> Result<()> {
+    let snapshots = &caller.data().debugger.as_ref().unwrap().snapshots;
+    let get = |n: usize| {
+        n.checked_sub(1)
+            .and_then(|index| snapshots.get(index))
+            .ok_or_else(|| Error::msg(format!("no snapshot {}", n)))
+    };
+    let (old, new) = (get(a)?, get(b)?);
+    let old: BTreeSet<u64> = old.iter().map(|(block, _)| block.serial).collect();
+    let added: Vec<_> = new
+        .iter()
+        .filter(|(block, _)| !old.contains(&block.serial))
+        .collect();
+    let bytes: usize = added.iter().map(|(block, _)| block.len).sum();
+    println!(
+        "{} blocks, {} bytes, in snapshot {} and not {}",
+        added.len(),
+        bytes,
+        b,
+        a
+    );
+    for (block, site) in added {
+        match site {
+            Some(site) => println!("  {:#x}: {} bytes from {}", block.addr, block.len, site),
+            None => println!("  {:#x}: {} bytes", block.addr, block.len),
+        }
+    }
+    Ok(())
+}
+
+fn print_globals(caller: &mut Caller<'_, HostState>) -> Result<()> {
+    let names = caller.data().debugger.as_ref().unwrap().globals.clone();
+    for name in names {
+        let Some(Extern::Global(global)) = caller.get_export(&name) else {
+            continue;
+        };
+        match global.get(&mut *caller) {
+            Val::I32(value) => println!("{}: i32 = {} ({:#x})", name, value, value as u32),
+            Val::I64(value) => println!("{}: i64 = {} ({:#x})", name, value, value as u64),
+            Val::F32(bits) => println!("{}: f32 = {}", name, f32::from_bits(bits)),
+            Val::F64(bits) => println!("{}: f64 = {}", name, f64::from_bits(bits)),
+            _ => {}
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,40 @@
+// Descriptions of errors that stopped the guest, in the terms of the Zong
+// program rather than of WebAssembly: an out-of-bounds memory access is most
+// likely an indexing bug, and stack exhaustion runaway recursion. wasmtime's
+// own description of the trap is shown as well with --verbose.
+
+use wasmtime::{Error, Trap};
+
+// Describes the error, with the raw trap after the Zong description if
+// verbose.
+pub fn describe(error: &Error, verbose: bool) -> String {
+    let raw = error.root_cause().to_string();
+    match error
+        .downcast_ref::<Trap>()
+        .copied()
+        .and_then(describe_trap)
+    {
+        Some(description) if verbose => format!("{} ({})", description, raw),
+        Some(description) => description.to_string(),
+        None => raw,
+    }
+}
+
+fn describe_trap(trap: Trap) -> Option<&'static str> {
+    match trap {
+        Trap::MemoryOutOfBounds | Trap::TableOutOfBounds | Trap::ArrayOutOfBounds => {
+            Some("index out of bounds")
+        }
+        Trap::HeapMisaligned => Some("misaligned memory access"),
+        Trap::IntegerDivisionByZero => Some("integer division by zero"),
+        Trap::IntegerOverflow => Some("integer overflow in division"),
+        Trap::BadConversionToInteger => Some("float out of range for conversion to integer"),
+        Trap::StackOverflow => Some("stack overflow"),
+        Trap::UnreachableCodeReached => Some("reached code marked as unreachable"),
+        Trap::IndirectCallToNull => Some("call through a null function pointer"),
+        Trap::BadSignature => Some("call through a function pointer of the wrong type"),
+        Trap::NullReference => Some("null reference"),
+        Trap::OutOfFuel => Some("ran out of fuel"),
+        _ => None,
+    }
+}
@@ -0,0 +1,154 @@
+// `wasmruntime diff-run`: runs two builds of a program with the same input
+// and reports the first place their behavior diverges, for checking that a
+// Zong compiler change does not change what programs do. Both get the same
+// stdin, the one from --stdin or else the process's read to its end, and the
+// same program arguments, and run as with --deterministic: the clocks are
+// frozen, random values come from --seed or 0, and NaNs are canonical. Their
+// stdout, stderr, and exit codes are compared, in that order.
+
+use crate::hostio::HostIo;
+use crate::sandbox::Profile;
+use crate::testsuite::{first_different_line, show_line};
+use crate::{
+    call_entry, check_resolved, diagnostics, engine_config, exitcode, host_linker,
+    make_deterministic, read_module, HostState,
+};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+use wasmtime::{Engine, Error, Result, Store};
+use wasmtime_wasi::I32Exit;
+
+// How a program's run ended.
+struct Outcome {
+    exit_code: i32,
+    stdout: Vec<u8>,
+    stderr: Vec<u8>,
+    // The error that stopped the program, if any.
+    error: Option<String>,
+}
+
+// Runs both modules and returns the exit code: 0 if they behaved the same,
+// 1 if not.
+pub fn run(
+    old: &Path,
+    new: &Path,
+    stdin: Option<&Path>,
+    seed: Option<u64>,
+    args: &[String],
+) -> Result<i32> {
+    let stdin = match stdin {
+        Some(path) => {
+            fs::read(path).map_err(|error| exitcode::unreadable(path.display(), error))?
+        }
+        None => {
+            let mut bytes = Vec::new();
+            std::io::stdin().read_to_end(&mut bytes)?;
+            bytes
+        }
+    };
+    let mut config = engine_config(false, false);
+    make_deterministic(&mut config);
+    let engine = Engine::new(&config)?;
+    let seed = seed.unwrap_or(0);
+    let run = |path: &Path| {
+        run_module(&engine, path, &stdin, seed, args)
+            .map_err(|error| error.context(path.display().to_string()))
+    };
+    let old_outcome = run(old)?;
+    let new_outcome = run(new)?;
+
+    for (stream, old_output, new_output) in [
+        ("stdout", &old_outcome.stdout, &new_outcome.stdout),
+        ("stderr", &old_outcome.stderr, &new_outcome.stderr),
+    ] {
+        if let Some(difference) = first_different_line(old_output, new_output) {
+            println!("{} differs at line {}:", stream, difference.line);
+            println!("  old: {}", show_line(difference.a));
+            println!("  new: {}", show_line(difference.b));
+            return Ok(1);
+        }
+    }
+    if old_outcome.exit_code != new_outcome.exit_code {
+        println!("exit codes differ:");
+        for (name, outcome) in [("old", &old_outcome), ("new", &new_outcome)] {
+            match &outcome.error {
+                Some(error) => println!("  {}: {} ({})", name, outcome.exit_code, error),
+                None => println!("  {}: {}", name, outcome.exit_code),
+            }
+        }
+        return Ok(1);
+    }
+    println!(
+        "same: exit code {}, {} bytes of stdout, {} bytes of stderr",
+        old_outcome.exit_code,
+        old_outcome.stdout.len(),
+        old_outcome.stderr.len()
+    );
+    Ok(0)
+}
+
+// Runs a program the way `wasmruntime run --deterministic` would with default
+// options, but with its streams in memory.
+fn run_module(
+    engine: &Engine,
+    wasm_path: &Path,
+    stdin: &[u8],
+    seed: u64,
+    args: &[String],
+) -> Result<Outcome> {
+    let wasm_bytes = read_module(wasm_path)?;
+    let module = wasmtime::Module::new(engine, &wasm_bytes)?;
+    let profile = Profile::default();
+    profile.check_imports_denying(&module, &[String::from("threads")])?;
+    let argv: Vec<String> = std::iter::once(wasm_path.display().to_string())
+        .chain(args.iter().cloned())
+        .collect();
+    let mut io = HostIo::default();
+    io.set_stdin(stdin.to_vec());
+    let stdout = io.capture_stdout();
+    let stderr = io.capture_stderr();
+    let mut state = HostState::for_module(&module, &wasm_bytes, None, &[], &argv, io, profile)?;
+    state.fixed_time = Some(0);
+    state.frozen_clocks = true;
+    state.rng = Some(StdRng::seed_from_u64(seed));
+    state.seeded = true;
+    let linker = host_linker(engine, &state)?;
+    let mut store = Store::new(engine, state);
+    store.limiter(|state| &mut state.limits);
+    store.set_epoch_deadline(1);
+    check_resolved(&linker, &mut store, &module, &argv[0])?;
+
+    let result = linker
+        .instantiate(&mut store, &module)
+        .and_then(|instance| {
+            let entry = match instance.get_func(&mut store, "main") {
+                Some(main) => Some((main, "main")),
+                None => instance
+                    .get_func(&mut store, "_start")
+                    .map(|start| (start, "_start")),
+            };
+            let (entry_func, entry) =
+                entry.ok_or_else(|| Error::msg("entry export 'main' not found"))?;
+            call_entry(&mut store, entry_func, entry)
+        });
+    let (exit_code, error) = match result {
+        Ok(exit_code) => (exit_code.unwrap_or(0), None),
+        Err(error) => match error.downcast_ref::<I32Exit>() {
+            Some(exit) => (exit.0, None),
+            None => (
+                exitcode::for_error(&error),
+                Some(diagnostics::describe(&error, false)),
+            ),
+        },
+    };
+    store.data_mut().flush_stdout()?;
+    Ok(Outcome {
+        exit_code,
+        stdout: stdout.contents(),
+        stderr: stderr.contents(),
+        error,
+    })
+}
@@ -0,0 +1,265 @@
+// Memory dumps for --dump-memory and `wasmruntime inspect`. A dump holds the
+// guest's exported globals and its memory as they were when the run ended,
+// whether it finished or trapped. It starts with text lines:
+//
+//   zong-dump 1
+//   global tstack i32 4101
+//   memory 131072
+//
+// with one global line per exported global, followed by the memory's bytes.
+// Counters added by --stats and the other instrumenting options are left
+// out.
+//
+// Snapshots for --snapshot-out are dumps taken after --snapshot-at's export
+// returns, with a `module SHA256` line after the first naming the module they
+// were taken of. --restore writes their memory and mutable globals back into
+// a new instance of the same module. Anything not exported, such as the
+// host_alloc heap's bookkeeping, open files, and timers, is not saved.
+
+use crate::memdump;
+use crate::repro::sha256_hex;
+use crate::slice_abi::Layout;
+use crate::HostState;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+use wasmtime::{Error, Extern, Instance, Mutability, Result, Store, Val};
+
+const DUMP_VERSION: u32 = 1;
+
+// Bytes of a slice's contents that inspect shows.
+const SHOWN_BYTES: usize = 256;
+
+pub struct Dump {
+    // SHA-256 of the module, for snapshots.
+    module: Option<String>,
+    // (name, type, value)
+    globals: Vec<(String, &'static str, String)>,
+    memory: Vec<u8>,
+}
+
+pub fn write(store: &mut Store<HostState>, instance: &Instance, path: &Path) -> Result<()> {
+    write_dump(store, instance, None, path)
+}
+
+// The dump's bytes, for a crash report.
+pub fn to_bytes(store: &mut Store<HostState>, instance: &Instance) -> Result<Vec<u8>> {
+    dump_bytes(store, instance, None)
+}
+
+// Writes a snapshot of an instance of the module in wasm_bytes.
+pub fn write_snapshot(
+    store: &mut Store<HostState>,
+    instance: &Instance,
+    wasm_bytes: &[u8],
+    path: &Path,
+) -> Result<()> {
+    write_dump(store, instance, Some(wasm_bytes), path)
+}
+
+fn write_dump(
+    store: &mut Store<HostState>,
+    instance: &Instance,
+    wasm_bytes: Option<&[u8]>,
+    path: &Path,
+) -> Result<()> {
+    let dump = dump_bytes(store, instance, wasm_bytes)?;
+    fs::write(path, dump).map_err(|error| Error::new(error).context(path.display().to_string()))
+}
+
+fn dump_bytes(
+    store: &mut Store<HostState>,
+    instance: &Instance,
+    wasm_bytes: Option<&[u8]>,
+) -> Result<Vec<u8>> {
+    let mut header = format!("zong-dump {}\n", DUMP_VERSION);
+    if let Some(wasm_bytes) = wasm_bytes {
+        writeln!(header, "module {}", sha256_hex(wasm_bytes))?;
+    }
+    let exports: Vec<_> = instance
+        .exports(&mut *store)
+        .map(|export| (export.name().to_string(), export.into_extern()))
+        .collect();
+    let mut memory = None;
+    for (name, export) in exports {
+        match export {
+            Extern::Global(global) if !name.starts_with("__zong_") => {
+                let (ty, value) = match global.get(&mut *store) {
+                    Val::I32(value) => ("i32", value.to_string()),
+                    Val::I64(value) => ("i64", value.to_string()),
+                    Val::F32(bits) => ("f32", f32::from_bits(bits).to_string()),
+                    Val::F64(bits) => ("f64", f64::from_bits(bits).to_string()),
+                    _ => continue,
+                };
+                writeln!(header, "global {} {} {}", name, ty, value)?;
+            }
+            Extern::Memory(exported) if name == "memory" => memory = Some(exported),
+            _ => {}
+        }
+    }
+    let data = match memory {
+        Some(memory) => memory.data(&*store),
+        None => &[],
+    };
+    writeln!(header, "memory {}", data.len())?;
+    let mut dump = header.into_bytes();
+    dump.extend_from_slice(data);
+    Ok(dump)
+}
+
+impl Dump {
+    pub fn load(path: &Path) -> Result<Dump> {
+        let invalid = |message: &str| {
+            Error::msg(format!(
+                "{}: invalid memory dump: {}",
+                path.display(),
+                message
+            ))
+        };
+        let bytes = fs::read(path)
+            .map_err(|error| Error::new(error).context(path.display().to_string()))?;
+        let mut rest = bytes.as_slice();
+        let mut next_line = || -> Result<String> {
+            let end = rest
+                .iter()
+                .position(|&byte| byte == b'\n')
+                .ok_or_else(|| invalid("truncated header"))?;
+            let line = String::from_utf8_lossy(&rest[..end]).into_owned();
+            rest = &rest[end + 1..];
+            Ok(line)
+        };
+        if next_line()? != format!("zong-dump {}", DUMP_VERSION) {
+            return Err(invalid("not a version 1 dump"));
+        }
+        let mut module = None;
+        let mut globals = Vec::new();
+        let memory_len = loop {
+            let line = next_line()?;
+            let fields: Vec<&str> = line.split(' ').collect();
+            match fields.as_slice() {
+                ["module", hash] if module.is_none() && globals.is_empty() => {
+                    module = Some(hash.to_string());
+                }
+                ["global", name, ty, value] => {
+                    let ty = match *ty {
+                        "i32" => "i32",
+                        "i64" => "i64",
+                        "f32" => "f32",
+                        "f64" => "f64",
+                        _ => return Err(invalid(&format!("unknown global type {}", ty))),
+                    };
+                    globals.push((name.to_string(), ty, value.to_string()));
+                }
+                ["memory", len] => {
+                    break len
+                        .parse::<usize>()
+                        .map_err(|_| invalid("malformed memory size"))?
+                }
+                _ => return Err(invalid(&format!("unexpected line {:?}", line))),
+            }
+        };
+        if rest.len() != memory_len {
+            return Err(invalid(&format!(
+                "expected {} bytes of memory, got {}",
+                memory_len,
+                rest.len()
+            )));
+        }
+        Ok(Dump {
+            module,
+            globals,
+            memory: rest.to_vec(),
+        })
+    }
+
+    // Writes a snapshot's memory and mutable globals into an instance of the
+    // module in wasm_bytes. Memory grows to the snapshot's size.
+    pub fn restore(
+        &self,
+        store: &mut Store<HostState>,
+        instance: &Instance,
+        wasm_bytes: &[u8],
+    ) -> Result<()> {
+        match &self.module {
+            Some(hash) if *hash == sha256_hex(wasm_bytes) => {}
+            Some(_) => return Err(Error::msg("snapshot was taken of a different module")),
+            None => return Err(Error::msg("memory dump is not a snapshot")),
+        }
+        for (name, ty, value) in &self.globals {
+            let Some(global) = instance.get_global(&mut *store, name) else {
+                return Err(Error::msg(format!(
+                    "snapshot global {} is not exported",
+                    name
+                )));
+            };
+            if global.ty(&*store).mutability() == Mutability::Const {
+                continue;
+            }
+            let invalid = || Error::msg(format!("invalid value for global {}: {}", name, value));
+            let value = match *ty {
+                "i32" => Val::I32(value.parse().map_err(|_| invalid())?),
+                "i64" => Val::I64(value.parse().map_err(|_| invalid())?),
+                "f32" => Val::F32(value.parse::<f32>().map_err(|_| invalid())?.to_bits()),
+                _ => Val::F64(value.parse::<f64>().map_err(|_| invalid())?.to_bits()),
+            };
+            global.set(&mut *store, value)?;
+        }
+        let Some(memory) = instance.get_memory(&mut *store, "memory") else {
+            return Ok(());
+        };
+        let size = memory.data_size(&*store);
+        if size > self.memory.len() {
+            return Err(Error::msg(format!(
+                "memory of {} bytes is larger than the snapshot's {}",
+                size,
+                self.memory.len()
+            )));
+        }
+        let page_size = memory.page_size(&*store) as usize;
+        if !self.memory.len().is_multiple_of(page_size) {
+            return Err(Error::msg(format!(
+                "snapshot memory of {} bytes is not a whole number of {}-byte pages",
+                self.memory.len(),
+                page_size
+            )));
+        }
+        memory.grow(&mut *store, ((self.memory.len() - size) / page_size) as u64)?;
+        let data = memory.data_mut(&mut *store);
+        if data.len() != self.memory.len() {
+            return Err(Error::msg(format!(
+                "memory grew to {} bytes instead of the snapshot's {}",
+                data.len(),
+                self.memory.len()
+            )));
+        }
+        data.copy_from_slice(&self.memory);
+        Ok(())
+    }
+
+    // Prints the globals and the memory size.
+    pub fn print_summary(&self) {
+        for (name, ty, value) in &self.globals {
+            println!("global {}: {} = {}", name, ty, value);
+        }
+        println!(
+            "memory: {} bytes ({} pages)",
+            self.memory.len(),
+            self.memory.len() / (64 << 10)
+        );
+    }
+}
+
+// Runs `wasmruntime inspect`: prints the slice at address in a dump, or a
+// summary of the dump without an address.
+pub fn inspect(path: &Path, address: Option<usize>) -> Result<i32> {
+    let dump = Dump::load(path)?;
+    match address {
+        Some(address) => {
+            for line in memdump::slice(&dump.memory, address, SHOWN_BYTES, Layout::default())? {
+                println!("{}", line);
+            }
+        }
+        None => dump.print_summary(),
+    }
+    Ok(0)
+}
@@ -0,0 +1,195 @@
+// Modules loaded at runtime from bytes the guest provides. Each child module
+// gets its own store, so it cannot touch the parent's memory, and its linker
+// only offers the host functions the parent explicitly granted.
+//
+// Children are held to the parent's limits: their tables to the parent's
+// --max-tables, their memories, each and all together, to the parent's
+// --max-memory, and their code to what is left of the parent's fuel, which
+// what they use is taken from. Children are never unloaded, so a parent may
+// load at most MAX_CHILDREN. A signal or the timeout stops a child at its
+// next epoch check, as it would the parent.
+
+use crate::hostio::Output;
+use crate::limits::{Limits, MemoryLimitExceeded};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use wasmtime::*;
+
+// Grant flags for load_module.
+pub const GRANT_PRINT: i32 = 1;
+
+pub const MAX_CHILDREN: usize = 64;
+
+struct ChildState {
+    // The parent's stdout, so output from both stays in order.
+    stdout: Output,
+    limits: Limits,
+    // Bytes the memories of all the parent's children hold.
+    children_memory: Arc<AtomicUsize>,
+}
+
+// The parent's limits for one memory, applied to the child's memory and to
+// those of all the children together.
+impl ResourceLimiter for ChildState {
+    fn memory_growing(
+        &mut self,
+        current: usize,
+        desired: usize,
+        maximum: Option<usize>,
+    ) -> Result<bool> {
+        let limit = self.limits.max_allocation();
+        let total = self.children_memory.load(Ordering::SeqCst) - current + desired;
+        if total > limit {
+            return Err(MemoryLimitExceeded {
+                limit,
+                desired: total,
+            }
+            .into());
+        }
+        let allowed = self.limits.memory_growing(current, desired, maximum)?;
+        if allowed {
+            self.children_memory.store(total, Ordering::SeqCst);
+        }
+        Ok(allowed)
+    }
+
+    fn table_growing(
+        &mut self,
+        current: usize,
+        desired: usize,
+        maximum: Option<usize>,
+    ) -> Result<bool> {
+        self.limits.table_growing(current, desired, maximum)
+    }
+
+    fn tables(&self) -> usize {
+        self.limits.tables()
+    }
+}
+
+struct Child {
+    store: Store<ChildState>,
+    instance: Instance,
+}
+
+// What a child gets from its parent.
+pub struct Inherited {
+    pub stdout: Output,
+    pub limits: Limits,
+    // Stops the child, as the parent's epoch callback stops the parent, if a
+    // signal arrived or the timeout passed.
+    pub check_interrupted: Box<dyn Fn() -> Result<()> + Send + Sync>,
+}
+
+#[derive(Default)]
+pub struct Children {
+    children: Vec<Child>,
+    memory: Arc<AtomicUsize>,
+}
+
+impl Children {
+    // Validates, compiles, and instantiates a child module. Returns its
+    // handle. With fuel metering, fuel is the parent's remaining fuel, and is
+    // updated with what instantiating the child left of it.
+    pub fn load(
+        &mut self,
+        engine: &Engine,
+        wasm_bytes: &[u8],
+        grants: i32,
+        inherited: Inherited,
+        fuel: Option<&mut u64>,
+    ) -> Result<i32> {
+        if self.children.len() >= MAX_CHILDREN {
+            return Err(Error::msg(format!(
+                "a module may load at most {} modules",
+                MAX_CHILDREN
+            )));
+        }
+        let module = Module::from_binary(engine, wasm_bytes)?;
+        let state = ChildState {
+            stdout: inherited.stdout,
+            limits: inherited.limits,
+            children_memory: self.memory.clone(),
+        };
+        let mut store = Store::new(engine, state);
+        store.limiter(|state| state);
+        store.set_epoch_deadline(1);
+        let check_interrupted = inherited.check_interrupted;
+        store.epoch_deadline_callback(move |_| {
+            check_interrupted()?;
+            Ok(UpdateDeadline::Continue(1))
+        });
+        let mut linker = Linker::new(engine);
+        if grants & GRANT_PRINT != 0 {
+            add_print(&mut linker)?;
+        }
+        let instance = with_fuel(&mut store, fuel, |store| linker.instantiate(store, &module))?;
+        self.children.push(Child { store, instance });
+        Ok((self.children.len() - 1) as i32)
+    }
+
+    // Calls an (i64) -> i64 export of a child, with the parent's remaining
+    // fuel as for load. Returns None if there is no such child or export.
+    pub fn call(
+        &mut self,
+        handle: i32,
+        name: &str,
+        arg: i64,
+        fuel: Option<&mut u64>,
+    ) -> Option<Result<i64>> {
+        let child = self.children.get_mut(usize::try_from(handle).ok()?)?;
+        let func = child
+            .instance
+            .get_typed_func::<i64, i64>(&mut child.store, name)
+            .ok()?;
+        Some(with_fuel(&mut child.store, fuel, |store| {
+            func.call(store, arg)
+        }))
+    }
+}
+
+// Runs f on the child's store with the given fuel, then updates it with what
+// is left.
+fn with_fuel<R>(
+    store: &mut Store<ChildState>,
+    fuel: Option<&mut u64>,
+    f: impl FnOnce(&mut Store<ChildState>) -> Result<R>,
+) -> Result<R> {
+    let Some(fuel) = fuel else {
+        return f(store);
+    };
+    store.set_fuel(*fuel)?;
+    let result = f(store);
+    *fuel = store.get_fuel()?;
+    result
+}
+
+fn add_print(linker: &mut Linker<ChildState>) -> Result<()> {
+    linker.func_wrap(
+        "env",
+        "print",
+        |caller: Caller<'_, ChildState>, n: i64| -> Result<()> {
+            caller
+                .data()
+                .stdout
+                .write_all(format!("{}\n", n).as_bytes())?;
+            Ok(())
+        },
+    )?;
+    linker.func_wrap(
+        "env",
+        "print_bytes",
+        |mut caller: Caller<'_, ChildState>, slice_ptr: i32| -> Result<()> {
+            let memory = crate::guest::memory(&mut caller, "print_bytes")?;
+            let bytes = crate::slice_abi::read_slice(
+                memory.data(&caller),
+                "print_bytes",
+                crate::guest::address(slice_ptr) as u64,
+                crate::slice_abi::Layout::default(),
+            )?;
+            caller.data().stdout.write_all(&bytes)?;
+            Ok(())
+        },
+    )?;
+    Ok(())
+}
@@ -0,0 +1,210 @@
+// Error codes for the fallible host functions: chdir, the file_, tcp_, and
+// shared_ functions, write_file, http_get, exec, parse_i64, random_bytes,
+// watch_path, on_signal, load_module, and spawn. On failure they return one
+// of these negative codes instead of a count, an fd, a handle, or a status,
+// and record a description of the failure such as
+// "file_open: data.txt: No such file or directory (os error 2)":
+//
+//   -1   OTHER               any other failure
+//   -2   NOT_FOUND           no such file or directory
+//   -3   PERMISSION_DENIED   outside the sandbox, in a read-only directory,
+//                            or denied by the host
+//   -4   ALREADY_EXISTS      the file already exists
+//   -5   NOT_A_DIRECTORY     a path component, or chdir's path, is not a
+//                            directory
+//   -6   IS_A_DIRECTORY      the path is a directory
+//   -7   INVALID_ARGUMENT    an unknown file mode, a port, size, or range
+//                            out of bounds, or a signal, module, or
+//                            function the host cannot use
+//   -8   BAD_FD              the fd or handle is not open, or not a file,
+//                            socket, or listener as the function needs
+//   -9   CONNECTION_REFUSED  nothing is listening at the address
+//   -10  CONNECTION_RESET    the peer reset or closed the connection
+//   -11  TIMED_OUT           the host or the peer did not respond in time
+//   -12  ADDRESS_IN_USE      the port is already being listened on
+//   -13  NOT_A_NUMBER        parse_i64's text is not a decimal number
+//   -14  OUT_OF_RANGE        parse_i64's number does not fit in an i64
+//
+// last_error_message(dest_slice_ptr) writes the description of the most
+// recent failure as a slice on the tstack, or an empty slice if no host
+// function has failed yet. As with C's errno, a later success does not clear
+// it.
+
+use crate::abi::Version;
+use crate::{guest, slice_abi, HostState};
+use std::fmt::Display;
+use std::io;
+use wasmtime::{Linker, Result, ValType};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(i32)]
+pub enum Errno {
+    Other = -1,
+    NotFound = -2,
+    PermissionDenied = -3,
+    AlreadyExists = -4,
+    NotADirectory = -5,
+    IsADirectory = -6,
+    InvalidArgument = -7,
+    BadFd = -8,
+    ConnectionRefused = -9,
+    ConnectionReset = -10,
+    TimedOut = -11,
+    AddressInUse = -12,
+    NotANumber = -13,
+    OutOfRange = -14,
+}
+
+impl Errno {
+    pub fn for_io(error: &io::Error) -> Errno {
+        match error.kind() {
+            io::ErrorKind::NotFound => Errno::NotFound,
+            io::ErrorKind::PermissionDenied | io::ErrorKind::ReadOnlyFilesystem => {
+                Errno::PermissionDenied
+            }
+            io::ErrorKind::AlreadyExists => Errno::AlreadyExists,
+            io::ErrorKind::NotADirectory => Errno::NotADirectory,
+            io::ErrorKind::IsADirectory => Errno::IsADirectory,
+            io::ErrorKind::InvalidInput => Errno::InvalidArgument,
+            io::ErrorKind::ConnectionRefused => Errno::ConnectionRefused,
+            io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::BrokenPipe => Errno::ConnectionReset,
+            io::ErrorKind::TimedOut => Errno::TimedOut,
+            io::ErrorKind::AddrInUse => Errno::AddressInUse,
+            _ => Errno::Other,
+        }
+    }
+}
+
+// The most recent host error, shared by every host function.
+#[derive(Default)]
+pub struct LastError {
+    message: String,
+}
+
+impl LastError {
+    // Records a failure of the function, returning the code for it to
+    // return.
+    pub fn set(&mut self, function: &str, errno: Errno, message: impl Display) -> i32 {
+        self.message = format!("{}: {}", function, message);
+        errno as i32
+    }
+
+    // Records a failed I/O operation on the named file, socket, or address.
+    pub fn io(&mut self, function: &str, target: &str, error: &io::Error) -> i32 {
+        self.set(
+            function,
+            Errno::for_io(error),
+            format_args!("{}: {}", target, error),
+        )
+    }
+
+    // Records a call with an fd that is not open, or not of the right kind.
+    pub fn bad_fd(&mut self, function: &str, fd: i32) -> i32 {
+        self.set(function, Errno::BadFd, format_args!("bad fd {}", fd))
+    }
+
+    // Records a call with a shared segment handle that is not open.
+    pub fn bad_handle(&mut self, function: &str, handle: i32) -> i32 {
+        self.set(
+            function,
+            Errno::BadFd,
+            format_args!("bad handle {}", handle),
+        )
+    }
+}
+
+pub fn add_to_linker(
+    linker: &mut Linker<HostState>,
+    abi: Version,
+    pointer: &ValType,
+) -> Result<()> {
+    slice_abi::define(
+        linker,
+        abi,
+        "last_error_message",
+        std::slice::from_ref(pointer),
+        |mut caller, params| -> Result<()> {
+            let message = caller.data().last_error.message.clone();
+            let dest_addr = guest::pointer(&params[0]) as usize;
+            slice_abi::push_slice(
+                &mut caller,
+                "last_error_message",
+                dest_addr,
+                message.as_bytes(),
+            )
+        },
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Runtime;
+
+    #[test]
+    fn codes_are_the_documented_ones() {
+        assert_eq!(Errno::Other as i32, -1);
+        assert_eq!(Errno::PermissionDenied as i32, -3);
+        assert_eq!(Errno::InvalidArgument as i32, -7);
+        assert_eq!(Errno::BadFd as i32, -8);
+        assert_eq!(Errno::OutOfRange as i32, -14);
+    }
+
+    #[test]
+    fn io_errors_map_to_codes() {
+        let code = |kind: io::ErrorKind| Errno::for_io(&io::Error::from(kind));
+        assert_eq!(code(io::ErrorKind::NotFound), Errno::NotFound);
+        assert_eq!(
+            code(io::ErrorKind::ReadOnlyFilesystem),
+            Errno::PermissionDenied
+        );
+        assert_eq!(code(io::ErrorKind::BrokenPipe), Errno::ConnectionReset);
+        assert_eq!(code(io::ErrorKind::AddrInUse), Errno::AddressInUse);
+        assert_eq!(code(io::ErrorKind::UnexpectedEof), Errno::Other);
+    }
+
+    #[test]
+    fn last_error_names_the_function() {
+        let mut last_error = LastError::default();
+        assert_eq!(last_error.bad_fd("file_read", 9), -8);
+        assert_eq!(last_error.message, "file_read: bad fd 9");
+        let error = io::Error::from(io::ErrorKind::NotFound);
+        assert_eq!(last_error.io("file_open", "data.txt", &error), -2);
+        assert_eq!(
+            last_error.message,
+            format!("file_open: data.txt: {}", error)
+        );
+    }
+
+    #[test]
+    fn last_error_message_gives_the_most_recent_failure() {
+        let module = r#"(module
+          (import "env" "chdir" (func $chdir (param i32) (result i32)))
+          (import "env" "last_error_message" (func $last_error_message (param i32)))
+          (import "env" "print_bytes" (func $print_bytes (param i32)))
+          (memory (export "memory") 1)
+          (global (export "tstack") (mut i32) (i32.const 1024))
+          (data (i32.const 0) "\20\00\00\00\00\00\00\00\05\00\00\00\00\00\00\00")
+          (data (i32.const 32) "/nope")
+          (func (export "fail") (result i32) (call $chdir (i32.const 0)))
+          (func (export "message")
+            (call $last_error_message (i32.const 16))
+            (call $print_bytes (i32.const 16))))"#;
+        let mut io = crate::HostIo::default();
+        let stdout = io.capture_stdout();
+        let mut runtime =
+            Runtime::with_io(&crate::Host::new().unwrap(), module.as_bytes(), io).unwrap();
+        runtime.invoke("message", &[]).unwrap();
+        assert!(stdout.contents().is_empty());
+        let results = runtime.invoke("fail", &[]).unwrap();
+        assert_eq!(results[0].unwrap_i32(), Errno::PermissionDenied as i32);
+        runtime.invoke("message", &[]).unwrap();
+        assert_eq!(
+            String::from_utf8(stdout.contents()).unwrap(),
+            "chdir: /nope: outside the sandbox"
+        );
+    }
+}
@@ -0,0 +1,123 @@
+// The guest's select-style event loop: waits until stdin is readable, a timer
+// is due, or a watched path changes, whichever comes first. Also the waits
+// that let blocking reads of stdin and sockets be interrupted.
+
+use crate::timers::Timers;
+use crate::watch::{FileWatcher, WatchEvent};
+use std::io;
+use std::time::{Duration, Instant};
+
+// How often to check for file events while also waiting on stdin, when paths
+// are being watched.
+const WATCH_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+// How often an interruptible wait checks whether to give up.
+const INTERRUPT_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+#[cfg(unix)]
+pub type RawFd = std::os::unix::io::RawFd;
+#[cfg(not(unix))]
+pub type RawFd = i32;
+
+#[cfg(unix)]
+pub const STDIN_FD: RawFd = libc::STDIN_FILENO;
+#[cfg(not(unix))]
+pub const STDIN_FD: RawFd = 0;
+
+pub enum Event {
+    StdinReadable,
+    // A due timer, by id. Its callback is not called; the guest decides what
+    // to do.
+    Timer(i32),
+    File(WatchEvent),
+}
+
+// Waits for the next event. A timeout of None waits forever. Returns None if
+// the timeout passes first. If stdin_is_fixed, stdin comes from memory and is
+// always readable. Fails if interrupted returns true, as wait_readable does.
+pub fn wait_event(
+    timers: &mut Timers,
+    mut watcher: Option<&mut FileWatcher>,
+    stdin_is_fixed: bool,
+    timeout: Option<Duration>,
+    interrupted: &dyn Fn() -> bool,
+) -> io::Result<Option<Event>> {
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    loop {
+        let now = Instant::now();
+        if let Some((id, _callback)) = timers.pop_due(now) {
+            return Ok(Some(Event::Timer(id)));
+        }
+        if let Some(watcher) = watcher.as_deref_mut() {
+            if let Some(event) = watcher.next_event(Some(Duration::ZERO)) {
+                return Ok(Some(Event::File(event)));
+            }
+        }
+        if stdin_is_fixed {
+            return Ok(Some(Event::StdinReadable));
+        }
+        if deadline.is_some_and(|deadline| now >= deadline) {
+            return Ok(None);
+        }
+        if interrupted() {
+            return Err(io::Error::other("interrupted"));
+        }
+
+        // Sleep in stdin's readiness wait until the earliest of the deadline
+        // and the next timer, waking to check for file events and
+        // interruptions
+        let poll_interval = match &watcher {
+            Some(watcher) if watcher.is_watching() => WATCH_POLL_INTERVAL,
+            _ => INTERRUPT_POLL_INTERVAL,
+        };
+        let wake = [deadline, timers.next_due()]
+            .into_iter()
+            .flatten()
+            .min()
+            .map_or(poll_interval, |wake| {
+                wake.saturating_duration_since(now).min(poll_interval)
+            });
+        if ready(STDIN_FD, Some(wake)) {
+            return Ok(Some(Event::StdinReadable));
+        }
+    }
+}
+
+// Waits until fd is readable, or until interrupted returns true, in which
+// case it fails so that the read is not attempted. The caller then stops the
+// guest with the interruption.
+pub fn wait_readable(fd: RawFd, interrupted: &dyn Fn() -> bool) -> io::Result<()> {
+    loop {
+        if interrupted() {
+            return Err(io::Error::other("interrupted"));
+        }
+        if ready(fd, Some(INTERRUPT_POLL_INTERVAL)) {
+            return Ok(());
+        }
+    }
+}
+
+// Waits up to the timeout (forever if None) for fd to be readable, without
+// consuming any input. End of file, and a pending connection on a listener,
+// count as readable.
+#[cfg(unix)]
+fn ready(fd: RawFd, timeout: Option<Duration>) -> bool {
+    let timeout_ms = match timeout {
+        Some(timeout) => timeout.as_millis().min(i32::MAX as u128) as i32,
+        None => -1,
+    };
+    let mut poll_fd = libc::pollfd {
+        fd,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    // SAFETY: poll_fd is a valid pollfd array of length 1
+    let ready = unsafe { libc::poll(&mut poll_fd, 1, timeout_ms) };
+    ready > 0
+}
+
+// Without poll(2), report the fd as readable and let reads block.
+#[cfg(not(unix))]
+fn ready(_fd: RawFd, _timeout: Option<Duration>) -> bool {
+    true
+}
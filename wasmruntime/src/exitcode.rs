@@ -0,0 +1,139 @@
+// Process exit codes. A guest that finishes chooses its own code, through
+// its entry's return value or proc_exit, and a guest stopped by a signal
+// exits with 128 plus the signal number. The codes below are for runs the
+// guest did not finish, so that wrappers can tell a crashed program from a
+// failed runtime:
+//
+//   1    the runtime failed, e.g. on an invalid module
+//   2    the command line is invalid
+//   66   the module or another input file cannot be read
+//   101  the guest called panic
+//   103  the guest ran out of fuel, stack, tstack, or memory
+//   124  the guest ran past --timeout
+//   134  the guest trapped
+
+use crate::guest::TstackOverflow;
+use crate::limits::MemoryLimitExceeded;
+use crate::panic::GuestPanic;
+use crate::timeout::TimedOut;
+use std::fmt;
+use wasmtime::{Error, Trap};
+use wasmtime_wasi::I32Exit;
+
+pub const HOST_ERROR: i32 = 1;
+pub const USAGE: i32 = 2;
+pub const NO_INPUT: i32 = 66;
+pub const GUEST_PANIC: i32 = 101;
+pub const RESOURCE_LIMIT: i32 = 103;
+pub const TIMEOUT: i32 = 124;
+pub const GUEST_TRAP: i32 = 134;
+
+/// How a run of the command line ended, which decides the process's exit
+/// code.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RunOutcome {
+    /// The program finished, or a subcommand other than run did, with this
+    /// exit code.
+    Exited(i32),
+    /// The guest was stopped by this signal, and did not handle it.
+    Signaled(i32),
+    /// The guest called panic.
+    Panicked,
+    /// The guest trapped, e.g. on unreachable or an out-of-bounds access.
+    Trapped,
+    /// The guest ran out of fuel, stack, tstack, or memory.
+    ResourceLimit,
+    /// The guest ran past --timeout.
+    TimedOut,
+    /// The command line is invalid.
+    Usage,
+    /// The module or another input file cannot be read.
+    UnreadableInput,
+    /// The runtime failed: an invalid module, or an error in a host function.
+    HostError,
+}
+
+impl RunOutcome {
+    /// The outcome of a run stopped by error.
+    pub fn for_error(error: &Error) -> RunOutcome {
+        if let Some(exit) = error.downcast_ref::<I32Exit>() {
+            return RunOutcome::Exited(exit.0);
+        }
+        if error.is::<TimedOut>() {
+            return RunOutcome::TimedOut;
+        }
+        if error.is::<MemoryLimitExceeded>() || error.is::<TstackOverflow>() {
+            return RunOutcome::ResourceLimit;
+        }
+        if error.is::<GuestPanic>() {
+            return RunOutcome::Panicked;
+        }
+        if error.is::<UsageError>() {
+            return RunOutcome::Usage;
+        }
+        if error.is::<UnreadableInput>() {
+            return RunOutcome::UnreadableInput;
+        }
+        match error.downcast_ref::<Trap>() {
+            Some(Trap::OutOfFuel | Trap::StackOverflow) => RunOutcome::ResourceLimit,
+            Some(_) => RunOutcome::Trapped,
+            None => RunOutcome::HostError,
+        }
+    }
+
+    pub fn exit_code(self) -> i32 {
+        match self {
+            RunOutcome::Exited(exit_code) => exit_code,
+            RunOutcome::Signaled(signal) => 128 + signal,
+            RunOutcome::Panicked => GUEST_PANIC,
+            RunOutcome::Trapped => GUEST_TRAP,
+            RunOutcome::ResourceLimit => RESOURCE_LIMIT,
+            RunOutcome::TimedOut => TIMEOUT,
+            RunOutcome::Usage => USAGE,
+            RunOutcome::UnreadableInput => NO_INPUT,
+            RunOutcome::HostError => HOST_ERROR,
+        }
+    }
+}
+
+pub fn for_error(error: &Error) -> i32 {
+    RunOutcome::for_error(error).exit_code()
+}
+
+// Error for options that cannot be used together, which clap cannot check.
+#[derive(Debug)]
+pub struct UsageError(pub String);
+
+impl fmt::Display for UsageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for UsageError {}
+
+pub fn usage(message: impl Into<String>) -> Error {
+    Error::new(UsageError(message.into()))
+}
+
+// Error for a file given on the command line that cannot be read.
+#[derive(Debug)]
+pub struct UnreadableInput {
+    pub path: String,
+    pub error: std::io::Error,
+}
+
+impl fmt::Display for UnreadableInput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.path, self.error)
+    }
+}
+
+impl std::error::Error for UnreadableInput {}
+
+pub fn unreadable(path: impl fmt::Display, error: std::io::Error) -> Error {
+    Error::new(UnreadableInput {
+        path: path.to_string(),
+        error,
+    })
+}
@@ -0,0 +1,333 @@
+// Scripted interaction tests for --expect. The runtime re-runs itself, minus
+// --expect, as a child process with piped stdin and stdout, or with --pty on
+// a pseudo-terminal, then follows a script of directives, one per line:
+//
+//   send <text>        write <text> and a newline to the program's stdin
+//   expect <text>      wait until <text> appears in the program's output
+//   row <n> <text>     wait until row <n> of the screen contains <text>
+//   timeout <seconds>  time limit for each following expect (default 5)
+//   exit <code>        close stdin and wait for the program to exit with <code>
+//
+// After the last directive, stdin is closed and the program is given the
+// timeout to exit before it is killed. Blank lines and lines starting with
+// '#' are ignored. Each expect searches only the output after the previous
+// match.
+//
+// On a pseudo-terminal the program's stdin, stdout, and stderr are all the
+// terminal, so it takes its terminal-only paths; the output then includes
+// the terminal's echo of sent text, and closing stdin sends an end-of-file
+// character (^D).
+//
+// The screen is a virtual terminal of TERMINAL_ROWS by TERMINAL_COLUMNS that
+// all of the program's output is drawn on, so row assertions check what a
+// user would see, after cursor movement and erasing, rather than the raw
+// bytes. Rows are numbered from 1.
+
+use crate::terminal::Terminal;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::Path;
+use std::process::{Child, ChildStdin, Command, ExitStatus, Stdio};
+use std::sync::mpsc::{self, Receiver};
+use std::time::{Duration, Instant};
+use wasmtime::{Error, Result};
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+const TERMINAL_ROWS: u16 = 24;
+const TERMINAL_COLUMNS: u16 = 80;
+
+// Returns the arguments to run the program with: args without the program
+// name, --expect, and --pty.
+pub fn child_args(args: &[String]) -> Vec<String> {
+    let mut child_args = Vec::new();
+    let mut args = args.iter().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--expect" => {
+                args.next();
+            }
+            "--pty" => {}
+            _ if arg.starts_with("--expect=") => {}
+            _ => child_args.push(arg.clone()),
+        }
+    }
+    child_args
+}
+
+enum Input {
+    Pipe(ChildStdin),
+    Terminal(File),
+}
+
+struct Session {
+    child: Child,
+    // None once closed.
+    input: Option<Input>,
+    output: Receiver<Vec<u8>>,
+    // Output not yet consumed by a match.
+    unmatched: Vec<u8>,
+    screen: Terminal,
+}
+
+impl Session {
+    fn send(&mut self, text: &str) -> bool {
+        match &mut self.input {
+            Some(Input::Pipe(stdin)) => writeln!(stdin, "{}", text).is_ok(),
+            Some(Input::Terminal(master)) => write!(master, "{}\r", text).is_ok(),
+            None => false,
+        }
+    }
+
+    fn close_input(&mut self) {
+        if let Some(Input::Terminal(master)) = &mut self.input {
+            let _ = master.write_all(b"\x04");
+        }
+        self.input = None;
+    }
+
+    // Closes the program's stdin and waits for it to exit. Returns None on
+    // timeout.
+    fn wait(&mut self, timeout: Duration) -> Result<Option<ExitStatus>> {
+        self.close_input();
+        let deadline = Instant::now() + timeout;
+        while Instant::now() < deadline {
+            if let Some(status) = self.child.try_wait()? {
+                return Ok(Some(status));
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        Ok(None)
+    }
+
+    // Waits for text to appear in the output and consumes the output up to
+    // the end of it. Returns false on timeout or if the program closed its
+    // output first.
+    fn expect(&mut self, text: &str, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(start) = find(&self.unmatched, text.as_bytes()) {
+                self.unmatched.drain(..start + text.len());
+                return true;
+            }
+            if !self.receive(deadline) {
+                return false;
+            }
+        }
+    }
+
+    // Waits for a row of the screen to contain text. Returns false on
+    // timeout or if the program closed its output first.
+    fn expect_row(&mut self, row: usize, text: &str, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if self.screen.row(row).is_some_and(|row| row.contains(text)) {
+                return true;
+            }
+            if !self.receive(deadline) {
+                return false;
+            }
+        }
+    }
+
+    // Waits for more output until the deadline. Returns whether there was
+    // any.
+    fn receive(&mut self, deadline: Instant) -> bool {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        match self.output.recv_timeout(remaining) {
+            Ok(bytes) => {
+                self.screen.feed(&bytes);
+                self.unmatched.extend(bytes);
+                true
+            }
+            Err(_) => false,
+        }
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    if needle.is_empty() {
+        return Some(0);
+    }
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+// Spawns the program on a new pseudo-terminal and returns it with the
+// terminal's master side, which carries both its input and its output.
+#[cfg(unix)]
+fn spawn_on_terminal(mut command: Command) -> Result<(Child, File)> {
+    use std::os::fd::{FromRawFd, OwnedFd};
+    use std::os::unix::process::CommandExt;
+
+    let mut master = -1;
+    let mut slave = -1;
+    let size = libc::winsize {
+        ws_row: TERMINAL_ROWS,
+        ws_col: TERMINAL_COLUMNS,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    // SAFETY: openpty only writes the two descriptors, and the name and
+    // termios pointers may be null
+    let result = unsafe {
+        libc::openpty(
+            &mut master,
+            &mut slave,
+            std::ptr::null_mut(),
+            std::ptr::null(),
+            &size,
+        )
+    };
+    if result != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    // SAFETY: openpty succeeded, so both descriptors are open and ours
+    let (master, slave) = unsafe { (File::from_raw_fd(master), OwnedFd::from_raw_fd(slave)) };
+    command
+        .stdin(slave.try_clone()?)
+        .stdout(slave.try_clone()?)
+        .stderr(slave);
+    // SAFETY: setsid and ioctl are async-signal-safe
+    unsafe {
+        command.pre_exec(|| {
+            // Make the terminal the child's controlling terminal
+            if libc::setsid() < 0 || libc::ioctl(0, libc::TIOCSCTTY, 0) < 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    // The command, and with it the parent's copies of the slave side, is
+    // dropped here, so reads from the master end once the child exits
+    Ok((command.spawn()?, master))
+}
+
+#[cfg(not(unix))]
+fn spawn_on_terminal(_command: Command) -> Result<(Child, File)> {
+    Err(Error::msg("--pty is not supported on this platform"))
+}
+
+// Runs the script against the program and returns the exit code: 0 if every
+// directive succeeded, 1 otherwise.
+pub fn run(script_path: &Path, child_args: &[String], pty: bool) -> Result<i32> {
+    let script = fs::read_to_string(script_path)?;
+    let mut command = Command::new(std::env::current_exe()?);
+    command.args(child_args);
+    let (child, input, mut stdout): (_, _, Box<dyn Read + Send>) = if pty {
+        let (child, master) = spawn_on_terminal(command)?;
+        let output = master.try_clone()?;
+        (child, Input::Terminal(master), Box::new(output))
+    } else {
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let stdin = child.stdin.take().unwrap();
+        let stdout = child.stdout.take().unwrap();
+        (child, Input::Pipe(stdin), Box::new(stdout))
+    };
+    let (sender, output) = mpsc::channel();
+    std::thread::spawn(move || {
+        let mut buffer = [0; 4096];
+        // A terminal's master side fails with EIO, rather than reaching end
+        // of file, once the child is gone
+        while let Ok(count @ 1..) = stdout.read(&mut buffer) {
+            if sender.send(buffer[..count].to_vec()).is_err() {
+                break;
+            }
+        }
+    });
+    let mut session = Session {
+        child,
+        input: Some(input),
+        output,
+        unmatched: Vec::new(),
+        screen: Terminal::new(TERMINAL_ROWS as usize, TERMINAL_COLUMNS as usize),
+    };
+
+    let mut timeout = DEFAULT_TIMEOUT;
+    for (line_number, line) in script.lines().enumerate() {
+        let line = line.trim_end();
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fail = |message: String| -> Result<i32> {
+            eprintln!("{}:{}: {}", script_path.display(), line_number + 1, message);
+            Ok(1)
+        };
+        let (directive, argument) = line.split_once(' ').unwrap_or((line, ""));
+        match directive {
+            "send" => {
+                if !session.send(argument) {
+                    let _ = session.child.kill();
+                    return fail(String::from("program is no longer reading its input"));
+                }
+            }
+            "expect" => {
+                if !session.expect(argument, timeout) {
+                    let _ = session.child.kill();
+                    return fail(format!(
+                        "did not see {:?} within {:?}; unmatched output: {:?}",
+                        argument,
+                        timeout,
+                        String::from_utf8_lossy(&session.unmatched)
+                    ));
+                }
+            }
+            "row" => {
+                let (row, text) = argument.split_once(' ').unwrap_or((argument, ""));
+                let row: usize = row
+                    .parse()
+                    .ok()
+                    .filter(|row| (1..=TERMINAL_ROWS as usize).contains(row))
+                    .ok_or_else(|| Error::msg(format!("invalid row {:?}", row)))?;
+                if !session.expect_row(row, text, timeout) {
+                    let _ = session.child.kill();
+                    return fail(format!(
+                        "row {} did not contain {:?} within {:?}; screen:\n{}",
+                        row,
+                        text,
+                        timeout,
+                        session.screen.dump()
+                    ));
+                }
+            }
+            "timeout" => {
+                let seconds: f64 = argument
+                    .parse()
+                    .map_err(|_| Error::msg(format!("invalid timeout {:?}", argument)))?;
+                timeout = Duration::from_secs_f64(seconds);
+            }
+            "exit" => {
+                let expected: i32 = argument
+                    .parse()
+                    .map_err(|_| Error::msg(format!("invalid exit code {:?}", argument)))?;
+                match session.wait(timeout)? {
+                    Some(status) if status.code() == Some(expected) => {}
+                    Some(status) => {
+                        return fail(format!("expected exit code {}, got {}", expected, status));
+                    }
+                    None => {
+                        let _ = session.child.kill();
+                        return fail(format!("program did not exit within {:?}", timeout));
+                    }
+                }
+            }
+            _ => {
+                let _ = session.child.kill();
+                return Err(Error::msg(format!(
+                    "{}:{}: unknown directive {:?}",
+                    script_path.display(),
+                    line_number + 1,
+                    directive
+                )));
+            }
+        }
+    }
+    if session.wait(timeout)?.is_none() {
+        let _ = session.child.kill();
+    }
+    Ok(0)
+}
@@ -0,0 +1,94 @@
+// Host extensions: extra host functions from outside this crate. A program
+// embedding the runtime registers an extension with register_extension,
+// before calling cli::main or creating a Host, and from then on every linker
+// the runtime makes includes its functions. Extensions are added after the
+// runtime's own host functions and may replace them.
+//
+// --plugin loads extensions from a shared library instead, which exports
+//
+//   #[no_mangle]
+//   pub fn zong_runtime_plugin() -> Vec<Box<dyn HostExtension>>
+//
+// Trait objects cross the library boundary, so a plugin must be built with
+// the same compiler and version of this crate as the runtime. Plugins run as
+// native code in the runtime's process, outside any sandbox.
+
+use crate::HostState;
+use std::sync::{Arc, Mutex};
+use wasmtime::{Linker, Result};
+
+/// A set of host functions to add to the runtime's linkers.
+pub trait HostExtension: Send + Sync {
+    /// Name the extension is reported under in errors.
+    fn name(&self) -> &str;
+
+    /// Defines the extension's functions on a linker.
+    fn register(&self, linker: &mut Linker<HostState>) -> Result<()>;
+}
+
+/// The function a plugin exports as `zong_runtime_plugin`.
+pub type PluginEntry = fn() -> Vec<Box<dyn HostExtension>>;
+
+static EXTENSIONS: Mutex<Vec<Arc<dyn HostExtension>>> = Mutex::new(Vec::new());
+
+/// Adds an extension's host functions to every linker made from now on.
+pub fn register_extension(extension: impl HostExtension + 'static) {
+    EXTENSIONS.lock().unwrap().push(Arc::new(extension));
+}
+
+// Defines the registered extensions' functions on a linker.
+pub fn add_to_linker(linker: &mut Linker<HostState>) -> Result<()> {
+    let extensions = EXTENSIONS.lock().unwrap().clone();
+    linker.allow_shadowing(true);
+    for extension in extensions {
+        extension
+            .register(linker)
+            .map_err(|error| error.context(format!("extension {}", extension.name())))?;
+    }
+    linker.allow_shadowing(false);
+    Ok(())
+}
+
+// Loads a plugin and registers its extensions. The library stays loaded for
+// the rest of the process.
+#[cfg(unix)]
+pub fn load_plugin(path: &str) -> Result<()> {
+    use std::ffi::{CStr, CString};
+    use wasmtime::Error;
+
+    let c_path = CString::new(path)?;
+    // SAFETY: Loading a plugin runs its initializers; like the runtime
+    // itself, the plugin is trusted to run native code.
+    let handle = unsafe { libc::dlopen(c_path.as_ptr(), libc::RTLD_NOW | libc::RTLD_LOCAL) };
+    if handle.is_null() {
+        // SAFETY: dlerror returns a C string describing the failure.
+        let message = unsafe { CStr::from_ptr(libc::dlerror()) };
+        return Err(Error::msg(format!(
+            "--plugin {}: {}",
+            path,
+            message.to_string_lossy()
+        )));
+    }
+    // SAFETY: handle is a loaded library, and the name is a C string.
+    let symbol = unsafe { libc::dlsym(handle, c"zong_runtime_plugin".as_ptr()) };
+    if symbol.is_null() {
+        return Err(Error::msg(format!(
+            "--plugin {}: the library does not export zong_runtime_plugin",
+            path
+        )));
+    }
+    // SAFETY: Plugins export zong_runtime_plugin as a PluginEntry.
+    let entry: PluginEntry = unsafe { std::mem::transmute(symbol) };
+    for extension in entry() {
+        EXTENSIONS.lock().unwrap().push(Arc::from(extension));
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn load_plugin(path: &str) -> Result<()> {
+    Err(wasmtime::Error::msg(format!(
+        "--plugin {}: plugins are not supported on this platform",
+        path
+    )))
+}
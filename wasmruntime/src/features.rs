@@ -0,0 +1,209 @@
+// Wasm proposals for --enable-feature and --disable-feature. Modules are
+// validated against the proposals wasmtime enables by default, which are the
+// finished ones, and those engine_config adds:
+//
+//   on:  simd, relaxed-simd, multi-memory, multi-value, bulk-memory,
+//        reference-types, function-references, gc, extended-const,
+//        tail-call, threads, memory64
+//   off: custom-page-sizes
+//
+// Winch supports fewer of them; with --backend winch, relaxed-simd,
+// tail-call, threads, function-references, and gc cannot be enabled.
+//
+// A module that fails to compile because it uses a proposal that is off is
+// reported with the flag that would let it run.
+
+use crate::backend::Backend;
+use clap::ValueEnum;
+use wasmparser::{Validator, WasmFeatures};
+use wasmtime::{Config, Error, Result};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, clap::ValueEnum)]
+pub enum Feature {
+    /// return_call and return_call_indirect
+    TailCall,
+    /// 128-bit vector instructions
+    Simd,
+    /// Vector instructions whose results may differ between hosts
+    RelaxedSimd,
+    /// More than one memory
+    MultiMemory,
+    /// Memories indexed by i64
+    Memory64,
+    /// Shared memories and atomic instructions
+    Threads,
+    /// externref, and more than one table
+    ReferenceTypes,
+    /// Typed function references and call_ref
+    FunctionReferences,
+    /// Garbage-collected structs and arrays
+    Gc,
+    /// memory.copy, memory.fill, and passive data segments
+    BulkMemory,
+    /// Functions and blocks with more than one result
+    MultiValue,
+    /// Arithmetic in constant expressions
+    ExtendedConst,
+    /// Memories with pages of one byte
+    CustomPageSizes,
+}
+
+impl Feature {
+    fn flags(self) -> WasmFeatures {
+        match self {
+            Feature::TailCall => WasmFeatures::TAIL_CALL,
+            Feature::Simd => WasmFeatures::SIMD,
+            Feature::RelaxedSimd => WasmFeatures::RELAXED_SIMD,
+            Feature::MultiMemory => WasmFeatures::MULTI_MEMORY,
+            Feature::Memory64 => WasmFeatures::MEMORY64,
+            Feature::Threads => WasmFeatures::THREADS,
+            Feature::ReferenceTypes => WasmFeatures::REFERENCE_TYPES,
+            Feature::FunctionReferences => WasmFeatures::FUNCTION_REFERENCES,
+            Feature::Gc => WasmFeatures::GC,
+            Feature::BulkMemory => WasmFeatures::BULK_MEMORY,
+            Feature::MultiValue => WasmFeatures::MULTI_VALUE,
+            Feature::ExtendedConst => WasmFeatures::EXTENDED_CONST,
+            Feature::CustomPageSizes => WasmFeatures::CUSTOM_PAGE_SIZES,
+        }
+    }
+
+    fn set(self, config: &mut Config, enable: bool) {
+        match self {
+            Feature::TailCall => config.wasm_tail_call(enable),
+            Feature::Simd => config.wasm_simd(enable),
+            Feature::RelaxedSimd => config.wasm_relaxed_simd(enable),
+            Feature::MultiMemory => config.wasm_multi_memory(enable),
+            Feature::Memory64 => config.wasm_memory64(enable),
+            Feature::Threads => config.wasm_threads(enable),
+            Feature::ReferenceTypes => config.wasm_reference_types(enable),
+            Feature::FunctionReferences => config.wasm_function_references(enable),
+            Feature::Gc => config.wasm_gc(enable),
+            Feature::BulkMemory => config.wasm_bulk_memory(enable),
+            Feature::MultiValue => config.wasm_multi_value(enable),
+            Feature::ExtendedConst => config.wasm_extended_const(enable),
+            Feature::CustomPageSizes => config.wasm_custom_page_sizes(enable),
+        };
+    }
+
+    fn name(self) -> String {
+        self.to_possible_value()
+            .expect("features are not skipped")
+            .get_name()
+            .to_string()
+    }
+}
+
+// What Winch cannot compile.
+const WINCH_UNSUPPORTED: WasmFeatures = WasmFeatures::RELAXED_SIMD
+    .union(WasmFeatures::TAIL_CALL)
+    .union(WasmFeatures::THREADS)
+    .union(WasmFeatures::FUNCTION_REFERENCES)
+    .union(WasmFeatures::GC);
+
+// The proposals a run's engine validates modules against.
+pub struct Features {
+    enabled: WasmFeatures,
+    backend: Backend,
+}
+
+impl Features {
+    // Applies the flags to config, after the backend's settings.
+    pub fn configure(
+        config: &mut Config,
+        backend: Backend,
+        enable: &[Feature],
+        disable: &[Feature],
+    ) -> Result<Features> {
+        if let Some(feature) = enable.iter().find(|feature| disable.contains(feature)) {
+            return Err(crate::exitcode::usage(format!(
+                "{} is both enabled and disabled",
+                feature.name()
+            )));
+        }
+        let mut enabled = WasmFeatures::WASM2
+            | WasmFeatures::MULTI_MEMORY
+            | WasmFeatures::RELAXED_SIMD
+            | WasmFeatures::TAIL_CALL
+            | WasmFeatures::EXTENDED_CONST
+            | WasmFeatures::GC_TYPES
+            | WasmFeatures::THREADS
+            | WasmFeatures::FUNCTION_REFERENCES
+            | WasmFeatures::GC
+            | WasmFeatures::MEMORY64;
+        if backend == Backend::Winch {
+            enabled.remove(WINCH_UNSUPPORTED);
+            if let Some(feature) = enable
+                .iter()
+                .find(|feature| WINCH_UNSUPPORTED.contains(feature.flags()))
+            {
+                return Err(crate::exitcode::usage(format!(
+                    "the winch backend does not support {}; use --backend cranelift",
+                    feature.name()
+                )));
+            }
+        }
+        for &feature in enable {
+            feature.set(config, true);
+            enabled.insert(feature.flags());
+        }
+        for &feature in disable {
+            feature.set(config, false);
+            enabled.remove(feature.flags());
+        }
+        Ok(Features { enabled, backend })
+    }
+
+    // The names of the proposals that are on.
+    pub fn names(&self) -> Vec<String> {
+        Feature::value_variants()
+            .iter()
+            .filter(|feature| self.enabled.contains(feature.flags()))
+            .map(|feature| feature.name())
+            .collect()
+    }
+
+    // Adds the flag to enable what the module needs to an error compiling
+    // it, if it failed for using proposals that are off.
+    pub fn explain(&self, error: Error, wasm_bytes: &[u8]) -> Error {
+        let validates = |features: WasmFeatures| {
+            Validator::new_with_features(features)
+                .validate_all(wasm_bytes)
+                .is_ok()
+        };
+        if !validates(WasmFeatures::all()) {
+            return error;
+        }
+        let needed: Vec<Feature> = Feature::value_variants()
+            .iter()
+            .copied()
+            .filter(|feature| {
+                !self.enabled.contains(feature.flags())
+                    && !validates(WasmFeatures::all().difference(feature.flags()))
+            })
+            .collect();
+        if needed.is_empty() {
+            return error;
+        }
+        let names: Vec<String> = needed.iter().map(|feature| feature.name()).collect();
+        let (features, are) = match needed.len() {
+            1 => ("feature", "is"),
+            _ => ("features", "are"),
+        };
+        let winch_unsupported = self.backend == Backend::Winch
+            && needed
+                .iter()
+                .any(|feature| WINCH_UNSUPPORTED.contains(feature.flags()));
+        let fix = if winch_unsupported {
+            String::from("run it with --backend cranelift instead")
+        } else {
+            format!("run it with --enable-feature {}", names.join(","))
+        };
+        error.context(format!(
+            "the module uses the {} {}, which {} off; {}",
+            names.join(", "),
+            features,
+            are,
+            fix
+        ))
+    }
+}
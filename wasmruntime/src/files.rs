@@ -0,0 +1,140 @@
+// Files opened by the guest with file_open, and TCP sockets from
+// tcp_connect, tcp_listen, and tcp_accept, in one table indexed by fd. Paths
+// are resolved through the sandbox before they get here, so this only deals
+// in host paths.
+
+use crate::events::{self, RawFd};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::Path;
+
+pub const MODE_READ: i32 = 0;
+// Creates the file, or truncates it if it exists.
+pub const MODE_WRITE: i32 = 1;
+// Creates the file if needed and writes at its end.
+pub const MODE_APPEND: i32 = 2;
+
+#[derive(Default)]
+pub struct Files {
+    // None for closed fds, which are not reused.
+    files: Vec<Option<Handle>>,
+}
+
+enum Handle {
+    File(File),
+    Stream(TcpStream),
+    Listener(TcpListener),
+}
+
+impl Files {
+    // Opens a file and returns its fd. Fails with InvalidInput for an unknown
+    // mode.
+    pub fn open(&mut self, host_path: &Path, mode: i32) -> io::Result<i32> {
+        let mut options = OpenOptions::new();
+        match mode {
+            MODE_READ => options.read(true),
+            MODE_WRITE => options.write(true).create(true).truncate(true),
+            MODE_APPEND => options.append(true).create(true),
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("unknown mode {}", mode),
+                ))
+            }
+        };
+        Ok(self.add(Handle::File(options.open(host_path)?)))
+    }
+
+    // Connects to host:port, where host is a name or an address, and returns
+    // the socket's fd.
+    pub fn connect(&mut self, host: &str, port: u16) -> io::Result<i32> {
+        Ok(self.add(Handle::Stream(TcpStream::connect((host, port))?)))
+    }
+
+    // Listens on a port on every interface and returns the listener's fd.
+    pub fn listen(&mut self, port: u16) -> io::Result<i32> {
+        Ok(self.add(Handle::Listener(TcpListener::bind(("0.0.0.0", port))?)))
+    }
+
+    // Waits for a connection to a listener and returns its socket's fd.
+    // Returns None if fd is not a listener. The wait fails once interrupted
+    // returns true.
+    pub fn accept(&mut self, fd: i32, interrupted: &dyn Fn() -> bool) -> Option<io::Result<i32>> {
+        let Handle::Listener(listener) = self.get(fd)? else {
+            return None;
+        };
+        let accepted =
+            events::wait_readable(raw_fd(listener), interrupted).and_then(|()| listener.accept());
+        Some(accepted.map(|(stream, _)| self.add(Handle::Stream(stream))))
+    }
+
+    // Reads up to max_len bytes: to the end of a file if it is shorter, or
+    // whatever a socket has received, waiting until it has some or
+    // interrupted returns true. Returns None for a bad fd; an empty result
+    // means end of file, or that the peer closed the connection.
+    pub fn read(
+        &mut self,
+        fd: i32,
+        max_len: usize,
+        interrupted: &dyn Fn() -> bool,
+    ) -> Option<io::Result<Vec<u8>>> {
+        Some(match self.get(fd)? {
+            Handle::File(file) => {
+                let mut bytes = Vec::new();
+                file.take(max_len as u64)
+                    .read_to_end(&mut bytes)
+                    .map(|_| bytes)
+            }
+            Handle::Stream(stream) => {
+                // A read returns at most what the socket has buffered, so a
+                // huge max_len need not be allocated
+                let mut bytes = vec![0; max_len.min(1 << 16)];
+                events::wait_readable(raw_fd(stream), interrupted)
+                    .and_then(|()| stream.read(&mut bytes))
+                    .map(|count| {
+                        bytes.truncate(count);
+                        bytes
+                    })
+            }
+            Handle::Listener(_) => return None,
+        })
+    }
+
+    // Writes all of the bytes. Returns None for a bad fd.
+    pub fn write(&mut self, fd: i32, bytes: &[u8]) -> Option<io::Result<()>> {
+        Some(match self.get(fd)? {
+            Handle::File(file) => file.write_all(bytes),
+            Handle::Stream(stream) => stream.write_all(bytes),
+            Handle::Listener(_) => return None,
+        })
+    }
+
+    // Returns whether the fd was open.
+    pub fn close(&mut self, fd: i32) -> bool {
+        let slot = usize::try_from(fd)
+            .ok()
+            .and_then(|fd| self.files.get_mut(fd));
+        slot.and_then(Option::take).is_some()
+    }
+
+    fn add(&mut self, handle: Handle) -> i32 {
+        self.files.push(Some(handle));
+        (self.files.len() - 1) as i32
+    }
+
+    fn get(&mut self, fd: i32) -> Option<&mut Handle> {
+        self.files.get_mut(usize::try_from(fd).ok()?)?.as_mut()
+    }
+}
+
+#[cfg(unix)]
+fn raw_fd(socket: &impl std::os::unix::io::AsRawFd) -> RawFd {
+    socket.as_raw_fd()
+}
+
+// Only used for waits, which do not wait without poll(2).
+#[cfg(not(unix))]
+fn raw_fd<T>(_socket: &T) -> RawFd {
+    -1
+}
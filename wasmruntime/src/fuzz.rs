@@ -0,0 +1,99 @@
+// Entry point for fuzzing the host functions, with the `fuzz` feature. The
+// targets in fuzz/ hand run_bytes modules that call the host functions with
+// adversarial pointers and lengths. Each module runs in the strict sandbox
+// with hard limits, so that no input can hang the fuzzer or exhaust its
+// memory: FUEL units of fuel, MAX_MEMORY bytes of memory, and none of the
+// host functions that wait, such as sleep_ms and WASI's poll_oneoff. Its
+// stdin is the given bytes, and its output is discarded.
+//
+// Traps and errors from host functions are expected outcomes. A panic in a
+// host function is a bug; run_bytes catches it and returns it as
+// RunResult::Panicked, for the target to report.
+
+use crate::hostio::HostIo;
+use crate::limits::Limits;
+use crate::sandbox::Profile;
+use crate::{call_entry, check_resolved, engine_config, exitcode, host_linker, HostState};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::OnceLock;
+use wasmtime::{Engine, Module, Result, Store};
+use wasmtime_wasi::I32Exit;
+
+const FUEL: u64 = 10_000_000;
+
+const MAX_MEMORY: usize = 64 << 20;
+
+// Capabilities whose host functions can block.
+const DENIED: &[&str] = &["timers", "process"];
+
+/// How a fuzzed module's run ended.
+#[derive(Debug)]
+pub enum RunResult {
+    /// The module was invalid, or imports something the fuzzer does not
+    /// provide.
+    Rejected(String),
+    /// The module's entry function returned or it exited with this code.
+    Exited(i32),
+    /// The module trapped, ran out of fuel, or a host function returned an
+    /// error, with the exit code `wasmruntime run` would report.
+    Failed(i32, String),
+    /// A host function panicked, with the panic's message.
+    Panicked(String),
+}
+
+/// Runs a module, given as wasm or WAT bytes, with stdin as its stdin.
+pub fn run_bytes(wasm_bytes: &[u8], stdin: &[u8]) -> RunResult {
+    match panic::catch_unwind(AssertUnwindSafe(|| run(wasm_bytes, stdin))) {
+        Ok(Ok(result)) => result,
+        Ok(Err(error)) => RunResult::Rejected(format!("{:#}", error)),
+        Err(payload) => {
+            let message = match payload.downcast_ref::<&str>() {
+                Some(message) => message.to_string(),
+                None => payload
+                    .downcast_ref::<String>()
+                    .cloned()
+                    .unwrap_or_else(|| String::from("non-string panic payload")),
+            };
+            RunResult::Panicked(message)
+        }
+    }
+}
+
+fn engine() -> &'static Engine {
+    static ENGINE: OnceLock<Engine> = OnceLock::new();
+    ENGINE.get_or_init(|| Engine::new(&engine_config(true, false)).expect("engine config is valid"))
+}
+
+// Returns an error only if the module cannot be run at all.
+fn run(wasm_bytes: &[u8], stdin: &[u8]) -> Result<RunResult> {
+    let engine = engine();
+    let module = Module::new(engine, wasm_bytes)?;
+    let denied: Vec<String> = DENIED.iter().map(|name| name.to_string()).collect();
+    Profile::Strict.check_imports_denying(&module, &denied)?;
+    let mut io = HostIo::default();
+    io.set_stdin(stdin.to_vec());
+    io.capture_stdout();
+    io.capture_stderr();
+    let state = HostState::for_module(&module, wasm_bytes, None, &[], &[], io, Profile::Strict)?;
+    let linker = host_linker(engine, &state)?;
+    let mut store = Store::new(engine, state);
+    store.data_mut().limits = Limits::new(Some(MAX_MEMORY), None);
+    store.limiter(|state| &mut state.limits);
+    store.set_fuel(FUEL)?;
+    store.set_epoch_deadline(1);
+    check_resolved(&linker, &mut store, &module, "module")?;
+    // A module without a main still runs its start function
+    let result = linker
+        .instantiate(&mut store, &module)
+        .and_then(|instance| match instance.get_func(&mut store, "main") {
+            Some(main) => call_entry(&mut store, main, "main"),
+            None => Ok(None),
+        });
+    Ok(match result {
+        Ok(exit_code) => RunResult::Exited(exit_code.unwrap_or(0)),
+        Err(error) => match error.downcast_ref::<I32Exit>() {
+            Some(exit) => RunResult::Exited(exit.0),
+            None => RunResult::Failed(exitcode::for_error(&error), format!("{:#}", error)),
+        },
+    })
+}
@@ -0,0 +1,367 @@
+// Access to the guest's memory and tstack from host functions. Addresses and
+// lengths come from the guest, so a compiler bug or a hand-written module can
+// pass anything; these helpers check the exports and bounds and return errors
+// naming the host function, which trap the guest, rather than panicking and
+// taking the whole runtime down.
+
+use crate::limits::MemoryLimitExceeded;
+use std::borrow::Cow;
+use std::cell::UnsafeCell;
+use std::fmt;
+use std::ops::Range;
+use std::sync::atomic::{AtomicU8, Ordering};
+use wasmtime::{
+    AsContext, AsContextMut, Caller, Error, Extern, ExternType, Global, Memory, Module, Mutability,
+    Result, SharedMemory, StoreContext, StoreContextMut, Val, ValType,
+};
+
+// Converts an address passed as an i32 to an offset into memory. Wasm
+// addresses are unsigned, so e.g. -1 is the last byte of a 4 GiB memory.
+pub fn address(addr: i32) -> usize {
+    addr as u32 as usize
+}
+
+// Converts a pointer argument to an address: an i32, as for address, or an
+// i64 from a module with a 64-bit memory.
+pub fn pointer(val: &Val) -> u64 {
+    match val {
+        Val::I64(addr) => *addr as u64,
+        _ => val.unwrap_i32() as u32 as u64,
+    }
+}
+
+// Whether the module's exported memory is a 64-bit one, from the memory64
+// proposal. Its addresses are i64s: host functions take pointers from such
+// a module as i64, and its tstack is a mutable i64 global.
+pub fn is_memory64(module: &Module) -> bool {
+    matches!(module.get_export("memory"), Some(ExternType::Memory(ty)) if ty.is_64())
+}
+
+// The type of the module's pointers, with is_memory64.
+pub fn pointer_type(memory64: bool) -> ValType {
+    if memory64 {
+        ValType::I64
+    } else {
+        ValType::I32
+    }
+}
+
+// The guest's memory: its own, or one shared with the threads it started
+// with spawn, with the same methods as Memory. Host functions see either
+// through GuestData.
+#[derive(Clone)]
+pub enum GuestMemory {
+    Local(Memory),
+    Shared(SharedMemory),
+}
+
+// Returns the guest's exported memory.
+pub fn memory<T>(caller: &mut Caller<'_, T>, function: &str) -> Result<GuestMemory> {
+    match caller.get_export("memory") {
+        Some(Extern::Memory(memory)) => Ok(GuestMemory::Local(memory)),
+        Some(Extern::SharedMemory(memory)) => Ok(GuestMemory::Shared(memory)),
+        _ => Err(Error::msg(format!(
+            "{}: the module does not export a memory",
+            function
+        ))),
+    }
+}
+
+// A shared memory's cells, for as long as the caller's borrow of the store.
+fn shared_cells<'a>(memory: &SharedMemory) -> &'a [UnsafeCell<u8>] {
+    // SAFETY: Instances using the memory keep it alive as long as their
+    // store, and since memory never shrinks, its first data_size bytes stay
+    // valid.
+    unsafe { std::slice::from_raw_parts(memory.data().as_ptr(), memory.data_size()) }
+}
+
+impl GuestMemory {
+    pub fn data<'a, T: 'a>(&self, store: impl Into<StoreContext<'a, T>>) -> GuestData<'a> {
+        match self {
+            GuestMemory::Local(memory) => GuestData::Local(memory.data(store)),
+            GuestMemory::Shared(memory) => GuestData::Shared(shared_cells(memory)),
+        }
+    }
+
+    pub fn data_mut<'a, T: 'a>(
+        &self,
+        store: impl Into<StoreContextMut<'a, T>>,
+    ) -> GuestDataMut<'a> {
+        match self {
+            GuestMemory::Local(memory) => GuestDataMut::Local(memory.data_mut(store)),
+            GuestMemory::Shared(memory) => GuestDataMut::Shared(shared_cells(memory)),
+        }
+    }
+
+    pub fn data_and_store_mut<'a, T>(
+        &self,
+        caller: &'a mut Caller<'_, T>,
+    ) -> (GuestDataMut<'a>, &'a mut T) {
+        match self {
+            GuestMemory::Local(memory) => {
+                let (data, state) = memory.data_and_store_mut(caller);
+                (GuestDataMut::Local(data), state)
+            }
+            GuestMemory::Shared(memory) => (
+                GuestDataMut::Shared(shared_cells(memory)),
+                caller.data_mut(),
+            ),
+        }
+    }
+
+    pub fn data_size(&self, store: impl AsContext) -> usize {
+        match self {
+            GuestMemory::Local(memory) => memory.data_size(store),
+            GuestMemory::Shared(memory) => memory.data_size(),
+        }
+    }
+
+    pub fn page_size(&self, store: impl AsContext) -> u64 {
+        match self {
+            GuestMemory::Local(memory) => memory.page_size(store),
+            GuestMemory::Shared(memory) => memory.page_size().into(),
+        }
+    }
+
+    pub fn grow(&self, store: impl AsContextMut, pages: u64) -> Result<u64> {
+        match self {
+            GuestMemory::Local(memory) => memory.grow(store, pages),
+            GuestMemory::Shared(memory) => memory.grow(pages),
+        }
+    }
+}
+
+// The bytes of the guest's memory, to read. A local memory's are borrowed.
+// Other threads may change a shared memory's at any time, which Rust does not
+// allow of bytes it has references to, so they are copied out instead, with
+// relaxed atomic loads; a host function reading data that another thread is
+// writing gets a mix of old and new bytes, as the guest would.
+#[derive(Clone, Copy)]
+pub enum GuestData<'a> {
+    Local(&'a [u8]),
+    Shared(&'a [UnsafeCell<u8>]),
+}
+
+// The bytes of the guest's memory, to read and write. Writes to a shared
+// memory are copied in with relaxed atomic stores.
+pub enum GuestDataMut<'a> {
+    Local(&'a mut [u8]),
+    Shared(&'a [UnsafeCell<u8>]),
+}
+
+impl<'a> GuestData<'a> {
+    pub fn size(self) -> usize {
+        match self {
+            GuestData::Local(data) => data.len(),
+            GuestData::Shared(cells) => cells.len(),
+        }
+    }
+
+    // The bytes in range, which must be inside memory, as range checks.
+    pub fn get(self, range: Range<usize>) -> Cow<'a, [u8]> {
+        match self {
+            GuestData::Local(data) => Cow::Borrowed(&data[range]),
+            GuestData::Shared(cells) => Cow::Owned(cells[range].iter().map(load).collect()),
+        }
+    }
+
+    // All of memory, for the debugging views.
+    pub fn contents(self) -> Cow<'a, [u8]> {
+        self.get(0..self.size())
+    }
+}
+
+impl<'a> GuestDataMut<'a> {
+    pub fn as_data(&self) -> GuestData<'_> {
+        match self {
+            GuestDataMut::Local(data) => GuestData::Local(data),
+            GuestDataMut::Shared(cells) => GuestData::Shared(cells),
+        }
+    }
+
+    pub fn into_data(self) -> GuestData<'a> {
+        match self {
+            GuestDataMut::Local(data) => GuestData::Local(data),
+            GuestDataMut::Shared(cells) => GuestData::Shared(cells),
+        }
+    }
+
+    // Copies bytes into memory at addr. They must fit, as range checks.
+    pub fn set(&mut self, addr: usize, bytes: &[u8]) {
+        match self {
+            GuestDataMut::Local(data) => data[addr..addr + bytes.len()].copy_from_slice(bytes),
+            GuestDataMut::Shared(cells) => {
+                for (cell, &byte) in cells[addr..addr + bytes.len()].iter().zip(bytes) {
+                    store(cell, byte);
+                }
+            }
+        }
+    }
+}
+
+fn load(cell: &UnsafeCell<u8>) -> u8 {
+    // SAFETY: The cell is valid, as shared_cells checks. Every access to it
+    // from Rust is atomic, and wasm's own accesses are outside Rust's memory
+    // model.
+    unsafe { AtomicU8::from_ptr(cell.get()) }.load(Ordering::Relaxed)
+}
+
+fn store(cell: &UnsafeCell<u8>, byte: u8) {
+    // SAFETY: As for load
+    unsafe { AtomicU8::from_ptr(cell.get()) }.store(byte, Ordering::Relaxed)
+}
+
+// Returns the guest's exported tstack global, the bump pointer that host
+// functions allocate returned data from. It is an i32, or an i64 in a module
+// with a 64-bit memory.
+pub fn tstack<T>(caller: &mut Caller<'_, T>, function: &str) -> Result<Global> {
+    let global = caller
+        .get_export("tstack")
+        .and_then(Extern::into_global)
+        .ok_or_else(|| Error::msg(format!("{}: the module does not export tstack", function)))?;
+    let ty = global.ty(&*caller);
+    if !matches!(ty.content(), ValType::I32 | ValType::I64) || ty.mutability() != Mutability::Var {
+        return Err(Error::msg(format!(
+            "{}: the tstack export is not a mutable i32 or i64 global",
+            function
+        )));
+    }
+    Ok(global)
+}
+
+// Returns the address the tstack global points to.
+fn tstack_top<T>(caller: &mut Caller<'_, T>, tstack: &Global) -> usize {
+    pointer(&tstack.get(&mut *caller)) as usize
+}
+
+// Points the tstack global at addr, keeping its type.
+fn set_tstack_top<T>(caller: &mut Caller<'_, T>, tstack: &Global, addr: usize) -> Result<()> {
+    let value = match tstack.get(&mut *caller) {
+        Val::I64(_) => Val::I64(addr as u64 as i64),
+        _ => Val::I32(addr as u32 as i32),
+    };
+    tstack.set(&mut *caller, value)
+}
+
+// Returns the range of len bytes at addr, checking that it is inside memory.
+pub fn range(data: GuestData<'_>, function: &str, addr: usize, len: usize) -> Result<Range<usize>> {
+    match addr.checked_add(len) {
+        Some(end) if end <= data.size() => Ok(addr..end),
+        _ => Err(Error::msg(format!(
+            "{}: {} bytes at {:#x} extend past the end of memory ({:#x} bytes)",
+            function,
+            len,
+            addr,
+            data.size()
+        ))),
+    }
+}
+
+// Copies N bytes out of memory at addr, e.g. for an integer.
+pub fn read<const N: usize>(data: GuestData<'_>, function: &str, addr: usize) -> Result<[u8; N]> {
+    let range = range(data, function, addr, N)?;
+    Ok(data.get(range).as_ref().try_into().unwrap())
+}
+
+// Copies bytes into memory at addr.
+pub fn write(data: &mut GuestDataMut<'_>, function: &str, addr: usize, bytes: &[u8]) -> Result<()> {
+    range(data.as_data(), function, addr, bytes.len())?;
+    data.set(addr, bytes);
+    Ok(())
+}
+
+// Copies bytes onto the tstack, advancing the tstack global past them, and
+// returns their address. Memory is grown to fit them if needed. If they would
+// reach past limit, e.g. into the host_alloc heap, or memory cannot grow,
+// because it is at its maximum, the guest is stopped with TstackOverflow.
+pub fn tstack_push<T>(
+    caller: &mut Caller<'_, T>,
+    function: &str,
+    bytes: &[u8],
+    limit: usize,
+) -> Result<u64> {
+    let memory = memory(caller, function)?;
+    let tstack = tstack(caller, function)?;
+    let current = tstack_top(caller, &tstack);
+    let end = reserve(caller, &memory, function, current, bytes.len(), limit)?;
+    write(&mut memory.data_mut(&mut *caller), function, current, bytes)?;
+    set_tstack_top(caller, &tstack, end)?;
+    Ok(current as u64)
+}
+
+// Returns how many bytes fit above the tstack, below limit.
+pub fn tstack_room<T>(caller: &mut Caller<'_, T>, function: &str, limit: usize) -> Result<usize> {
+    let tstack = tstack(caller, function)?;
+    Ok(limit.saturating_sub(tstack_top(caller, &tstack)))
+}
+
+// Returns the address of len bytes just above the tstack, 8-byte aligned,
+// which a host function may use without moving the tstack, until it pushes
+// something. Memory is grown to fit them as for tstack_push.
+pub fn tstack_scratch<T>(
+    caller: &mut Caller<'_, T>,
+    function: &str,
+    len: usize,
+    limit: usize,
+) -> Result<usize> {
+    let memory = memory(caller, function)?;
+    let tstack = tstack(caller, function)?;
+    let start = tstack_top(caller, &tstack).next_multiple_of(8);
+    reserve(caller, &memory, function, start, len, limit)?;
+    Ok(start)
+}
+
+// Grows memory to fit len bytes at start, on the tstack, and returns their
+// end.
+fn reserve<T>(
+    caller: &mut Caller<'_, T>,
+    memory: &GuestMemory,
+    function: &str,
+    start: usize,
+    len: usize,
+    limit: usize,
+) -> Result<usize> {
+    let overflow = || TstackOverflow {
+        function: function.to_string(),
+        len,
+        tstack: start,
+    };
+    let end = start
+        .checked_add(len)
+        .filter(|&end| end <= limit)
+        .ok_or_else(overflow)?;
+    let size = memory.data_size(&*caller);
+    if end > size {
+        let page_size = memory.page_size(&*caller) as usize;
+        let pages = (end - size).div_ceil(page_size) as u64;
+        if let Err(error) = memory.grow(&mut *caller, pages) {
+            // A limit set by the user is reported as such
+            if error.is::<MemoryLimitExceeded>() {
+                return Err(error);
+            }
+            return Err(overflow().into());
+        }
+    }
+    Ok(end)
+}
+
+// Error that stops the guest when data returned by a host function does not
+// fit on the tstack.
+#[derive(Debug)]
+pub struct TstackOverflow {
+    function: String,
+    len: usize,
+    tstack: usize,
+}
+
+impl fmt::Display for TstackOverflow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: tstack overflow: {} bytes at {:#x} do not fit on the tstack",
+            self.function, self.len, self.tstack
+        )
+    }
+}
+
+impl std::error::Error for TstackOverflow {}
@@ -0,0 +1,189 @@
+// OS-level hardening for --harden. Once the runtime is set up, a seccomp
+// filter makes the kernel refuse syscalls that no host function needs, such
+// as spawning processes or loading kernel modules, so a bug in a host
+// function cannot be turned into one of them.
+
+use wasmtime::Result;
+
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+mod linux {
+    use libc::*;
+
+    // From <linux/audit.h>; libc does not define these.
+    #[cfg(target_arch = "x86_64")]
+    pub const AUDIT_ARCH: u32 = 0xc000_003e;
+    #[cfg(target_arch = "aarch64")]
+    pub const AUDIT_ARCH: u32 = 0xc000_00b7;
+
+    // Offsets into struct seccomp_data. ARG0_OFFSET is the low half of the
+    // first argument.
+    pub const NR_OFFSET: u32 = 0;
+    pub const ARCH_OFFSET: u32 = 4;
+    pub const ARG0_OFFSET: u32 = 16;
+
+    // Set in the numbers of syscalls made with the x32 ABI, which has the
+    // same audit arch as x86_64 and would otherwise slip past the denylist.
+    #[cfg(target_arch = "x86_64")]
+    pub const X32_SYSCALL_BIT: u32 = 0x4000_0000;
+
+    #[cfg(target_arch = "x86_64")]
+    const ARCH_DENIED_SYSCALLS: &[c_long] = &[SYS_fork, SYS_vfork];
+    #[cfg(target_arch = "aarch64")]
+    const ARCH_DENIED_SYSCALLS: &[c_long] = &[];
+
+    pub const NETWORK_SYSCALLS: &[c_long] = &[
+        SYS_socket,
+        SYS_socketpair,
+        SYS_connect,
+        SYS_bind,
+        SYS_listen,
+        SYS_accept,
+        SYS_accept4,
+    ];
+
+    // libc's BPF_STMT and BPF_JUMP are needlessly unsafe.
+    pub fn stmt(code: u32, k: u32) -> sock_filter {
+        jump(code, k, 0, 0)
+    }
+
+    pub fn jump(code: u32, k: u32, jt: u8, jf: u8) -> sock_filter {
+        sock_filter {
+            code: code as u16,
+            jt,
+            jf,
+            k,
+        }
+    }
+
+    pub fn denied_syscalls() -> Vec<c_long> {
+        let mut syscalls = vec![
+            SYS_execve,
+            SYS_execveat,
+            SYS_ptrace,
+            SYS_process_vm_readv,
+            SYS_process_vm_writev,
+            SYS_mount,
+            SYS_umount2,
+            SYS_pivot_root,
+            SYS_chroot,
+            SYS_setns,
+            SYS_unshare,
+            SYS_setuid,
+            SYS_setreuid,
+            SYS_setresuid,
+            SYS_setfsuid,
+            SYS_setgid,
+            SYS_setregid,
+            SYS_setresgid,
+            SYS_setfsgid,
+            SYS_setgroups,
+            // io_uring can open sockets and files without the syscalls for
+            // them
+            SYS_io_uring_setup,
+            SYS_io_uring_enter,
+            SYS_io_uring_register,
+            SYS_kexec_load,
+            SYS_init_module,
+            SYS_finit_module,
+            SYS_delete_module,
+            SYS_reboot,
+            SYS_swapon,
+            SYS_swapoff,
+            SYS_bpf,
+            SYS_perf_event_open,
+            SYS_keyctl,
+            SYS_add_key,
+            SYS_request_key,
+        ];
+        syscalls.extend_from_slice(ARCH_DENIED_SYSCALLS);
+        syscalls
+    }
+}
+
+// Installs the filter for every thread of the process. Denied syscalls fail
+// with EPERM. Network syscalls are also denied unless allow_network is set.
+// x32 syscalls kill the process.
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+pub fn apply(allow_network: bool) -> Result<()> {
+    use libc::*;
+    use linux::*;
+
+    let mut denied = denied_syscalls();
+    if !allow_network {
+        denied.extend_from_slice(NETWORK_SYSCALLS);
+    }
+
+    let load = BPF_LD | BPF_W | BPF_ABS;
+    let jump_if_equal = BPF_JMP | BPF_JEQ | BPF_K;
+    let jump_if_set = BPF_JMP | BPF_JSET | BPF_K;
+    let ret = BPF_RET | BPF_K;
+    let deny = stmt(ret, SECCOMP_RET_ERRNO | EPERM as u32);
+    let mut filter = vec![
+        // Syscall numbers differ between architectures, so refuse to run
+        // anything built for another one
+        stmt(load, ARCH_OFFSET),
+        jump(jump_if_equal, AUDIT_ARCH, 1, 0),
+        stmt(ret, SECCOMP_RET_KILL_PROCESS),
+        stmt(load, NR_OFFSET),
+    ];
+    #[cfg(target_arch = "x86_64")]
+    filter.extend([
+        jump(jump_if_set, X32_SYSCALL_BIT, 0, 1),
+        stmt(ret, SECCOMP_RET_KILL_PROCESS),
+    ]);
+    filter.extend([
+        // clone may only start threads, for wasmtime and the host functions;
+        // a new process could run anything without execve
+        jump(jump_if_equal, SYS_clone as u32, 0, 4),
+        stmt(load, ARG0_OFFSET),
+        jump(jump_if_set, CLONE_THREAD as u32, 1, 0),
+        deny,
+        stmt(ret, SECCOMP_RET_ALLOW),
+        // clone3's flags are behind a pointer, which the filter cannot
+        // read. Without it, libc falls back to clone.
+        jump(jump_if_equal, SYS_clone3 as u32, 0, 1),
+        stmt(ret, SECCOMP_RET_ERRNO | ENOSYS as u32),
+    ]);
+    for syscall in denied {
+        filter.push(jump(jump_if_equal, syscall as u32, 0, 1));
+        filter.push(deny);
+    }
+    filter.push(stmt(ret, SECCOMP_RET_ALLOW));
+    let program = sock_fprog {
+        len: filter.len() as u16,
+        filter: filter.as_mut_ptr(),
+    };
+
+    // SAFETY: program points at filter, which outlives both calls
+    unsafe {
+        if prctl(PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) != 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        if syscall(
+            SYS_seccomp,
+            SECCOMP_SET_MODE_FILTER,
+            SECCOMP_FILTER_FLAG_TSYNC,
+            &program as *const sock_fprog,
+        ) != 0
+        {
+            return Err(std::io::Error::last_os_error().into());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+)))]
+pub fn apply(_allow_network: bool) -> Result<()> {
+    Err(wasmtime::Error::msg(
+        "--harden is not supported on this platform",
+    ))
+}
@@ -0,0 +1,207 @@
+// The heap behind host_alloc and host_free, for Zong programs to allocate
+// from before the language has an allocator of its own. The heap is a region
+// of the guest's memory starting where memory ended at the first host_alloc
+// call and reaching --heap-size bytes further. Memory grows as allocations
+// reach further into the region, and from then on the tstack may not grow
+// into it. Blocks are allocated first fit from a free list, which merges
+// neighbouring blocks as they are freed.
+
+use std::collections::{BTreeMap, HashMap};
+
+pub const DEFAULT_SIZE: usize = 64 << 20;
+
+// Alignment and size granularity of blocks, enough for any wasm value.
+const ALIGN: usize = 16;
+
+// Wasm32 addresses end at 4 GiB.
+const ADDRESS_SPACE: usize = 1 << 32;
+
+pub struct Heap {
+    size: usize,
+    // Start of the region, set by the first allocation.
+    base: Option<usize>,
+    // End of the part of the region in use; everything above it is free.
+    top: usize,
+    // Free blocks below top, by address, with their lengths.
+    free: BTreeMap<usize, usize>,
+    // Allocated blocks, by address.
+    allocated: HashMap<usize, Block>,
+    // Number of allocations so far.
+    allocations: u64,
+}
+
+// A block that has not been freed.
+#[derive(Clone, Copy)]
+pub struct Block {
+    pub addr: usize,
+    pub len: usize,
+    // Which allocation made the block, counting from 0, so that a block
+    // freed and allocated again at the same address is a different block.
+    pub serial: u64,
+}
+
+impl Heap {
+    pub fn new(size: usize) -> Heap {
+        Heap {
+            size,
+            base: None,
+            top: 0,
+            free: BTreeMap::new(),
+            allocated: HashMap::new(),
+            allocations: 0,
+        }
+    }
+
+    // Start of the region, if anything has been allocated.
+    pub fn base(&self) -> Option<usize> {
+        self.base
+    }
+
+    // End of the part of the region in use, which memory must reach.
+    pub fn top(&self) -> usize {
+        self.top
+    }
+
+    // Allocates a block of at least len bytes and returns its address, or
+    // None if the region has no room. memory_end is the size of memory, where
+    // the region starts if this is the first allocation.
+    pub fn alloc(&mut self, len: usize, memory_end: usize) -> Option<usize> {
+        let base = match self.base {
+            Some(base) => base,
+            None => {
+                let base = memory_end.next_multiple_of(ALIGN);
+                self.base = Some(base);
+                self.top = base;
+                base
+            }
+        };
+        let len = len.max(1).checked_next_multiple_of(ALIGN)?;
+        let fit = self
+            .free
+            .iter()
+            .find(|&(_, &free_len)| free_len >= len)
+            .map(|(&addr, &free_len)| (addr, free_len));
+        let addr = match fit {
+            Some((addr, free_len)) => {
+                self.free.remove(&addr);
+                if free_len > len {
+                    self.free.insert(addr + len, free_len - len);
+                }
+                addr
+            }
+            None => {
+                let end = self.top.checked_add(len)?;
+                if end > base.saturating_add(self.size) || end > ADDRESS_SPACE {
+                    return None;
+                }
+                let addr = self.top;
+                self.top = end;
+                addr
+            }
+        };
+        let serial = self.allocations;
+        self.allocations += 1;
+        self.allocated.insert(addr, Block { addr, len, serial });
+        Some(addr)
+    }
+
+    // The blocks not freed yet, by address.
+    pub fn outstanding(&self) -> Vec<Block> {
+        let mut blocks: Vec<Block> = self.allocated.values().copied().collect();
+        blocks.sort_by_key(|block| block.addr);
+        blocks
+    }
+
+    // Frees the block at addr, returning false if there is no such block.
+    pub fn free(&mut self, addr: usize) -> bool {
+        let Some(Block { mut len, .. }) = self.allocated.remove(&addr) else {
+            return false;
+        };
+        let mut addr = addr;
+        if let Some(next_len) = self.free.remove(&(addr + len)) {
+            len += next_len;
+        }
+        if let Some((&previous, &previous_len)) = self.free.range(..addr).next_back() {
+            if previous + previous_len == addr {
+                self.free.remove(&previous);
+                addr = previous;
+                len += previous_len;
+            }
+        }
+        if addr + len == self.top {
+            self.top = addr;
+        } else {
+            self.free.insert(addr, len);
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allocates_aligned_blocks_from_the_end_of_memory() {
+        let mut heap = Heap::new(1024);
+        assert_eq!(heap.alloc(1, 100), Some(112));
+        assert_eq!(heap.base(), Some(112));
+        assert_eq!(heap.alloc(17, 100), Some(128));
+        assert_eq!(heap.alloc(0, 100), Some(160));
+        assert_eq!(heap.top(), 176);
+    }
+
+    #[test]
+    fn fails_when_the_region_is_full() {
+        let mut heap = Heap::new(64);
+        assert_eq!(heap.alloc(48, 0), Some(0));
+        assert_eq!(heap.alloc(32, 0), None);
+        assert_eq!(heap.alloc(16, 0), Some(48));
+        assert_eq!(heap.alloc(usize::MAX, 0), None);
+    }
+
+    #[test]
+    fn reuses_freed_blocks_first_fit() {
+        let mut heap = Heap::new(1024);
+        let a = heap.alloc(32, 0).unwrap();
+        let b = heap.alloc(16, 0).unwrap();
+        let _c = heap.alloc(16, 0).unwrap();
+        assert!(heap.free(a));
+        assert!(!heap.free(a));
+        assert!(heap.free(b));
+        // a and b merged, so 48 bytes fit where they were
+        assert_eq!(heap.alloc(48, 0), Some(a));
+        assert_eq!(heap.alloc(16, 0), Some(64));
+    }
+
+    #[test]
+    fn freeing_the_last_blocks_lowers_the_top() {
+        let mut heap = Heap::new(1024);
+        let a = heap.alloc(16, 0).unwrap();
+        let b = heap.alloc(16, 0).unwrap();
+        let c = heap.alloc(16, 0).unwrap();
+        assert_eq!(heap.top(), 48);
+        assert!(heap.free(b));
+        assert_eq!(heap.top(), 48);
+        assert!(heap.free(c));
+        assert_eq!(heap.top(), 16);
+        assert!(heap.free(a));
+        assert_eq!(heap.top(), 0);
+        assert_eq!(heap.alloc(64, 0), Some(0));
+    }
+
+    #[test]
+    fn outstanding_blocks_have_serials() {
+        let mut heap = Heap::new(1024);
+        let a = heap.alloc(16, 0).unwrap();
+        heap.alloc(32, 0).unwrap();
+        heap.free(a);
+        heap.alloc(16, 0).unwrap();
+        let blocks: Vec<(usize, usize, u64)> = heap
+            .outstanding()
+            .iter()
+            .map(|block| (block.addr, block.len, block.serial))
+            .collect();
+        assert_eq!(blocks, [(0, 16, 2), (16, 32, 1)]);
+    }
+}
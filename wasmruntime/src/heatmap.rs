@@ -0,0 +1,184 @@
+// Memory access heatmaps for --heatmap. Before compiling, every load and
+// store in the module is instrumented with a call to a host import that
+// records its effective address. Accesses are counted per BUCKET_SIZE range
+// of addresses, and the report labels each range with the region it falls
+// in: static data below the initial tstack, the tstack up to the highest it
+// grew, or the heap above that.
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+use walrus::ir::{Call, Const, Instr, InstrSeq, LocalGet, LocalSet, StoreKind, Value, VisitorMut};
+use walrus::{FunctionId, InstrLocId, LocalId, ValType};
+use wasmtime::Result;
+
+pub const IMPORT_MODULE: &str = "zong_heatmap";
+pub const IMPORT_NAME: &str = "record";
+
+const BUCKET_SIZE: u64 = 4096;
+const BAR_WIDTH: u64 = 40;
+
+// Temporaries holding a memory instruction's operands while the address is
+// recorded.
+struct Temporaries {
+    address: LocalId,
+    i32: LocalId,
+    i64: LocalId,
+    f32: LocalId,
+    f64: LocalId,
+    v128: LocalId,
+}
+
+// Inserts record(address, offset, is_store) before each load and store.
+struct RecordAccesses {
+    record: FunctionId,
+    temporaries: Temporaries,
+}
+
+impl VisitorMut for RecordAccesses {
+    fn start_instr_seq_mut(&mut self, seq: &mut InstrSeq) {
+        let instrs = std::mem::take(&mut seq.instrs);
+        for (instr, loc) in instrs {
+            let access = match &instr {
+                Instr::Load(load) => Some((load.arg.offset, None)),
+                Instr::Store(store) => {
+                    Some((store.arg.offset, Some(self.value_temporary(store.kind))))
+                }
+                _ => None,
+            };
+            if let Some((offset, value)) = access {
+                let address = self.temporaries.address;
+                let mut record = Vec::new();
+                if let Some(value) = value {
+                    record.push(Instr::LocalSet(LocalSet { local: value }));
+                }
+                record.extend([
+                    Instr::LocalSet(LocalSet { local: address }),
+                    Instr::LocalGet(LocalGet { local: address }),
+                    Instr::Const(Const {
+                        value: Value::I32(offset as i32),
+                    }),
+                    Instr::Const(Const {
+                        value: Value::I32(value.is_some() as i32),
+                    }),
+                    Instr::Call(Call { func: self.record }),
+                    Instr::LocalGet(LocalGet { local: address }),
+                ]);
+                if let Some(value) = value {
+                    record.push(Instr::LocalGet(LocalGet { local: value }));
+                }
+                seq.instrs.extend(
+                    record
+                        .into_iter()
+                        .map(|instr| (instr, InstrLocId::default())),
+                );
+            }
+            seq.instrs.push((instr, loc));
+        }
+    }
+}
+
+impl RecordAccesses {
+    fn value_temporary(&self, kind: StoreKind) -> LocalId {
+        match kind {
+            StoreKind::I32 { .. } | StoreKind::I32_8 { .. } | StoreKind::I32_16 { .. } => {
+                self.temporaries.i32
+            }
+            StoreKind::I64 { .. }
+            | StoreKind::I64_8 { .. }
+            | StoreKind::I64_16 { .. }
+            | StoreKind::I64_32 { .. } => self.temporaries.i64,
+            StoreKind::F32 => self.temporaries.f32,
+            StoreKind::F64 => self.temporaries.f64,
+            StoreKind::V128 => self.temporaries.v128,
+        }
+    }
+}
+
+pub fn instrument(wasm_bytes: &[u8]) -> Result<Vec<u8>> {
+    let wasm_bytes = wat::parse_bytes(wasm_bytes)?;
+    let mut module = walrus::Module::from_buffer(&wasm_bytes)?;
+    let record_type = module
+        .types
+        .add(&[ValType::I32, ValType::I32, ValType::I32], &[]);
+    let (record, _) = module.add_import_func(IMPORT_MODULE, IMPORT_NAME, record_type);
+    let mut visitor = RecordAccesses {
+        record,
+        temporaries: Temporaries {
+            address: module.locals.add(ValType::I32),
+            i32: module.locals.add(ValType::I32),
+            i64: module.locals.add(ValType::I64),
+            f32: module.locals.add(ValType::F32),
+            f64: module.locals.add(ValType::F64),
+            v128: module.locals.add(ValType::V128),
+        },
+    };
+    for (_, func) in module.funcs.iter_local_mut() {
+        walrus::ir::dfs_pre_order_mut(&mut visitor, func, func.entry_block());
+    }
+    Ok(module.emit_wasm())
+}
+
+#[derive(Default)]
+pub struct Heatmap {
+    // (loads, stores) by bucket start address.
+    buckets: BTreeMap<u64, (u64, u64)>,
+    // Initial tstack pointer, where static data ends.
+    pub data_end: u64,
+    tstack_high: u64,
+}
+
+impl Heatmap {
+    pub fn record(&mut self, address: u32, offset: u32, is_store: bool, tstack: u64) {
+        let address = address as u64 + offset as u64;
+        let bucket = self
+            .buckets
+            .entry(address / BUCKET_SIZE * BUCKET_SIZE)
+            .or_default();
+        if is_store {
+            bucket.1 += 1;
+        } else {
+            bucket.0 += 1;
+        }
+        self.tstack_high = self.tstack_high.max(tstack);
+    }
+
+    fn region(&self, address: u64) -> &'static str {
+        if address < self.data_end {
+            "data"
+        } else if address < self.tstack_high {
+            "tstack"
+        } else {
+            "heap"
+        }
+    }
+
+    pub fn write_report(&self, path: &Path) -> Result<()> {
+        let hottest = self
+            .buckets
+            .values()
+            .map(|(loads, stores)| loads + stores)
+            .max()
+            .unwrap_or(0);
+        let mut report = format!(
+            "{:<8} {:<21} {:>12} {:>12}\n",
+            "region", "range", "loads", "stores"
+        );
+        for (start, (loads, stores)) in &self.buckets {
+            let bar = "#".repeat(((loads + stores) * BAR_WIDTH).div_ceil(hottest) as usize);
+            writeln!(
+                report,
+                "{:<8} {:#010x}-{:#010x} {:>12} {:>12} {}",
+                self.region(*start),
+                start,
+                start + BUCKET_SIZE - 1,
+                loads,
+                stores,
+                bar
+            )?;
+        }
+        fs::write(path, report)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,419 @@
+// The guest's standard streams. HostState holds a HostIo, and every host
+// function that reads stdin or writes stdout or stderr goes through it, as
+// does WASI, so a run can take its input from a file, memory, or any reader
+// and send its output to a file, a buffer, or any writer as well as to the
+// process's own streams.
+//
+// Reads of the process's stdin can be made interruptible, so that a guest
+// waiting for input still stops for a signal or --timeout.
+//
+// Stdout is buffered, and shared between the Zong print functions and WASI's
+// fd_write so that their output stays in order. Stderr is not buffered, and
+// stdout is flushed before each write to it so that the two streams
+// interleave in the order the guest wrote them.
+//
+// Bytes reach both streams unchanged, with no newline translation, so a
+// guest can write binary data like an image to a pipe or file. On Windows
+// that means writing to the stream's handle rather than through io::stdout()
+// unless it is a console, as with O_BINARY.
+
+use bytes::Bytes;
+use std::fs::OpenOptions;
+use std::io::{self, BufRead, BufReader, BufWriter, IoSlice, IsTerminal, Read, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use wasmtime::{Error, Result};
+use wasmtime_wasi::{
+    HostInputStream, HostOutputStream, StdinStream, StdoutStream, StreamError, StreamResult,
+    Subscribe,
+};
+
+const STDOUT_BUFFER_SIZE: usize = 1 << 16;
+
+/// Where a program's stdin comes from and its stdout and stderr go. The
+/// default is the process's own streams.
+pub struct HostIo {
+    stdin: Input,
+    stdout: Output,
+    stderr: Output,
+}
+
+enum Input {
+    // The process's stdin.
+    Process,
+    // A reader, e.g. contents fixed by --stdin or a repro manifest. Shared
+    // with WASI's stdin, so both read from the same position.
+    Reader(SharedReader),
+    // The process's stdin read through a reader, e.g. one recording it for
+    // --record. It is waited on like the process's stdin.
+    ProcessReader(SharedReader),
+}
+
+// The process's stdin, read from its file descriptor rather than through
+// io::stdin()'s buffer, so that each read can first wait for input and give up
+// once interrupted returns true.
+struct InterruptibleStdin {
+    interrupted: Box<dyn Fn() -> bool + Send>,
+}
+
+type SharedReader = Arc<Mutex<BufReader<Box<dyn Read + Send>>>>;
+
+type SharedWriter = Arc<Mutex<BufWriter<Box<dyn Write + Send>>>>;
+
+// An output stream. Clones write to the same stream.
+#[derive(Clone)]
+pub struct Output {
+    writer: SharedWriter,
+    isatty: bool,
+    // Flushed before each write, for WASI's stderr.
+    flush_first: Option<SharedWriter>,
+}
+
+// WASI's view of a reader stdin.
+struct InputStream(SharedReader);
+
+/// Output collected in memory by HostIo::capture_stdout.
+#[derive(Clone, Default)]
+pub struct Captured(Arc<Mutex<Vec<u8>>>);
+
+impl Default for HostIo {
+    // The process's streams.
+    fn default() -> HostIo {
+        HostIo {
+            stdin: Input::Process,
+            stdout: Output::new(
+                process_stream(io::stdout()),
+                STDOUT_BUFFER_SIZE,
+                io::stdout().is_terminal(),
+            ),
+            stderr: Output::new(process_stream(io::stderr()), 0, io::stderr().is_terminal()),
+        }
+    }
+}
+
+impl HostIo {
+    /// Gives the program these bytes as its stdin.
+    pub fn set_stdin(&mut self, bytes: Vec<u8>) {
+        self.set_stdin_reader(io::Cursor::new(bytes));
+    }
+
+    /// Reads the program's stdin from reader.
+    pub fn set_stdin_reader(&mut self, reader: impl Read + Send + 'static) {
+        self.stdin = Input::Reader(shared_reader(reader));
+    }
+
+    // Reads the process's stdin through reader, which wraps it.
+    pub fn set_process_stdin_reader(&mut self, reader: impl Read + Send + 'static) {
+        self.stdin = Input::ProcessReader(shared_reader(reader));
+    }
+
+    /// Writes the program's stdout to writer, through a buffer that is
+    /// flushed when the program reads input or stops.
+    pub fn set_stdout(&mut self, writer: impl Write + Send + 'static) {
+        self.stdout = Output::new(Box::new(writer), STDOUT_BUFFER_SIZE, false);
+    }
+
+    /// Writes the program's stderr to writer, unbuffered.
+    pub fn set_stderr(&mut self, writer: impl Write + Send + 'static) {
+        self.stderr = Output::new(Box::new(writer), 0, false);
+    }
+
+    /// Collects the program's stdout in memory, returning the buffer.
+    pub fn capture_stdout(&mut self) -> Captured {
+        let captured = Captured::default();
+        self.set_stdout(captured.clone());
+        captured
+    }
+
+    /// Collects the program's stderr in memory, returning the buffer.
+    pub fn capture_stderr(&mut self) -> Captured {
+        let captured = Captured::default();
+        self.set_stderr(captured.clone());
+        captured
+    }
+
+    // Sends stdout to a file, for --stdout, replacing or appending to its
+    // contents.
+    pub fn stdout_to_file(&mut self, path: &Path, append: bool) -> Result<()> {
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .append(append)
+            .truncate(!append)
+            .open(path)
+            .map_err(|error| Error::new(error).context(path.display().to_string()))?;
+        self.set_stdout(file);
+        Ok(())
+    }
+
+    // Makes reads of the process's stdin fail once interrupted returns true,
+    // instead of blocking until input arrives. Stdin from a reader is left
+    // alone.
+    pub fn interrupt_stdin(&mut self, interrupted: impl Fn() -> bool + Send + 'static) {
+        if let Input::Process = self.stdin {
+            self.set_process_stdin_reader(InterruptibleStdin {
+                interrupted: Box::new(interrupted),
+            });
+        }
+    }
+
+    // Reads stdin with read, after flushing stdout so that a prompt printed
+    // without a newline is visible.
+    pub fn read_stdin<R>(
+        &mut self,
+        read: impl FnOnce(&mut dyn BufRead) -> io::Result<R>,
+    ) -> io::Result<R> {
+        self.stdout.flush()?;
+        match &mut self.stdin {
+            Input::Process => read(&mut io::stdin().lock()),
+            Input::Reader(reader) | Input::ProcessReader(reader) => {
+                read(&mut *reader.lock().unwrap())
+            }
+        }
+    }
+
+    // Whether stdin is the process's, which can be waited on, rather than a
+    // reader, which is taken to be always ready.
+    pub fn stdin_is_process(&self) -> bool {
+        matches!(self.stdin, Input::Process | Input::ProcessReader(_))
+    }
+
+    pub fn write_stdout(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.stdout.write_all(bytes)
+    }
+
+    // Writes the fragments to stdout in order. Fragments that fit in the
+    // buffer are copied into it, and larger writes go to the stream as one
+    // vectored write where it supports them.
+    pub fn write_stdout_vectored(&mut self, fragments: &[&[u8]]) -> io::Result<()> {
+        self.stdout.write_all_vectored(fragments)
+    }
+
+    pub fn write_stderr(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.stdout.flush()?;
+        self.stderr.write_all(bytes)
+    }
+
+    pub fn flush_stdout(&mut self) -> io::Result<()> {
+        self.stdout.flush()
+    }
+
+    // Another handle to the same streams, for threads started with spawn.
+    pub fn share(&self) -> HostIo {
+        let stdin = match &self.stdin {
+            Input::Process => Input::Process,
+            Input::Reader(reader) => Input::Reader(reader.clone()),
+            Input::ProcessReader(reader) => Input::ProcessReader(reader.clone()),
+        };
+        HostIo {
+            stdin,
+            stdout: self.stdout.clone(),
+            stderr: self.stderr.clone(),
+        }
+    }
+
+    // A handle to stdout, for modules loaded with load_module.
+    pub fn stdout(&self) -> Output {
+        self.stdout.clone()
+    }
+
+    pub fn stdout_is_terminal(&self) -> bool {
+        self.stdout.isatty
+    }
+
+    pub fn stderr_is_terminal(&self) -> bool {
+        self.stderr.isatty
+    }
+
+    // Whether stdin is the process's and a terminal.
+    pub fn stdin_is_terminal(&self) -> bool {
+        self.stdin_is_process() && io::stdin().is_terminal()
+    }
+
+    // The streams for WASI. Stdin is None if it is the process's, unwrapped,
+    // which WASI reads itself.
+    pub fn wasi_stdin(&self) -> Option<impl StdinStream> {
+        match &self.stdin {
+            Input::Process => None,
+            Input::Reader(reader) | Input::ProcessReader(reader) => {
+                Some(InputStream(reader.clone()))
+            }
+        }
+    }
+
+    pub fn wasi_stdout(&self) -> impl StdoutStream {
+        self.stdout.clone()
+    }
+
+    pub fn wasi_stderr(&self) -> impl StdoutStream {
+        Output {
+            flush_first: Some(self.stdout.writer.clone()),
+            ..self.stderr.clone()
+        }
+    }
+}
+
+// A writer for the process's stdout or stderr that writes bytes as is.
+#[cfg(windows)]
+fn process_stream<S>(stream: S) -> Box<dyn Write + Send>
+where
+    S: Write + Send + IsTerminal + std::os::windows::io::AsHandle + 'static,
+{
+    if stream.is_terminal() {
+        return Box::new(stream);
+    }
+    match stream.as_handle().try_clone_to_owned() {
+        Ok(handle) => Box::new(std::fs::File::from(handle)),
+        Err(_) => Box::new(stream),
+    }
+}
+
+#[cfg(not(windows))]
+fn process_stream(stream: impl Write + Send + 'static) -> Box<dyn Write + Send> {
+    Box::new(stream)
+}
+
+fn shared_reader(reader: impl Read + Send + 'static) -> SharedReader {
+    let reader: Box<dyn Read + Send> = Box::new(reader);
+    Arc::new(Mutex::new(BufReader::new(reader)))
+}
+
+impl Read for InterruptibleStdin {
+    #[cfg(unix)]
+    fn read(&mut self, bytes: &mut [u8]) -> io::Result<usize> {
+        crate::events::wait_readable(crate::events::STDIN_FD, &*self.interrupted)?;
+        // SAFETY: bytes is valid for writes of its length
+        let count =
+            unsafe { libc::read(libc::STDIN_FILENO, bytes.as_mut_ptr().cast(), bytes.len()) };
+        if count < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(count as usize)
+    }
+
+    // Without poll(2), reads block as usual.
+    #[cfg(not(unix))]
+    fn read(&mut self, bytes: &mut [u8]) -> io::Result<usize> {
+        io::stdin().read(bytes)
+    }
+}
+
+impl Output {
+    fn new(writer: Box<dyn Write + Send>, buffer_size: usize, isatty: bool) -> Output {
+        Output {
+            writer: Arc::new(Mutex::new(BufWriter::with_capacity(buffer_size, writer))),
+            isatty,
+            flush_first: None,
+        }
+    }
+
+    pub fn write_all(&self, bytes: &[u8]) -> io::Result<()> {
+        if let Some(first) = &self.flush_first {
+            first.lock().unwrap().flush()?;
+        }
+        self.writer.lock().unwrap().write_all(bytes)
+    }
+
+    fn write_all_vectored(&self, fragments: &[&[u8]]) -> io::Result<()> {
+        if let Some(first) = &self.flush_first {
+            first.lock().unwrap().flush()?;
+        }
+        let mut slices: Vec<IoSlice> = fragments
+            .iter()
+            .map(|fragment| IoSlice::new(fragment))
+            .collect();
+        let mut slices = slices.as_mut_slice();
+        IoSlice::advance_slices(&mut slices, 0);
+        let mut writer = self.writer.lock().unwrap();
+        while !slices.is_empty() {
+            match writer.write_vectored(slices) {
+                Ok(0) => return Err(io::Error::from(io::ErrorKind::WriteZero)),
+                Ok(written) => IoSlice::advance_slices(&mut slices, written),
+                Err(error) if error.kind() == io::ErrorKind::Interrupted => {}
+                Err(error) => return Err(error),
+            }
+        }
+        Ok(())
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        self.writer.lock().unwrap().flush()
+    }
+}
+
+impl StdoutStream for Output {
+    fn stream(&self) -> Box<dyn HostOutputStream> {
+        Box::new(self.clone())
+    }
+
+    fn isatty(&self) -> bool {
+        self.isatty
+    }
+}
+
+#[wasmtime_wasi::async_trait]
+impl Subscribe for Output {
+    async fn ready(&mut self) {}
+}
+
+impl HostOutputStream for Output {
+    fn write(&mut self, bytes: Bytes) -> StreamResult<()> {
+        self.write_all(&bytes)
+            .map_err(|error| StreamError::LastOperationFailed(error.into()))
+    }
+
+    fn flush(&mut self) -> StreamResult<()> {
+        Output::flush(self).map_err(|error| StreamError::LastOperationFailed(error.into()))
+    }
+
+    fn check_write(&mut self) -> StreamResult<usize> {
+        Ok(1 << 20)
+    }
+}
+
+impl StdinStream for InputStream {
+    fn stream(&self) -> Box<dyn HostInputStream> {
+        Box::new(InputStream(self.0.clone()))
+    }
+
+    fn isatty(&self) -> bool {
+        false
+    }
+}
+
+#[wasmtime_wasi::async_trait]
+impl Subscribe for InputStream {
+    async fn ready(&mut self) {}
+}
+
+impl HostInputStream for InputStream {
+    fn read(&mut self, size: usize) -> StreamResult<Bytes> {
+        let mut bytes = vec![0; size];
+        match self.0.lock().unwrap().read(&mut bytes) {
+            Ok(0) if size > 0 => Err(StreamError::Closed),
+            Ok(count) => {
+                bytes.truncate(count);
+                Ok(Bytes::from(bytes))
+            }
+            Err(error) => Err(StreamError::LastOperationFailed(error.into())),
+        }
+    }
+}
+
+impl Captured {
+    /// The output so far.
+    pub fn contents(&self) -> Vec<u8> {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+impl Write for Captured {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(bytes);
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,30 @@
+// HTTP requests for http_get. Requests block the guest until the whole
+// response has arrived; https URLs are checked against the bundled root
+// certificates.
+
+use std::io::Read;
+use std::time::Duration;
+
+// How long to wait for a connection and then for each read.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+// Fetches url and returns the response's status code and body. Error
+// statuses are responses like any other; only failing to get a response at
+// all, e.g. a malformed URL or a refused connection, is an error.
+pub fn get(url: &str) -> Result<(u16, Vec<u8>), String> {
+    let agent = ureq::AgentBuilder::new()
+        .timeout_connect(TIMEOUT)
+        .timeout_read(TIMEOUT)
+        .build();
+    let response = match agent.get(url).call() {
+        Ok(response) | Err(ureq::Error::Status(_, response)) => response,
+        Err(error) => return Err(error.to_string()),
+    };
+    let status = response.status();
+    let mut body = Vec::new();
+    response
+        .into_reader()
+        .read_to_end(&mut body)
+        .map_err(|error| error.to_string())?;
+    Ok((status, body))
+}
@@ -0,0 +1,299 @@
+// `wasmruntime inspect` on a module: prints what the runtime cares about in
+// it, for finding why compiler output and the runtime disagree without
+// reaching for wasm-objdump. Memory dumps go to dump::inspect instead.
+//
+//   prog.wasm: 1234 bytes, Zong ABI version 1
+//   imports:
+//     env.print: func (i32) -> ()
+//   exports:
+//     memory: memory 0
+//     tstack: global 0
+//     main: func 1 () -> ()
+//   memories:
+//     0: 2 pages, no maximum
+//   globals:
+//     0: mutable i32 = 65536
+//   data segments:
+//     0: 12 bytes at 1024 in memory 0
+//   custom sections:
+//     name: 31 bytes
+//     zong.abi: 1 bytes, version 1
+//   functions:
+//     1 main: 45 bytes of code
+//
+// Functions are numbered as the module's code refers to them, so imports
+// come first. Names come from the name section; functions without one also
+// go by the export names that refer to them.
+
+use crate::{abi, dump, exitcode, read_module, sourcemap};
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+use wasmparser::{
+    CompositeInnerType, ConstExpr, DataKind, ExternalKind, FuncType, KnownCustom, Name, Operator,
+    Parser, Payload, TypeRef,
+};
+use wasmtime::Result;
+
+pub fn run(path: &Path, address: Option<usize>) -> Result<i32> {
+    let bytes = fs::read(path).map_err(|error| exitcode::unreadable(path.display(), error))?;
+    if bytes.starts_with(b"zong-dump ") {
+        return dump::inspect(path, address);
+    }
+    if address.is_some() {
+        return Err(exitcode::usage(
+            "an address can only be given with a memory dump, not a module",
+        ));
+    }
+    let wasm_bytes = read_module(path)?;
+    print!("{}", describe(&path.display().to_string(), &wasm_bytes)?);
+    Ok(0)
+}
+
+fn describe(name: &str, wasm_bytes: &[u8]) -> Result<String> {
+    let mut types = Vec::new();
+    // Type index of each function, imported ones first
+    let mut functions = Vec::new();
+    let mut imports = Vec::new();
+    let mut exports = Vec::new();
+    let mut memories = Vec::new();
+    let mut globals = Vec::new();
+    let mut data = Vec::new();
+    let mut custom = Vec::new();
+    let mut code_sizes = Vec::new();
+    let mut names = HashMap::new();
+    for payload in Parser::new(0).parse_all(wasm_bytes) {
+        match payload? {
+            Payload::TypeSection(reader) => {
+                for group in reader {
+                    for ty in group?.into_types() {
+                        types.push(match ty.composite_type.inner {
+                            CompositeInnerType::Func(ty) => Some(ty),
+                            _ => None,
+                        });
+                    }
+                }
+            }
+            Payload::ImportSection(reader) => {
+                for import in reader {
+                    let import = import?;
+                    let ty = match import.ty {
+                        TypeRef::Func(index) => {
+                            functions.push(index);
+                            format!("func {}", signature(&types, index))
+                        }
+                        TypeRef::Memory(ty) => {
+                            memories.push(format!("{}, imported", memory(&ty)));
+                            format!("memory {}", memories.len() - 1)
+                        }
+                        TypeRef::Global(ty) => {
+                            globals.push(format!("{}, imported", global_type(&ty)));
+                            format!("global {}", globals.len() - 1)
+                        }
+                        TypeRef::Table(_) => String::from("table"),
+                        TypeRef::Tag(_) => String::from("tag"),
+                    };
+                    imports.push(format!("{}.{}: {}", import.module, import.name, ty));
+                }
+            }
+            Payload::FunctionSection(reader) => {
+                for index in reader {
+                    functions.push(index?);
+                }
+            }
+            Payload::MemorySection(reader) => {
+                for ty in reader {
+                    memories.push(memory(&ty?));
+                }
+            }
+            Payload::GlobalSection(reader) => {
+                for global in reader {
+                    let global = global?;
+                    globals.push(format!(
+                        "{} = {}",
+                        global_type(&global.ty),
+                        const_expr(&global.init_expr)
+                    ));
+                }
+            }
+            Payload::ExportSection(reader) => {
+                for export in reader {
+                    let export = export?;
+                    exports.push((export.name.to_string(), export.kind, export.index));
+                }
+            }
+            Payload::DataSection(reader) => {
+                for segment in reader {
+                    let segment = segment?;
+                    data.push(match segment.kind {
+                        DataKind::Passive => format!("{} bytes, passive", segment.data.len()),
+                        DataKind::Active {
+                            memory_index,
+                            offset_expr,
+                        } => format!(
+                            "{} bytes at {} in memory {}",
+                            segment.data.len(),
+                            const_expr(&offset_expr),
+                            memory_index
+                        ),
+                    });
+                }
+            }
+            Payload::CodeSectionEntry(body) => code_sizes.push(body.range().len()),
+            Payload::CustomSection(reader) => {
+                if let KnownCustom::Name(reader) = reader.as_known() {
+                    for name in reader {
+                        if let Name::Function(map) = name? {
+                            for naming in map {
+                                let naming = naming?;
+                                names.insert(naming.index, naming.name.to_string());
+                            }
+                        }
+                    }
+                }
+                custom.push(custom_section(reader.name(), reader.data()));
+            }
+            _ => {}
+        }
+    }
+
+    let version = match abi::version(wasm_bytes) {
+        Ok(version) => format!("Zong ABI version {}", version.number()),
+        Err(error) => format!("{:#}", error),
+    };
+    let mut out = String::new();
+    writeln!(out, "{}: {} bytes, {}", name, wasm_bytes.len(), version)?;
+    let mut list = |heading: &str, lines: Vec<String>| {
+        if !lines.is_empty() {
+            writeln!(out, "{}:", heading)?;
+            for line in lines {
+                writeln!(out, "  {}", line)?;
+            }
+        }
+        std::fmt::Result::Ok(())
+    };
+    list("imports", imports)?;
+    list(
+        "exports",
+        exports
+            .iter()
+            .map(|(name, kind, index)| match kind {
+                ExternalKind::Func => format!(
+                    "{}: func {} {}",
+                    name,
+                    index,
+                    match functions.get(*index as usize) {
+                        Some(&ty) => signature(&types, ty),
+                        None => String::from("(unknown type)"),
+                    }
+                ),
+                ExternalKind::Memory => format!("{}: memory {}", name, index),
+                ExternalKind::Global => format!("{}: global {}", name, index),
+                ExternalKind::Table => format!("{}: table {}", name, index),
+                ExternalKind::Tag => format!("{}: tag {}", name, index),
+            })
+            .collect(),
+    )?;
+    let numbered = |items: Vec<String>| -> Vec<String> {
+        items
+            .into_iter()
+            .enumerate()
+            .map(|(index, item)| format!("{}: {}", index, item))
+            .collect()
+    };
+    list("memories", numbered(memories))?;
+    list("globals", numbered(globals))?;
+    list("data segments", numbered(data))?;
+    list("custom sections", custom)?;
+    let imported = functions.len() - code_sizes.len();
+    let function_name = |index: u32| {
+        names.get(&index).cloned().or_else(|| {
+            exports
+                .iter()
+                .find(|(_, kind, export)| *kind == ExternalKind::Func && *export == index)
+                .map(|(name, _, _)| name.clone())
+        })
+    };
+    list(
+        "functions",
+        code_sizes
+            .iter()
+            .enumerate()
+            .map(|(defined, size)| {
+                let index = (imported + defined) as u32;
+                match function_name(index) {
+                    Some(name) => format!("{} {}: {} bytes of code", index, name, size),
+                    None => format!("{}: {} bytes of code", index, size),
+                }
+            })
+            .collect(),
+    )?;
+    Ok(out)
+}
+
+// The signature of a function with this type index, as (i32, i64) -> (i32).
+fn signature(types: &[Option<FuncType>], index: u32) -> String {
+    let Some(Some(ty)) = types.get(index as usize) else {
+        return format!("(type {})", index);
+    };
+    let list = |types: &[wasmparser::ValType]| {
+        let types: Vec<String> = types.iter().map(|ty| ty.to_string()).collect();
+        format!("({})", types.join(", "))
+    };
+    format!("{} -> {}", list(ty.params()), list(ty.results()))
+}
+
+fn memory(ty: &wasmparser::MemoryType) -> String {
+    let mut description = match ty.maximum {
+        Some(maximum) => format!("{} pages, at most {}", ty.initial, maximum),
+        None => format!("{} pages, no maximum", ty.initial),
+    };
+    if ty.memory64 {
+        description.push_str(", 64-bit");
+    }
+    if ty.shared {
+        description.push_str(", shared");
+    }
+    description
+}
+
+fn global_type(ty: &wasmparser::GlobalType) -> String {
+    let mutability = if ty.mutable { "mutable" } else { "immutable" };
+    format!("{} {}", mutability, ty.content_type)
+}
+
+// The value of a constant expression of a single instruction, or "(expr)".
+fn const_expr(expr: &ConstExpr) -> String {
+    let operators: Vec<Operator> = match expr.get_operators_reader().into_iter().collect() {
+        Ok(operators) => operators,
+        Err(_) => return String::from("(invalid)"),
+    };
+    match operators.as_slice() {
+        [Operator::I32Const { value }, Operator::End] => value.to_string(),
+        [Operator::I64Const { value }, Operator::End] => value.to_string(),
+        [Operator::F32Const { value }, Operator::End] => f32::from_bits(value.bits()).to_string(),
+        [Operator::F64Const { value }, Operator::End] => f64::from_bits(value.bits()).to_string(),
+        [Operator::GlobalGet { global_index }, Operator::End] => {
+            format!("global {}", global_index)
+        }
+        _ => String::from("(expr)"),
+    }
+}
+
+// A custom section's name and size, with what the runtime reads from the
+// Zong ones.
+fn custom_section(name: &str, data: &[u8]) -> String {
+    let detail = match name {
+        abi::SECTION_NAME => Some(format!("version {}", String::from_utf8_lossy(data).trim())),
+        sourcemap::SECTION_NAME => Some(match sourcemap::SourceMap::parse(data) {
+            Ok(map) => map.summary(),
+            Err(error) => format!("invalid: {:#}", error),
+        }),
+        _ => None,
+    };
+    match detail {
+        Some(detail) => format!("{}: {} bytes, {}", name, data.len(), detail),
+        None => format!("{}: {} bytes", name, data.len()),
+    }
+}
@@ -0,0 +1,81 @@
+// The env_i128 import module: 128-bit integer operations, so the Zong
+// compiler can support a wider integer type before it lowers the arithmetic
+// itself. Each operand and result is a signed i128 in guest memory, as its
+// two i64 halves [low: i64 at offset 0, high: i64 at offset 8]:
+//
+//   i128_mul(a_ptr, b_ptr, dest_ptr) writes a * b, wrapping on overflow as
+//     i64.mul does.
+//   i128_divmod(a_ptr, b_ptr, quotient_ptr, remainder_ptr) writes a / b,
+//     rounded toward zero, and the remainder, which has the sign of a. It
+//     traps on division by zero, or on the overflow of the minimum divided
+//     by -1, as i64.div_s does.
+//   i128_to_string(value_ptr, dest_slice_ptr) writes the value in decimal as
+//     a slice on the tstack, as i64_to_string does.
+
+use crate::{guest, slice_abi, HostState};
+use wasmtime::{Caller, Error, Linker, Result};
+
+pub const IMPORT_MODULE: &str = "env_i128";
+
+// The i128 at ptr.
+fn read(caller: &mut Caller<'_, HostState>, function: &str, ptr: i32) -> Result<i128> {
+    let memory = guest::memory(caller, function)?;
+    let data = memory.data(&*caller);
+    let bytes: [u8; 16] = guest::read(data, function, guest::address(ptr))?;
+    let low = u64::from_le_bytes(bytes[..8].try_into().unwrap());
+    let high = i64::from_le_bytes(bytes[8..].try_into().unwrap());
+    Ok(i128::from(high) << 64 | i128::from(low))
+}
+
+fn write(caller: &mut Caller<'_, HostState>, function: &str, ptr: i32, value: i128) -> Result<()> {
+    let mut bytes = [0; 16];
+    bytes[..8].copy_from_slice(&(value as u64).to_le_bytes());
+    bytes[8..].copy_from_slice(&((value >> 64) as i64).to_le_bytes());
+    let memory = guest::memory(caller, function)?;
+    let mut data = memory.data_mut(&mut *caller);
+    guest::write(&mut data, function, guest::address(ptr), &bytes)
+}
+
+pub fn add_to_linker(linker: &mut Linker<HostState>) -> Result<()> {
+    linker.func_wrap(
+        IMPORT_MODULE,
+        "i128_mul",
+        |mut caller: Caller<'_, HostState>, a_ptr: i32, b_ptr: i32, dest_ptr: i32| -> Result<()> {
+            let a = read(&mut caller, "i128_mul", a_ptr)?;
+            let b = read(&mut caller, "i128_mul", b_ptr)?;
+            write(&mut caller, "i128_mul", dest_ptr, a.wrapping_mul(b))
+        },
+    )?;
+    linker.func_wrap(
+        IMPORT_MODULE,
+        "i128_divmod",
+        |mut caller: Caller<'_, HostState>,
+         a_ptr: i32,
+         b_ptr: i32,
+         quotient_ptr: i32,
+         remainder_ptr: i32|
+         -> Result<()> {
+            let a = read(&mut caller, "i128_divmod", a_ptr)?;
+            let b = read(&mut caller, "i128_divmod", b_ptr)?;
+            if b == 0 {
+                return Err(Error::msg("i128_divmod: integer divide by zero"));
+            }
+            let (Some(quotient), Some(remainder)) = (a.checked_div(b), a.checked_rem(b)) else {
+                return Err(Error::msg("i128_divmod: integer overflow"));
+            };
+            write(&mut caller, "i128_divmod", quotient_ptr, quotient)?;
+            write(&mut caller, "i128_divmod", remainder_ptr, remainder)
+        },
+    )?;
+    linker.func_wrap(
+        IMPORT_MODULE,
+        "i128_to_string",
+        |mut caller: Caller<'_, HostState>, value_ptr: i32, dest_slice_ptr: i32| -> Result<()> {
+            let value = read(&mut caller, "i128_to_string", value_ptr)?;
+            let text = value.to_string();
+            let dest_addr = guest::address(dest_slice_ptr);
+            slice_abi::push_slice(&mut caller, "i128_to_string", dest_addr, text.as_bytes())
+        },
+    )?;
+    Ok(())
+}
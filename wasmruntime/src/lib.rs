@@ -0,0 +1,3506 @@
+// The Zong runtime: runs WebAssembly modules compiled by the Zong compiler,
+// with the host functions they import. The wasmruntime binary is a thin
+// wrapper around cli::main; Runtime is the API for embedding programs in
+// other Rust code.
+
+mod abi;
+mod abitest;
+mod allocprofile;
+mod audit;
+mod backend;
+mod backtrace;
+mod bench;
+mod bundle;
+mod cache;
+mod callcount;
+mod calltrace;
+mod checkpoint;
+pub mod cli;
+mod commands;
+mod component;
+mod config;
+mod conststr;
+mod coverage;
+mod crash;
+mod debugger;
+mod diagnostics;
+mod diffrun;
+mod dump;
+mod dynload;
+mod errno;
+mod events;
+mod exitcode;
+mod expect;
+mod extension;
+mod features;
+mod files;
+#[cfg(feature = "fuzz")]
+pub mod fuzz;
+mod guest;
+mod harden;
+mod heap;
+mod heatmap;
+mod hostio;
+mod http;
+mod inspect;
+mod int128;
+mod limits;
+mod logging;
+mod math;
+mod memdump;
+mod panic;
+mod pool;
+mod print_fmt;
+mod profile;
+mod progress;
+mod refs;
+mod repl;
+mod report;
+mod repro;
+mod rerun;
+mod runtime;
+mod sandbox;
+mod session;
+mod shared;
+mod signals;
+mod signature;
+mod slice_abi;
+mod sourcemap;
+mod stats;
+mod subprocess;
+mod terminal;
+mod testsuite;
+mod threads;
+mod timeout;
+mod timers;
+mod trace;
+mod tty;
+mod wasttest;
+mod watch;
+mod watchpoint;
+
+use allocprofile::AllocProfile;
+use audit::AuditLog;
+use bundle::Bundle;
+use callcount::Instrumented;
+use calltrace::CallTracer;
+use checkpoint::Checkpointer;
+use cli::Options;
+use crash::CrashReporter;
+use debugger::Debugger;
+use errno::{Errno, LastError};
+use events::Event;
+pub use exitcode::RunOutcome;
+pub use extension::{register_extension, HostExtension, PluginEntry};
+use features::Features;
+use heap::Heap;
+use heatmap::Heatmap;
+pub use hostio::{Captured, HostIo};
+use limits::{Limits, MemoryLimitExceeded};
+use panic::GuestPanic;
+pub use pool::{Job, JobResult, Pool};
+use profile::Sampler;
+use progress::Progress;
+use rand::rngs::StdRng;
+use rand::{Rng, RngCore, SeedableRng};
+use report::{EngineSettings, OutputFormat, Report, RunEnvironment, StdioCounts};
+use repro::Manifest;
+pub use runtime::{CompiledProgram, Host, ProgramInstance, RunLimits, Runtime};
+use sandbox::{Preopen, Profile, Sandbox};
+use session::{Recorder, Replay, Session};
+use signals::{PendingSignal, SignalInterrupt};
+use sourcemap::SourceMap;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io::{self, Read};
+use std::num::IntErrorKind;
+use std::panic::{catch_unwind, resume_unwind, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use threads::Threads;
+use timeout::{Deadline, TimedOut};
+use timers::Timers;
+use trace::Tracer;
+use wasmtime::*;
+use wasmtime_wasi::preview1::{add_to_linker_sync, WasiP1Ctx};
+use wasmtime_wasi::{DirPerms, FilePerms, I32Exit, WasiCtxBuilder};
+use watch::FileWatcher;
+use watchpoint::Watchpoint;
+
+// Embedders need the same wasmtime as the runtime for Val and Linker.
+pub use wasmtime;
+
+const WASI_MODULE: &str = "wasi_snapshot_preview1";
+
+// Per-store host state. Each piece is only created if the module imports
+// something that needs it, so a print-only Zong program pays for nothing else.
+pub struct HostState {
+    wasi: Option<WasiP1Ctx>,
+    // Custom sections by name, for get_embedded.
+    embedded: HashMap<String, Vec<u8>>,
+    // The guest's stdin, stdout, and stderr. Stdout is buffered, and flushed
+    // when the guest calls flush, writes to stderr, waits for input, or stops
+    // running.
+    io: HostIo,
+    // Active progress report between progress_begin and progress_end.
+    progress: Option<Progress>,
+    // Directories the guest may access, and its working directory.
+    sandbox: Sandbox,
+    // Files opened with file_open and TCP sockets.
+    files: files::Files,
+    // Created by the first watch_path call.
+    watcher: Option<FileWatcher>,
+    timers: Timers,
+    // Set if the module can register signal handlers and listening for
+    // signals succeeded.
+    pending_signal: Option<PendingSignal>,
+    // Set by --timeout.
+    deadline: Option<Deadline>,
+    // Allocations made with host_alloc, sized by --heap-size, once the
+    // module first allocates. Shared with threads started with spawn.
+    heap: Option<Arc<Mutex<Heap>>>,
+    heap_size: usize,
+    // Set by --alloc-profile. Shared with threads, like the heap.
+    alloc_profile: Option<Arc<Mutex<AllocProfile>>>,
+    // Host strings and reference slots, for externref host functions.
+    refs: refs::Refs,
+    // Strings registered with const_str_register.
+    const_strings: conststr::ConstStrings,
+    // The most recent failure of a host function, for last_error_message.
+    last_error: LastError,
+    // Set in a thread started with spawn: the end of its tstack.
+    thread_stack_end: Option<usize>,
+    // Set if the module imports spawn and has a shared memory.
+    threads: Option<Threads>,
+    // Export names of the guest's signal handlers, by signal number.
+    signal_handlers: HashMap<i32, String>,
+    // Shared segments opened by this instance, indexed by handle.
+    shared_segments: Vec<shared::Segment>,
+    // Modules the guest loaded at runtime with load_module.
+    children: dynload::Children,
+    // Resource limits enforced on the store.
+    limits: Limits,
+    // Set by --audit-log.
+    audit_log: Option<AuditLog>,
+    // Set by --heatmap.
+    heatmap: Option<Heatmap>,
+    // Set by --trace.
+    trace: Option<Tracer>,
+    // Set by --trace-calls.
+    call_trace: Option<CallTracer>,
+    // Set by `wasmruntime debug`.
+    debugger: Option<Debugger>,
+    // Set by --watch-addr, once the module is instantiated.
+    watchpoint: Option<Watchpoint>,
+    // Set by --watch-stores.
+    watch_stores: bool,
+    // Set by --sample-profile.
+    sampler: Option<Sampler>,
+    // Set by --checkpoint-every, until the guest stops.
+    checkpointer: Option<Checkpointer>,
+    // Set by --output json or --stats.
+    stdio: Option<StdioCounts>,
+    // The Zong ABI version the module was compiled for, which the host
+    // functions are defined for.
+    abi: abi::Version,
+    // Set by --strict-abi.
+    strict_abi: bool,
+    // Filters and formats log messages.
+    logger: logging::Logger,
+    // Set by read_key once it puts the terminal in raw mode, which dropping
+    // it ends.
+    raw_mode: Option<tty::RawMode>,
+    // Whether the module's memory is a 64-bit one, which the host functions
+    // are defined for along with the ABI version.
+    memory64: bool,
+    // Bytes of a command's stdout that exec keeps, set by
+    // --exec-output-limit.
+    exec_output_limit: usize,
+    // Set by --record or --replay.
+    session: Option<Session>,
+    // Set by --fixed-time: the realtime clock's reading, in nanoseconds
+    // since the Unix epoch.
+    fixed_time: Option<u64>,
+    // Set by --deterministic, which also sets fixed_time: the other clocks
+    // read 0.
+    frozen_clocks: bool,
+    // When the store was created. clock_monotonic_ns counts from here.
+    started: Instant,
+    // Backs random_i64 and random_bytes, and WASI's random_get if seeded by
+    // --seed. Seeded from the host's entropy when first used, if not by
+    // --seed.
+    rng: Option<StdRng>,
+    seeded: bool,
+    // Program arguments, those after -- on the command line.
+    args: Vec<String>,
+    // The process's environment variables, as of the start of the run, if
+    // the module reads them.
+    environment: Vec<(String, String)>,
+}
+
+impl HostState {
+    fn for_module(
+        module: &Module,
+        wasm_bytes: &[u8],
+        assets_dir: Option<&Path>,
+        dirs: &[Preopen],
+        // The module's path then the program arguments, or empty.
+        argv: &[String],
+        io: HostIo,
+        profile: Profile,
+    ) -> Result<HostState> {
+        if !dirs.is_empty() && !profile.allows_host_data() {
+            return Err(exitcode::usage(
+                "--dir cannot be used with the strict sandbox",
+            ));
+        }
+        // A strict sandbox hides the bundled assets along with the rest of
+        // the host
+        let assets_dir = assets_dir.filter(|_| profile.allows_host_data());
+        let wasi = if imports_module(module, WASI_MODULE) {
+            // Create WASI context with stdio and environment access
+            let mut builder = WasiCtxBuilder::new();
+            builder.inherit_stdio();
+            if profile.allows_host_data() {
+                builder.inherit_env().args(argv);
+            }
+            if profile.allows_network() {
+                builder.inherit_network().allow_ip_name_lookup(true);
+            }
+            if let Some(stdin) = io.wasi_stdin() {
+                builder.stdin(stdin);
+            }
+            builder.stdout(io.wasi_stdout()).stderr(io.wasi_stderr());
+            // Bundled assets are visible to the guest as a read-only "assets"
+            // directory.
+            if let Some(assets_dir) = assets_dir {
+                builder.preopened_dir(assets_dir, "assets", DirPerms::READ, FilePerms::READ)?;
+            }
+            for dir in dirs {
+                let (dir_perms, file_perms) = if dir.writable {
+                    (DirPerms::all(), FilePerms::all())
+                } else {
+                    (DirPerms::READ, FilePerms::READ)
+                };
+                builder.preopened_dir(
+                    &dir.host_path,
+                    dir.guest_path.to_string_lossy(),
+                    dir_perms,
+                    file_perms,
+                )?;
+            }
+            Some(builder.build_p1())
+        } else {
+            None
+        };
+        // Precompiled code has no custom sections to read the version from
+        let precompiled = cache::is_precompiled(module.engine(), wasm_bytes);
+        let abi = if precompiled {
+            abi::Version::default()
+        } else {
+            abi::version(wasm_bytes)?
+        };
+        let embedded = if imports_func(module, "env", "get_embedded") {
+            if precompiled {
+                return Err(Error::msg(
+                    "get_embedded needs the module's custom sections, which precompiled \
+                     code does not keep",
+                ));
+            }
+            custom_sections(wasm_bytes)?
+        } else {
+            HashMap::new()
+        };
+        let environment = if ["env_get", "env_count", "env_at"]
+            .iter()
+            .any(|name| imports_func(module, "env", name))
+        {
+            env::vars_os()
+                .map(|(name, value)| {
+                    (
+                        name.to_string_lossy().into_owned(),
+                        value.to_string_lossy().into_owned(),
+                    )
+                })
+                .collect()
+        } else {
+            Vec::new()
+        };
+        Ok(HostState {
+            wasi,
+            embedded,
+            io,
+            progress: None,
+            // The first --dir, if any, is the initial working directory
+            sandbox: Sandbox::new(
+                dirs.iter()
+                    .cloned()
+                    .chain(assets_dir.map(|assets_dir| Preopen {
+                        guest_path: PathBuf::from("/assets"),
+                        host_path: assets_dir.to_path_buf(),
+                        writable: false,
+                    }))
+                    .collect(),
+            ),
+            files: files::Files::default(),
+            watcher: None,
+            timers: Timers::default(),
+            pending_signal: None,
+            deadline: None,
+            heap: None,
+            heap_size: heap::DEFAULT_SIZE,
+            alloc_profile: None,
+            refs: refs::Refs::default(),
+            const_strings: conststr::ConstStrings::default(),
+            last_error: LastError::default(),
+            thread_stack_end: None,
+            threads: None,
+            signal_handlers: HashMap::new(),
+            shared_segments: Vec::new(),
+            children: dynload::Children::default(),
+            limits: Limits::new(profile.memory_limit(), None),
+            audit_log: None,
+            heatmap: None,
+            trace: None,
+            call_trace: None,
+            debugger: None,
+            watchpoint: None,
+            watch_stores: false,
+            sampler: None,
+            checkpointer: None,
+            stdio: None,
+            abi,
+            strict_abi: false,
+            logger: logging::Logger::default(),
+            raw_mode: None,
+            memory64: guest::is_memory64(module),
+            exec_output_limit: subprocess::DEFAULT_OUTPUT_LIMIT,
+            session: None,
+            fixed_time: None,
+            frozen_clocks: false,
+            started: Instant::now(),
+            rng: None,
+            seeded: false,
+            args: argv.get(1..).unwrap_or_default().to_vec(),
+            environment,
+        })
+    }
+
+    // The names of the host environment variables the guest can read, with
+    // env_get or through WASI, sorted.
+    fn visible_env_names(&self, profile: Profile) -> Vec<String> {
+        let mut names: Vec<String> = if self.wasi.is_some() && profile.allows_host_data() {
+            env::vars_os()
+                .map(|(name, _)| name.to_string_lossy().into_owned())
+                .collect()
+        } else {
+            self.environment
+                .iter()
+                .map(|(name, _)| name.clone())
+                .collect()
+        };
+        names.sort();
+        names
+    }
+
+    fn audit(&mut self, operation: &str, target: &str, ok: bool, detail: &str) {
+        if let Some(audit_log) = &mut self.audit_log {
+            audit_log.record(operation, target, ok, detail);
+        }
+    }
+
+    // Reads from the guest's stdin with read, which returns the number of
+    // bytes it consumed. Fails, rather than returning the read's error, if
+    // the read gave up because the guest was interrupted.
+    fn read_stdin(
+        &mut self,
+        read: impl FnOnce(&mut dyn io::BufRead) -> io::Result<usize>,
+    ) -> Result<io::Result<usize>> {
+        let count = match self.io.read_stdin(read) {
+            Ok(count) => count,
+            Err(error) => {
+                self.check_interrupted()?;
+                return Ok(Err(error));
+            }
+        };
+        if let Some(stdio) = &mut self.stdio {
+            stdio.stdin += count as u64;
+        }
+        Ok(Ok(count))
+    }
+
+    fn write_stdout(&mut self, bytes: &[u8]) -> Result<()> {
+        self.io.write_stdout(bytes)?;
+        if let Some(stdio) = &mut self.stdio {
+            stdio.stdout += bytes.len() as u64;
+        }
+        Ok(())
+    }
+
+    fn write_stdout_vectored(&mut self, fragments: &[&[u8]]) -> Result<()> {
+        self.io.write_stdout_vectored(fragments)?;
+        if let Some(stdio) = &mut self.stdio {
+            let len: usize = fragments.iter().map(|fragment| fragment.len()).sum();
+            stdio.stdout += len as u64;
+        }
+        Ok(())
+    }
+
+    fn write_stderr(&mut self, bytes: &[u8]) -> Result<()> {
+        self.io.write_stderr(bytes)?;
+        if let Some(stdio) = &mut self.stdio {
+            stdio.stderr += bytes.len() as u64;
+        }
+        Ok(())
+    }
+
+    // Writes out the guest's buffered output.
+    fn flush_stdout(&mut self) -> io::Result<()> {
+        self.io.flush_stdout()
+    }
+
+    // Returns the guest's reading of a clock, given the host's: the fixed
+    // time for the realtime clock with --fixed-time, 0 for the others with
+    // --deterministic, the recorded reading with --replay, and otherwise the
+    // host's, which --record logs.
+    fn read_clock(&mut self, function: &str, clock: u32, host_value: u64) -> Result<u64> {
+        let value = match self.fixed_time {
+            Some(fixed_time) if clock == CLOCK_REALTIME => fixed_time,
+            _ if self.frozen_clocks => 0,
+            _ => host_value,
+        };
+        match &mut self.session {
+            Some(Session::Record(recorder)) => {
+                recorder.clock(clock, value);
+                Ok(value)
+            }
+            Some(Session::Replay(replay)) => replay.clock(function, clock),
+            None => Ok(value),
+        }
+    }
+
+    // The heap, created on the first allocation.
+    fn heap(&mut self) -> &Arc<Mutex<Heap>> {
+        let size = self.heap_size;
+        self.heap
+            .get_or_insert_with(|| Arc::new(Mutex::new(Heap::new(size))))
+    }
+
+    fn rng(&mut self) -> &mut StdRng {
+        self.rng.get_or_insert_with(StdRng::from_entropy)
+    }
+
+    // The address the tstack must stay below: the end of a thread's tstack,
+    // or else the start of the heap, once anything has been allocated there,
+    // or the end of the address space.
+    fn tstack_limit(&self) -> usize {
+        let end = if self.memory64 { usize::MAX } else { 1 << 32 };
+        self.thread_stack_end.unwrap_or_else(|| {
+            let base = self
+                .heap
+                .as_ref()
+                .and_then(|heap| heap.lock().unwrap().base());
+            base.unwrap_or(end)
+        })
+    }
+
+    // How host functions read the module's slices.
+    fn slice_layout(&self) -> slice_abi::Layout {
+        slice_abi::Layout {
+            memory64: self.memory64,
+            strict: self.strict_abi,
+        }
+    }
+
+    // Whether check_interrupted would stop the guest, for waits that cannot
+    // borrow the state. It does not consume the signal.
+    fn interrupt_check(&self) -> impl Fn() -> bool + Send + 'static {
+        let pending_signal = self.pending_signal.clone();
+        let deadline = self.deadline.clone();
+        move || {
+            pending_signal
+                .as_ref()
+                .is_some_and(PendingSignal::is_pending)
+                || deadline
+                    .as_ref()
+                    .is_some_and(|deadline| deadline.check().is_some())
+        }
+    }
+
+    // Stops a blocking host call the way an epoch check stops the guest, if
+    // a signal for a guest handler arrived or the timeout passed.
+    fn check_interrupted(&self) -> Result<()> {
+        check_interrupted(self.pending_signal.as_ref(), self.deadline.as_ref())
+    }
+
+    // check_interrupted for a child module's epoch checks, which cannot
+    // borrow the state.
+    fn child_interrupt_check(&self) -> Box<dyn Fn() -> Result<()> + Send + Sync> {
+        let pending_signal = self.pending_signal.clone();
+        let deadline = self.deadline.clone();
+        Box::new(move || check_interrupted(pending_signal.as_ref(), deadline.as_ref()))
+    }
+
+    // check_interrupted for a spawned thread's epoch checks. A signal stops
+    // the thread but is left pending, so that the main thread stops too and
+    // runs the guest's handler for it.
+    fn thread_interrupt_check(&self) -> Box<dyn Fn() -> Result<()> + Send + Sync> {
+        let pending_signal = self.pending_signal.clone();
+        let deadline = self.deadline.clone();
+        Box::new(move || {
+            if let Some(signal) = pending_signal.as_ref().and_then(PendingSignal::peek) {
+                return Err(SignalInterrupt(signal).into());
+            }
+            check_interrupted(None, deadline.as_ref())
+        })
+    }
+
+    // Gives the guest random bytes: the recorded ones with --replay, and
+    // otherwise those already in dest, which --record logs.
+    fn take_random(&mut self, function: &str, dest: &mut [u8]) -> Result<()> {
+        match &mut self.session {
+            Some(Session::Record(recorder)) => recorder.random(dest),
+            Some(Session::Replay(replay)) => replay.random(function, dest)?,
+            None => {}
+        }
+        Ok(())
+    }
+}
+
+// How often sleep_ms checks whether the guest has been interrupted.
+const SLEEP_POLL: Duration = Duration::from_millis(10);
+
+// Clock ids, the same as WASI's so that session logs use one numbering.
+const CLOCK_REALTIME: u32 = 0;
+const CLOCK_MONOTONIC: u32 = 1;
+
+// Returns a line read from stdin without its "\n" or "\r\n".
+fn strip_newline(line: &[u8]) -> &[u8] {
+    match line.strip_suffix(b"\n") {
+        Some(line) => line.strip_suffix(b"\r").unwrap_or(line),
+        None => line,
+    }
+}
+
+fn imports_module(module: &Module, module_name: &str) -> bool {
+    module
+        .imports()
+        .any(|import| import.module() == module_name)
+}
+
+fn imports_func(module: &Module, module_name: &str, name: &str) -> bool {
+    module
+        .imports()
+        .any(|import| import.module() == module_name && import.name() == name)
+}
+
+// Allocates len bytes from the heap, growing memory to fit, for host_alloc
+// and spawn. Returns None if the heap is full or memory cannot grow.
+fn heap_alloc(
+    caller: &mut Caller<'_, HostState>,
+    function: &str,
+    len: usize,
+) -> Result<Option<usize>> {
+    let memory = guest::memory(caller, function)?;
+    let memory_end = memory.data_size(&*caller);
+    let mut heap = caller.data_mut().heap().lock().unwrap();
+    let Some(addr) = heap.alloc(len, memory_end) else {
+        return Ok(None);
+    };
+    let top = heap.top();
+    drop(heap);
+    if top > memory_end {
+        let page_size = memory.page_size(&*caller) as usize;
+        let pages = (top - memory_end).div_ceil(page_size) as u64;
+        if let Err(error) = memory.grow(&mut *caller, pages) {
+            caller.data_mut().heap().lock().unwrap().free(addr);
+            // A limit set by the user is reported as such
+            if error.is::<MemoryLimitExceeded>() {
+                return Err(error);
+            }
+            return Ok(None);
+        }
+    }
+    Ok(Some(addr))
+}
+
+// The error to stop the guest with if a signal for a guest handler arrived
+// or the timeout passed, consuming the signal.
+fn check_interrupted(
+    pending_signal: Option<&PendingSignal>,
+    deadline: Option<&Deadline>,
+) -> Result<()> {
+    if let Some(signal) = pending_signal.and_then(PendingSignal::take) {
+        return Err(SignalInterrupt(signal).into());
+    }
+    if let Some(timed_out) = deadline.and_then(Deadline::check) {
+        return Err(timed_out.into());
+    }
+    Ok(())
+}
+
+// Whether the error is one check_interrupted stops the guest with.
+fn is_interruption(error: &Error) -> bool {
+    error.is::<SignalInterrupt>() || error.is::<TimedOut>()
+}
+
+// Calls the () -> () function at the given index of the guest's exported
+// function table.
+fn call_table_func(caller: &mut Caller<'_, HostState>, index: u32) -> Result<()> {
+    let table = caller
+        .get_export("__indirect_function_table")
+        .and_then(|export| export.into_table())
+        .ok_or_else(|| Error::msg("callbacks require an exported __indirect_function_table"))?;
+    let func = table
+        .get(&mut *caller, index.into())
+        .and_then(|entry| entry.unwrap_func().cloned())
+        .ok_or_else(|| Error::msg(format!("no callback function at table index {}", index)))?;
+    func.typed::<(), ()>(&*caller)?.call(&mut *caller, ())
+}
+
+fn host_page_size() -> u64 {
+    #[cfg(unix)]
+    {
+        // SAFETY: sysconf has no preconditions
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+        if page_size > 0 {
+            return page_size as u64;
+        }
+    }
+    4096
+}
+
+// Reads a module file, or stdin if the path is "-", assembling it first if it
+// is WAT text rather than a wasm binary or precompiled code, so hand-written
+// .wat repros run directly. Reading the module from stdin uses all of it, so
+// the program itself gets no input.
+fn read_module(path: &Path) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    let result = if path == Path::new("-") {
+        io::stdin().read_to_end(&mut bytes).map(|_| ())
+    } else {
+        fs::read(path).map(|contents| bytes = contents)
+    };
+    result.map_err(|error| exitcode::unreadable(path.display(), error))?;
+    // Precompiled code is an ELF file, which no WAT text starts like, so it
+    // is told apart without making an engine to call detect_precompiled
+    if bytes.starts_with(b"\0asm") || bytes.starts_with(b"\x7fELF") {
+        return Ok(bytes);
+    }
+    match wat::parse_bytes(&bytes) {
+        Ok(wasm_bytes) => Ok(wasm_bytes.into_owned()),
+        Err(mut error) => {
+            error.set_path(path);
+            Err(error.into())
+        }
+    }
+}
+
+// Collects the module's custom sections by name. If a name repeats, the first
+// section wins.
+fn custom_sections(wasm_bytes: &[u8]) -> Result<HashMap<String, Vec<u8>>> {
+    let mut sections = HashMap::new();
+    for payload in wasmparser::Parser::new(0).parse_all(wasm_bytes) {
+        if let wasmparser::Payload::CustomSection(section) = payload? {
+            sections
+                .entry(section.name().to_string())
+                .or_insert_with(|| section.data().to_vec());
+        }
+    }
+    Ok(sections)
+}
+
+// Runs the program described by the options and returns the process exit code.
+fn run(options: &Options) -> Result<RunOutcome> {
+    // A replayed run takes its inputs from the manifest. The module may be
+    // given explicitly, e.g. if it has moved since the manifest was written.
+    let manifest = match &options.repro {
+        Some(repro) => Some(Manifest::load(Path::new(repro))?),
+        None => None,
+    };
+    let wasm_file = match (&options.wasm_file, &manifest) {
+        (Some(wasm_file), _) => wasm_file.clone(),
+        (None, Some(manifest)) => manifest.module.clone(),
+        (None, None) => unreachable!("parse_args requires a module or a manifest"),
+    };
+    let entry = match &manifest {
+        Some(manifest) => manifest.entry.clone(),
+        None => options.entry.clone(),
+    };
+    let program_args = match &manifest {
+        Some(manifest) => manifest.args.clone(),
+        None => options.program_args.clone(),
+    };
+    if wasm_file == "-" && options.emit_repro.is_some() {
+        return Err(Error::msg(
+            "--emit-repro cannot record a module read from stdin",
+        ));
+    }
+    let stdin = match (&manifest, &options.stdin) {
+        (Some(manifest), _) => Some(manifest.stdin_bytes()?),
+        (None, Some(stdin)) => {
+            Some(fs::read(stdin).map_err(|error| exitcode::unreadable(stdin, error))?)
+        }
+        // Recording a manifest needs all of stdin up front
+        (None, None) if options.emit_repro.is_some() => {
+            let mut stdin = Vec::new();
+            io::stdin().read_to_end(&mut stdin)?;
+            Some(stdin)
+        }
+        // The debugger reads its commands from stdin
+        (None, None) if options.breakpoints.is_some() => Some(Vec::new()),
+        (None, None) => None,
+    };
+
+    let wasm_path = Path::new(&wasm_file);
+    let bundle = if bundle::is_bundle(wasm_path) {
+        Some(Bundle::open(wasm_path)?)
+    } else {
+        None
+    };
+    let wasm_bytes = match &bundle {
+        Some(bundle) => bundle.wasm_bytes.clone(),
+        None => read_module(wasm_path)?,
+    };
+    let assets_dir = bundle
+        .as_ref()
+        .and_then(|bundle| bundle.assets_dir.as_deref());
+
+    if let Some(manifest) = &manifest {
+        manifest.check_module(&wasm_bytes)?;
+    }
+    let mut session = match (&options.record, &options.replay) {
+        (Some(record), _) => Some(Session::Record(Recorder::create(
+            Path::new(record),
+            &wasm_bytes,
+        )?)),
+        (None, Some(replay)) => Some(Session::Replay(Replay::load(
+            Path::new(replay),
+            &wasm_bytes,
+        )?)),
+        (None, None) => None,
+    };
+    // A crash report replays the run's session log, so the run is recorded
+    // to one unless --record or --replay names one already
+    let crash_reporter = match &options.crash_report {
+        Some(dir) => Some(CrashReporter::new(
+            Path::new(dir),
+            options
+                .record
+                .as_ref()
+                .or(options.replay.as_ref())
+                .map(Path::new),
+            &wasm_bytes,
+            entry.as_deref(),
+            &program_args,
+        )?),
+        None => None,
+    };
+    if let Some(session_log) = crash_reporter
+        .as_ref()
+        .and_then(CrashReporter::session_log_to_record)
+    {
+        session = Some(Session::Record(Recorder::create(session_log, &wasm_bytes)?));
+    }
+    let signing_key = match &options.verify_signature {
+        Some(key) => Some(signature::load_key(Path::new(key))?),
+        None => None,
+    };
+    if let Some(key) = &signing_key {
+        signature::verify(key, wasm_path, &wasm_bytes)?;
+    }
+    // Write the manifest before running so it exists even if the run fails
+    if let Some(emit_repro) = &options.emit_repro {
+        let stdin = stdin.as_deref().unwrap_or_default();
+        Manifest::new(
+            &wasm_file,
+            &wasm_bytes,
+            entry.clone(),
+            program_args.clone(),
+            stdin,
+        )
+        .save(Path::new(emit_repro))?;
+    }
+
+    // Create Wasmtime engine and module
+    let fuel = options.fuel.or(options.sandbox.fuel());
+    let mut config = engine_config(fuel.is_some(), options.debug_info);
+    options.backend.configure(&mut config, fuel.is_some())?;
+    let features = Features::configure(
+        &mut config,
+        options.backend,
+        &options.enable_feature,
+        &options.disable_feature,
+    )?;
+    if let Some(profiler) = options.profile {
+        config.profiler(profiler.strategy());
+    }
+    if options.deterministic {
+        make_deterministic(&mut config);
+    }
+    let engine = Engine::new(&config)?;
+    // What the JSON report records about the run, besides the visible
+    // environment variables, which depend on the module
+    let mut environment = (options.output == OutputFormat::Json).then(|| RunEnvironment {
+        runtime_version: env!("CARGO_PKG_VERSION"),
+        wasmtime_version: report::WASMTIME_VERSION,
+        module_sha256: repro::sha256_hex(&wasm_bytes),
+        seed: options.seed.or(options.deterministic.then_some(0)),
+        fixed_time: options.fixed_time.or(options.deterministic.then_some(0)),
+        env: Vec::new(),
+        stdin_sha256: stdin.as_deref().map(repro::sha256_hex),
+        engine: EngineSettings {
+            backend: options.backend.name().to_string(),
+            fuel: fuel.is_some(),
+            deterministic: options.deterministic,
+            debug_info: options.debug_info,
+            features: features.names(),
+        },
+    });
+    if component::is_component(&wasm_bytes) {
+        return component::run(&engine, &wasm_bytes, program_args, stdin, fuel, options);
+    }
+    let precompiled = cache::is_precompiled(&engine, &wasm_bytes);
+    // Modules that start threads get their shared memory and function table
+    // first, so that the other instrumentation applies to the main thread
+    let threaded = if precompiled || options.deterministic {
+        None
+    } else {
+        threads::instrument(&wasm_bytes)?
+    };
+    let (wasm_bytes, thread_bytes) = match threaded {
+        Some((main_bytes, thread_bytes)) => (main_bytes, Some(thread_bytes)),
+        None => (wasm_bytes, None),
+    };
+    let instrumenting = options.pgo_out.is_some()
+        || options.callgraph.is_some()
+        || options.coverage.is_some()
+        || options.stats
+        || options.breakpoints.is_some()
+        || options.trace_calls
+        || options.trace
+        || options.heatmap.is_some()
+        || options.watch_stores;
+    if precompiled && instrumenting {
+        return Err(Error::msg(
+            "--pgo-out, --callgraph, --coverage, --stats, --trace, --trace-calls, --heatmap, \
+             --watch-stores, and the debugger need the module's wasm, not precompiled code",
+        ));
+    }
+    // Call counting, which --coverage and --stats also use, tracing, the
+    // debugger, and heatmaps run an instrumented copy of the module. Guest
+    // calls are traced before the host call wrappers are added, and memory
+    // accesses are instrumented last, so that no instrumentation is counted
+    // or traced as the program's own calls.
+    let instrumented = if options.pgo_out.is_some()
+        || options.callgraph.is_some()
+        || options.coverage.is_some()
+        || options.stats
+    {
+        Some(callcount::instrument(&wasm_bytes)?)
+    } else {
+        None
+    };
+    let mut run_bytes = match &instrumented {
+        Some(instrumented) => Cow::Borrowed(instrumented.wasm_bytes.as_slice()),
+        None => Cow::Borrowed(wasm_bytes.as_slice()),
+    };
+    let mut call_tracer = None;
+    if options.trace_calls {
+        let (traced_bytes, traced) = calltrace::instrument(&run_bytes)?;
+        run_bytes = Cow::Owned(traced_bytes);
+        call_tracer = Some(traced);
+    }
+    let mut tracer = None;
+    if options.trace {
+        let (traced_bytes, traced) = trace::instrument(&run_bytes)?;
+        run_bytes = Cow::Owned(traced_bytes);
+        tracer = Some(traced);
+    }
+    let mut debugger = None;
+    if let Some(breakpoints) = &options.breakpoints {
+        let (debugged_bytes, debugged) = debugger::instrument(&run_bytes, breakpoints)?;
+        run_bytes = Cow::Owned(debugged_bytes);
+        debugger = Some(debugged);
+    }
+    if options.heatmap.is_some() {
+        run_bytes = Cow::Owned(heatmap::instrument(&run_bytes)?);
+    }
+    if options.watch_stores {
+        run_bytes = Cow::Owned(watchpoint::instrument(&run_bytes)?);
+    }
+    if options.allow_precompiled && options.sandbox == Profile::Strict {
+        return Err(exitcode::usage(
+            "--allow-precompiled cannot be used with the strict sandbox, since precompiled \
+             code is native code",
+        ));
+    }
+    let cache_dir = options.cache_dir.as_deref().map(Path::new);
+    let allow_precompiled = options.allow_precompiled;
+    let module = cache::load(&engine, &run_bytes, cache_dir, allow_precompiled)
+        .map_err(|error| features.explain(error, &run_bytes))?;
+    // Source locations are code offsets, so they only fit the module as
+    // compiled, not an instrumented copy
+    let source_map =
+        if options.source_dir.is_some() && !precompiled && run_bytes == wasm_bytes.as_slice() {
+            SourceMap::load(&wasm_bytes)?
+        } else {
+            None
+        };
+    if options.audit {
+        audit::print_report(&module);
+        return Ok(RunOutcome::Exited(0));
+    }
+    let sandbox = options.sandbox.allowing_network(options.allow_net)?;
+    if options.allow_exec && sandbox == Profile::Strict {
+        return Err(exitcode::usage(
+            "--allow-exec cannot be used with the strict sandbox",
+        ));
+    }
+    // Running commands is outside every sandbox profile, so it needs
+    // --allow-exec too
+    let mut denied = options.deny.clone();
+    if !options.allow_exec {
+        denied.push(String::from("subprocess"));
+    }
+    // Threads would make output depend on scheduling
+    if options.deterministic {
+        denied.push(String::from("threads"));
+    }
+    sandbox.check_imports_denying(&module, &denied)?;
+    let dirs: Vec<Preopen> = options
+        .dirs
+        .iter()
+        .chain(&options.ro_dirs)
+        .cloned()
+        .collect();
+    let argv: Vec<String> = std::iter::once(wasm_file.clone())
+        .chain(program_args)
+        .collect();
+    // A recorded run reads stdin through the session log, and a replayed one
+    // reads what was logged
+    let mut io = HostIo::default();
+    match (stdin, &mut session) {
+        (_, Some(Session::Replay(replay))) => io.set_stdin(replay.take_stdin()),
+        (Some(stdin), Some(Session::Record(recorder))) => {
+            io.set_stdin_reader(recorder.stdin(io::Cursor::new(stdin)))
+        }
+        (None, Some(Session::Record(recorder))) => {
+            io.set_process_stdin_reader(recorder.stdin(io::stdin()))
+        }
+        (Some(stdin), None) => io.set_stdin(stdin),
+        (None, None) => {}
+    }
+    if let Some(stdout) = &options.stdout {
+        io.stdout_to_file(Path::new(stdout), options.append)?;
+    }
+    let mut store = Store::new(
+        &engine,
+        HostState::for_module(&module, &wasm_bytes, assets_dir, &dirs, &argv, io, sandbox)?,
+    );
+    store.data_mut().limits = Limits::new(
+        options.max_memory.or(options.sandbox.memory_limit()),
+        options.max_tables,
+    );
+    store.limiter(|state| &mut state.limits);
+    if let Some(audit_log) = &options.audit_log {
+        store.data_mut().audit_log = Some(AuditLog::open(Path::new(audit_log))?);
+    }
+    if options.heatmap.is_some() {
+        store.data_mut().heatmap = Some(Heatmap::default());
+    }
+    store.data_mut().trace = tracer;
+    store.data_mut().call_trace = call_tracer;
+    store.data_mut().debugger = debugger;
+    store.data_mut().watch_stores = options.watch_stores;
+    if options.output == OutputFormat::Json || options.stats {
+        store.data_mut().stdio = Some(StdioCounts::default());
+    }
+    store.data_mut().strict_abi = options.strict_abi;
+    store.data_mut().logger = logging::Logger {
+        level: options.log_level,
+        timestamps: options.log_timestamps,
+    };
+    if let Some(exec_output_limit) = options.exec_output_limit {
+        store.data_mut().exec_output_limit = exec_output_limit;
+    }
+    store.data_mut().session = session;
+    store.data_mut().fixed_time = options.fixed_time.or(options.deterministic.then_some(0));
+    store.data_mut().frozen_clocks = options.deterministic;
+    if let Some(seed) = options.seed.or(options.deterministic.then_some(0)) {
+        store.data_mut().rng = Some(StdRng::seed_from_u64(seed));
+        store.data_mut().seeded = true;
+    }
+    if let Some(fuel) = fuel {
+        store.set_fuel(fuel)?;
+    }
+
+    // The epoch only advances when a signal arrives, the timeout passes, or
+    // it is time for a --sample-profile sample, which then interrupts the
+    // guest at its next epoch check
+    store.set_epoch_deadline(1);
+    // SIGINT and SIGTERM stop the guest at its next epoch check, so that its
+    // output is flushed and --stats printed before exiting with 128 plus the
+    // signal number, or the guest's on_signal handler decides the exit code
+    let pending_signal = signals::install(&engine).ok();
+    let deadline = options
+        .timeout
+        .map(|timeout| Deadline::start(&engine, timeout));
+    store.data_mut().pending_signal = pending_signal.clone();
+    store.data_mut().deadline = deadline.clone();
+    // Host calls make no epoch checks, so ones waiting for input check for
+    // the signal or the timeout themselves
+    let interrupted = store.data().interrupt_check();
+    store.data_mut().io.interrupt_stdin(interrupted);
+    if let Some(heap_size) = options.heap_size {
+        store.data_mut().heap_size = heap_size;
+    }
+    if options.sample_profile.is_some() {
+        store.data_mut().sampler = Some(Sampler::start(&engine));
+    }
+    if options.alloc_profile {
+        // Sites are found by code offset, like trap locations
+        let source_map = if !precompiled && run_bytes == wasm_bytes.as_slice() {
+            SourceMap::load(&wasm_bytes)?
+        } else {
+            None
+        };
+        let profile = AllocProfile::new(source_map);
+        store.data_mut().alloc_profile = Some(Arc::new(Mutex::new(profile)));
+    }
+    if let Some(interval) = options.checkpoint_every {
+        let checkpointer = Checkpointer::start(&engine, interval, &store.data().io);
+        store.data_mut().checkpointer = Some(checkpointer);
+    }
+    store.epoch_deadline_callback(move |mut context| {
+        check_interrupted(pending_signal.as_ref(), deadline.as_ref())?;
+        if context.data().sampler.is_some() {
+            let backtrace = WasmBacktrace::force_capture(&context);
+            if let Some(sampler) = &mut context.data_mut().sampler {
+                sampler.record(&backtrace);
+            }
+        }
+        let checkpoint_due = context
+            .data()
+            .checkpointer
+            .as_ref()
+            .is_some_and(Checkpointer::take_due);
+        if checkpoint_due {
+            let fuel_consumed = match fuel {
+                Some(limit) => Some(limit - context.get_fuel()?),
+                None => None,
+            };
+            let state = context.data_mut();
+            state.flush_stdout()?;
+            if let Some(checkpointer) = &mut state.checkpointer {
+                checkpointer.checkpoint(fuel_consumed, &mut state.io);
+            }
+        }
+        Ok(UpdateDeadline::Continue(1))
+    });
+
+    if !options.plugins.is_empty() && sandbox == Profile::Strict {
+        return Err(exitcode::usage(
+            "--plugin cannot be used with the strict sandbox, since plugins are native code",
+        ));
+    }
+    for plugin in &options.plugins {
+        extension::load_plugin(plugin)?;
+    }
+    let mut linker = host_linker(&engine, store.data())?;
+
+    // Instantiate linked modules first, in order, so each can use the
+    // exports of those before it. The instances live in the same store as
+    // the main module. Libraries given after the main module are linked
+    // under their file name without the extension.
+    let mut linked = options.link_instances.clone();
+    for file in &options.libraries {
+        let name = Path::new(file)
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .ok_or_else(|| Error::msg(format!("{}: not a module file name", file)))?;
+        linked.push((name, file.clone()));
+    }
+    let mut linked_modules = Vec::new();
+    for (name, file) in linked {
+        let linked_bytes = read_module(Path::new(&file))?;
+        if let Some(key) = &signing_key {
+            signature::verify(key, Path::new(&file), &linked_bytes)?;
+        }
+        let linked_module = cache::load(&engine, &linked_bytes, cache_dir, allow_precompiled)?;
+        sandbox.check_imports_denying(&linked_module, &denied)?;
+        linked_modules.push((name, file, linked_module));
+    }
+    let modules = linked_modules.iter().map(|(_, _, module)| module);
+    let shared_memory = define_shared_memory(&mut linker, &mut store, modules.chain([&module]))?;
+    if let (Some(thread_bytes), Some(memory)) = (thread_bytes, shared_memory) {
+        let thread_module = cache::load(&engine, &thread_bytes, cache_dir, false)?;
+        store.data_mut().threads = Some(Threads::new(
+            &engine,
+            thread_module,
+            &wasm_bytes,
+            memory,
+            sandbox,
+        ));
+    }
+    for (name, file, linked_module) in &linked_modules {
+        check_resolved(&linker, &mut store, linked_module, file)?;
+        let linked_instance = linker.instantiate(&mut store, linked_module)?;
+        linker.instance(&mut store, name, linked_instance)?;
+    }
+
+    // Instantiate the module using the linker
+    check_resolved(&linker, &mut store, &module, &wasm_file)?;
+    let started = Instant::now();
+    let instance = linker.instantiate(&mut store, &module)?;
+    // Everything below the initial tstack is static data
+    if let Some(tstack) = instance.get_global(&mut store, "tstack") {
+        let tstack = guest::pointer(&tstack.get(&mut store));
+        if let Some(heatmap) = &mut store.data_mut().heatmap {
+            heatmap.data_end = tstack;
+        }
+    }
+    if options.harden {
+        harden::apply(sandbox.allows_network())?;
+    }
+
+    let entry = match entry {
+        Some(entry) => entry,
+        None if instance.get_func(&mut store, "main").is_none()
+            && instance.get_func(&mut store, "_start").is_some() =>
+        {
+            String::from("_start")
+        }
+        None => String::from("main"),
+    };
+
+    if let Some(restore) = &options.restore {
+        dump::Dump::load(Path::new(restore))?
+            .restore(&mut store, &instance, &wasm_bytes)
+            .map_err(|error| error.context(restore.clone()))?;
+    }
+    if let Some(range) = options.watch_addr {
+        let memory = match instance.get_export(&mut store, "memory") {
+            Some(Extern::Memory(memory)) => guest::GuestMemory::Local(memory),
+            Some(Extern::SharedMemory(memory)) => guest::GuestMemory::Shared(memory),
+            _ => {
+                return Err(Error::msg(
+                    "--watch-addr: the module does not export a memory",
+                ))
+            }
+        };
+        watchpoint::install(&mut store, range, memory)?;
+    }
+
+    // Get the entry function export and call it. An entry returning an i32
+    // or i64 reports the process exit code, as does an exit or WASI
+    // proc_exit call.
+    // With --invoke, the named export is called with the given arguments
+    // instead, and its results are printed. Either way, --snapshot-at's
+    // export is called and the snapshot written first.
+    // A host panic is reported as a crash, then unwinds as usual.
+    let result = catch_unwind(AssertUnwindSafe(|| {
+        take_snapshot(&mut store, &instance, options, &wasm_bytes).and_then(|()| {
+            match options.invoke.split_first() {
+                Some((export, args)) => {
+                    commands::invoke_instance(&mut store, &instance, export, args).map(|()| None)
+                }
+                None => {
+                    let entry_func = instance
+                        .get_func(&mut store, &entry)
+                        .ok_or_else(|| Error::msg(format!("entry export '{}' not found", entry)))?;
+                    call_entry(&mut store, entry_func, &entry)
+                }
+            }
+        })
+    }));
+    let result = match result {
+        Ok(result) => result,
+        Err(payload) => {
+            if let Some(crash_reporter) = &crash_reporter {
+                let message = format!("host panic: {}", crash::panic_message(&*payload));
+                let error = Error::msg(message);
+                report_crash(
+                    crash_reporter,
+                    &mut store,
+                    &instance,
+                    &error,
+                    started.elapsed(),
+                );
+            }
+            resume_unwind(payload);
+        }
+    };
+    if let Some(checkpointer) = store.data_mut().checkpointer.take() {
+        checkpointer.finish(&mut store.data_mut().io);
+    }
+    if let (Some(crash_reporter), Err(error)) = (&crash_reporter, &result) {
+        if crash::is_crash(error) {
+            store.data_mut().flush_stdout()?;
+            report_crash(
+                crash_reporter,
+                &mut store,
+                &instance,
+                error,
+                started.elapsed(),
+            );
+        }
+    }
+    // With --output json, an error that stopped the guest goes in the report
+    // instead of being printed.
+    let mut failure = None;
+    let outcome = match result {
+        Ok(exit_code) => RunOutcome::Exited(exit_code.unwrap_or(0)),
+        Err(error) => {
+            if let Some(exit) = error.downcast_ref::<I32Exit>() {
+                RunOutcome::Exited(exit.0)
+            } else if let Some(SignalInterrupt(signal)) = error.downcast_ref() {
+                if *signal == signals::SIGTERM {
+                    write_checkpoint(&mut store, &instance, options, &wasm_bytes)?;
+                }
+                call_signal_handler(&mut store, &instance, *signal)?
+            } else if options.output == OutputFormat::Json {
+                let outcome = RunOutcome::for_error(&error);
+                failure = Some(error);
+                outcome
+            } else if let Some(timed_out) = error.downcast_ref::<TimedOut>() {
+                store.data_mut().flush_stdout()?;
+                eprintln!("Error: {}", timed_out);
+                if let Some(backtrace) = error.downcast_ref::<WasmBacktrace>() {
+                    backtrace::print_backtrace(backtrace);
+                }
+                if let Some(tstack) = instance.get_global(&mut store, "tstack") {
+                    let tstack = guest::pointer(&tstack.get(&mut store));
+                    eprintln!("tstack pointer: {:#x}", tstack);
+                }
+                end_of_run(
+                    &mut store,
+                    &instance,
+                    options,
+                    instrumented.as_ref(),
+                    started.elapsed(),
+                )?;
+                return Ok(RunOutcome::TimedOut);
+            } else {
+                store.data_mut().flush_stdout()?;
+                let source_dir = options.source_dir.as_deref().map(Path::new);
+                let printed = match (&source_map, source_dir) {
+                    (Some(source_map), Some(source_dir)) => {
+                        source_map.print_trap(&error, source_dir, options.verbose)
+                    }
+                    _ => false,
+                };
+                if printed || backtrace::print_error(&error, options.verbose) {
+                    end_of_run(
+                        &mut store,
+                        &instance,
+                        options,
+                        instrumented.as_ref(),
+                        started.elapsed(),
+                    )?;
+                    return Ok(RunOutcome::for_error(&error));
+                }
+                return Err(error);
+            }
+        }
+    };
+    store.data_mut().flush_stdout()?;
+    let elapsed = started.elapsed();
+    let fuel_consumed = match fuel {
+        Some(limit) => Some(limit - store.get_fuel()?),
+        None => None,
+    };
+    // The JSON report stays the last line of output
+    end_of_run(
+        &mut store,
+        &instance,
+        options,
+        instrumented.as_ref(),
+        elapsed,
+    )?;
+    if options.output == OutputFormat::Json {
+        let mut report = Report::new(outcome.exit_code(), store.data().stdio.as_ref(), elapsed);
+        report.memory_pages = instance
+            .get_memory(&mut store, "memory")
+            .map(|memory| memory.size(&store));
+        report.fuel_consumed = fuel_consumed;
+        if let Some(error) = &failure {
+            report.set_error(error);
+        }
+        if let Some(environment) = &mut environment {
+            environment.env = store.data().visible_env_names(options.sandbox);
+        }
+        report.environment = environment;
+        report.print();
+        if failure.is_some() {
+            return Ok(outcome);
+        }
+    } else if let (Some(consumed), Some(_)) = (fuel_consumed, options.fuel) {
+        eprintln!("fuel consumed: {}", consumed);
+    }
+    if let Some(instrumented) = &instrumented {
+        let counts = instrumented.counts(&mut store, &instance)?;
+        if let Some(pgo_out) = &options.pgo_out {
+            instrumented.write_profile(&counts, Path::new(pgo_out))?;
+        }
+        if let Some(callgraph) = &options.callgraph {
+            instrumented.write_callgraph(&counts, Path::new(callgraph))?;
+        }
+    }
+    if let (Some(heatmap), Some(path)) = (&store.data().heatmap, &options.heatmap) {
+        heatmap.write_report(Path::new(path))?;
+    }
+
+    Ok(outcome)
+}
+
+// Writes a crash report of the error that stopped the guest, with the
+// --output json report of it.
+fn report_crash(
+    crash_reporter: &CrashReporter,
+    store: &mut Store<HostState>,
+    instance: &Instance,
+    error: &Error,
+    elapsed: Duration,
+) {
+    let exit_code = RunOutcome::for_error(error).exit_code();
+    let mut report = Report::new(exit_code, store.data().stdio.as_ref(), elapsed);
+    report.memory_pages = instance
+        .get_memory(&mut *store, "memory")
+        .map(|memory| memory.size(&*store));
+    report.set_error(error);
+    crash_reporter.write(store, instance, error, &report);
+}
+
+// With --snapshot-at, calls the export and writes the snapshot.
+fn take_snapshot(
+    store: &mut Store<HostState>,
+    instance: &Instance,
+    options: &Options,
+    wasm_bytes: &[u8],
+) -> Result<()> {
+    let (Some(export), Some(path)) = (&options.snapshot_at, &options.snapshot_out) else {
+        return Ok(());
+    };
+    let init = instance
+        .get_func(&mut *store, export)
+        .ok_or_else(|| Error::msg(format!("--snapshot-at export '{}' not found", export)))?;
+    init.typed::<(), ()>(&*store)?.call(&mut *store, ())?;
+    dump::write_snapshot(store, instance, wasm_bytes, Path::new(path))
+}
+
+// With --checkpoint-out, writes a snapshot of the guest stopped by SIGTERM.
+// Failing to write it is only a warning, so that the guest's handler still
+// runs.
+fn write_checkpoint(
+    store: &mut Store<HostState>,
+    instance: &Instance,
+    options: &Options,
+    wasm_bytes: &[u8],
+) -> Result<()> {
+    let Some(path) = &options.checkpoint_out else {
+        return Ok(());
+    };
+    store.data_mut().flush_stdout()?;
+    match dump::write_snapshot(store, instance, wasm_bytes, Path::new(path)) {
+        Ok(()) => eprintln!("checkpoint written to {}", path),
+        Err(error) => eprintln!("warning: cannot write checkpoint: {:#}", error),
+    }
+    Ok(())
+}
+
+// Prints --stats and --memdump and writes --coverage, --dump-memory, and
+// --sample-profile once the guest has stopped, whether it finished or
+// trapped.
+fn end_of_run(
+    store: &mut Store<HostState>,
+    instance: &Instance,
+    options: &Options,
+    instrumented: Option<&Instrumented>,
+    elapsed: Duration,
+) -> Result<()> {
+    if let (true, Some(instrumented)) = (options.stats, instrumented) {
+        stats::print(store, instance, instrumented, elapsed)?;
+    }
+    if let (Some(instrumented), Some(path)) = (instrumented, &options.coverage) {
+        let counts = instrumented.counts(&mut *store, instance)?;
+        instrumented.write_coverage(&counts, Path::new(path))?;
+    }
+    if let Some(path) = &options.dump_memory {
+        dump::write(store, instance, Path::new(path))?;
+    }
+    if !options.memdumps.is_empty() {
+        memdump::print(store, instance, &options.memdumps)?;
+    }
+    if let (Some(sampler), Some(path)) = (&store.data().sampler, &options.sample_profile) {
+        sampler.write(Path::new(path))?;
+    }
+    if let Some(profile) = &store.data().alloc_profile {
+        profile.lock().unwrap().print();
+    }
+    Ok(())
+}
+
+// Engine configuration for running modules. Epoch interruption lets signals
+// and timeouts stop the guest, and the threads proposal allows the shared
+// memory that spawn needs. The GC proposal lets the compiler prototype
+// garbage-collected types beside the host strings of refs, and memory64 lets
+// it target memories larger than 4 GiB. With debug_info, compiled code
+// carries DWARF and is registered with native debuggers through the GDB JIT
+// interface, and is left unoptimized so that locals and line stepping match
+// the source.
+fn engine_config(consume_fuel: bool, debug_info: bool) -> Config {
+    let mut config = Config::new();
+    config.epoch_interruption(true);
+    config.consume_fuel(consume_fuel);
+    config.wasm_threads(true);
+    config.wasm_function_references(true);
+    config.wasm_gc(true);
+    config.wasm_memory64(true);
+    if debug_info {
+        config.debug_info(true);
+        config.cranelift_opt_level(OptLevel::None);
+    }
+    config
+}
+
+// Makes compiled code give the same results on every host, for
+// --deterministic and diff-run. NaN bit patterns and relaxed SIMD results
+// otherwise depend on the host.
+fn make_deterministic(config: &mut Config) {
+    config.cranelift_nan_canonicalization(true);
+    config.relaxed_simd_deterministic(true);
+}
+
+// Creates a linker with the host functions for a store: WASI if the module
+// uses it, the Zong runtime's own, and those that the run's options need.
+fn host_linker(engine: &Engine, state: &HostState) -> Result<Linker<HostState>> {
+    let mut linker = runtime_linker(engine, state.wasi.is_some(), state.abi, state.memory64)?;
+    if state.wasi.is_some() {
+        if state.audit_log.is_some() {
+            audit_wasi(&mut linker)?;
+        }
+        if state.stdio.is_some() {
+            report::count_wasi_stdio(&mut linker)?;
+        }
+        if state.session.is_some() || state.fixed_time.is_some() || state.seeded {
+            replayable_wasi(&mut linker)?;
+        }
+    }
+
+    // Memory accesses in modules instrumented for --heatmap:
+    // record(address, offset, is_store).
+    if state.heatmap.is_some() {
+        linker.func_wrap(
+            heatmap::IMPORT_MODULE,
+            heatmap::IMPORT_NAME,
+            |mut caller: Caller<'_, HostState>, address: i32, offset: i32, is_store: i32| {
+                let tstack = match caller.get_export("tstack").and_then(Extern::into_global) {
+                    Some(tstack) => guest::pointer(&tstack.get(&mut caller)),
+                    None => 0,
+                };
+                let heatmap = caller.data_mut().heatmap.as_mut().unwrap();
+                heatmap.record(address as u32, offset as u32, is_store != 0, tstack);
+            },
+        )?;
+    }
+
+    // Stores in modules instrumented for --watch-stores: store() does
+    // nothing, since entering the host checks the watchpoint.
+    if state.watch_stores {
+        linker.func_wrap(watchpoint::IMPORT_MODULE, watchpoint::IMPORT_NAME, || {})?;
+    }
+
+    // Host calls in modules instrumented for --trace: arg(bits) passes each
+    // argument of the next call, enter(index) logs the call, and
+    // exit(index, result) logs its return.
+    if state.trace.is_some() {
+        linker.func_wrap(
+            trace::IMPORT_MODULE,
+            "arg",
+            |mut caller: Caller<'_, HostState>, bits: i64| {
+                if let Some(tracer) = &mut caller.data_mut().trace {
+                    tracer.arg(bits);
+                }
+            },
+        )?;
+        linker.func_wrap(
+            trace::IMPORT_MODULE,
+            "enter",
+            |mut caller: Caller<'_, HostState>, index: i32| {
+                trace::enter(&mut caller, index as usize)
+            },
+        )?;
+        linker.func_wrap(
+            trace::IMPORT_MODULE,
+            "exit",
+            |mut caller: Caller<'_, HostState>, index: i32, result: i64| {
+                trace::exit(&mut caller, index as usize, result)
+            },
+        )?;
+    }
+
+    // Guest calls in modules instrumented for --trace-calls: enter(index)
+    // and exit(index) log entering and leaving the function at index.
+    if state.call_trace.is_some() {
+        linker.func_wrap(calltrace::IMPORT_MODULE, "enter", calltrace::enter)?;
+        linker.func_wrap(calltrace::IMPORT_MODULE, "exit", calltrace::exit)?;
+    }
+
+    // Function entries in modules instrumented for the debugger:
+    // enter(index) stops at breakpoints.
+    if state.debugger.is_some() {
+        linker.func_wrap(debugger::IMPORT_MODULE, "enter", debugger::enter)?;
+    }
+
+    Ok(linker)
+}
+
+// Creates a linker with the Zong runtime's host functions, and WASI's if wasi
+// is set. It does not depend on any one run's options, only on the Zong ABI
+// version the host functions are defined for and whether they take 64-bit
+// addresses, so a single linker can serve every run on an engine of modules
+// for that version and memory.
+fn runtime_linker(
+    engine: &Engine,
+    wasi: bool,
+    abi: abi::Version,
+    memory64: bool,
+) -> Result<Linker<HostState>> {
+    let pointer = guest::pointer_type(memory64);
+    // Create a linker to handle both WASI and custom imports
+    let mut linker = Linker::new(engine);
+    
+    // Add WASI functions to the linker, but only if the module uses them
+    if wasi {
+        add_to_linker_sync(&mut linker, |state: &mut HostState| {
+            state
+                .wasi
+                .as_mut()
+                .expect("WASI context is created for modules importing WASI")
+        })?;
+    }
+    
+    // f64 math functions, under env_math
+    math::add_to_linker(&mut linker)?;
+    // i128_mul, i128_divmod, and i128_to_string, under env_i128
+    int128::add_to_linker(&mut linker)?;
+    // Host strings held as externrefs
+    refs::add_to_linker(&mut linker, abi, &pointer)?;
+    // const_str_register(slice_ptr) and const_str_print(id)
+    conststr::add_to_linker(&mut linker, abi, &pointer)?;
+    // last_error_message(dest_slice_ptr), for the error codes of host
+    // functions
+    errno::add_to_linker(&mut linker, abi, &pointer)?;
+    // log(level, msg_slice_ptr), filtered by --log-level
+    logging::add_to_linker(&mut linker, abi, &pointer)?;
+    // term_is_tty, term_size, and read_key
+    tty::add_to_linker(&mut linker, &pointer)?;
+
+    // Add legacy custom functions for backward compatibility
+    linker.func_wrap(
+        "env",
+        "print",
+        |mut caller: Caller<'_, HostState>, n: i64| -> Result<()> {
+            caller
+                .data_mut()
+                .write_stdout(format!("{}\n", n).as_bytes())
+        },
+    )?;
+
+    // Typed prints, each writing the value and a newline like print. Floats
+    // use the shortest form that reads back as the same value, always with a
+    // decimal point or an exponent (1.0, 0.1, 1e100, inf, NaN). print_bool
+    // writes true for any nonzero value.
+    linker.func_wrap(
+        "env",
+        "print_i32",
+        |mut caller: Caller<'_, HostState>, n: i32| -> Result<()> {
+            caller
+                .data_mut()
+                .write_stdout(format!("{}\n", n).as_bytes())
+        },
+    )?;
+    linker.func_wrap(
+        "env",
+        "print_f32",
+        |mut caller: Caller<'_, HostState>, x: f32| -> Result<()> {
+            caller
+                .data_mut()
+                .write_stdout(format!("{:?}\n", x).as_bytes())
+        },
+    )?;
+    linker.func_wrap(
+        "env",
+        "print_f64",
+        |mut caller: Caller<'_, HostState>, x: f64| -> Result<()> {
+            caller
+                .data_mut()
+                .write_stdout(format!("{:?}\n", x).as_bytes())
+        },
+    )?;
+    linker.func_wrap(
+        "env",
+        "print_bool",
+        |mut caller: Caller<'_, HostState>, b: i32| -> Result<()> {
+            let text: &[u8] = if b != 0 { b"true\n" } else { b"false\n" };
+            caller.data_mut().write_stdout(text)
+        },
+    )?;
+
+    slice_abi::define(
+        &mut linker,
+        abi,
+        "print_bytes",
+        std::slice::from_ref(&pointer),
+        |mut caller, params| -> Result<()> {
+            let slice_ptr = guest::pointer(&params[0]);
+            
+            // Read slice structure from WASM memory
+            let memory = guest::memory(&mut caller, "print_bytes")?;
+            let (data, state) = memory.data_and_store_mut(&mut caller);
+            let data = data.as_data();
+            let string_bytes =
+                slice_abi::read_slice(data, "print_bytes", slice_ptr, state.slice_layout())?;
+            
+            // Write raw bytes to stdout (no trailing newline)
+            state.write_stdout(&string_bytes)
+        },
+    )?;
+
+    // print_bytes_n(slices_ptr, count) writes the count slices in the array
+    // of slice structures at slices_ptr to stdout, in order, in one host
+    // call, for programs that emit output in many small fragments. The array
+    // holds structures in both versions of the ABI.
+    slice_abi::define(
+        &mut linker,
+        abi,
+        "print_bytes_n",
+        &[pointer.clone(), ValType::I64],
+        |mut caller, params| -> Result<()> {
+            let (slices_ptr, count) = (guest::pointer(&params[0]), params[1].unwrap_i64());
+            let count = u64::try_from(count)
+                .map_err(|_| Error::msg(format!("print_bytes_n: invalid count {}", count)))?;
+            let memory = guest::memory(&mut caller, "print_bytes_n")?;
+            let (data, state) = memory.data_and_store_mut(&mut caller);
+            let data = data.as_data();
+            let layout = state.slice_layout();
+            let fragments = (0..count)
+                .map(|index| {
+                    let slice_ptr = slices_ptr
+                        .saturating_add(index.saturating_mul(slice_abi::SLICE_SIZE as u64));
+                    slice_abi::read_slice(data, "print_bytes_n", slice_ptr, layout)
+                })
+                .collect::<Result<Vec<_>>>()?;
+            let fragments: Vec<&[u8]> = fragments.iter().map(|fragment| &fragment[..]).collect();
+            state.write_stdout_vectored(&fragments)
+        },
+    )?;
+
+    slice_abi::define(
+        &mut linker,
+        abi,
+        "print_fmt",
+        &[pointer.clone(), pointer.clone(), ValType::I64],
+        |mut caller, params| -> Result<()> {
+            let fmt_slice_ptr = guest::pointer(&params[0]);
+            let args_ptr = guest::pointer(&params[1]);
+            let args_len = params[2].unwrap_i64();
+            let memory = guest::memory(&mut caller, "print_fmt")?;
+            let (data, state) = memory.data_and_store_mut(&mut caller);
+            let data = data.as_data();
+            let text = print_fmt::format(
+                data,
+                fmt_slice_ptr,
+                args_ptr,
+                args_len,
+                state.slice_layout(),
+            )?;
+            state.write_stdout(&text)
+        },
+    )?;
+
+    // flush() writes out output buffered by the print functions, e.g. a
+    // prompt or a progress line, which is otherwise only written when the
+    // guest reads input or stops.
+    linker.func_wrap(
+        "env",
+        "flush",
+        |mut caller: Caller<'_, HostState>| -> Result<()> {
+            caller.data_mut().flush_stdout()?;
+            Ok(())
+        },
+    )?;
+
+    // eprint(n) and eprint_bytes(slice_ptr) are print and print_bytes for
+    // stderr.
+    linker.func_wrap(
+        "env",
+        "eprint",
+        |mut caller: Caller<'_, HostState>, n: i64| -> Result<()> {
+            caller
+                .data_mut()
+                .write_stderr(format!("{}\n", n).as_bytes())
+        },
+    )?;
+    slice_abi::define(
+        &mut linker,
+        abi,
+        "eprint_bytes",
+        std::slice::from_ref(&pointer),
+        |mut caller, params| -> Result<()> {
+            let slice_ptr = guest::pointer(&params[0]);
+            let memory = guest::memory(&mut caller, "eprint_bytes")?;
+            let (data, state) = memory.data_and_store_mut(&mut caller);
+            let data = data.as_data();
+            let bytes =
+                slice_abi::read_slice(data, "eprint_bytes", slice_ptr, state.slice_layout())?;
+            state.write_stderr(&bytes)
+        },
+    )?;
+
+    // read_line(dest_addr) -> status reads a line from stdin and writes it as
+    // a slice at dest_addr, without its "\n" or "\r\n", with the text on the
+    // tstack. It returns 1 if it read a line, even an empty one, 0 at end of
+    // input, and -1 if reading failed or the line is not valid UTF-8; in the
+    // last two cases the slice is empty. With a 64-bit memory, dest_addr is
+    // an i64, as are the addresses every host function takes.
+    slice_abi::define(
+        &mut linker,
+        abi,
+        "read_line",
+        std::slice::from_ref(&pointer),
+        |mut caller, params| -> Result<i32> {
+            // Get destination address from parameter
+            let dest_addr = guest::pointer(&params[0]) as usize;
+            
+            // Read a line from stdin
+            let mut line = String::new();
+            let result = caller
+                .data_mut()
+                .read_stdin(|stdin| stdin.read_line(&mut line))?;
+            let status = match result {
+                Ok(0) => 0,
+                Ok(_) => 1,
+                Err(_) => -1,
+            };
+            if status != 1 {
+                // Write an empty slice to the destination
+                let memory = guest::memory(&mut caller, "read_line")?;
+                let mut data = memory.data_mut(&mut caller);
+                slice_abi::write_slice(&mut data, "read_line", dest_addr, 0, 0)?;
+                return Ok(status);
+            }
+            let input_bytes = strip_newline(line.as_bytes());
+            let input_len = input_bytes.len() as u64;
+
+            // Write input bytes to tstack, advancing it past them
+            let limit = caller.data().tstack_limit();
+            let input_ptr = guest::tstack_push(&mut caller, "read_line", input_bytes, limit)?;
+
+            // Write slice structure to the destination address:
+            // [items_ptr: i32 or i64 at offset 0, length: i64 at offset 8]
+            let memory = guest::memory(&mut caller, "read_line")?;
+            let mut data = memory.data_mut(&mut caller);
+            slice_abi::write_slice(&mut data, "read_line", dest_addr, input_ptr, input_len)?;
+            Ok(status)
+        },
+    )?;
+
+    // read_line_utf8(dest_addr) reads a line like read_line, but replaces
+    // invalid UTF-8 with U+FFFD instead of failing, so the text is
+    // always valid UTF-8. It writes [line: slice at offset 0, char_count: i64
+    // at offset 16], with the line on the tstack; the slice's length is the
+    // byte length.
+    slice_abi::define(
+        &mut linker,
+        abi,
+        "read_line_utf8",
+        std::slice::from_ref(&pointer),
+        |mut caller, params| -> Result<()> {
+            let dest_addr = guest::pointer(&params[0]) as usize;
+            let mut bytes = Vec::new();
+            let result = caller
+                .data_mut()
+                .read_stdin(|stdin| stdin.read_until(b'\n', &mut bytes))?;
+            if result.is_err() {
+                bytes.clear();
+            }
+            let line = String::from_utf8_lossy(strip_newline(&bytes));
+
+            slice_abi::push_slice(&mut caller, "read_line_utf8", dest_addr, line.as_bytes())?;
+            let memory = guest::memory(&mut caller, "read_line_utf8")?;
+            let mut data = memory.data_mut(&mut caller);
+            let char_count = line.chars().count() as u64;
+            guest::write(
+                &mut data,
+                "read_line_utf8",
+                dest_addr + 16,
+                &char_count.to_le_bytes(),
+            )
+        },
+    )?;
+
+    // Binary stdin. read_bytes(dest_slice_ptr, max_len) -> count reads what
+    // is available, up to max_len bytes, and writes it as a slice on the
+    // tstack. It returns the number of bytes read, 0 at end of input, or -1
+    // if reading failed. read_all(dest_slice_ptr) reads the rest of stdin the
+    // same way; a failed read traps.
+    slice_abi::define(
+        &mut linker,
+        abi,
+        "read_bytes",
+        &[pointer.clone(), ValType::I64],
+        |mut caller, params| -> Result<i64> {
+            let dest_addr = guest::pointer(&params[0]) as usize;
+            let max_len = params[1].unwrap_i64();
+            let max_len = usize::try_from(max_len)
+                .map_err(|_| Error::msg(format!("read_bytes: invalid max_len {}", max_len)))?;
+            // Reads return at most a pipe buffer's worth, so a huge max_len
+            // need not be allocated
+            let mut bytes = vec![0; max_len.min(1 << 16)];
+            let result = caller
+                .data_mut()
+                .read_stdin(|stdin| stdin.read(&mut bytes))?;
+            let count = match result {
+                Ok(count) => count,
+                Err(_) => 0,
+            };
+            slice_abi::push_slice(&mut caller, "read_bytes", dest_addr, &bytes[..count])?;
+            Ok(if result.is_ok() { count as i64 } else { -1 })
+        },
+    )?;
+    slice_abi::define(
+        &mut linker,
+        abi,
+        "read_all",
+        std::slice::from_ref(&pointer),
+        |mut caller, params| -> Result<()> {
+            let dest_addr = guest::pointer(&params[0]) as usize;
+            let mut bytes = Vec::new();
+            let result = caller
+                .data_mut()
+                .read_stdin(|stdin| stdin.read_to_end(&mut bytes))?;
+            result.map_err(|error| Error::new(error).context("read_all"))?;
+            slice_abi::push_slice(&mut caller, "read_all", dest_addr, &bytes)
+        },
+    )?;
+
+    // panic(msg_slice_ptr) stops the guest, reporting the message and the
+    // guest's backtrace, with the exit code for a trap.
+    slice_abi::define(
+        &mut linker,
+        abi,
+        "panic",
+        std::slice::from_ref(&pointer),
+        |mut caller, params| -> Result<()> {
+            let msg_slice_ptr = guest::pointer(&params[0]);
+            let message = slice_abi::read_string(&mut caller, "panic", msg_slice_ptr)?;
+            Err(GuestPanic(message).into())
+        },
+    )?;
+
+    // host_alloc(size) -> ptr allocates size bytes from a heap in the guest's
+    // memory and returns their address, 16-byte aligned, or 0 if the heap is
+    // full. host_free(ptr) frees such a block; freeing 0 does nothing, and
+    // freeing anything else not allocated is an error.
+    linker.func_new(
+        "env",
+        "host_alloc",
+        FuncType::new(engine, [ValType::I64], [pointer.clone()]),
+        move |mut caller: Caller<'_, HostState>, params, results| {
+            let size = params[0].unwrap_i64();
+            let len = usize::try_from(size)
+                .map_err(|_| Error::msg(format!("host_alloc: invalid size {}", size)))?;
+            let addr = heap_alloc(&mut caller, "host_alloc", len)?.unwrap_or(0);
+            match &caller.data().alloc_profile {
+                Some(profile) if addr != 0 => {
+                    let backtrace = WasmBacktrace::force_capture(&caller);
+                    profile.lock().unwrap().record_alloc(&backtrace, addr, len);
+                }
+                _ => {}
+            }
+            results[0] = if memory64 {
+                Val::I64(addr as i64)
+            } else {
+                Val::I32(addr as u32 as i32)
+            };
+            Ok(())
+        },
+    )?;
+    linker.func_new(
+        "env",
+        "host_free",
+        FuncType::new(engine, [pointer.clone()], []),
+        |caller: Caller<'_, HostState>, params, _results| {
+            let addr = guest::pointer(&params[0]) as usize;
+            let heap = caller.data().heap.as_ref();
+            let freed = heap.is_some_and(|heap| heap.lock().unwrap().free(addr));
+            match &caller.data().alloc_profile {
+                Some(profile) if freed => profile.lock().unwrap().record_free(addr),
+                _ => {}
+            }
+            if addr != 0 && !freed {
+                return Err(Error::msg(format!(
+                    "host_free: {:#x} is not a block allocated by host_alloc",
+                    addr
+                )));
+            }
+            Ok(())
+        },
+    )?;
+
+    // Number conversions. parse_i64(slice_ptr, result_ptr) -> status parses
+    // the slice as a decimal i64 with an optional sign and writes it at
+    // result_ptr, returning 0, or returns NOT_A_NUMBER or OUT_OF_RANGE
+    // without writing. i64_to_string(value, dest_slice_ptr) writes the value
+    // in decimal as a slice on the tstack.
+    slice_abi::define(
+        &mut linker,
+        abi,
+        "parse_i64",
+        &[pointer.clone(), pointer.clone()],
+        |mut caller, params| -> Result<i32> {
+            let (slice_ptr, result_ptr) = (guest::pointer(&params[0]), guest::pointer(&params[1]));
+            let memory = guest::memory(&mut caller, "parse_i64")?;
+            let (mut data, state) = memory.data_and_store_mut(&mut caller);
+            let text = slice_abi::read_slice(
+                data.as_data(),
+                "parse_i64",
+                slice_ptr,
+                state.slice_layout(),
+            )?
+            .into_owned();
+            let value = match std::str::from_utf8(&text).map(str::parse::<i64>) {
+                Ok(Ok(value)) => value,
+                Ok(Err(error)) => {
+                    let errno = match error.kind() {
+                        IntErrorKind::PosOverflow | IntErrorKind::NegOverflow => Errno::OutOfRange,
+                        _ => Errno::NotANumber,
+                    };
+                    let message = format_args!("\"{}\": {}", text.escape_ascii(), error);
+                    return Ok(state.last_error.set("parse_i64", errno, message));
+                }
+                Err(error) => {
+                    let message = format_args!("\"{}\": {}", text.escape_ascii(), error);
+                    return Ok(state
+                        .last_error
+                        .set("parse_i64", Errno::NotANumber, message));
+                }
+            };
+            let result_addr = result_ptr as usize;
+            guest::write(&mut data, "parse_i64", result_addr, &value.to_le_bytes())?;
+            Ok(0)
+        },
+    )?;
+    slice_abi::define(
+        &mut linker,
+        abi,
+        "i64_to_string",
+        &[ValType::I64, pointer.clone()],
+        |mut caller, params| -> Result<()> {
+            let value = params[0].unwrap_i64();
+            let dest_addr = guest::pointer(&params[1]) as usize;
+            let text = value.to_string();
+            slice_abi::push_slice(&mut caller, "i64_to_string", dest_addr, text.as_bytes())
+        },
+    )?;
+
+    slice_abi::define(
+        &mut linker,
+        abi,
+        "get_embedded",
+        &[pointer.clone(), pointer.clone()],
+        |mut caller, params| -> Result<()> {
+            let name =
+                slice_abi::read_string(&mut caller, "get_embedded", guest::pointer(&params[0]))?;
+            let dest_addr = guest::pointer(&params[1]) as usize;
+
+            // A missing section is reported as a null, empty slice
+            let section = caller.data().embedded.get(&name).cloned();
+            let section = section.as_deref().unwrap_or_default();
+            slice_abi::push_slice(&mut caller, "get_embedded", dest_addr, section)
+        },
+    )?;
+
+    // args_count() returns the number of program arguments, those after -- on
+    // the runtime's command line. args_get(index, dest_addr) writes the
+    // argument at index as a slice on the tstack and returns 0, or returns -1
+    // if there is no such argument.
+    linker.func_wrap(
+        "env",
+        "args_count",
+        |caller: Caller<'_, HostState>| -> i64 { caller.data().args.len() as i64 },
+    )?;
+    slice_abi::define(
+        &mut linker,
+        abi,
+        "args_get",
+        &[ValType::I64, pointer.clone()],
+        |mut caller, params| -> Result<i32> {
+            let index = params[0].unwrap_i64();
+            let dest_addr = guest::pointer(&params[1]) as usize;
+            let Some(arg) = usize::try_from(index)
+                .ok()
+                .and_then(|index| caller.data().args.get(index))
+                .cloned()
+            else {
+                return Ok(-1);
+            };
+            slice_abi::push_slice(&mut caller, "args_get", dest_addr, arg.as_bytes())?;
+            Ok(0)
+        },
+    )?;
+
+    // exit(code) stops the program, and the process exits with the code. Like
+    // a code returned from main, it is truncated to 32 bits.
+    linker.func_wrap("env", "exit", |code: i64| -> Result<()> {
+        Err(I32Exit(code as i32).into())
+    })?;
+
+    // env_get(name_slice_ptr, dest_addr) writes the value of the named
+    // environment variable as a slice on the tstack and returns 1, or returns
+    // 0 if it is not set. env_count() returns the number of variables, and
+    // env_at(index, dest_addr) writes [name: slice at offset 0, value: slice
+    // at offset 16], both on the tstack, returning 0, or -1 if there is no
+    // such variable.
+    slice_abi::define(
+        &mut linker,
+        abi,
+        "env_get",
+        &[pointer.clone(), pointer.clone()],
+        |mut caller, params| -> Result<i32> {
+            let name_slice_ptr = guest::pointer(&params[0]);
+            let dest_addr = guest::pointer(&params[1]) as usize;
+            let name = slice_abi::read_string(&mut caller, "env_get", name_slice_ptr)?;
+            let Some(value) = caller
+                .data()
+                .environment
+                .iter()
+                .find(|(candidate, _)| *candidate == name)
+                .map(|(_, value)| value.clone())
+            else {
+                return Ok(0);
+            };
+            slice_abi::push_slice(&mut caller, "env_get", dest_addr, value.as_bytes())?;
+            Ok(1)
+        },
+    )?;
+    linker.func_wrap("env", "env_count", |caller: Caller<'_, HostState>| -> i64 {
+        caller.data().environment.len() as i64
+    })?;
+    slice_abi::define(
+        &mut linker,
+        abi,
+        "env_at",
+        &[ValType::I64, pointer.clone()],
+        |mut caller, params| -> Result<i32> {
+            let index = params[0].unwrap_i64();
+            let dest_addr = guest::pointer(&params[1]) as usize;
+            let Some((name, value)) = usize::try_from(index)
+                .ok()
+                .and_then(|index| caller.data().environment.get(index))
+                .cloned()
+            else {
+                return Ok(-1);
+            };
+            slice_abi::push_slice(&mut caller, "env_at", dest_addr, name.as_bytes())?;
+            slice_abi::push_slice(&mut caller, "env_at", dest_addr + 16, value.as_bytes())?;
+            Ok(0)
+        },
+    )?;
+
+    // sysinfo(dest_addr) writes the host's platform details:
+    // [os: slice at offset 0, arch: slice at offset 16, cpu_count: i64 at
+    // offset 32, page_size: i64 at offset 40]. The strings are on the tstack.
+    slice_abi::define(
+        &mut linker,
+        abi,
+        "sysinfo",
+        std::slice::from_ref(&pointer),
+        |mut caller, params| -> Result<()> {
+            let dest_addr = guest::pointer(&params[0]) as usize;
+            let os = env::consts::OS.as_bytes();
+            let arch = env::consts::ARCH.as_bytes();
+            let cpu_count = std::thread::available_parallelism().map_or(1, |count| count.get());
+
+            slice_abi::push_slice(&mut caller, "sysinfo", dest_addr, os)?;
+            slice_abi::push_slice(&mut caller, "sysinfo", dest_addr + 16, arch)?;
+            let memory = guest::memory(&mut caller, "sysinfo")?;
+            let mut data = memory.data_mut(&mut caller);
+            let cpu_count = (cpu_count as u64).to_le_bytes();
+            guest::write(&mut data, "sysinfo", dest_addr + 32, &cpu_count)?;
+            guest::write(
+                &mut data,
+                "sysinfo",
+                dest_addr + 40,
+                &host_page_size().to_le_bytes(),
+            )
+        },
+    )?;
+
+    // getcwd(dest_slice_ptr) writes the guest working directory as a slice on
+    // the tstack. chdir(path_slice_ptr) changes it, returning 0 on success or
+    // an error code if the path is not a directory inside the sandbox.
+    slice_abi::define(
+        &mut linker,
+        abi,
+        "getcwd",
+        std::slice::from_ref(&pointer),
+        |mut caller, params| -> Result<()> {
+            let dest_addr = guest::pointer(&params[0]) as usize;
+            let cwd = caller.data().sandbox.cwd().to_string_lossy().into_owned();
+            slice_abi::push_slice(&mut caller, "getcwd", dest_addr, cwd.as_bytes())
+        },
+    )?;
+    slice_abi::define(
+        &mut linker,
+        abi,
+        "chdir",
+        std::slice::from_ref(&pointer),
+        |mut caller, params| -> Result<i32> {
+            let path_slice_ptr = guest::pointer(&params[0]);
+            let path = slice_abi::read_string(&mut caller, "chdir", path_slice_ptr)?;
+            let state = caller.data_mut();
+            let error = match state.sandbox.resolve(&path) {
+                None => Some((Errno::PermissionDenied, "outside the sandbox")),
+                Some(host_path) if !host_path.exists() => Some((Errno::NotFound, "not found")),
+                Some(_) if !state.sandbox.chdir(&path) => {
+                    Some((Errno::NotADirectory, "not a directory"))
+                }
+                Some(_) => None,
+            };
+            let detail = error.map_or("", |(_, problem)| problem);
+            state.audit("chdir", &path, error.is_none(), detail);
+            Ok(match error {
+                Some((errno, problem)) => {
+                    let message = format_args!("{}: {}", path, problem);
+                    state.last_error.set("chdir", errno, message)
+                }
+                None => 0,
+            })
+        },
+    )?;
+
+    // Files: file_open(path_slice_ptr, mode) opens a file inside the sandbox
+    // and returns an fd, or an error code on failure. Modes: 0 read, 1 write
+    // (creating or truncating the file), 2 append (creating the file);
+    // writing needs a writable directory. file_read(fd, dest_addr, max_len)
+    // reads up to max_len bytes and writes them as a slice on the tstack,
+    // returning the number of bytes read (0 at end of file).
+    // file_write(fd, data_slice_ptr) writes the slice, returning the number
+    // of bytes written. Both return an error code on a bad fd or an I/O
+    // error. file_close(fd) returns 0, or BAD_FD if the fd was not open.
+    slice_abi::define(
+        &mut linker,
+        abi,
+        "file_open",
+        &[pointer.clone(), ValType::I32],
+        |mut caller, params| -> Result<i32> {
+            let (path_slice_ptr, mode) = (guest::pointer(&params[0]), params[1].unwrap_i32());
+            let path = slice_abi::read_string(&mut caller, "file_open", path_slice_ptr)?;
+            let state = caller.data_mut();
+            let host_path = match state.sandbox.resolve(&path) {
+                Some(_) if mode != files::MODE_READ && !state.sandbox.is_writable(&path) => {
+                    state.audit("file_open", &path, false, "read-only directory");
+                    let message = format_args!("{}: read-only directory", path);
+                    return Ok(state
+                        .last_error
+                        .set("file_open", Errno::PermissionDenied, message));
+                }
+                Some(host_path) => host_path,
+                None => {
+                    state.audit("file_open", &path, false, "outside the sandbox");
+                    let message = format_args!("{}: outside the sandbox", path);
+                    return Ok(state
+                        .last_error
+                        .set("file_open", Errno::PermissionDenied, message));
+                }
+            };
+            match state.files.open(&host_path, mode) {
+                Ok(fd) => {
+                    state.audit("file_open", &path, true, &format!("mode {}", mode));
+                    Ok(fd)
+                }
+                Err(error) => {
+                    state.audit("file_open", &path, false, &error.to_string());
+                    Ok(state.last_error.io("file_open", &path, &error))
+                }
+            }
+        },
+    )?;
+    slice_abi::define(
+        &mut linker,
+        abi,
+        "file_read",
+        &[ValType::I32, pointer.clone(), ValType::I64],
+        |mut caller, params| -> Result<i64> {
+            let fd = params[0].unwrap_i32();
+            let dest_addr = guest::pointer(&params[1]) as usize;
+            let max_len = usize::try_from(params[2].unwrap_i64()).unwrap_or(0);
+            let state = caller.data_mut();
+            let bytes = match state.files.read(fd, max_len, &|| false) {
+                Some(Ok(bytes)) => bytes,
+                Some(Err(error)) => {
+                    let target = format!("fd {}", fd);
+                    return Ok(state.last_error.io("file_read", &target, &error).into());
+                }
+                None => return Ok(state.last_error.bad_fd("file_read", fd).into()),
+            };
+            slice_abi::push_slice(&mut caller, "file_read", dest_addr, &bytes)?;
+            Ok(bytes.len() as i64)
+        },
+    )?;
+    slice_abi::define(
+        &mut linker,
+        abi,
+        "file_write",
+        &[ValType::I32, pointer.clone()],
+        |mut caller, params| -> Result<i64> {
+            let (fd, data_slice_ptr) = (params[0].unwrap_i32(), guest::pointer(&params[1]));
+            let memory = guest::memory(&mut caller, "file_write")?;
+            let layout = caller.data().slice_layout();
+            let (data, state) = memory.data_and_store_mut(&mut caller);
+            let data = data.as_data();
+            let bytes = slice_abi::read_slice(data, "file_write", data_slice_ptr, layout)?;
+            Ok(match state.files.write(fd, &bytes) {
+                Some(Ok(())) => bytes.len() as i64,
+                Some(Err(error)) => {
+                    let target = format!("fd {}", fd);
+                    state.last_error.io("file_write", &target, &error).into()
+                }
+                None => state.last_error.bad_fd("file_write", fd).into(),
+            })
+        },
+    )?;
+    linker.func_wrap(
+        "env",
+        "file_close",
+        |mut caller: Caller<'_, HostState>, fd: i32| -> i32 {
+            let state = caller.data_mut();
+            if state.files.close(fd) {
+                0
+            } else {
+                state.last_error.bad_fd("file_close", fd)
+            }
+        },
+    )?;
+    // write_file(path_slice_ptr, data_slice_ptr) writes the slice to a file
+    // inside the sandbox as is, creating or truncating the file, and returns
+    // 0, or an error code on failure. Like file_open in write mode, it needs a
+    // writable directory.
+    slice_abi::define(
+        &mut linker,
+        abi,
+        "write_file",
+        &[pointer.clone(), pointer.clone()],
+        |mut caller, params| -> Result<i32> {
+            let (path_slice_ptr, data_slice_ptr) =
+                (guest::pointer(&params[0]), guest::pointer(&params[1]));
+            let path = slice_abi::read_string(&mut caller, "write_file", path_slice_ptr)?;
+            let memory = guest::memory(&mut caller, "write_file")?;
+            let layout = caller.data().slice_layout();
+            let (data, state) = memory.data_and_store_mut(&mut caller);
+            let data = data.as_data();
+            let bytes = slice_abi::read_slice(data, "write_file", data_slice_ptr, layout)?;
+            let host_path = match state.sandbox.resolve(&path) {
+                Some(_) if !state.sandbox.is_writable(&path) => {
+                    state.audit("write_file", &path, false, "read-only directory");
+                    let message = format_args!("{}: read-only directory", path);
+                    return Ok(state.last_error.set(
+                        "write_file",
+                        Errno::PermissionDenied,
+                        message,
+                    ));
+                }
+                Some(host_path) => host_path,
+                None => {
+                    state.audit("write_file", &path, false, "outside the sandbox");
+                    let message = format_args!("{}: outside the sandbox", path);
+                    return Ok(state.last_error.set(
+                        "write_file",
+                        Errno::PermissionDenied,
+                        message,
+                    ));
+                }
+            };
+            match std::fs::write(&host_path, &bytes) {
+                Ok(()) => {
+                    let detail = format!("{} bytes", bytes.len());
+                    state.audit("write_file", &path, true, &detail);
+                    Ok(0)
+                }
+                Err(error) => {
+                    state.audit("write_file", &path, false, &error.to_string());
+                    Ok(state.last_error.io("write_file", &path, &error))
+                }
+            }
+        },
+    )?;
+
+    // TCP, for --allow-net: tcp_connect(host_slice_ptr, port) connects to a
+    // host name or address, and tcp_listen(port) listens on a port on every
+    // interface. Both return an fd, or an error code on failure.
+    // tcp_accept(fd) waits for a connection to a listener and returns its
+    // fd, or an error code. Sockets share the fd table with files, and are
+    // closed with file_close. tcp_read(fd, dest_addr, max_len) waits for data
+    // and writes up to max_len bytes as a slice on the tstack, returning the
+    // number of bytes read (0 once the peer closes the connection).
+    // tcp_write(fd, data_slice_ptr) sends the slice, returning the number of
+    // bytes sent. Both return an error code on a bad fd or an I/O error.
+    slice_abi::define(
+        &mut linker,
+        abi,
+        "tcp_connect",
+        &[pointer.clone(), ValType::I32],
+        |mut caller, params| -> Result<i32> {
+            let (host_slice_ptr, port) = (guest::pointer(&params[0]), params[1].unwrap_i32());
+            let host = slice_abi::read_string(&mut caller, "tcp_connect", host_slice_ptr)?;
+            let state = caller.data_mut();
+            let target = format!("{}:{}", host, port);
+            let Ok(port) = u16::try_from(port) else {
+                state.audit("tcp_connect", &target, false, "invalid port");
+                let message = format_args!("{}: invalid port", target);
+                return Ok(state
+                    .last_error
+                    .set("tcp_connect", Errno::InvalidArgument, message));
+            };
+            match state.files.connect(&host, port) {
+                Ok(fd) => {
+                    state.audit("tcp_connect", &target, true, "");
+                    Ok(fd)
+                }
+                Err(error) => {
+                    state.audit("tcp_connect", &target, false, &error.to_string());
+                    Ok(state.last_error.io("tcp_connect", &target, &error))
+                }
+            }
+        },
+    )?;
+    linker.func_wrap(
+        "env",
+        "tcp_listen",
+        |mut caller: Caller<'_, HostState>, port: i32| -> i32 {
+            let state = caller.data_mut();
+            let target = format!("port {}", port);
+            let Ok(port) = u16::try_from(port) else {
+                state.audit("tcp_listen", &target, false, "invalid port");
+                let message = format_args!("{}: invalid port", target);
+                return state
+                    .last_error
+                    .set("tcp_listen", Errno::InvalidArgument, message);
+            };
+            match state.files.listen(port) {
+                Ok(fd) => {
+                    state.audit("tcp_listen", &target, true, "");
+                    fd
+                }
+                Err(error) => {
+                    state.audit("tcp_listen", &target, false, &error.to_string());
+                    state.last_error.io("tcp_listen", &target, &error)
+                }
+            }
+        },
+    )?;
+    linker.func_wrap(
+        "env",
+        "tcp_accept",
+        |mut caller: Caller<'_, HostState>, fd: i32| -> Result<i32> {
+            let state = caller.data_mut();
+            state.flush_stdout()?;
+            let target = format!("fd {}", fd);
+            let interrupted = state.interrupt_check();
+            match state.files.accept(fd, &interrupted) {
+                Some(Ok(accepted)) => {
+                    state.audit("tcp_accept", &target, true, &format!("fd {}", accepted));
+                    Ok(accepted)
+                }
+                Some(Err(error)) => {
+                    state.check_interrupted()?;
+                    state.audit("tcp_accept", &target, false, &error.to_string());
+                    Ok(state.last_error.io("tcp_accept", &target, &error))
+                }
+                None => Ok(state.last_error.bad_fd("tcp_accept", fd)),
+            }
+        },
+    )?;
+    slice_abi::define(
+        &mut linker,
+        abi,
+        "tcp_read",
+        &[ValType::I32, pointer.clone(), ValType::I64],
+        |mut caller, params| -> Result<i64> {
+            let fd = params[0].unwrap_i32();
+            let dest_addr = guest::pointer(&params[1]) as usize;
+            let max_len = usize::try_from(params[2].unwrap_i64()).unwrap_or(0);
+            let state = caller.data_mut();
+            state.flush_stdout()?;
+            let interrupted = state.interrupt_check();
+            let bytes = match state.files.read(fd, max_len, &interrupted) {
+                Some(Ok(bytes)) => bytes,
+                Some(Err(error)) => {
+                    state.check_interrupted()?;
+                    let target = format!("fd {}", fd);
+                    return Ok(state.last_error.io("tcp_read", &target, &error).into());
+                }
+                None => return Ok(state.last_error.bad_fd("tcp_read", fd).into()),
+            };
+            slice_abi::push_slice(&mut caller, "tcp_read", dest_addr, &bytes)?;
+            Ok(bytes.len() as i64)
+        },
+    )?;
+    slice_abi::define(
+        &mut linker,
+        abi,
+        "tcp_write",
+        &[ValType::I32, pointer.clone()],
+        |mut caller, params| -> Result<i64> {
+            let (fd, data_slice_ptr) = (params[0].unwrap_i32(), guest::pointer(&params[1]));
+            let memory = guest::memory(&mut caller, "tcp_write")?;
+            let layout = caller.data().slice_layout();
+            let (data, state) = memory.data_and_store_mut(&mut caller);
+            let data = data.as_data();
+            let bytes = slice_abi::read_slice(data, "tcp_write", data_slice_ptr, layout)?;
+            Ok(match state.files.write(fd, &bytes) {
+                Some(Ok(())) => bytes.len() as i64,
+                Some(Err(error)) => {
+                    let target = format!("fd {}", fd);
+                    state.last_error.io("tcp_write", &target, &error).into()
+                }
+                None => state.last_error.bad_fd("tcp_write", fd).into(),
+            })
+        },
+    )?;
+
+    // http_get(url_slice_ptr, dest_slice_ptr), for --allow-net, fetches an
+    // http or https URL and writes the response body as a slice on the
+    // tstack. It returns the status code, including for error statuses, or
+    // OTHER, writing nothing, if there was no response.
+    slice_abi::define(
+        &mut linker,
+        abi,
+        "http_get",
+        &[pointer.clone(), pointer.clone()],
+        |mut caller, params| -> Result<i32> {
+            let url_slice_ptr = guest::pointer(&params[0]);
+            let dest_addr = guest::pointer(&params[1]) as usize;
+            let url = slice_abi::read_string(&mut caller, "http_get", url_slice_ptr)?;
+            let state = caller.data_mut();
+            state.flush_stdout()?;
+            let (status, body) = match http::get(&url) {
+                Ok(response) => response,
+                Err(error) => {
+                    state.audit("http_get", &url, false, &error);
+                    let message = format_args!("{}: {}", url, error);
+                    return Ok(state.last_error.set("http_get", Errno::Other, message));
+                }
+            };
+            state.audit("http_get", &url, true, &format!("status {}", status));
+            slice_abi::push_slice(&mut caller, "http_get", dest_addr, &body)?;
+            Ok(i32::from(status))
+        },
+    )?;
+
+    // exec(cmd_slice_ptr, stdout_dest_slice_ptr), for --allow-exec, runs a
+    // shell command and waits for it to exit. It writes what the command
+    // printed to stdout, up to --exec-output-limit bytes, as a slice on the
+    // tstack, passes on what it printed to stderr, and returns its exit
+    // code. It returns an error code, writing nothing, if the command could
+    // not be started.
+    slice_abi::define(
+        &mut linker,
+        abi,
+        "exec",
+        &[pointer.clone(), pointer.clone()],
+        |mut caller, params| -> Result<i32> {
+            let cmd_slice_ptr = guest::pointer(&params[0]);
+            let dest_addr = guest::pointer(&params[1]) as usize;
+            let command = slice_abi::read_string(&mut caller, "exec", cmd_slice_ptr)?;
+            let state = caller.data_mut();
+            state.flush_stdout()?;
+            let dir = state.sandbox.resolve(".");
+            let output = match subprocess::run(&command, dir.as_deref(), state.exec_output_limit) {
+                Ok(output) => output,
+                Err(error) => {
+                    state.audit("exec", &command, false, &error.to_string());
+                    return Ok(state.last_error.io("exec", &command, &error));
+                }
+            };
+            let detail = format!("exit code {}", output.exit_code);
+            state.audit("exec", &command, true, &detail);
+            state.write_stderr(&output.stderr)?;
+            slice_abi::push_slice(&mut caller, "exec", dest_addr, &output.stdout)?;
+            Ok(output.exit_code)
+        },
+    )?;
+
+    // spawn(func_index, arg) -> thread starts a thread calling function
+    // func_index of the module, which must take an i64 and return nothing,
+    // with arg, and returns its id. It returns INVALID_ARGUMENT if the
+    // function does not exist or has another type, or OTHER if the heap has
+    // no room for the thread's tstack.
+    // join(thread) waits for a thread to finish and returns 0, or 1 if it
+    // trapped, or -1 if there is no such thread or it was already joined.
+    // Threads share the memory; see threads.rs.
+    linker.func_wrap(
+        "env",
+        "spawn",
+        |mut caller: Caller<'_, HostState>, func_index: i32, arg: i64| -> Result<i32> {
+            let target = func_index.to_string();
+            let func = match caller.get_export(threads::TABLE) {
+                Some(Extern::Table(table)) => table
+                    .get(&mut caller, func_index as u32 as u64)
+                    .and_then(|func| func.as_func().flatten().copied()),
+                _ => None,
+            };
+            let takes_i64 = func.is_some_and(|func| func.typed::<i64, ()>(&caller).is_ok());
+            if caller.data().threads.is_none() || !takes_i64 {
+                let state = caller.data_mut();
+                let problem = "not a function taking an i64";
+                state.audit("spawn", &target, false, problem);
+                let message = format_args!("{}: {}", target, problem);
+                return Ok(state
+                    .last_error
+                    .set("spawn", Errno::InvalidArgument, message));
+            }
+            let Some(stack) = heap_alloc(&mut caller, "spawn", threads::STACK_SIZE)? else {
+                let state = caller.data_mut();
+                state.audit("spawn", &target, false, "heap full");
+                let message = "no room in the heap for the thread's tstack";
+                return Ok(state.last_error.set("spawn", Errno::Other, message));
+            };
+            // The thread gets half of the fuel left
+            let fuel = caller.get_fuel().ok().map(|fuel| fuel / 2);
+            if let Some(fuel) = fuel {
+                caller.set_fuel(caller.get_fuel()? - fuel)?;
+            }
+            let state = caller.data_mut();
+            state.flush_stdout()?;
+            let inherited = threads::Inherited {
+                io: state.io.share(),
+                heap: state.heap().clone(),
+                alloc_profile: state.alloc_profile.clone(),
+                limits: state.limits.clone(),
+                check_interrupted: state.thread_interrupt_check(),
+            };
+            let threads = state.threads.as_mut().unwrap();
+            let id = threads.spawn(func_index as u32, arg, stack, inherited, fuel);
+            state.audit("spawn", &target, true, &format!("thread {}", id));
+            Ok(id)
+        },
+    )?;
+    linker.func_wrap(
+        "env",
+        "join",
+        |mut caller: Caller<'_, HostState>, id: i32| -> Result<i32> {
+            let state = caller.data_mut();
+            state.flush_stdout()?;
+            let joined = loop {
+                state.check_interrupted()?;
+                let Some(threads) = &mut state.threads else {
+                    return Ok(-1);
+                };
+                match threads.is_running(id) {
+                    None => return Ok(-1),
+                    Some(true) => std::thread::sleep(SLEEP_POLL),
+                    Some(false) => break threads.join(id).unwrap(),
+                }
+            };
+            state.heap().lock().unwrap().free(joined.stack);
+            // Give back the fuel the thread did not use
+            if let Some(fuel) = joined.fuel {
+                caller.set_fuel(caller.get_fuel()? + fuel)?;
+            }
+            Ok(if joined.ok { 0 } else { 1 })
+        },
+    )?;
+
+    // watch_path(path_slice_ptr) starts watching a file or directory tree
+    // inside the sandbox, returning 0 on success or an error code on failure.
+    // next_event(dest_addr, timeout_ms) waits for a change to any watched path
+    // (forever if timeout_ms is negative) and writes [kind: i64 at offset 0,
+    // path: slice at offset 8], with the path on the tstack. It returns 1 if
+    // an event was written, 0 on timeout, or -1 if nothing is being watched.
+    // Kinds: 1 created, 2 modified, 3 removed, 4 renamed.
+    slice_abi::define(
+        &mut linker,
+        abi,
+        "watch_path",
+        std::slice::from_ref(&pointer),
+        |mut caller, params| -> Result<i32> {
+            let path_slice_ptr = guest::pointer(&params[0]);
+            let path = slice_abi::read_string(&mut caller, "watch_path", path_slice_ptr)?;
+            let state = caller.data_mut();
+            let Some(host_path) = state.sandbox.resolve(&path) else {
+                state.audit("file_watch", &path, false, "outside the sandbox");
+                let message = format_args!("{}: outside the sandbox", path);
+                return Ok(state
+                    .last_error
+                    .set("watch_path", Errno::PermissionDenied, message));
+            };
+            let result = match &mut state.watcher {
+                Some(watcher) => watcher.watch(&host_path),
+                None => FileWatcher::new().and_then(|watcher| {
+                    let watcher = state.watcher.insert(watcher);
+                    watcher.watch(&host_path)
+                }),
+            };
+            match result {
+                Ok(()) => {
+                    state.audit("file_watch", &path, true, "");
+                    Ok(0)
+                }
+                Err(error) => {
+                    state.audit("file_watch", &path, false, &error.to_string());
+                    let message = format_args!("{}: {}", path, error);
+                    Ok(state
+                        .last_error
+                        .set("watch_path", watch::errno(&error), message))
+                }
+            }
+        },
+    )?;
+    slice_abi::define(
+        &mut linker,
+        abi,
+        "next_event",
+        &[pointer.clone(), ValType::I64],
+        |mut caller, params| -> Result<i32> {
+            let dest_addr = guest::pointer(&params[0]) as usize;
+            let timeout_ms = params[1].unwrap_i64();
+            let state = caller.data_mut();
+            state.flush_stdout()?;
+            let Some(watcher) = state
+                .watcher
+                .as_mut()
+                .filter(|watcher| watcher.is_watching())
+            else {
+                return Ok(-1);
+            };
+            let deadline = u64::try_from(timeout_ms)
+                .ok()
+                .map(|ms| Instant::now() + Duration::from_millis(ms));
+            // Skip events for paths with no name inside the sandbox, such as
+            // a preopen that has since been moved, rather than show the guest
+            // a host path
+            let (event, path) = loop {
+                let timeout = deadline.map_or(SLEEP_POLL, |deadline| {
+                    deadline
+                        .saturating_duration_since(Instant::now())
+                        .min(SLEEP_POLL)
+                });
+                match watcher.next_event(Some(timeout)) {
+                    Some(event) => {
+                        if let Some(path) = state.sandbox.guest_path(&event.host_path) {
+                            break (event, path);
+                        }
+                    }
+                    None if deadline.is_some_and(|deadline| Instant::now() >= deadline) => {
+                        return Ok(0);
+                    }
+                    None => {
+                        check_interrupted(state.pending_signal.as_ref(), state.deadline.as_ref())?
+                    }
+                }
+            };
+            let path = path.to_string_lossy().into_owned();
+
+            slice_abi::push_slice(&mut caller, "next_event", dest_addr + 8, path.as_bytes())?;
+            let memory = guest::memory(&mut caller, "next_event")?;
+            let mut data = memory.data_mut(&mut caller);
+            guest::write(
+                &mut data,
+                "next_event",
+                dest_addr,
+                &event.kind.to_le_bytes(),
+            )?;
+            Ok(1)
+        },
+    )?;
+
+    // Clocks, in nanoseconds: clock_monotonic_ns() counts from when the
+    // program started and never goes backwards, for measuring elapsed time,
+    // and clock_realtime_ns() is the time since the Unix epoch, or the time
+    // given by --fixed-time.
+    linker.func_wrap(
+        "env",
+        "clock_monotonic_ns",
+        |mut caller: Caller<'_, HostState>| -> Result<i64> {
+            let state = caller.data_mut();
+            let elapsed = state.started.elapsed().as_nanos() as u64;
+            let value = state.read_clock("clock_monotonic_ns", CLOCK_MONOTONIC, elapsed)?;
+            Ok(value as i64)
+        },
+    )?;
+    linker.func_wrap(
+        "env",
+        "clock_realtime_ns",
+        |mut caller: Caller<'_, HostState>| -> Result<i64> {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |time| time.as_nanos() as u64);
+            let value = caller
+                .data_mut()
+                .read_clock("clock_realtime_ns", CLOCK_REALTIME, now)?;
+            Ok(value as i64)
+        },
+    )?;
+
+    // sleep_ms(ms) blocks for ms milliseconds, or not at all if ms is not
+    // positive. It wakes every SLEEP_POLL to check for a signal or the
+    // timeout, which would otherwise wait for it to finish.
+    linker.func_wrap(
+        "env",
+        "sleep_ms",
+        |mut caller: Caller<'_, HostState>, ms: i64| -> Result<()> {
+            let until = Instant::now() + Duration::from_millis(ms.max(0) as u64);
+            let state = caller.data_mut();
+            state.flush_stdout()?;
+            loop {
+                state.check_interrupted()?;
+                let now = Instant::now();
+                if now >= until {
+                    return Ok(());
+                }
+                std::thread::sleep((until - now).min(SLEEP_POLL));
+            }
+        },
+    )?;
+
+    // Randomness: random_i64() returns a random number, and
+    // random_bytes(dest_slice_ptr, len) writes len random bytes as a slice
+    // on the tstack and returns 0, or INVALID_ARGUMENT if len is negative or
+    // more than the tstack has room for. They come from a generator seeded
+    // from the host's entropy, or by --seed for a reproducible sequence.
+    linker.func_wrap(
+        "env",
+        "random_i64",
+        |mut caller: Caller<'_, HostState>| -> Result<i64> {
+            let state = caller.data_mut();
+            let mut bytes = state.rng().gen::<i64>().to_le_bytes();
+            state.take_random("random_i64", &mut bytes)?;
+            Ok(i64::from_le_bytes(bytes))
+        },
+    )?;
+    slice_abi::define(
+        &mut linker,
+        abi,
+        "random_bytes",
+        &[pointer.clone(), ValType::I64],
+        |mut caller, params| -> Result<i32> {
+            let dest_addr = guest::pointer(&params[0]) as usize;
+            let len = params[1].unwrap_i64();
+            // Check that the bytes fit before making room for them, so that
+            // the guest cannot make the host allocate without bound
+            let state = caller.data();
+            let limit = state.tstack_limit().min(state.limits.max_allocation());
+            let room = guest::tstack_room(&mut caller, "random_bytes", limit)?;
+            let Some(len) = usize::try_from(len).ok().filter(|&len| len <= room) else {
+                let message = format_args!("len {} is not 0 to {} bytes", len, room);
+                return Ok(caller.data_mut().last_error.set(
+                    "random_bytes",
+                    Errno::InvalidArgument,
+                    message,
+                ));
+            };
+            let mut bytes = vec![0; len];
+            let state = caller.data_mut();
+            state.rng().fill_bytes(&mut bytes);
+            state.take_random("random_bytes", &mut bytes)?;
+            slice_abi::push_slice(&mut caller, "random_bytes", dest_addr, &bytes)?;
+            Ok(0)
+        },
+    )?;
+
+    // Timers: set_timeout(callback, delay_ms) and set_interval(callback,
+    // interval_ms) schedule a () -> () function from the guest's exported
+    // __indirect_function_table and return a timer id; clear_timer(id) cancels
+    // one, returning 1 if it was pending. Callbacks only run inside
+    // wait_timers(), the guest's event loop, which returns once no timers are
+    // left.
+    linker.func_wrap(
+        "env",
+        "set_timeout",
+        |mut caller: Caller<'_, HostState>, callback: i32, delay_ms: i64| -> i32 {
+            let delay = Duration::from_millis(delay_ms.max(0) as u64);
+            caller
+                .data_mut()
+                .timers
+                .schedule(callback as u32, delay, false)
+        },
+    )?;
+    linker.func_wrap(
+        "env",
+        "set_interval",
+        |mut caller: Caller<'_, HostState>, callback: i32, interval_ms: i64| -> i32 {
+            let interval = Duration::from_millis(interval_ms.max(0) as u64);
+            caller
+                .data_mut()
+                .timers
+                .schedule(callback as u32, interval, true)
+        },
+    )?;
+    linker.func_wrap(
+        "env",
+        "clear_timer",
+        |mut caller: Caller<'_, HostState>, id: i32| -> i32 {
+            caller.data_mut().timers.cancel(id) as i32
+        },
+    )?;
+    linker.func_wrap(
+        "env",
+        "wait_timers",
+        |mut caller: Caller<'_, HostState>| -> Result<()> {
+            caller.data_mut().flush_stdout()?;
+            while let Some(due) = caller.data().timers.next_due() {
+                // Wake every SLEEP_POLL, as sleep_ms does, so that a signal or
+                // the timeout need not wait for the next timer
+                caller.data().check_interrupted()?;
+                let now = Instant::now();
+                if due > now {
+                    std::thread::sleep((due - now).min(SLEEP_POLL));
+                    continue;
+                }
+                if let Some((_id, callback)) = caller.data_mut().timers.pop_due(Instant::now()) {
+                    call_table_func(&mut caller, callback)?;
+                }
+            }
+            Ok(())
+        },
+    )?;
+
+    // wait_event(dest_addr, timeout_ms) blocks until stdin is readable, a
+    // timer is due, or a watched path changes (forever if timeout_ms is
+    // negative) and writes [kind: i64 at offset 0, id: i64 at offset 8, path:
+    // slice at offset 16]. It returns 1 if an event was written, 0 on timeout.
+    // Kinds: 1 stdin readable, 2 timer due (id is the timer id; its callback
+    // is not called), 3 path changed (id is the next_event kind, path is on
+    // the tstack).
+    slice_abi::define(
+        &mut linker,
+        abi,
+        "wait_event",
+        &[pointer.clone(), ValType::I64],
+        |mut caller, params| -> Result<i32> {
+            let dest_addr = guest::pointer(&params[0]) as usize;
+            let timeout_ms = params[1].unwrap_i64();
+            let deadline = u64::try_from(timeout_ms)
+                .ok()
+                .map(|ms| Instant::now() + Duration::from_millis(ms));
+            let state = caller.data_mut();
+            state.flush_stdout()?;
+            let stdin_is_fixed = !state.io.stdin_is_process();
+            let interrupted = state.interrupt_check();
+            let (kind, id, path) = loop {
+                let timeout =
+                    deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+                let event = events::wait_event(
+                    &mut state.timers,
+                    state.watcher.as_mut(),
+                    stdin_is_fixed,
+                    timeout,
+                    &interrupted,
+                );
+                match event {
+                    Err(_) => {
+                        state.check_interrupted()?;
+                        continue;
+                    }
+                    Ok(None) => return Ok(0),
+                    Ok(Some(Event::StdinReadable)) => break (1, 0, String::new()),
+                    Ok(Some(Event::Timer(id))) => break (2, id as i64, String::new()),
+                    // Skip path changes outside the sandbox, as next_event
+                    // does
+                    Ok(Some(Event::File(event))) => {
+                        if let Some(path) = state.sandbox.guest_path(&event.host_path) {
+                            break (3, event.kind, path.to_string_lossy().into_owned());
+                        }
+                    }
+                }
+            };
+
+            slice_abi::push_slice(&mut caller, "wait_event", dest_addr + 16, path.as_bytes())?;
+            let memory = guest::memory(&mut caller, "wait_event")?;
+            let mut data = memory.data_mut(&mut caller);
+            guest::write(
+                &mut data,
+                "wait_event",
+                dest_addr,
+                &(kind as i64).to_le_bytes(),
+            )?;
+            guest::write(&mut data, "wait_event", dest_addr + 8, &id.to_le_bytes())?;
+            Ok(1)
+        },
+    )?;
+
+    // on_signal(signal, handler_slice_ptr) registers the export named by the
+    // slice as the handler for SIGINT (2) or SIGTERM (15), returning 0 on
+    // success or INVALID_ARGUMENT if the signal cannot be handled. When the
+    // signal arrives, the guest is stopped and the handler is called with the
+    // signal number. Its i32 result, if it has one, becomes the exit code;
+    // otherwise the process exits with 128 + signal.
+    slice_abi::define(
+        &mut linker,
+        abi,
+        "on_signal",
+        &[ValType::I32, pointer.clone()],
+        |mut caller, params| -> Result<i32> {
+            let (signal, handler_slice_ptr) = (params[0].unwrap_i32(), guest::pointer(&params[1]));
+            let handler = slice_abi::read_string(&mut caller, "on_signal", handler_slice_ptr)?;
+            let state = caller.data_mut();
+            if state.pending_signal.is_none()
+                || (signal != signals::SIGINT && signal != signals::SIGTERM)
+            {
+                let message = format_args!("signal {} cannot be handled", signal);
+                return Ok(state
+                    .last_error
+                    .set("on_signal", Errno::InvalidArgument, message));
+            }
+            state.signal_handlers.insert(signal, handler);
+            Ok(0)
+        },
+    )?;
+
+    // Shared segments: shared_open(name_slice_ptr, size) opens the named
+    // segment, creating it with the given size if needed, and returns a
+    // handle. shared_size(handle) returns its size.
+    // shared_write(handle, offset, data_slice_ptr) copies the slice into the
+    // segment, returning the number of bytes written. shared_read(handle,
+    // offset, dest_addr, len) copies up to len bytes out to dest_addr,
+    // returning the number of bytes read. They return BAD_FD for a handle
+    // that is not open and INVALID_ARGUMENT for a range outside the segment,
+    // and shared_open returns INVALID_ARGUMENT if the size is negative or
+    // more than a linear memory may hold (--max-memory, or 4 GiB), so that a
+    // guest cannot make the host allocate without bound.
+    slice_abi::define(
+        &mut linker,
+        abi,
+        "shared_open",
+        &[pointer.clone(), ValType::I64],
+        |mut caller, params| -> Result<i32> {
+            let (name_slice_ptr, size) = (guest::pointer(&params[0]), params[1].unwrap_i64());
+            let name = slice_abi::read_string(&mut caller, "shared_open", name_slice_ptr)?;
+            let state = caller.data_mut();
+            let limit = state.limits.max_allocation();
+            let Some(size) = usize::try_from(size).ok().filter(|&size| size <= limit) else {
+                return Ok(state.last_error.set(
+                    "shared_open",
+                    Errno::InvalidArgument,
+                    format_args!("{}: size {} is not 0 to {} bytes", name, size, limit),
+                ));
+            };
+            state.shared_segments.push(shared::open(&name, size));
+            Ok((state.shared_segments.len() - 1) as i32)
+        },
+    )?;
+    linker.func_wrap(
+        "env",
+        "shared_size",
+        |mut caller: Caller<'_, HostState>, handle: i32| -> i64 {
+            let state = caller.data_mut();
+            match state.shared_segments.get(handle as usize) {
+                Some(segment) => segment.lock().unwrap().len() as i64,
+                None => state.last_error.bad_handle("shared_size", handle).into(),
+            }
+        },
+    )?;
+    slice_abi::define(
+        &mut linker,
+        abi,
+        "shared_write",
+        &[ValType::I32, ValType::I64, pointer.clone()],
+        |mut caller, params| -> Result<i64> {
+            let handle = params[0].unwrap_i32();
+            let offset = params[1].unwrap_i64();
+            let data_slice_ptr = guest::pointer(&params[2]);
+            let Some(segment) = caller.data().shared_segments.get(handle as usize).cloned() else {
+                let state = caller.data_mut();
+                return Ok(state.last_error.bad_handle("shared_write", handle).into());
+            };
+            let memory = guest::memory(&mut caller, "shared_write")?;
+            let layout = caller.data().slice_layout();
+            let data = slice_abi::read_slice(
+                memory.data(&caller),
+                "shared_write",
+                data_slice_ptr,
+                layout,
+            )?;
+            let len = data.len();
+            match shared::write(&segment, offset as usize, &data) {
+                Some(()) => Ok(len as i64),
+                None => {
+                    let message =
+                        format_args!("{} bytes at {} are outside the segment", len, offset);
+                    let state = caller.data_mut();
+                    Ok(state
+                        .last_error
+                        .set("shared_write", Errno::InvalidArgument, message)
+                        .into())
+                }
+            }
+        },
+    )?;
+    slice_abi::define(
+        &mut linker,
+        abi,
+        "shared_read",
+        &[ValType::I32, ValType::I64, pointer.clone(), ValType::I64],
+        |mut caller, params| -> Result<i64> {
+            let (handle, offset) = (params[0].unwrap_i32(), params[1].unwrap_i64());
+            let dest_addr = guest::pointer(&params[2]) as usize;
+            let len = params[3].unwrap_i64();
+            let Some(segment) = caller.data().shared_segments.get(handle as usize).cloned() else {
+                let state = caller.data_mut();
+                return Ok(state.last_error.bad_handle("shared_read", handle).into());
+            };
+            let Some(bytes) = shared::read(&segment, offset as usize, len as usize) else {
+                let state = caller.data_mut();
+                let message = format_args!("offset {} is outside the segment", offset);
+                return Ok(state
+                    .last_error
+                    .set("shared_read", Errno::InvalidArgument, message)
+                    .into());
+            };
+            let memory = guest::memory(&mut caller, "shared_read")?;
+            let mut data = memory.data_mut(&mut caller);
+            guest::write(&mut data, "shared_read", dest_addr, &bytes)?;
+            Ok(bytes.len() as i64)
+        },
+    )?;
+
+    // Runtime module loading: load_module(bytes_slice_ptr, grants) compiles
+    // and instantiates the wasm module in the slice in its own store and
+    // returns a handle, or INVALID_ARGUMENT if the module is invalid, needs
+    // imports that were not granted, or would be more than
+    // dynload::MAX_CHILDREN. The child only gets env.print and
+    // env.print_bytes if grants includes GRANT_PRINT (1).
+    // module_call(handle, name_slice_ptr, arg, result_ptr) calls the child's
+    // (i64) -> i64 export and stores the result as an i64 at result_ptr. It
+    // returns 0 on success, -1 if there is no such export, or -2 if the child
+    // trapped. Children share the parent's fuel and memory limits, and a
+    // signal or the timeout stops the parent as well as the child.
+    slice_abi::define(
+        &mut linker,
+        abi,
+        "load_module",
+        &[pointer.clone(), ValType::I32],
+        |mut caller, params| -> Result<i32> {
+            let (bytes_slice_ptr, grants) = (guest::pointer(&params[0]), params[1].unwrap_i32());
+            let memory = guest::memory(&mut caller, "load_module")?;
+            let layout = caller.data().slice_layout();
+            let wasm_bytes = slice_abi::read_slice(
+                memory.data(&caller),
+                "load_module",
+                bytes_slice_ptr,
+                layout,
+            )?
+            .to_vec();
+            let engine = caller.engine().clone();
+            let mut fuel = caller.get_fuel().ok();
+            let state = caller.data_mut();
+            let inherited = dynload::Inherited {
+                stdout: state.io.stdout(),
+                limits: state.limits.clone(),
+                check_interrupted: state.child_interrupt_check(),
+            };
+            let children = &mut state.children;
+            let result = children.load(&engine, &wasm_bytes, grants, inherited, fuel.as_mut());
+            if let Some(fuel) = fuel {
+                caller.set_fuel(fuel)?;
+            }
+            match result {
+                Ok(handle) => Ok(handle),
+                Err(error) if is_interruption(&error) => Err(error),
+                Err(error) => {
+                    let message = format_args!("{:#}", error);
+                    let state = caller.data_mut();
+                    Ok(state
+                        .last_error
+                        .set("load_module", Errno::InvalidArgument, message))
+                }
+            }
+        },
+    )?;
+    slice_abi::define(
+        &mut linker,
+        abi,
+        "module_call",
+        &[ValType::I32, pointer.clone(), ValType::I64, pointer.clone()],
+        |mut caller, params| -> Result<i32> {
+            let handle = params[0].unwrap_i32();
+            let name_slice_ptr = guest::pointer(&params[1]);
+            let arg = params[2].unwrap_i64();
+            let result_ptr = guest::pointer(&params[3]) as usize;
+            let name = slice_abi::read_string(&mut caller, "module_call", name_slice_ptr)?;
+            let mut fuel = caller.get_fuel().ok();
+            let result = caller
+                .data_mut()
+                .children
+                .call(handle, &name, arg, fuel.as_mut());
+            if let Some(fuel) = fuel {
+                caller.set_fuel(fuel)?;
+            }
+            let result = match result {
+                Some(Ok(result)) => result,
+                // A signal or the timeout stops the parent too
+                Some(Err(error)) if is_interruption(&error) => return Err(error),
+                Some(Err(_)) => return Ok(-2),
+                None => return Ok(-1),
+            };
+            let memory = guest::memory(&mut caller, "module_call")?;
+            let mut data = memory.data_mut(&mut caller);
+            guest::write(&mut data, "module_call", result_ptr, &result.to_le_bytes())?;
+            Ok(0)
+        },
+    )?;
+
+    // Progress reporting: progress_begin(label_slice_ptr, total) starts a report,
+    // progress_update(current) advances it, and progress_end() finishes it. A
+    // total of 0 or less means the total is unknown.
+    slice_abi::define(
+        &mut linker,
+        abi,
+        "progress_begin",
+        &[pointer.clone(), ValType::I64],
+        |mut caller, params| -> Result<()> {
+            let (label_slice_ptr, total) = (guest::pointer(&params[0]), params[1].unwrap_i64());
+            let label = slice_abi::read_string(&mut caller, "progress_begin", label_slice_ptr)?;
+            let state = caller.data_mut();
+            if let Some(previous) = state.progress.take() {
+                previous.end(&mut state.io);
+            }
+            state.progress = Some(Progress::begin(label, total, &mut state.io));
+            Ok(())
+        },
+    )?;
+    linker.func_wrap(
+        "env",
+        "progress_update",
+        |mut caller: Caller<'_, HostState>, current: i64| {
+            let state = caller.data_mut();
+            if let Some(progress) = &mut state.progress {
+                progress.update(current, &mut state.io);
+            }
+        },
+    )?;
+    linker.func_wrap(
+        "env",
+        "progress_end",
+        |mut caller: Caller<'_, HostState>| {
+            let state = caller.data_mut();
+            if let Some(progress) = state.progress.take() {
+                progress.end(&mut state.io);
+            }
+        },
+    )?;
+
+    extension::add_to_linker(&mut linker)?;
+    Ok(linker)
+}
+
+// Linked modules share one memory by importing it as env.memory, which the
+// runtime creates if any module imports it: as large as the largest import
+// asks for, and limited by the smallest maximum. Host functions use the
+// calling module's "memory" export, so each module that calls them should
+// also export the memory it imports. If the main module's import is marked
+// shared, as for spawn, the memory is a shared memory, which is returned.
+fn define_shared_memory<'a>(
+    linker: &mut Linker<HostState>,
+    store: &mut Store<HostState>,
+    modules: impl IntoIterator<Item = &'a Module>,
+) -> Result<Option<SharedMemory>> {
+    let mut shared: Option<(u64, Option<u64>)> = None;
+    let mut is_shared = false;
+    for module in modules {
+        for import in module.imports() {
+            let ExternType::Memory(ty) = import.ty() else {
+                continue;
+            };
+            if (import.module(), import.name()) != ("env", "memory") {
+                continue;
+            }
+            is_shared = ty.is_shared();
+            let (minimum, maximum) = shared.get_or_insert((0, None));
+            *minimum = (*minimum).max(ty.minimum());
+            if let Some(limit) = ty.maximum() {
+                *maximum = Some(maximum.map_or(limit, |maximum| maximum.min(limit)));
+            }
+        }
+    }
+    let Some((minimum, maximum)) = shared else {
+        return Ok(None);
+    };
+    let too_large = |_| Error::msg("env.memory: imported memory is too large");
+    let minimum = u32::try_from(minimum).map_err(too_large)?;
+    let maximum = maximum.map(u32::try_from).transpose().map_err(too_large)?;
+    if is_shared {
+        let maximum =
+            maximum.ok_or_else(|| Error::msg("env.memory: shared memory needs a maximum"))?;
+        let memory = SharedMemory::new(store.engine(), MemoryType::shared(minimum, maximum))?;
+        linker.define(&mut *store, "env", "memory", memory.clone())?;
+        return Ok(Some(memory));
+    }
+    let memory = Memory::new(&mut *store, MemoryType::new(minimum, maximum))?;
+    linker.define(&mut *store, "env", "memory", memory)?;
+    Ok(None)
+}
+
+// Checks that the linker defines every import of the module, reporting all
+// of the missing ones at once; instantiation would only report the first.
+fn check_resolved(
+    linker: &Linker<HostState>,
+    store: &mut Store<HostState>,
+    module: &Module,
+    module_path: &str,
+) -> Result<()> {
+    let unresolved: Vec<String> = module
+        .imports()
+        .filter(|import| linker.get_by_import(&mut *store, import).is_none())
+        .map(|import| match import.ty() {
+            ExternType::Func(ty) => format!("  {}.{}: {}", import.module(), import.name(), ty),
+            _ => format!("  {}.{}", import.module(), import.name()),
+        })
+        .collect();
+    if unresolved.is_empty() {
+        return Ok(());
+    }
+    Err(Error::msg(format!(
+        "{} has imports that the runtime does not provide:\n{}",
+        module_path,
+        unresolved.join("\n")
+    )))
+}
+
+// Re-registers the WASI functions that open files, read the environment, or
+// accept network connections with wrappers that record each call in the
+// audit log. The wrappers call wasmtime-wasi's implementations directly,
+// since a host function cannot call another through the linker and still
+// give it the guest's memory.
+fn audit_wasi(linker: &mut Linker<HostState>) -> Result<()> {
+    use wasmtime_wasi::preview1::wasi_snapshot_preview1 as p1;
+    use wasmtime_wasi::runtime::in_tokio;
+
+    linker.allow_shadowing(true);
+    linker.func_wrap(
+        WASI_MODULE,
+        "path_open",
+        |mut caller: Caller<'_, HostState>,
+         fd: i32,
+         dirflags: i32,
+         path_ptr: i32,
+         path_len: i32,
+         oflags: i32,
+         fs_rights_base: i64,
+         fs_rights_inheriting: i64,
+         fdflags: i32,
+         opened_fd_ptr: i32|
+         -> Result<i32> {
+            let errno = call_wasi(&mut caller, |ctx, memory| {
+                in_tokio(p1::path_open(
+                    ctx,
+                    memory,
+                    fd,
+                    dirflags,
+                    path_ptr,
+                    path_len,
+                    oflags,
+                    fs_rights_base,
+                    fs_rights_inheriting,
+                    fdflags,
+                    opened_fd_ptr,
+                ))
+            })?;
+            // wasmtime-wasi has checked the path's bounds if the call succeeded
+            let memory = guest::memory(&mut caller, "path_open")?;
+            let data = memory.data(&caller);
+            let path = match guest::range(
+                data,
+                "path_open",
+                guest::address(path_ptr),
+                guest::address(path_len),
+            ) {
+                Ok(range) => String::from_utf8_lossy(&data.get(range)).into_owned(),
+                Err(_) => String::new(),
+            };
+            audit_errno(&mut caller, "file_open", &path, errno);
+            Ok(errno)
+        },
+    )?;
+    linker.func_wrap(
+        WASI_MODULE,
+        "environ_get",
+        |mut caller: Caller<'_, HostState>, environ_ptr: i32, buf_ptr: i32| -> Result<i32> {
+            let errno = call_wasi(&mut caller, |ctx, memory| {
+                p1::environ_get(ctx, memory, environ_ptr, buf_ptr)
+            })?;
+            audit_errno(&mut caller, "env_read", "", errno);
+            Ok(errno)
+        },
+    )?;
+    linker.func_wrap(
+        WASI_MODULE,
+        "sock_accept",
+        |mut caller: Caller<'_, HostState>,
+         fd: i32,
+         flags: i32,
+         accepted_fd_ptr: i32|
+         -> Result<i32> {
+            let errno = call_wasi(&mut caller, |ctx, memory| {
+                p1::sock_accept(ctx, memory, fd, flags, accepted_fd_ptr)
+            })?;
+            audit_errno(&mut caller, "network_accept", &format!("fd {}", fd), errno);
+            Ok(errno)
+        },
+    )?;
+    linker.allow_shadowing(false);
+    Ok(())
+}
+
+// Shadows WASI's clock and random functions, for --record, --replay,
+// --fixed-time, and --seed, with wrappers that log each value the real
+// function returns, or replace it with the logged, fixed, or seeded one.
+// Replaced calls still go to wasmtime-wasi first so that they fail the same
+// way, e.g. for an unknown clock or an invalid pointer.
+fn replayable_wasi(linker: &mut Linker<HostState>) -> Result<()> {
+    use wasmtime_wasi::preview1::wasi_snapshot_preview1 as p1;
+
+    linker.allow_shadowing(true);
+    linker.func_wrap(
+        WASI_MODULE,
+        "clock_time_get",
+        |mut caller: Caller<'_, HostState>,
+         clock: i32,
+         precision: i64,
+         time_ptr: i32|
+         -> Result<i32> {
+            let errno = call_wasi(&mut caller, |ctx, memory| {
+                p1::clock_time_get(ctx, memory, clock, precision, time_ptr)
+            })?;
+            if errno != 0 {
+                return Ok(errno);
+            }
+            let memory = guest::memory(&mut caller, "clock_time_get")?;
+            let (mut data, state) = memory.data_and_store_mut(&mut caller);
+            let time_addr = guest::address(time_ptr);
+            let host_value =
+                u64::from_le_bytes(guest::read(data.as_data(), "clock_time_get", time_addr)?);
+            let value = state.read_clock("clock_time_get", clock as u32, host_value)?;
+            data.set(time_addr, &value.to_le_bytes());
+            Ok(errno)
+        },
+    )?;
+    linker.func_wrap(
+        WASI_MODULE,
+        "random_get",
+        |mut caller: Caller<'_, HostState>, buf_ptr: i32, buf_len: i32| -> Result<i32> {
+            let errno = call_wasi(&mut caller, |ctx, memory| {
+                p1::random_get(ctx, memory, buf_ptr, buf_len)
+            })?;
+            if errno != 0 {
+                return Ok(errno);
+            }
+            let memory = guest::memory(&mut caller, "random_get")?;
+            let (mut data, state) = memory.data_and_store_mut(&mut caller);
+            let range = guest::range(
+                data.as_data(),
+                "random_get",
+                guest::address(buf_ptr),
+                guest::address(buf_len),
+            )?;
+            let mut bytes = data.as_data().get(range.clone()).into_owned();
+            if state.seeded {
+                state.rng().fill_bytes(&mut bytes);
+            }
+            state.take_random("random_get", &mut bytes)?;
+            data.set(range.start, &bytes);
+            Ok(errno)
+        },
+    )?;
+    linker.allow_shadowing(false);
+    Ok(())
+}
+
+// Calls a wasmtime-wasi preview1 implementation with the guest's memory.
+fn call_wasi(
+    caller: &mut Caller<'_, HostState>,
+    func: impl FnOnce(&mut WasiP1Ctx, &mut wiggle::GuestMemory<'_>) -> Result<i32>,
+) -> Result<i32> {
+    let memory = caller
+        .get_export("memory")
+        .ok_or_else(|| Error::msg("missing required memory export"))?;
+    match memory {
+        Extern::Memory(memory) => {
+            let (data, state) = memory.data_and_store_mut(caller);
+            func(wasi_ctx(state), &mut wiggle::GuestMemory::Unshared(data))
+        }
+        Extern::SharedMemory(memory) => func(
+            wasi_ctx(caller.data_mut()),
+            &mut wiggle::GuestMemory::Shared(memory.data()),
+        ),
+        _ => Err(Error::msg("missing required memory export")),
+    }
+}
+
+fn wasi_ctx(state: &mut HostState) -> &mut WasiP1Ctx {
+    state
+        .wasi
+        .as_mut()
+        .expect("WASI context is created for modules importing WASI")
+}
+
+fn audit_errno(caller: &mut Caller<'_, HostState>, operation: &str, target: &str, errno: i32) {
+    let detail = if errno == 0 {
+        String::new()
+    } else {
+        format!("errno {}", errno)
+    };
+    caller
+        .data_mut()
+        .audit(operation, target, errno == 0, &detail);
+}
+
+// Calls the guest's handler for a signal that interrupted it and returns the
+// exit code.
+fn call_signal_handler(
+    store: &mut Store<HostState>,
+    instance: &Instance,
+    signal: i32,
+) -> Result<RunOutcome> {
+    let Some(handler) = store.data().signal_handlers.get(&signal).cloned() else {
+        return Ok(RunOutcome::Signaled(signal));
+    };
+    let handler_func = instance
+        .get_func(&mut *store, &handler)
+        .ok_or_else(|| Error::msg(format!("signal handler export '{}' not found", handler)))?;
+    if let Ok(handler_func) = handler_func.typed::<i32, ()>(&*store) {
+        handler_func.call(&mut *store, signal)?;
+        return Ok(RunOutcome::Signaled(signal));
+    }
+    let handler_func = handler_func.typed::<i32, i32>(&*store).map_err(|_| {
+        Error::msg(format!(
+            "signal handler '{}' must have type (i32) -> () or (i32) -> i32",
+            handler
+        ))
+    })?;
+    Ok(RunOutcome::Exited(handler_func.call(&mut *store, signal)?))
+}
+
+fn call_entry(store: &mut Store<HostState>, entry_func: Func, entry: &str) -> Result<Option<i32>> {
+    if let Ok(entry_func) = entry_func.typed::<(), ()>(&*store) {
+        entry_func.call(&mut *store, ())?;
+        return Ok(None);
+    }
+    if let Ok(entry_func) = entry_func.typed::<(), i64>(&*store) {
+        return Ok(Some(entry_func.call(&mut *store, ())? as i32));
+    }
+    let entry_func = entry_func.typed::<(), i32>(&*store).map_err(|_| {
+        Error::msg(format!(
+            "entry export '{}' must have type () -> (), () -> i32, or () -> i64",
+            entry
+        ))
+    })?;
+    Ok(Some(entry_func.call(&mut *store, ())?))
+}
\ No newline at end of file
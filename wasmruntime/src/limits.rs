@@ -0,0 +1,86 @@
+// Resource limits on the guest's store, from the sandbox profile and
+// --max-memory and --max-tables. Growing a memory past the limit stops the
+// guest with MemoryLimitExceeded, rather than making memory.grow return -1,
+// since Zong programs do not check its result and would fail later with a
+// confusing out-of-bounds trap.
+
+use std::fmt;
+use wasmtime::{ResourceLimiter, Result};
+
+#[derive(Clone)]
+pub struct Limits {
+    // Maximum size of each linear memory, in bytes.
+    memory_size: Option<usize>,
+    // Maximum number of tables in the store.
+    tables: usize,
+    // Size of the largest memory, in bytes, for --stats.
+    pub peak_memory: usize,
+}
+
+impl Limits {
+    pub fn new(memory_size: Option<usize>, tables: Option<usize>) -> Limits {
+        Limits {
+            memory_size,
+            tables: tables.unwrap_or(wasmtime::DEFAULT_TABLE_LIMIT),
+            peak_memory: 0,
+        }
+    }
+
+    // The most memory the guest may have in one place, in bytes: the limit
+    // on each linear memory, or 4 GiB, the most a 32-bit memory can hold.
+    pub fn max_allocation(&self) -> usize {
+        self.memory_size.unwrap_or(1 << 32)
+    }
+}
+
+impl ResourceLimiter for Limits {
+    fn memory_growing(
+        &mut self,
+        _current: usize,
+        desired: usize,
+        maximum: Option<usize>,
+    ) -> Result<bool> {
+        if let Some(limit) = self.memory_size {
+            if desired > limit {
+                return Err(MemoryLimitExceeded { limit, desired }.into());
+            }
+        }
+        let allowed = maximum.is_none_or(|maximum| desired <= maximum);
+        if allowed {
+            self.peak_memory = self.peak_memory.max(desired);
+        }
+        Ok(allowed)
+    }
+
+    fn table_growing(
+        &mut self,
+        _current: usize,
+        desired: usize,
+        maximum: Option<usize>,
+    ) -> Result<bool> {
+        Ok(maximum.is_none_or(|maximum| desired <= maximum))
+    }
+
+    fn tables(&self) -> usize {
+        self.tables
+    }
+}
+
+// Error that stops the guest when a memory would grow past the limit.
+#[derive(Debug)]
+pub struct MemoryLimitExceeded {
+    pub limit: usize,
+    pub desired: usize,
+}
+
+impl fmt::Display for MemoryLimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "memory limit exceeded: the program needs {} bytes of memory, but the limit is {} bytes",
+            self.desired, self.limit
+        )
+    }
+}
+
+impl std::error::Error for MemoryLimitExceeded {}
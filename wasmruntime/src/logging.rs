@@ -0,0 +1,114 @@
+// Logging for Zong programs and their standard library:
+// log(level, msg_slice_ptr) writes the message and a newline to stderr at a
+// level of 0 for debug, 1 for info, 2 for warn, or 3 for error. Messages
+// below --log-level, which is info unless set, are dropped, so that debug
+// output can stay in a program and --log-level off silences it all.
+//
+//   [warn] config file not found, using defaults
+//
+// With --log-timestamps, each message starts with the seconds since the run
+// started, as in `0.012 [warn] ...`. On a terminal the level is colored.
+
+use crate::abi::Version;
+use crate::{guest, slice_abi, HostState};
+use std::time::Instant;
+use wasmtime::{Error, Linker, Result, ValType};
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Default, clap::ValueEnum)]
+pub enum LogLevel {
+    /// Everything, including debug messages
+    Debug,
+    /// Info, warn, and error messages
+    #[default]
+    Info,
+    /// Warn and error messages
+    Warn,
+    /// Only error messages
+    Error,
+    /// No messages
+    Off,
+}
+
+impl LogLevel {
+    // The level a guest passes to log.
+    fn from_guest(level: i32) -> Option<LogLevel> {
+        match level {
+            0 => Some(LogLevel::Debug),
+            1 => Some(LogLevel::Info),
+            2 => Some(LogLevel::Warn),
+            3 => Some(LogLevel::Error),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            LogLevel::Debug => "debug",
+            LogLevel::Info => "info",
+            LogLevel::Warn => "warn",
+            LogLevel::Error => "error",
+            LogLevel::Off => "off",
+        }
+    }
+
+    // The escape sequence coloring the level on a terminal.
+    fn color(self) -> &'static str {
+        match self {
+            LogLevel::Debug => "\x1b[90m",
+            LogLevel::Info => "\x1b[32m",
+            LogLevel::Warn => "\x1b[33m",
+            LogLevel::Error | LogLevel::Off => "\x1b[31m",
+        }
+    }
+}
+
+// Set by --log-level and --log-timestamps.
+#[derive(Default)]
+pub struct Logger {
+    pub level: LogLevel,
+    pub timestamps: bool,
+}
+
+impl Logger {
+    // The line for a message at level, which is not below the filter.
+    fn line(&self, started: Instant, color: bool, level: LogLevel, message: &str) -> String {
+        let mut line = String::new();
+        if self.timestamps {
+            line.push_str(&format!("{:.3} ", started.elapsed().as_secs_f64()));
+        }
+        if color {
+            line.push_str(&format!("[{}{}\x1b[0m] ", level.color(), level.name()));
+        } else {
+            line.push_str(&format!("[{}] ", level.name()));
+        }
+        line.push_str(message);
+        line.push('\n');
+        line
+    }
+}
+
+pub fn add_to_linker(
+    linker: &mut Linker<HostState>,
+    abi: Version,
+    pointer: &ValType,
+) -> Result<()> {
+    slice_abi::define(
+        linker,
+        abi,
+        "log",
+        &[ValType::I32, pointer.clone()],
+        |mut caller, params| -> Result<()> {
+            let (level, msg_slice_ptr) = (params[0].unwrap_i32(), guest::pointer(&params[1]));
+            let level = LogLevel::from_guest(level)
+                .ok_or_else(|| Error::msg(format!("log: invalid level {}", level)))?;
+            if level < caller.data().logger.level {
+                return Ok(());
+            }
+            let message = slice_abi::read_string(&mut caller, "log", msg_slice_ptr)?;
+            let state = caller.data_mut();
+            let color = state.io.stderr_is_terminal();
+            let line = state.logger.line(state.started, color, level, &message);
+            state.write_stderr(line.as_bytes())
+        },
+    )
+}
@@ -0,0 +1,19 @@
+// The env_math import module: f64 math functions, so the Zong compiler can
+// lower its math library to host calls instead of bundling a software
+// floating-point implementation. Each is the Rust f64 method of the same
+// name, except that pow is powf and log is the natural logarithm, ln.
+
+use wasmtime::{Linker, Result};
+
+pub const IMPORT_MODULE: &str = "env_math";
+
+pub fn add_to_linker<T>(linker: &mut Linker<T>) -> Result<()> {
+    linker.func_wrap(IMPORT_MODULE, "sqrt", f64::sqrt)?;
+    linker.func_wrap(IMPORT_MODULE, "sin", f64::sin)?;
+    linker.func_wrap(IMPORT_MODULE, "cos", f64::cos)?;
+    linker.func_wrap(IMPORT_MODULE, "pow", f64::powf)?;
+    linker.func_wrap(IMPORT_MODULE, "log", f64::ln)?;
+    linker.func_wrap(IMPORT_MODULE, "floor", f64::floor)?;
+    linker.func_wrap(IMPORT_MODULE, "ceil", f64::ceil)?;
+    Ok(())
+}
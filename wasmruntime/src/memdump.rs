@@ -0,0 +1,219 @@
+// Annotated views of guest memory, for --memdump and the debugger: a hexdump
+// of a range, or a known Zong layout decoded at an address. A view is given
+// as [LAYOUT@]ADDRESS[:LEN], with numbers in decimal or 0x-prefixed hex:
+//
+//   0x1000:64       64 bytes from 0x1000 as hex and ASCII
+//   slice@0x1ff0    the slice structure at 0x1ff0, and up to LEN bytes of
+//                   its contents
+//
+// A hexdump marks the row that the tstack pointer is in. --trace names each
+// slice it shows by the view that decodes it, so that a call's arguments can
+// be looked at in full.
+
+use crate::guest::{self, GuestData, GuestMemory};
+use crate::slice_abi::Layout;
+use crate::HostState;
+use std::fmt::Write as _;
+use wasmtime::{Error, Extern, Instance, Result, Store};
+
+// Bytes per row of a hexdump.
+const ROW: usize = 16;
+
+// Bytes a hexdump shows without a LEN.
+const DEFAULT_HEX_LEN: u64 = 64;
+
+// Bytes of a slice's contents shown without a LEN.
+const DEFAULT_SLICE_LEN: u64 = 256;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum View {
+    Hex,
+    // [ptr at offset 0, len: i64 at offset 8]
+    Slice,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Spec {
+    pub view: View,
+    pub address: u64,
+    pub len: u64,
+}
+
+fn number(value: &str) -> Option<u64> {
+    match value.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    }
+}
+
+impl Spec {
+    pub fn parse(value: &str) -> Result<Spec, String> {
+        let (view, rest) = match value.split_once('@') {
+            Some(("hex", rest)) => (View::Hex, rest),
+            Some(("slice", rest)) => (View::Slice, rest),
+            Some((view, _)) => {
+                return Err(format!("unknown layout {}; expected hex or slice", view))
+            }
+            None => (View::Hex, value),
+        };
+        let (address, len) = match rest.split_once(':') {
+            Some((address, len)) => (number(address), number(len)),
+            None => (
+                number(rest),
+                Some(match view {
+                    View::Hex => DEFAULT_HEX_LEN,
+                    View::Slice => DEFAULT_SLICE_LEN,
+                }),
+            ),
+        };
+        match (address, len) {
+            (Some(address), Some(len)) => Ok(Spec { view, address, len }),
+            _ => Err(String::from(
+                "expected [LAYOUT@]ADDRESS[:LEN], such as 0x1000:64 or slice@4096",
+            )),
+        }
+    }
+
+    // The view's lines, given where the tstack pointer is if known.
+    pub fn lines(self, memory: &[u8], layout: Layout, tstack: Option<u64>) -> Result<Vec<String>> {
+        let address = usize::try_from(self.address).unwrap_or(usize::MAX);
+        let len = usize::try_from(self.len).unwrap_or(usize::MAX);
+        match self.view {
+            View::Hex => hexdump(memory, address, len, tstack),
+            View::Slice => slice(memory, address, len, layout),
+        }
+    }
+}
+
+// Formats bytes of memory as hex and ASCII, 16 per row.
+pub fn hexdump(
+    memory: &[u8],
+    address: usize,
+    len: usize,
+    tstack: Option<u64>,
+) -> Result<Vec<String>> {
+    let range = guest::range(GuestData::Local(memory), "memdump", address, len)?;
+    let mut lines = Vec::new();
+    for (row, bytes) in memory[range].chunks(ROW).enumerate() {
+        let start = address + row * ROW;
+        let hex: Vec<String> = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+        let text: String = bytes
+            .iter()
+            .map(|&byte| {
+                if byte.is_ascii_graphic() || byte == b' ' {
+                    byte as char
+                } else {
+                    '.'
+                }
+            })
+            .collect();
+        let mut line = format!(
+            "{:08x}  {:<width$}  {}",
+            start,
+            hex.join(" "),
+            text,
+            width = ROW * 3 - 1
+        );
+        if let Some(tstack) =
+            tstack.filter(|tstack| (start as u64..(start + bytes.len()) as u64).contains(tstack))
+        {
+            let _ = write!(
+                line,
+                "{:pad$}  <- tstack {:#x}",
+                "",
+                tstack,
+                pad = ROW - bytes.len()
+            );
+        }
+        lines.push(line);
+    }
+    Ok(lines)
+}
+
+// Decodes the slice structure at address, showing up to shown bytes of its
+// contents.
+pub fn slice(memory: &[u8], address: usize, shown: usize, layout: Layout) -> Result<Vec<String>> {
+    let field = |offset: usize, len: usize| {
+        address
+            .checked_add(offset)
+            .and_then(|start| memory.get(start..start.checked_add(len)?))
+            .ok_or_else(|| {
+                Error::msg(format!(
+                    "slice at {:#x} is outside memory of {} bytes",
+                    address,
+                    memory.len()
+                ))
+            })
+    };
+    let ptr = if layout.memory64 {
+        u64::from_le_bytes(field(0, 8)?.try_into().unwrap())
+    } else {
+        u32::from_le_bytes(field(0, 4)?.try_into().unwrap()) as u64
+    };
+    let len = i64::from_le_bytes(field(8, 8)?.try_into().unwrap());
+    let mut lines = vec![
+        format!("slice at {:#x}:", address),
+        format!("  ptr: {:#x}", ptr),
+        format!("  len: {}", len),
+    ];
+    let contents = usize::try_from(ptr)
+        .ok()
+        .zip(usize::try_from(len).ok())
+        .and_then(|(ptr, len)| memory.get(ptr..ptr.checked_add(len)?));
+    lines.push(match contents {
+        Some(contents) => {
+            let text = contents[..contents.len().min(shown)]
+                .escape_ascii()
+                .to_string();
+            if shown < contents.len() {
+                format!(
+                    "  bytes: \"{}\" ... ({} more)",
+                    text,
+                    contents.len() - shown
+                )
+            } else {
+                format!("  bytes: \"{}\"", text)
+            }
+        }
+        None => String::from("  bytes: outside memory"),
+    });
+    Ok(lines)
+}
+
+// Names the slice structure at slice_ptr as the view that decodes it.
+pub fn slice_view(slice_ptr: u64) -> String {
+    format!("slice@{:#x}", slice_ptr)
+}
+
+// Names len bytes at address as the view that dumps them.
+pub fn hex_view(address: u64, len: u64) -> String {
+    format!("{:#x}:{}", address, len)
+}
+
+// Prints the --memdump views to stderr once the guest has stopped.
+pub fn print(store: &mut Store<HostState>, instance: &Instance, specs: &[Spec]) -> Result<()> {
+    let memory = match instance.get_export(&mut *store, "memory") {
+        Some(Extern::Memory(memory)) => GuestMemory::Local(memory),
+        Some(Extern::SharedMemory(memory)) => GuestMemory::Shared(memory),
+        _ => return Err(Error::msg("--memdump: the module does not export a memory")),
+    };
+    let tstack = instance
+        .get_global(&mut *store, "tstack")
+        .map(|tstack| guest::pointer(&tstack.get(&mut *store)));
+    let layout = store.data().slice_layout();
+    store.data_mut().flush_stdout()?;
+    for spec in specs {
+        for line in spec.lines(&memory.data(&*store).contents(), layout, tstack)? {
+            eprintln!("{}", line);
+        }
+    }
+    Ok(())
+}
+
+// The memory's view at spec, for the debugger's memdump command.
+pub fn print_view(memory: &[u8], spec: Spec, layout: Layout, tstack: Option<u64>) -> Result<()> {
+    for line in spec.lines(memory, layout, tstack)? {
+        println!("{}", line);
+    }
+    Ok(())
+}
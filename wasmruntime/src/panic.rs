@@ -0,0 +1,18 @@
+// Guest panics. The compiler emits a call to panic(msg_slice_ptr) for failed
+// assertions and bounds checks, which stops the guest with a GuestPanic
+// error. It is reported like a trap, with the guest's backtrace, but with the
+// guest's message in place of a trap description.
+
+use std::fmt;
+
+// Error that stops the guest when it panics.
+#[derive(Debug)]
+pub struct GuestPanic(pub String);
+
+impl fmt::Display for GuestPanic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Zong panic: {}", self.0)
+    }
+}
+
+impl std::error::Error for GuestPanic {}
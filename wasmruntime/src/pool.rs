@@ -0,0 +1,183 @@
+// Concurrent runs for embedders, such as a server running each request's
+// program, or a batch job running thousands: a Pool runs Jobs on a fixed
+// number of threads and hands back their results in the order they finish.
+// Each job's program runs in an instance of its own, as with
+// CompiledProgram::instantiate_with_limits, so jobs share nothing but the
+// compiled code: each has its own memory, stdin, captured stdout and stderr,
+// and fuel, memory, and time limits.
+//
+// Timeouts are enforced by epoch checks, so the Pool has a thread that
+// advances the Host's engine epoch every TICK while it exists. Only
+// instances whose timeout has passed stop at those checks; stores made on
+// the same engine other than through Runtime or CompiledProgram need an
+// epoch deadline callback of their own to let the guest continue.
+
+use crate::runtime::{CompiledProgram, Host, RunLimits};
+use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+use wasmtime::{Engine, Error, Result};
+
+// How often the epoch advances, and so about how late a timeout stops the
+// guest.
+const TICK: Duration = Duration::from_millis(10);
+
+/// A run of a program for a Pool.
+pub struct Job {
+    /// The program, compiled with the Pool's Host.
+    pub program: CompiledProgram,
+    pub stdin: Vec<u8>,
+    /// Program arguments, those after -- on the runtime's command line.
+    pub args: Vec<String>,
+    pub limits: RunLimits,
+}
+
+/// How a Job ended.
+pub struct JobResult {
+    /// The number Pool::submit gave the job.
+    pub id: usize,
+    /// The exit code, as ProgramInstance::run_main gives it, or the error
+    /// that stopped the guest, such as a trap or a limit it reached.
+    pub exit_code: Result<i32>,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+    /// Fuel consumed, if the job had a fuel limit.
+    pub fuel_consumed: Option<u64>,
+}
+
+/// Runs Jobs concurrently on a fixed number of threads.
+pub struct Pool {
+    engine: Engine,
+    jobs: Option<Sender<(usize, Job)>>,
+    results: Receiver<JobResult>,
+    workers: Vec<JoinHandle<()>>,
+    ticker: Option<JoinHandle<()>>,
+    stopping: Arc<AtomicBool>,
+    submitted: usize,
+    finished: usize,
+}
+
+impl Pool {
+    /// Starts a pool of the given number of threads, for programs compiled
+    /// with host.
+    pub fn new(host: &Host, threads: NonZeroUsize) -> Pool {
+        let (jobs, queue) = mpsc::channel::<(usize, Job)>();
+        let (done, results) = mpsc::channel();
+        let queue = Arc::new(Mutex::new(queue));
+        let workers = (0..threads.get())
+            .map(|_| {
+                let queue = queue.clone();
+                let done = done.clone();
+                std::thread::spawn(move || loop {
+                    let next = queue.lock().unwrap().recv();
+                    let Ok((id, job)) = next else {
+                        return;
+                    };
+                    if done.send(run(id, job)).is_err() {
+                        return;
+                    }
+                })
+            })
+            .collect();
+
+        let engine = host.engine().clone();
+        let stopping = Arc::new(AtomicBool::new(false));
+        let ticker = {
+            let engine = engine.clone();
+            let stopping = stopping.clone();
+            std::thread::spawn(move || {
+                while !stopping.load(Ordering::SeqCst) {
+                    std::thread::sleep(TICK);
+                    engine.increment_epoch();
+                }
+            })
+        };
+        Pool {
+            engine,
+            jobs: Some(jobs),
+            results,
+            workers,
+            ticker: Some(ticker),
+            stopping,
+            submitted: 0,
+            finished: 0,
+        }
+    }
+
+    /// Queues a job and returns its id, which its JobResult has. Fails if
+    /// the program was compiled with a different Host, whose epoch the Pool
+    /// does not advance.
+    pub fn submit(&mut self, job: Job) -> Result<usize> {
+        if !Engine::same(job.program.engine(), &self.engine) {
+            return Err(Error::msg(
+                "the job's program was compiled with a different Host than the Pool's",
+            ));
+        }
+        let id = self.submitted;
+        self.jobs
+            .as_ref()
+            .unwrap()
+            .send((id, job))
+            .map_err(|_| Error::msg("the pool's threads have stopped"))?;
+        self.submitted += 1;
+        Ok(id)
+    }
+}
+
+/// Waits for the next job to finish and gives its result, ending once every
+/// job submitted so far has been given.
+impl Iterator for Pool {
+    type Item = JobResult;
+
+    fn next(&mut self) -> Option<JobResult> {
+        if self.finished == self.submitted {
+            return None;
+        }
+        let result = self.results.recv().ok()?;
+        self.finished += 1;
+        Some(result)
+    }
+}
+
+impl Drop for Pool {
+    // Waits for the jobs already submitted, dropping their results.
+    fn drop(&mut self) {
+        self.jobs = None;
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+        self.stopping.store(true, Ordering::SeqCst);
+        if let Some(ticker) = self.ticker.take() {
+            let _ = ticker.join();
+        }
+    }
+}
+
+fn run(id: usize, job: Job) -> JobResult {
+    let mut instance = match job
+        .program
+        .instantiate_with_limits(&job.stdin, &job.args, job.limits)
+    {
+        Ok(instance) => instance,
+        Err(error) => {
+            return JobResult {
+                id,
+                exit_code: Err(error),
+                stdout: Vec::new(),
+                stderr: Vec::new(),
+                fuel_consumed: None,
+            }
+        }
+    };
+    let exit_code = instance.run_main();
+    JobResult {
+        id,
+        exit_code,
+        stdout: instance.stdout(),
+        stderr: instance.stderr(),
+        fuel_consumed: instance.fuel_consumed(),
+    }
+}
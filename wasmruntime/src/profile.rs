@@ -0,0 +1,98 @@
+// Profiling for --profile and --sample-profile. --profile has wasmtime
+// describe the compiled code to an external profiler: a jitdump file for
+// `perf record -k mono` and `perf inject --jit`, or VTune's JIT API.
+//
+// --sample-profile needs no external tools. A background thread bumps the
+// engine's epoch every SAMPLE_INTERVAL, and at its next epoch check the guest
+// records its call stack. The stacks are written out in the collapsed format
+// that flamegraph.pl and inferno read, one line per stack, callers first:
+//
+//   main;compute;divide 12
+//
+// Guests only check the epoch in wasm code, so time spent blocked in a host
+// call is counted at the first check after it returns.
+
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use wasmtime::{Engine, Error, ProfilingStrategy, Result, WasmBacktrace};
+
+const SAMPLE_INTERVAL: Duration = Duration::from_millis(1);
+
+#[derive(Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Profiler {
+    /// Write a jitdump file for perf
+    Perf,
+    /// Report compiled code to VTune
+    Vtune,
+}
+
+impl Profiler {
+    pub fn strategy(self) -> ProfilingStrategy {
+        match self {
+            Profiler::Perf => ProfilingStrategy::JitDump,
+            Profiler::Vtune => ProfilingStrategy::VTune,
+        }
+    }
+}
+
+pub struct Sampler {
+    // Sample counts by collapsed stack.
+    stacks: HashMap<String, u64>,
+    stopped: Arc<AtomicBool>,
+}
+
+impl Sampler {
+    pub fn start(engine: &Engine) -> Sampler {
+        let stopped = Arc::new(AtomicBool::new(false));
+        let engine = engine.clone();
+        let thread_stopped = stopped.clone();
+        std::thread::spawn(move || {
+            while !thread_stopped.load(Ordering::SeqCst) {
+                std::thread::sleep(SAMPLE_INTERVAL);
+                engine.increment_epoch();
+            }
+        });
+        Sampler {
+            stacks: HashMap::new(),
+            stopped,
+        }
+    }
+
+    pub fn record(&mut self, backtrace: &WasmBacktrace) {
+        let mut stack = String::new();
+        for frame in backtrace.frames().iter().rev() {
+            if !stack.is_empty() {
+                stack.push(';');
+            }
+            match frame.func_name() {
+                Some(name) => stack.push_str(name),
+                None => write!(stack, "<function {}>", frame.func_index()).unwrap(),
+            }
+        }
+        if !stack.is_empty() {
+            *self.stacks.entry(stack).or_insert(0) += 1;
+        }
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let mut stacks: Vec<_> = self.stacks.iter().collect();
+        stacks.sort();
+        let mut collapsed = String::new();
+        for (stack, samples) in stacks {
+            writeln!(collapsed, "{} {}", stack, samples)?;
+        }
+        fs::write(path, collapsed)
+            .map_err(|error| Error::new(error).context(path.display().to_string()))
+    }
+}
+
+impl Drop for Sampler {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::SeqCst);
+    }
+}
@@ -0,0 +1,91 @@
+// Progress reporting for long-running guest computations. On a terminal the
+// progress is drawn as a bar that redraws in place on stderr; otherwise it is
+// logged as plain lines, at most once per LOG_INTERVAL.
+
+use crate::hostio::HostIo;
+use std::time::{Duration, Instant};
+
+const BAR_WIDTH: usize = 30;
+const REDRAW_INTERVAL: Duration = Duration::from_millis(50);
+const LOG_INTERVAL: Duration = Duration::from_secs(1);
+
+pub struct Progress {
+    label: String,
+    total: i64,
+    current: i64,
+    is_tty: bool,
+    last_report: Option<Instant>,
+}
+
+impl Progress {
+    pub fn begin(label: String, total: i64, io: &mut HostIo) -> Progress {
+        let mut progress = Progress {
+            label,
+            total,
+            current: 0,
+            is_tty: io.stderr_is_terminal(),
+            last_report: None,
+        };
+        progress.report(true, io);
+        progress
+    }
+
+    pub fn update(&mut self, current: i64, io: &mut HostIo) {
+        self.current = current;
+        self.report(false, io);
+    }
+
+    pub fn end(mut self, io: &mut HostIo) {
+        self.current = self.current.max(self.total);
+        self.report(true, io);
+        if self.is_tty {
+            let _ = io.write_stderr(b"\n");
+        }
+    }
+
+    fn report(&mut self, force: bool, io: &mut HostIo) {
+        let interval = if self.is_tty {
+            REDRAW_INTERVAL
+        } else {
+            LOG_INTERVAL
+        };
+        let now = Instant::now();
+        if !force && self.last_report.is_some_and(|last| now - last < interval) {
+            return;
+        }
+        self.last_report = Some(now);
+
+        let line = if self.is_tty {
+            // Redraw the bar in place and clear any leftover characters
+            format!("\r{} {}\x1b[K", self.label, self.bar())
+        } else {
+            format!("{}: {}\n", self.label, self.counts())
+        };
+        let _ = io.write_stderr(line.as_bytes());
+    }
+
+    fn bar(&self) -> String {
+        let filled = if self.total > 0 {
+            (self.current.clamp(0, self.total) as f64 / self.total as f64 * BAR_WIDTH as f64)
+                as usize
+        } else {
+            0
+        };
+        format!(
+            "[{}{}] {}",
+            "#".repeat(filled),
+            "-".repeat(BAR_WIDTH - filled),
+            self.counts()
+        )
+    }
+
+    fn counts(&self) -> String {
+        if self.total > 0 {
+            let percent = self.current.clamp(0, self.total) * 100 / self.total;
+            format!("{}/{} ({}%)", self.current, self.total, percent)
+        } else {
+            // Unknown total: only the count is meaningful
+            self.current.to_string()
+        }
+    }
+}
@@ -0,0 +1,206 @@
+// Host strings as externrefs, for prototyping garbage-collected Zong strings
+// and objects with the reference types and GC proposals. A host string lives
+// in the runtime, outside linear memory; the guest holds it as an externref,
+// which it can keep in locals, globals, and tables but not in memory, and
+// which the engine's collector frees once nothing refers to it.
+//
+//   host_intern_string(slice_ptr) -> externref copies the slice into a host
+//     string, replacing invalid UTF-8. Strings with the same bytes share one
+//     copy.
+//   host_string_len(ref) -> i64 returns its length in bytes.
+//   host_string_get(ref, dest_slice_ptr) writes its bytes as a slice on the
+//     tstack.
+//   host_string_eq(a, b) -> i32 returns 1 if both have the same bytes, or 0.
+//
+// Code that cannot hold an externref, such as a struct in linear memory,
+// keeps a handle to one instead, from a table of references in the host:
+//
+//   host_ref_store(ref) -> i32 puts the reference in a free slot and returns
+//     the slot's handle, which is never 0.
+//   host_ref_load(handle) -> externref returns the reference in a slot.
+//   host_ref_drop(handle) frees the slot.
+//
+// A null reference, or one the runtime did not create, is an error for all
+// but host_ref_store, which stores whatever it is given. Interned strings are
+// kept for the life of the instance, up to MAX_INTERNED_BYTES in all.
+
+use crate::abi::Version;
+use crate::{guest, slice_abi, HostState};
+use std::collections::HashSet;
+use std::sync::Arc;
+use wasmtime::{AsContext, Caller, Error, ExternRef, Linker, Result, Rooted, Val, ValType};
+
+const MAX_INTERNED_BYTES: usize = 64 << 20;
+
+const MAX_SLOTS: usize = 1 << 20;
+
+// The payload of the externrefs the runtime creates.
+struct HostString(Arc<str>);
+
+#[derive(Default)]
+pub struct Refs {
+    interned: HashSet<Arc<str>>,
+    interned_bytes: usize,
+    // Slot handle - 1 indexes this; freed slots are None.
+    slots: Vec<Option<Stored>>,
+    free: Vec<usize>,
+}
+
+// A reference the guest stored, which is a host string or null.
+type Stored = Option<Arc<str>>;
+
+fn string<'a>(
+    store: &'a impl AsContext,
+    function: &str,
+    externref: Option<Rooted<ExternRef>>,
+) -> Result<&'a Arc<str>> {
+    let externref = externref.ok_or_else(|| Error::msg(format!("{}: null reference", function)))?;
+    externref
+        .data(store)?
+        .downcast_ref::<HostString>()
+        .map(|string| &string.0)
+        .ok_or_else(|| Error::msg(format!("{}: not a host string", function)))
+}
+
+fn new_ref(
+    caller: &mut Caller<'_, HostState>,
+    string: Option<Arc<str>>,
+) -> Result<Option<Rooted<ExternRef>>> {
+    string
+        .map(|string| ExternRef::new(&mut *caller, HostString(string)))
+        .transpose()
+}
+
+impl slice_abi::HostResult for Option<Rooted<ExternRef>> {
+    fn types() -> Vec<ValType> {
+        vec![ValType::EXTERNREF]
+    }
+
+    fn write(self, results: &mut [Val]) {
+        results[0] = Val::ExternRef(self);
+    }
+}
+
+impl Refs {
+    fn intern(&mut self, text: String) -> Result<Arc<str>> {
+        if let Some(string) = self.interned.get(text.as_str()) {
+            return Ok(string.clone());
+        }
+        if self.interned_bytes + text.len() > MAX_INTERNED_BYTES {
+            return Err(Error::msg(format!(
+                "host_intern_string: interned strings would exceed {} bytes",
+                MAX_INTERNED_BYTES
+            )));
+        }
+        let string: Arc<str> = Arc::from(text);
+        self.interned_bytes += string.len();
+        self.interned.insert(string.clone());
+        Ok(string)
+    }
+
+    fn slot(&mut self, function: &str, handle: i32) -> Result<&mut Option<Stored>> {
+        usize::try_from(handle)
+            .ok()
+            .and_then(|handle| handle.checked_sub(1))
+            .and_then(|index| self.slots.get_mut(index))
+            .filter(|slot| slot.is_some())
+            .ok_or_else(|| Error::msg(format!("{}: invalid reference handle {}", function, handle)))
+    }
+}
+
+pub fn add_to_linker(
+    linker: &mut Linker<HostState>,
+    abi: Version,
+    pointer: &ValType,
+) -> Result<()> {
+    slice_abi::define(
+        linker,
+        abi,
+        "host_intern_string",
+        std::slice::from_ref(pointer),
+        |mut caller, params| -> Result<Option<Rooted<ExternRef>>> {
+            let slice_ptr = guest::pointer(&params[0]);
+            let text = slice_abi::read_string(&mut caller, "host_intern_string", slice_ptr)?;
+            let string = caller.data_mut().refs.intern(text)?;
+            new_ref(&mut caller, Some(string))
+        },
+    )?;
+    linker.func_wrap(
+        "env",
+        "host_string_len",
+        |caller: Caller<'_, HostState>, string_ref: Option<Rooted<ExternRef>>| -> Result<i64> {
+            Ok(string(&caller, "host_string_len", string_ref)?.len() as i64)
+        },
+    )?;
+    slice_abi::define(
+        linker,
+        abi,
+        "host_string_get",
+        &[ValType::EXTERNREF, pointer.clone()],
+        |mut caller, params| -> Result<()> {
+            let string_ref = params[0].unwrap_externref().copied();
+            let dest_addr = guest::pointer(&params[1]) as usize;
+            let text = string(&caller, "host_string_get", string_ref)?.clone();
+            slice_abi::push_slice(&mut caller, "host_string_get", dest_addr, text.as_bytes())
+        },
+    )?;
+    linker.func_wrap(
+        "env",
+        "host_string_eq",
+        |caller: Caller<'_, HostState>,
+         a: Option<Rooted<ExternRef>>,
+         b: Option<Rooted<ExternRef>>|
+         -> Result<i32> {
+            let a = string(&caller, "host_string_eq", a)?;
+            let b = string(&caller, "host_string_eq", b)?;
+            Ok((a == b) as i32)
+        },
+    )?;
+
+    linker.func_wrap(
+        "env",
+        "host_ref_store",
+        |mut caller: Caller<'_, HostState>, externref: Option<Rooted<ExternRef>>| -> Result<i32> {
+            let stored = match externref {
+                Some(_) => Some(string(&caller, "host_ref_store", externref)?.clone()),
+                None => None,
+            };
+            let refs = &mut caller.data_mut().refs;
+            let index = match refs.free.pop() {
+                Some(index) => index,
+                None if refs.slots.len() < MAX_SLOTS => {
+                    refs.slots.push(None);
+                    refs.slots.len() - 1
+                }
+                None => {
+                    return Err(Error::msg(format!(
+                        "host_ref_store: all {} slots are in use",
+                        MAX_SLOTS
+                    )))
+                }
+            };
+            refs.slots[index] = Some(stored);
+            Ok(index as i32 + 1)
+        },
+    )?;
+    linker.func_wrap(
+        "env",
+        "host_ref_load",
+        |mut caller: Caller<'_, HostState>, handle: i32| -> Result<Option<Rooted<ExternRef>>> {
+            let slot = caller.data_mut().refs.slot("host_ref_load", handle)?;
+            let stored = slot.clone().flatten();
+            new_ref(&mut caller, stored)
+        },
+    )?;
+    linker.func_wrap(
+        "env",
+        "host_ref_drop",
+        |mut caller: Caller<'_, HostState>, handle: i32| -> Result<()> {
+            let refs = &mut caller.data_mut().refs;
+            *refs.slot("host_ref_drop", handle)? = None;
+            refs.free.push(handle as usize - 1);
+            Ok(())
+        },
+    )?;
+    Ok(())
+}
@@ -0,0 +1,130 @@
+// `wasmruntime repl`: calls a module's exports interactively. Each line of
+// stdin is an export name and its arguments, like `add 2 3`, parsed as
+// `invoke` parses them; the results are printed one per line. The module is
+// instantiated once, so memory and globals carry over between calls, and a
+// call that traps is reported without ending the session. With --on-trap
+// restart, the trap also replaces the instance with a fresh one, for when the
+// trap may have left memory half updated. Lines starting with
+// a colon are commands: `:exports` lists the function exports and `:quit`
+// ends the session, as does end of input.
+//
+// A line whose first word is not an export is Zong code. An expression, like
+// `1 + 2`, is compiled as a program that prints it, and a line ending in a
+// semicolon as a main function of those statements. The program is built by
+// running `COMPILER build -o OUT.wasm IN.zong` and runs in an instance of its
+// own, with the same host: Zong cannot name another program's memory or
+// globals, so the session's are left as they were.
+
+use crate::{backtrace, commands, read_module, Host, Runtime};
+use std::fs;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::path::Path;
+use std::process::Command;
+use wasmtime::{Error, Result};
+
+// What the session does with the instance after a call traps.
+#[derive(Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum OnTrap {
+    /// Go on with the instance as the trap left it
+    Keep,
+    /// Go on with a fresh instance
+    Restart,
+}
+
+pub fn run(wasm_file: &Path, compiler: &str, on_trap: OnTrap) -> Result<i32> {
+    let host = Host::new()?;
+    let wasm_bytes = read_module(wasm_file)?;
+    let mut runtime = Runtime::with_host(&host, &wasm_bytes)?;
+    let interactive = io::stdin().is_terminal();
+    let mut line = String::new();
+    loop {
+        if interactive {
+            print!("> ");
+            io::stdout().flush()?;
+        }
+        line.clear();
+        if io::stdin().lock().read_line(&mut line)? == 0 {
+            break;
+        }
+        let mut words = line.split_whitespace();
+        let Some(name) = words.next() else {
+            continue;
+        };
+        let args: Vec<String> = words.map(String::from).collect();
+        match name {
+            ":quit" => break,
+            ":exports" => print_exports(&runtime),
+            _ => {
+                let is_export = runtime.module().get_export(name).is_some();
+                let result = if is_export {
+                    call(&mut runtime, name, &args)
+                } else {
+                    evaluate(&host, compiler, line.trim())
+                };
+                let Err(error) = result else {
+                    continue;
+                };
+                // Errors with a backtrace stopped the guest, unlike bad
+                // arguments
+                let trapped = backtrace::print_error(&error, false);
+                if !trapped {
+                    eprintln!("Error: {:#}", error);
+                }
+                if trapped && is_export && on_trap == OnTrap::Restart {
+                    runtime = Runtime::with_host(&host, &wasm_bytes)?;
+                    eprintln!("restarted with a fresh instance");
+                }
+            }
+        }
+    }
+    Ok(0)
+}
+
+fn call(runtime: &mut Runtime, name: &str, args: &[String]) -> Result<()> {
+    let ty = runtime
+        .module()
+        .get_export(name)
+        .and_then(|ty| ty.func().cloned())
+        .ok_or_else(|| Error::msg(format!("no function export '{}'", name)))?;
+    let args = commands::parse_args(&ty, name, args)?;
+    commands::print_results(&runtime.invoke(name, &args)?);
+    Ok(())
+}
+
+fn evaluate(host: &Host, compiler: &str, code: &str) -> Result<()> {
+    let source = if code.ends_with(';') {
+        format!("func main() {{\n    {}\n}}\n", code)
+    } else {
+        format!("func main() {{\n    print({});\n}}\n", code)
+    };
+    let base = std::env::temp_dir().join(format!("zong-repl-{}", std::process::id()));
+    let source_path = base.with_extension("zong");
+    let wasm_path = base.with_extension("wasm");
+    fs::write(&source_path, source)?;
+    let output = Command::new(compiler)
+        .arg("build")
+        .arg("-o")
+        .arg(&wasm_path)
+        .arg(&source_path)
+        .output();
+    let _ = fs::remove_file(&source_path);
+    let output = output
+        .map_err(|error| Error::msg(format!("cannot run the compiler {}: {}", compiler, error)))?;
+    if !output.status.success() {
+        let _ = fs::remove_file(&wasm_path);
+        let message = String::from_utf8_lossy(&output.stderr);
+        return Err(Error::msg(message.trim().to_string()));
+    }
+    let wasm_bytes = fs::read(&wasm_path);
+    let _ = fs::remove_file(&wasm_path);
+    Runtime::with_host(host, &wasm_bytes?)?.run_main()?;
+    Ok(())
+}
+
+fn print_exports(runtime: &Runtime) {
+    for export in runtime.module().exports() {
+        if let Some(ty) = export.ty().func() {
+            println!("{}: {}", export.name(), ty);
+        }
+    }
+}
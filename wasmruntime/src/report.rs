@@ -0,0 +1,216 @@
+// Machine-readable run reports for --output json. Instead of printing errors
+// and fuel use as text, the runtime writes one JSON object to stderr when the
+// run ends, as its last line of output:
+//
+//   {"exit_code":134,"error":"wasm trap: integer divide by zero",
+//    "backtrace":[{"index":3,"name":"main","offset":83}],"time_secs":0.0012,
+//    "memory_pages":17,"fuel_consumed":null,"stdout_bytes":12,"stderr_bytes":0}
+//
+// error and backtrace are null and empty if the guest finished. time_secs
+// covers instantiating the module and calling its entry point, and is null,
+// like memory_pages, if the run failed before that. Memory never shrinks, so
+// its final size is also its peak. fuel_consumed is null unless the run
+// consumes fuel. The byte counts are what the guest wrote to stdout and
+// stderr through the Zong print functions and WASI's fd_write.
+//
+// A run that got as far as the guest also has an "environment" object, with
+// what else the result depended on, for understanding and reproducing it
+// later:
+//
+//   "environment":{"runtime_version":"0.1.0","wasmtime_version":"26.0",
+//    "module_sha256":"9f2c...","seed":null,"fixed_time":null,
+//    "env":["HOME","PATH"],"stdin_sha256":null,
+//    "engine":{"backend":"cranelift","fuel":false,"deterministic":false,
+//     "debug_info":false,"features":["simd","memory64",...]}}
+//
+// seed and fixed_time are those set by --seed, --fixed-time, or
+// --deterministic. env names the host environment variables the guest could
+// read. stdin_sha256 is the hash of the stdin given by --stdin or a repro
+// manifest, and is null if the guest read the process's stdin.
+
+use crate::{call_wasi, guest, HostState, RunOutcome, WASI_MODULE};
+use serde::Serialize;
+use std::time::Duration;
+use wasmtime::{Caller, Error, Linker, Result, WasmBacktrace};
+
+// The wasmtime the runtime is built with, as in Cargo.toml.
+pub const WASMTIME_VERSION: &str = "26.0";
+
+#[derive(Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputFormat {
+    /// Report errors as text
+    Text,
+    /// Report how the run ended as a JSON object on stderr
+    Json,
+}
+
+// Bytes the guest has read from stdin and written to stdout and stderr.
+#[derive(Default)]
+pub struct StdioCounts {
+    pub stdin: u64,
+    pub stdout: u64,
+    pub stderr: u64,
+}
+
+#[derive(Serialize)]
+struct Frame {
+    index: u32,
+    name: Option<String>,
+    offset: Option<usize>,
+}
+
+#[derive(Serialize, Default)]
+pub struct Report {
+    pub exit_code: i32,
+    error: Option<String>,
+    backtrace: Vec<Frame>,
+    pub time_secs: Option<f64>,
+    pub memory_pages: Option<u64>,
+    pub fuel_consumed: Option<u64>,
+    stdout_bytes: u64,
+    stderr_bytes: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub environment: Option<RunEnvironment>,
+}
+
+#[derive(Serialize)]
+pub struct RunEnvironment {
+    pub runtime_version: &'static str,
+    pub wasmtime_version: &'static str,
+    pub module_sha256: String,
+    pub seed: Option<u64>,
+    pub fixed_time: Option<u64>,
+    pub env: Vec<String>,
+    pub stdin_sha256: Option<String>,
+    pub engine: EngineSettings,
+}
+
+// How the engine that ran the module was configured.
+#[derive(Serialize)]
+pub struct EngineSettings {
+    pub backend: String,
+    pub fuel: bool,
+    pub deterministic: bool,
+    pub debug_info: bool,
+    // The wasm proposals modules were validated against.
+    pub features: Vec<String>,
+}
+
+impl Report {
+    pub fn new(exit_code: i32, output: Option<&StdioCounts>, elapsed: Duration) -> Report {
+        Report {
+            exit_code,
+            time_secs: Some(elapsed.as_secs_f64()),
+            stdout_bytes: output.map_or(0, |output| output.stdout),
+            stderr_bytes: output.map_or(0, |output| output.stderr),
+            ..Report::default()
+        }
+    }
+
+    // Records the error that stopped the guest, with its backtrace.
+    pub fn set_error(&mut self, error: &Error) {
+        self.error = Some(error.root_cause().to_string());
+        if let Some(backtrace) = error.downcast_ref::<WasmBacktrace>() {
+            self.backtrace = backtrace
+                .frames()
+                .iter()
+                .map(|frame| Frame {
+                    index: frame.func_index(),
+                    name: frame.func_name().map(String::from),
+                    offset: frame.module_offset(),
+                })
+                .collect();
+        }
+    }
+
+    pub fn print(&self) {
+        eprintln!(
+            "{}",
+            serde_json::to_string(self).expect("reports serialize to JSON")
+        );
+    }
+}
+
+// Reports an error that ended the run before the guest started, e.g. an
+// unreadable module, and returns the process exit code.
+pub fn print_error(error: &Error) -> RunOutcome {
+    let outcome = RunOutcome::for_error(error);
+    let mut report = Report {
+        exit_code: outcome.exit_code(),
+        ..Report::default()
+    };
+    report.error = Some(format!("{:#}", error));
+    report.print();
+    outcome
+}
+
+// Re-registers WASI's fd_write and fd_read with wrappers that count the bytes
+// written to stdout and stderr and read from stdin.
+pub fn count_wasi_stdio(linker: &mut Linker<HostState>) -> Result<()> {
+    use wasmtime_wasi::preview1::wasi_snapshot_preview1 as p1;
+    use wasmtime_wasi::runtime::in_tokio;
+
+    linker.allow_shadowing(true);
+    linker.func_wrap(
+        WASI_MODULE,
+        "fd_write",
+        |mut caller: Caller<'_, HostState>,
+         fd: i32,
+         iovs_ptr: i32,
+         iovs_len: i32,
+         nwritten_ptr: i32|
+         -> Result<i32> {
+            let errno = call_wasi(&mut caller, |ctx, memory| {
+                in_tokio(p1::fd_write(
+                    ctx,
+                    memory,
+                    fd,
+                    iovs_ptr,
+                    iovs_len,
+                    nwritten_ptr,
+                ))
+            })?;
+            if errno == 0 && (fd == 1 || fd == 2) {
+                // wasmtime-wasi has written nwritten if the call succeeded
+                let memory = guest::memory(&mut caller, "fd_write")?;
+                let data = memory.data(&caller);
+                let written = guest::read(data, "fd_write", guest::address(nwritten_ptr))?;
+                let written = u32::from_le_bytes(written) as u64;
+                if let Some(output) = &mut caller.data_mut().stdio {
+                    match fd {
+                        1 => output.stdout += written,
+                        _ => output.stderr += written,
+                    }
+                }
+            }
+            Ok(errno)
+        },
+    )?;
+    linker.func_wrap(
+        WASI_MODULE,
+        "fd_read",
+        |mut caller: Caller<'_, HostState>,
+         fd: i32,
+         iovs_ptr: i32,
+         iovs_len: i32,
+         nread_ptr: i32|
+         -> Result<i32> {
+            let errno = call_wasi(&mut caller, |ctx, memory| {
+                in_tokio(p1::fd_read(ctx, memory, fd, iovs_ptr, iovs_len, nread_ptr))
+            })?;
+            if errno == 0 && fd == 0 {
+                // wasmtime-wasi has written nread if the call succeeded
+                let memory = guest::memory(&mut caller, "fd_read")?;
+                let data = memory.data(&caller);
+                let read = guest::read(data, "fd_read", guest::address(nread_ptr))?;
+                let read = u32::from_le_bytes(read) as u64;
+                if let Some(stdio) = &mut caller.data_mut().stdio {
+                    stdio.stdin += read;
+                }
+            }
+            Ok(errno)
+        },
+    )?;
+    linker.allow_shadowing(false);
+    Ok(())
+}
@@ -0,0 +1,101 @@
+// Reproducibility manifests: everything needed to re-run a program exactly
+// as it ran before, in one file that can be attached to a bug report.
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::Path;
+use wasmtime::{Error, Result};
+
+const MANIFEST_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize)]
+pub struct Manifest {
+    pub version: u32,
+    // Path of the module as given on the command line.
+    pub module: String,
+    pub module_sha256: String,
+    pub entry: Option<String>,
+    // Program arguments, those after -- on the command line.
+    #[serde(default)]
+    pub args: Vec<String>,
+    // Full stdin contents, hex-encoded.
+    pub stdin: String,
+}
+
+impl Manifest {
+    pub fn new(
+        module: &str,
+        wasm_bytes: &[u8],
+        entry: Option<String>,
+        args: Vec<String>,
+        stdin: &[u8],
+    ) -> Manifest {
+        Manifest {
+            version: MANIFEST_VERSION,
+            module: module.to_string(),
+            module_sha256: sha256_hex(wasm_bytes),
+            entry,
+            args,
+            stdin: hex_encode(stdin),
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Manifest> {
+        let text = fs::read_to_string(path)?;
+        let manifest: Manifest = serde_json::from_str(&text).map_err(|error| {
+            Error::msg(format!(
+                "{}: invalid repro manifest: {}",
+                path.display(),
+                error
+            ))
+        })?;
+        if manifest.version != MANIFEST_VERSION {
+            return Err(Error::msg(format!(
+                "{}: unsupported repro manifest version {}",
+                path.display(),
+                manifest.version
+            )));
+        }
+        Ok(manifest)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        fs::write(path, serde_json::to_string_pretty(self)? + "\n")?;
+        Ok(())
+    }
+
+    pub fn stdin_bytes(&self) -> Result<Vec<u8>> {
+        hex_decode(&self.stdin).ok_or_else(|| Error::msg("repro manifest has malformed stdin"))
+    }
+
+    // Fails if the module being replayed is not the one that was recorded.
+    pub fn check_module(&self, wasm_bytes: &[u8]) -> Result<()> {
+        let actual = sha256_hex(wasm_bytes);
+        if actual != self.module_sha256 {
+            return Err(Error::msg(format!(
+                "module does not match repro manifest: expected sha256 {}, got {}",
+                self.module_sha256, actual
+            )));
+        }
+        Ok(())
+    }
+}
+
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    hex_encode(&Sha256::digest(bytes))
+}
+
+pub fn hex_encode(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+pub fn hex_decode(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
@@ -0,0 +1,81 @@
+// --watch: runs the program again whenever its module, or the source file
+// given with --source, changes, for an edit-compile-run loop. Each run is a
+// separate process with the same arguments minus --watch and --source, so
+// every run starts from a fresh runtime, and the screen is cleared before
+// it if stdout is a terminal. The files' directories are watched rather than
+// the files themselves, since compilers and editors often replace a file
+// instead of writing to it.
+
+use crate::watch::FileWatcher;
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+use wasmtime::{Error, Result};
+
+// How long to wait for more changes after the first, so that a compiler
+// writing its output in several steps causes one run, not several.
+const SETTLE_TIME: Duration = Duration::from_millis(100);
+
+// Returns the arguments to run the program with: args without the program
+// name, --watch, and --source.
+fn child_args(args: &[String]) -> Vec<String> {
+    let mut child_args = Vec::new();
+    let mut args = args.iter().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--source" => {
+                args.next();
+            }
+            "--watch" => {}
+            _ if arg.starts_with("--source=") => {}
+            _ => child_args.push(arg.clone()),
+        }
+    }
+    child_args
+}
+
+// Runs the program each time one of the files changes. Only returns on
+// errors.
+pub fn run(args: &[String], wasm_file: Option<&str>, source: Option<&str>) -> Result<i32> {
+    let wasm_file = match wasm_file {
+        Some("-") | None => return Err(Error::msg("--watch needs a module file to watch")),
+        Some(wasm_file) => wasm_file,
+    };
+    let mut watcher = FileWatcher::new()?;
+    let mut watched = Vec::new();
+    for file in std::iter::once(wasm_file).chain(source) {
+        let path = absolute(Path::new(file))?;
+        let dir = path.parent().unwrap_or(Path::new("/"));
+        watcher
+            .watch_entries(dir)
+            .map_err(|error| Error::new(error).context(dir.display().to_string()))?;
+        watched.push(path);
+    }
+
+    let exe = std::env::current_exe()?;
+    let child_args = child_args(args);
+    loop {
+        if io::stdout().is_terminal() {
+            print!("\x1b[2J\x1b[H");
+            io::stdout().flush()?;
+        }
+        let status = Command::new(&exe).args(&child_args).status()?;
+        eprintln!("wasmruntime: {}; waiting for changes", status);
+        // Wait for a change to one of the files, which may have happened
+        // during the run, then let a burst of changes settle
+        loop {
+            let event = watcher.next_event(None);
+            if event.is_some_and(|event| watched.contains(&event.host_path)) {
+                break;
+            }
+        }
+        while watcher.next_event(Some(SETTLE_TIME)).is_some() {}
+    }
+}
+
+// Makes a path absolute without resolving symbolic links, to compare with
+// the paths in events.
+fn absolute(path: &Path) -> Result<PathBuf> {
+    std::path::absolute(path).map_err(|error| Error::new(error).context(path.display().to_string()))
+}
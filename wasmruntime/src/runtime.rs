@@ -0,0 +1,420 @@
+// Embedding API: runs Zong programs from other Rust code without going
+// through the wasmruntime binary. A program gets the same host functions as
+// under `wasmruntime run` with the default sandbox profile, WASI included if
+// it imports it, and any registered extensions, and uses the process's stdin,
+// stdout, and stderr unless given a HostIo with others. As without
+// --allow-exec, a program that imports exec is rejected unless the Host
+// allows it with Host::allow_exec.
+//
+// A CompiledProgram is for running the same program many times, such as with
+// each input of a property-based test: it is compiled and linked once, and
+// each of its ProgramInstances has its own store and memory, and its own
+// stdin, arguments, captured output, and limits. A Pool runs such instances
+// on a set of threads.
+
+use crate::extension::HostExtension;
+use crate::hostio::{Captured, HostIo};
+use crate::limits::Limits;
+use crate::sandbox::Profile;
+use crate::timeout::Deadline;
+use crate::{
+    abi, call_entry, check_interrupted, check_resolved, engine_config, read_module, runtime_linker,
+    HostState,
+};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use wasmtime::{
+    Engine, Error, Func, Instance, InstancePre, Linker, Module, Result, Store, UpdateDeadline, Val,
+};
+use wasmtime_wasi::I32Exit;
+
+/// The engine that programs are compiled with and the host functions they
+/// are linked with. Setting these up takes longer than running a small
+/// program, so code that runs many programs should create one Host and make
+/// each Runtime from it with Runtime::with_host. Each Runtime still gets its
+/// own store.
+#[derive(Clone)]
+pub struct Host {
+    engine: Engine,
+    // One for each supported Zong ABI version, in the order of
+    // abi::SUPPORTED, then one for each for modules with a 64-bit memory.
+    // They include WASI, which modules that do not import it never call.
+    linkers: Vec<Linker<HostState>>,
+    allow_exec: bool,
+}
+
+impl Host {
+    pub fn new() -> Result<Host> {
+        Host::with_engine_config(false)
+    }
+
+    /// Like new, but the engine meters fuel, so that store fuel can be set
+    /// and read as with --fuel. Metering makes programs run slower.
+    pub fn with_fuel_metering() -> Result<Host> {
+        Host::with_engine_config(true)
+    }
+
+    fn with_engine_config(consume_fuel: bool) -> Result<Host> {
+        let engine = Engine::new(&engine_config(consume_fuel, false))?;
+        let linkers = [false, true]
+            .iter()
+            .flat_map(|&memory64| {
+                abi::SUPPORTED
+                    .iter()
+                    .map(move |&version| (version, memory64))
+            })
+            .map(|(version, memory64)| runtime_linker(&engine, true, version, memory64))
+            .collect::<Result<_>>()?;
+        Ok(Host {
+            engine,
+            linkers,
+            allow_exec: false,
+        })
+    }
+
+    /// Lets programs run shell commands with exec, as --allow-exec does.
+    pub fn allow_exec(&mut self) {
+        self.allow_exec = true;
+    }
+
+    /// Adds an extension's host functions to this Host's linker only, unlike
+    /// register_extension, which adds them to every linker made after it.
+    pub fn add_extension(&mut self, extension: &dyn HostExtension) -> Result<()> {
+        for linker in &mut self.linkers {
+            linker.allow_shadowing(true);
+            extension.register(linker)?;
+            linker.allow_shadowing(false);
+        }
+        Ok(())
+    }
+
+    /// The engine modules are compiled with.
+    pub fn engine(&self) -> &Engine {
+        &self.engine
+    }
+
+    /// The host functions, for linking a module without a Runtime. They are
+    /// defined the way modules without a zong.abi section expect.
+    pub fn linker(&self) -> &Linker<HostState> {
+        self.linker_for(abi::Version::default(), false)
+    }
+
+    /// Checks that the module only imports host functions this Host grants,
+    /// as Runtime and CompiledProgram do before running it.
+    pub fn check_imports(&self, module: &Module) -> Result<()> {
+        let denied = if self.allow_exec {
+            Vec::new()
+        } else {
+            vec![String::from("subprocess")]
+        };
+        Profile::default().check_imports_denying(module, &denied)
+    }
+
+    fn linker_for(&self, version: abi::Version, memory64: bool) -> &Linker<HostState> {
+        let index = abi::SUPPORTED
+            .iter()
+            .position(|&supported| supported == version)
+            .expect("Host has a linker for each supported version");
+        let offset = if memory64 { abi::SUPPORTED.len() } else { 0 };
+        &self.linkers[offset + index]
+    }
+}
+
+/// A compiled Zong program and the store it runs in. The module is
+/// instantiated by the first run or invoke, and later calls reuse the same
+/// instance and memory.
+pub struct Runtime {
+    store: Store<HostState>,
+    linker: Linker<HostState>,
+    module: Module,
+    instance: Option<Instance>,
+}
+
+impl Runtime {
+    /// Compiles a module from its wasm binary or WAT text.
+    pub fn new(wasm_bytes: &[u8]) -> Result<Runtime> {
+        Runtime::with_host(&Host::new()?, wasm_bytes)
+    }
+
+    /// Compiles a module with a shared engine and host functions.
+    pub fn with_host(host: &Host, wasm_bytes: &[u8]) -> Result<Runtime> {
+        Runtime::with_io(host, wasm_bytes, HostIo::default())
+    }
+
+    /// Compiles a module that reads and writes the given streams instead of
+    /// the process's, e.g. with its stdout collected by
+    /// HostIo::capture_stdout for checking a program's output.
+    pub fn with_io(host: &Host, wasm_bytes: &[u8], io: HostIo) -> Result<Runtime> {
+        let wasm_bytes = &wat::parse_bytes(wasm_bytes)?;
+        let module = Module::new(&host.engine, wasm_bytes)?;
+        host.check_imports(&module)?;
+        let state =
+            HostState::for_module(&module, wasm_bytes, None, &[], &[], io, Profile::default())?;
+        let mut linker = host.linker_for(state.abi, state.memory64).clone();
+        linker.allow_shadowing(true);
+        let store = new_store(&host.engine, state);
+        Ok(Runtime {
+            store,
+            linker,
+            module,
+            instance: None,
+        })
+    }
+
+    /// Compiles the module in a .wasm or .wat file.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Runtime> {
+        Runtime::new(&read_module(path.as_ref())?)
+    }
+
+    /// The linker the module's imports are resolved with. Functions defined
+    /// on it before the module is instantiated are available to the module,
+    /// replacing any host function of the same name.
+    pub fn linker(&mut self) -> &mut Linker<HostState> {
+        &mut self.linker
+    }
+
+    /// The compiled module, e.g. for looking up the types of its exports.
+    pub fn module(&self) -> &Module {
+        &self.module
+    }
+
+    /// Runs the program's `main` and returns its exit code: main's result if
+    /// it returns an i32 or i64, the code passed to `exit` or WASI's
+    /// proc_exit, or 0.
+    pub fn run_main(&mut self) -> Result<i32> {
+        let main = self.func("main")?;
+        run_main(&mut self.store, main)
+    }
+
+    /// Calls an exported function and returns its results.
+    pub fn invoke(&mut self, name: &str, args: &[Val]) -> Result<Vec<Val>> {
+        let func = self.func(name)?;
+        invoke(&mut self.store, func, args)
+    }
+
+    fn func(&mut self, name: &str) -> Result<Func> {
+        let instance = match self.instance {
+            Some(instance) => instance,
+            None => {
+                check_resolved(&self.linker, &mut self.store, &self.module, "module")?;
+                let instance = self.linker.instantiate(&mut self.store, &self.module)?;
+                *self.instance.insert(instance)
+            }
+        };
+        instance
+            .get_func(&mut self.store, name)
+            .ok_or_else(|| Error::msg(format!("no function export '{}'", name)))
+    }
+}
+
+/// A program compiled and linked once, to run many times. Each instantiate
+/// makes a ProgramInstance with a fresh store and memory, which costs far
+/// less than compiling a Runtime for each run.
+#[derive(Clone)]
+pub struct CompiledProgram {
+    engine: Engine,
+    module: Module,
+    wasm_bytes: Arc<[u8]>,
+    pre: InstancePre<HostState>,
+}
+
+/// An instance of a CompiledProgram, independent of its others. Its stdout
+/// and stderr are collected in memory.
+pub struct ProgramInstance {
+    store: Store<HostState>,
+    instance: Instance,
+    stdout: Captured,
+    stderr: Captured,
+    // The fuel the store started with, if limited.
+    fuel: Option<u64>,
+}
+
+/// Limits on an instance, like --fuel, --max-memory, and --timeout for
+/// `wasmruntime run`. A guest that reaches one stops with an error: a trap
+/// for running out of fuel, and otherwise one that says which limit.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RunLimits {
+    /// Fuel the guest may consume, about one unit per instruction. Needs a
+    /// Host made with Host::with_fuel_metering.
+    pub fuel: Option<u64>,
+    /// Most bytes each of the guest's memories may hold.
+    pub max_memory: Option<usize>,
+    /// Wall-clock time the instance may run for from when it is made. It is
+    /// only enforced while a Pool advances the engine's epoch, as every Pool
+    /// of the Host does.
+    pub timeout: Option<Duration>,
+}
+
+impl CompiledProgram {
+    /// Compiles a module from its wasm binary or WAT text, and links it with
+    /// the host's functions.
+    pub fn new(host: &Host, wasm_bytes: &[u8]) -> Result<CompiledProgram> {
+        let wasm_bytes: Arc<[u8]> = Arc::from(wat::parse_bytes(wasm_bytes)?);
+        let module = Module::new(&host.engine, &wasm_bytes)?;
+        host.check_imports(&module)?;
+        let state = HostState::for_module(
+            &module,
+            &wasm_bytes,
+            None,
+            &[],
+            &[],
+            HostIo::default(),
+            Profile::default(),
+        )?;
+        let linker = host.linker_for(state.abi, state.memory64);
+        check_resolved(
+            linker,
+            &mut Store::new(&host.engine, state),
+            &module,
+            "module",
+        )?;
+        let pre = linker.instantiate_pre(&module)?;
+        Ok(CompiledProgram {
+            engine: host.engine.clone(),
+            module,
+            wasm_bytes,
+            pre,
+        })
+    }
+
+    /// Compiles the module in a .wasm or .wat file.
+    pub fn from_file(host: &Host, path: impl AsRef<Path>) -> Result<CompiledProgram> {
+        CompiledProgram::new(host, &read_module(path.as_ref())?)
+    }
+
+    /// The compiled module, e.g. for looking up the types of its exports.
+    pub fn module(&self) -> &Module {
+        &self.module
+    }
+
+    /// The engine the program was compiled with, its Host's.
+    pub fn engine(&self) -> &Engine {
+        &self.engine
+    }
+
+    /// Instantiates the program with no stdin and no arguments.
+    pub fn instantiate(&self) -> Result<ProgramInstance> {
+        self.instantiate_with(&[], &[])
+    }
+
+    /// Instantiates the program with the given stdin and program arguments,
+    /// those after -- on the runtime's command line.
+    pub fn instantiate_with(&self, stdin: &[u8], args: &[String]) -> Result<ProgramInstance> {
+        self.instantiate_with_limits(stdin, args, RunLimits::default())
+    }
+
+    /// Like instantiate_with, with limits on the instance.
+    pub fn instantiate_with_limits(
+        &self,
+        stdin: &[u8],
+        args: &[String],
+        limits: RunLimits,
+    ) -> Result<ProgramInstance> {
+        let mut io = HostIo::default();
+        io.set_stdin(stdin.to_vec());
+        let stdout = io.capture_stdout();
+        let stderr = io.capture_stderr();
+        let argv: Vec<String> = std::iter::once(String::from("program"))
+            .chain(args.iter().cloned())
+            .collect();
+        let mut state = HostState::for_module(
+            &self.module,
+            &self.wasm_bytes,
+            None,
+            &[],
+            &argv,
+            io,
+            Profile::default(),
+        )?;
+        state.limits = Limits::new(limits.max_memory, None);
+        state.deadline = limits.timeout.map(Deadline::from_now);
+        let mut store = new_store(&self.engine, state);
+        if let Some(fuel) = limits.fuel {
+            store.set_fuel(fuel)?;
+        }
+        let instance = self.pre.instantiate(&mut store)?;
+        Ok(ProgramInstance {
+            store,
+            instance,
+            stdout,
+            stderr,
+            fuel: limits.fuel,
+        })
+    }
+}
+
+impl ProgramInstance {
+    /// Runs the program's `main` and returns its exit code, as
+    /// Runtime::run_main does.
+    pub fn run_main(&mut self) -> Result<i32> {
+        let main = self.func("main")?;
+        run_main(&mut self.store, main)
+    }
+
+    /// Calls an exported function and returns its results.
+    pub fn invoke(&mut self, name: &str, args: &[Val]) -> Result<Vec<Val>> {
+        let func = self.func(name)?;
+        invoke(&mut self.store, func, args)
+    }
+
+    /// What the program has written to stdout so far.
+    pub fn stdout(&self) -> Vec<u8> {
+        self.stdout.contents()
+    }
+
+    /// What the program has written to stderr so far.
+    pub fn stderr(&self) -> Vec<u8> {
+        self.stderr.contents()
+    }
+
+    /// The fuel consumed so far, if RunLimits gave the instance fuel.
+    pub fn fuel_consumed(&self) -> Option<u64> {
+        let remaining = self.store.get_fuel().ok()?;
+        Some(self.fuel? - remaining)
+    }
+
+    fn func(&mut self, name: &str) -> Result<Func> {
+        self.instance
+            .get_func(&mut self.store, name)
+            .ok_or_else(|| Error::msg(format!("no function export '{}'", name)))
+    }
+}
+
+fn new_store(engine: &Engine, state: HostState) -> Store<HostState> {
+    let deadline = state.deadline.clone();
+    let mut store = Store::new(engine, state);
+    store.limiter(|state| &mut state.limits);
+    // With a fuel-metering engine, a store without fuel cannot run at all
+    if store.get_fuel().is_ok() {
+        store.set_fuel(u64::MAX).expect("the engine meters fuel");
+    }
+    // The epoch only advances while a Pool is running on the engine, which
+    // only stops instances whose timeout has passed
+    store.set_epoch_deadline(1);
+    store.epoch_deadline_callback(move |_| {
+        check_interrupted(None, deadline.as_ref())?;
+        Ok(UpdateDeadline::Continue(1))
+    });
+    store
+}
+
+fn run_main(store: &mut Store<HostState>, main: Func) -> Result<i32> {
+    let result = call_entry(&mut *store, main, "main");
+    store.data_mut().flush_stdout()?;
+    match result {
+        Ok(exit_code) => Ok(exit_code.unwrap_or(0)),
+        Err(error) => match error.downcast_ref::<I32Exit>() {
+            Some(exit) => Ok(exit.0),
+            None => Err(error),
+        },
+    }
+}
+
+fn invoke(store: &mut Store<HostState>, func: Func, args: &[Val]) -> Result<Vec<Val>> {
+    let mut results = vec![Val::I32(0); func.ty(&*store).results().len()];
+    let result = func.call(&mut *store, args, &mut results);
+    store.data_mut().flush_stdout()?;
+    result?;
+    Ok(results)
+}
@@ -0,0 +1,364 @@
+// The guest's view of the host filesystem: a set of preopened directories,
+// each mounted at a guest path like "/assets", plus a virtual working
+// directory. Guest paths never reach the host without being resolved inside
+// one of the preopens. Also the --sandbox profiles, which bundle what a guest
+// is allowed to do.
+
+use crate::audit;
+use std::path::{Component, Path, PathBuf};
+use wasmtime::{Error, Module, Result};
+
+#[derive(Clone)]
+pub struct Preopen {
+    // Absolute guest path the directory is mounted at.
+    pub guest_path: PathBuf,
+    pub host_path: PathBuf,
+    // Whether the guest may create and modify files in the directory.
+    pub writable: bool,
+}
+
+pub struct Sandbox {
+    preopens: Vec<Preopen>,
+    // Absolute guest path. Always inside a preopen, except "/" when there are
+    // no preopens at all.
+    cwd: PathBuf,
+}
+
+impl Preopen {
+    // Parses a --dir or --ro-dir value, HOST_DIR[::GUEST_DIR]. The guest path
+    // defaults to the host directory's canonical path.
+    pub fn parse(value: &str, writable: bool) -> Result<Preopen, String> {
+        let (host_path, guest_path) = match value.split_once("::") {
+            Some((host_path, guest_path)) => (host_path, Some(guest_path)),
+            None => (value, None),
+        };
+        let host_path = Path::new(host_path)
+            .canonicalize()
+            .map_err(|error| format!("{}: {}", host_path, error))?;
+        if !host_path.is_dir() {
+            return Err(format!("{} is not a directory", host_path.display()));
+        }
+        let guest_path = match guest_path {
+            Some(guest_path) if guest_path.starts_with('/') => PathBuf::from(guest_path),
+            Some(guest_path) => Path::new("/").join(guest_path),
+            None => host_path.clone(),
+        };
+        Ok(Preopen {
+            guest_path,
+            host_path,
+            writable,
+        })
+    }
+}
+
+impl Sandbox {
+    pub fn new(preopens: Vec<Preopen>) -> Sandbox {
+        let cwd = preopens
+            .first()
+            .map_or_else(|| PathBuf::from("/"), |preopen| preopen.guest_path.clone());
+        Sandbox { preopens, cwd }
+    }
+
+    pub fn cwd(&self) -> &Path {
+        &self.cwd
+    }
+
+    // Changes the working directory. Fails, leaving the working directory
+    // unchanged, if the path is outside the sandbox or is not a directory.
+    pub fn chdir(&mut self, guest_path: &str) -> bool {
+        let guest_path = self.absolute(guest_path);
+        match self.resolve_absolute(&guest_path) {
+            Some(host_path) if host_path.is_dir() => {
+                self.cwd = guest_path;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // Maps a guest path, relative to the working directory or absolute, to a
+    // host path. Returns None if the path is outside every preopen, including
+    // via symlinks.
+    pub fn resolve(&self, guest_path: &str) -> Option<PathBuf> {
+        self.resolve_absolute(&self.absolute(guest_path))
+    }
+
+    // Whether the guest may modify the file or directory at a guest path.
+    // False if it is outside the sandbox.
+    pub fn is_writable(&self, guest_path: &str) -> bool {
+        let guest_path = self.absolute(guest_path);
+        self.preopens
+            .iter()
+            .find(|preopen| guest_path.starts_with(&preopen.guest_path))
+            .is_some_and(|preopen| preopen.writable)
+    }
+
+    // Maps a host path produced by resolve back to the guest path it is
+    // visible at.
+    pub fn guest_path(&self, host_path: &Path) -> Option<PathBuf> {
+        self.preopens.iter().find_map(|preopen| {
+            let root = preopen.host_path.canonicalize().ok()?;
+            let relative = host_path.strip_prefix(root).ok()?;
+            Some(preopen.guest_path.join(relative))
+        })
+    }
+
+    // Maps an absolute guest path to a host path. Returns None if the path is
+    // outside every preopen, including via symlinks.
+    fn resolve_absolute(&self, guest_path: &Path) -> Option<PathBuf> {
+        let preopen = self
+            .preopens
+            .iter()
+            .find(|preopen| guest_path.starts_with(&preopen.guest_path))?;
+        let relative = guest_path.strip_prefix(&preopen.guest_path).ok()?;
+        let host_path = preopen.host_path.join(relative);
+
+        // Follow symlinks for the part of the path that exists and make sure
+        // it still lands inside the preopen.
+        let root = preopen.host_path.canonicalize().ok()?;
+        let mut existing = host_path.as_path();
+        let mut missing = Vec::new();
+        let canonical = loop {
+            match existing.canonicalize() {
+                Ok(canonical) => break canonical,
+                Err(_) => {
+                    missing.push(existing.file_name()?);
+                    existing = existing.parent()?;
+                }
+            }
+        };
+        if !canonical.starts_with(&root) {
+            return None;
+        }
+        Some(
+            missing
+                .iter()
+                .rev()
+                .fold(canonical, |path, name| path.join(name)),
+        )
+    }
+
+    // Lexically normalizes a guest path into an absolute one. ".." never goes
+    // above "/".
+    fn absolute(&self, guest_path: &str) -> PathBuf {
+        let mut absolute = if guest_path.starts_with('/') {
+            PathBuf::from("/")
+        } else {
+            self.cwd.clone()
+        };
+        for component in Path::new(guest_path).components() {
+            match component {
+                Component::Normal(name) => absolute.push(name),
+                Component::ParentDir => {
+                    absolute.pop();
+                }
+                Component::RootDir | Component::CurDir | Component::Prefix(_) => {}
+            }
+        }
+        absolute
+    }
+}
+
+#[derive(Clone, Copy, Default, PartialEq)]
+pub enum Profile {
+    // Only stdio, timers, and the module's own data; no files, environment,
+    // or network. Memory and fuel are limited.
+    Strict,
+    // Everything the runtime provides except network access.
+    #[default]
+    Default,
+    // Everything, including network access for WASI sockets.
+    Permissive,
+}
+
+// Capabilities (as named by the audit) that a strict sandbox grants.
+const STRICT_CAPABILITIES: &[&str] = &[
+    "stdout",
+    "stderr",
+    "stdin",
+    "timers",
+    "embedded data",
+    "clock",
+    "math",
+    "conversion",
+    "memory",
+    "references",
+    "random",
+    "process",
+];
+
+impl Profile {
+    pub fn parse(name: &str) -> Option<Profile> {
+        match name {
+            "strict" => Some(Profile::Strict),
+            "default" => Some(Profile::Default),
+            "permissive" => Some(Profile::Permissive),
+            _ => None,
+        }
+    }
+
+    fn allows(self, capability: &str) -> bool {
+        match self {
+            Profile::Strict => STRICT_CAPABILITIES.contains(&capability),
+            Profile::Default => capability != "network",
+            Profile::Permissive => true,
+        }
+    }
+
+    // Fails if the module imports a host function granting none of the
+    // capabilities this profile allows.
+    pub fn check_imports(self, module: &Module) -> Result<()> {
+        self.check_imports_denying(module, &[])
+    }
+
+    // Like check_imports, but also treats the capabilities in denied, from
+    // --deny, as not granted.
+    pub fn check_imports_denying(self, module: &Module, denied: &[String]) -> Result<()> {
+        for import in module.imports() {
+            let Some(capabilities) = audit::capability(import.module(), import.name()) else {
+                continue;
+            };
+            if !capabilities.split(", ").any(|capability| {
+                self.allows(capability) && !denied.iter().any(|denied| denied == capability)
+            }) {
+                return Err(Error::msg(format!(
+                    "import {}.{} needs the '{}' capability, which the sandbox does not grant",
+                    import.module(),
+                    import.name(),
+                    capabilities
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    // Whether the guest may see the host's filesystem (bundled assets),
+    // environment variables, and arguments.
+    pub fn allows_host_data(self) -> bool {
+        self != Profile::Strict
+    }
+
+    // The profile with network access added, for --allow-net. The default
+    // profile then grants everything, like the permissive one.
+    pub fn allowing_network(self, allow_net: bool) -> Result<Profile> {
+        match (self, allow_net) {
+            (Profile::Strict, true) => Err(crate::exitcode::usage(
+                "--allow-net cannot be used with the strict sandbox",
+            )),
+            (Profile::Default, true) => Ok(Profile::Permissive),
+            (profile, _) => Ok(profile),
+        }
+    }
+
+    pub fn allows_network(self) -> bool {
+        self.allows("network")
+    }
+
+    pub fn memory_limit(self) -> Option<usize> {
+        match self {
+            Profile::Strict => Some(256 << 20),
+            Profile::Default | Profile::Permissive => None,
+        }
+    }
+
+    pub fn fuel(self) -> Option<u64> {
+        match self {
+            Profile::Strict => Some(10_000_000_000),
+            Profile::Default | Profile::Permissive => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    // A fresh directory holding "data/file" and "outside", with the sandbox
+    // mounting host "data" at guest "/data", removed when dropped.
+    struct Fixture {
+        root: PathBuf,
+        sandbox: Sandbox,
+    }
+
+    impl Fixture {
+        fn new(name: &str) -> Fixture {
+            let root =
+                std::env::temp_dir().join(format!("zong-sandbox-{}-{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&root);
+            fs::create_dir_all(root.join("data/sub")).unwrap();
+            fs::write(root.join("data/file"), "").unwrap();
+            fs::write(root.join("outside"), "").unwrap();
+            let root = root.canonicalize().unwrap();
+            let data = root.join("data");
+            let preopen = Preopen::parse(&format!("{}::data", data.display()), true).unwrap();
+            Fixture {
+                root,
+                sandbox: Sandbox::new(vec![preopen]),
+            }
+        }
+    }
+
+    impl Drop for Fixture {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.root);
+        }
+    }
+
+    #[test]
+    fn resolves_paths_inside_the_preopen() {
+        let fixture = Fixture::new("inside");
+        let data = fixture.root.join("data");
+        let sandbox = &fixture.sandbox;
+        assert_eq!(sandbox.cwd(), Path::new("/data"));
+        assert_eq!(sandbox.resolve("file"), Some(data.join("file")));
+        assert_eq!(
+            sandbox.resolve("/data/sub/../file"),
+            Some(data.join("file"))
+        );
+        assert_eq!(sandbox.resolve("./sub/new"), Some(data.join("sub/new")));
+        assert_eq!(
+            sandbox.guest_path(&data.join("file")),
+            Some(PathBuf::from("/data/file"))
+        );
+    }
+
+    #[test]
+    fn dot_dot_cannot_leave_the_preopen() {
+        let fixture = Fixture::new("dotdot");
+        let sandbox = &fixture.sandbox;
+        assert_eq!(sandbox.resolve("../outside"), None);
+        assert_eq!(sandbox.resolve("/../../outside"), None);
+        assert_eq!(sandbox.resolve("/datafile"), None);
+        assert!(!sandbox.is_writable("../outside"));
+        assert!(sandbox.is_writable("sub/new"));
+    }
+
+    #[test]
+    fn chdir_stays_inside_the_sandbox() {
+        let mut fixture = Fixture::new("chdir");
+        let sandbox = &mut fixture.sandbox;
+        assert!(sandbox.chdir("sub"));
+        assert_eq!(sandbox.cwd(), Path::new("/data/sub"));
+        assert!(!sandbox.chdir("../file"));
+        assert!(!sandbox.chdir("/"));
+        assert_eq!(sandbox.cwd(), Path::new("/data/sub"));
+        assert!(sandbox.chdir(".."));
+        assert_eq!(sandbox.cwd(), Path::new("/data"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_cannot_leave_the_preopen() {
+        let fixture = Fixture::new("symlink");
+        let data = fixture.root.join("data");
+        std::os::unix::fs::symlink(fixture.root.join("outside"), data.join("escape")).unwrap();
+        std::os::unix::fs::symlink(&fixture.root, data.join("parent")).unwrap();
+        std::os::unix::fs::symlink(data.join("file"), data.join("link")).unwrap();
+        let sandbox = &fixture.sandbox;
+        assert_eq!(sandbox.resolve("escape"), None);
+        assert_eq!(sandbox.resolve("parent/outside"), None);
+        assert_eq!(sandbox.resolve("parent/new"), None);
+        assert_eq!(sandbox.resolve("link"), Some(data.join("file")));
+    }
+}
@@ -0,0 +1,315 @@
+// Session logs, for --record and --replay: everything a run took from
+// outside the program that could differ between runs, so that a replayed
+// run computes exactly what the recorded one did.
+//
+// The log has one JSON object per line. The first identifies the module:
+//
+//   {"kind": "session", "version": 1, "module_sha256": HEX}
+//
+// and each later one is a value the program was given, in the order it was
+// given them:
+//
+//   {"kind": "stdin", "data": HEX}              bytes read from stdin
+//   {"kind": "clock", "clock": ID, "value": N}   a clock reading, in ns
+//   {"kind": "random", "data": HEX}             random bytes
+//
+// A replay gives the program the recorded stdin bytes as its stdin, then
+// answers each clock or random request with the next recorded value of its
+// kind. A program that asks for more than was recorded, or for a different
+// clock, has diverged from the recording, and the replay stops with an error.
+
+use crate::repro::{hex_decode, hex_encode, sha256_hex};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use wasmtime::{Error, Result};
+
+const SESSION_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum Entry {
+    Session { version: u32, module_sha256: String },
+    Stdin { data: String },
+    Clock { clock: u32, value: u64 },
+    Random { data: String },
+}
+
+pub enum Session {
+    Record(Recorder),
+    Replay(Replay),
+}
+
+// Writes a session log. Clones write to the same log.
+#[derive(Clone)]
+pub struct Recorder(Arc<Mutex<File>>);
+
+// Reads stdin from a reader, recording what it reads.
+pub struct RecordedStdin<R> {
+    reader: R,
+    recorder: Recorder,
+}
+
+// The values from a session log that are yet to be replayed.
+pub struct Replay {
+    stdin: Vec<u8>,
+    clocks: VecDeque<(u32, u64)>,
+    random: VecDeque<u8>,
+}
+
+impl Recorder {
+    pub fn create(path: &Path, wasm_bytes: &[u8]) -> Result<Recorder> {
+        let file = File::create(path)
+            .map_err(|error| Error::new(error).context(path.display().to_string()))?;
+        let recorder = Recorder(Arc::new(Mutex::new(file)));
+        recorder.write(&Entry::Session {
+            version: SESSION_VERSION,
+            module_sha256: sha256_hex(wasm_bytes),
+        });
+        Ok(recorder)
+    }
+
+    pub fn stdin<R: Read>(&self, reader: R) -> RecordedStdin<R> {
+        RecordedStdin {
+            reader,
+            recorder: self.clone(),
+        }
+    }
+
+    pub fn clock(&self, clock: u32, value: u64) {
+        self.write(&Entry::Clock { clock, value });
+    }
+
+    pub fn random(&self, bytes: &[u8]) {
+        self.write(&Entry::Random {
+            data: hex_encode(bytes),
+        });
+    }
+
+    // Each entry is written as it happens, so the log covers a run that
+    // crashes. Like the audit log, the log is best-effort; failing to write
+    // it must not change how the guest runs.
+    fn write(&self, entry: &Entry) {
+        let line = serde_json::to_string(entry).expect("entries serialize") + "\n";
+        let _ = self.0.lock().unwrap().write_all(line.as_bytes());
+    }
+}
+
+impl<R: Read> Read for RecordedStdin<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let count = self.reader.read(buf)?;
+        if count > 0 {
+            self.recorder.write(&Entry::Stdin {
+                data: hex_encode(&buf[..count]),
+            });
+        }
+        Ok(count)
+    }
+}
+
+impl Replay {
+    // Loads a session log, failing if it was recorded for a different module.
+    pub fn load(path: &Path, wasm_bytes: &[u8]) -> Result<Replay> {
+        let invalid = |line: usize, message: &str| {
+            Error::msg(format!(
+                "{}:{}: invalid session log: {}",
+                path.display(),
+                line,
+                message
+            ))
+        };
+        let text = fs::read_to_string(path)
+            .map_err(|error| Error::new(error).context(path.display().to_string()))?;
+        let mut replay = Replay {
+            stdin: Vec::new(),
+            clocks: VecDeque::new(),
+            random: VecDeque::new(),
+        };
+        for (index, line) in text.lines().enumerate() {
+            let entry: Entry = serde_json::from_str(line)
+                .map_err(|error| invalid(index + 1, &error.to_string()))?;
+            let malformed = || invalid(index + 1, "malformed hex data");
+            match entry {
+                Entry::Session {
+                    version,
+                    module_sha256,
+                } if index == 0 => {
+                    if version != SESSION_VERSION {
+                        return Err(invalid(
+                            1,
+                            &format!("unsupported session log version {}", version),
+                        ));
+                    }
+                    let actual = sha256_hex(wasm_bytes);
+                    if actual != module_sha256 {
+                        return Err(Error::msg(format!(
+                            "module does not match session log: expected sha256 {}, got {}",
+                            module_sha256, actual
+                        )));
+                    }
+                }
+                _ if index == 0 => return Err(invalid(1, "missing session header")),
+                Entry::Session { .. } => return Err(invalid(index + 1, "repeated session header")),
+                Entry::Stdin { data } => replay
+                    .stdin
+                    .extend(hex_decode(&data).ok_or_else(malformed)?),
+                Entry::Clock { clock, value } => replay.clocks.push_back((clock, value)),
+                Entry::Random { data } => replay
+                    .random
+                    .extend(hex_decode(&data).ok_or_else(malformed)?),
+            }
+        }
+        if text.is_empty() {
+            return Err(invalid(1, "missing session header"));
+        }
+        Ok(replay)
+    }
+
+    // Everything the recorded run read from stdin.
+    pub fn take_stdin(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.stdin)
+    }
+
+    // The next recorded reading, which must be of the same clock.
+    pub fn clock(&mut self, function: &str, clock: u32) -> Result<u64> {
+        match self.clocks.front() {
+            Some(&(recorded, value)) if recorded == clock => {
+                self.clocks.pop_front();
+                Ok(value)
+            }
+            Some(&(recorded, _)) => Err(Error::msg(format!(
+                "{}: replay diverged: the program read clock {}, but the session log has \
+                 clock {} next",
+                function, clock, recorded
+            ))),
+            None => Err(Error::msg(format!(
+                "{}: replay diverged: the session log has no more clock readings",
+                function
+            ))),
+        }
+    }
+
+    // Fills dest with the next recorded random bytes.
+    pub fn random(&mut self, function: &str, dest: &mut [u8]) -> Result<()> {
+        if self.random.len() < dest.len() {
+            return Err(Error::msg(format!(
+                "{}: replay diverged: the program asked for {} random bytes, but the session \
+                 log has {} left",
+                function,
+                dest.len(),
+                self.random.len()
+            )));
+        }
+        let len = dest.len();
+        for (byte, recorded) in dest.iter_mut().zip(self.random.drain(..len)) {
+            *byte = recorded;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    // A session log path of its own, removed when dropped.
+    struct LogFile(PathBuf);
+
+    impl LogFile {
+        fn new(name: &str) -> LogFile {
+            LogFile(std::env::temp_dir().join(format!(
+                "zong-session-{}-{}.jsonl",
+                name,
+                std::process::id()
+            )))
+        }
+    }
+
+    impl Drop for LogFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn replays_what_was_recorded() {
+        let log = LogFile::new("replay");
+        let recorder = Recorder::create(&log.0, b"module").unwrap();
+        let mut stdin = String::new();
+        recorder
+            .stdin(&b"line\n"[..])
+            .read_to_string(&mut stdin)
+            .unwrap();
+        recorder.clock(1, 100);
+        recorder.random(&[1, 2, 3]);
+        recorder.clock(0, 200);
+        drop(recorder);
+
+        let mut replay = Replay::load(&log.0, b"module").unwrap();
+        assert_eq!(replay.take_stdin(), b"line\n");
+        assert_eq!(replay.clock("clock_time_get", 1).unwrap(), 100);
+        let mut bytes = [0; 2];
+        replay.random("random_get", &mut bytes).unwrap();
+        assert_eq!(bytes, [1, 2]);
+        assert_eq!(replay.clock("clock_time_get", 0).unwrap(), 200);
+        let mut bytes = [0; 1];
+        replay.random("random_get", &mut bytes).unwrap();
+        assert_eq!(bytes, [3]);
+    }
+
+    #[test]
+    fn replay_stops_when_the_program_diverges() {
+        let log = LogFile::new("diverge");
+        let recorder = Recorder::create(&log.0, b"module").unwrap();
+        recorder.clock(1, 100);
+        recorder.random(&[1]);
+        drop(recorder);
+
+        let mut replay = Replay::load(&log.0, b"module").unwrap();
+        let error = replay.clock("clock_time_get", 0).unwrap_err().to_string();
+        assert!(
+            error.contains("read clock 0, but the session log has clock 1"),
+            "{}",
+            error
+        );
+        replay.clock("clock_time_get", 1).unwrap();
+        let error = replay.clock("clock_time_get", 1).unwrap_err().to_string();
+        assert!(error.contains("no more clock readings"), "{}", error);
+        let error = replay
+            .random("random_get", &mut [0; 2])
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("asked for 2 random bytes"), "{}", error);
+    }
+
+    #[test]
+    fn rejects_logs_of_other_modules_and_bad_logs() {
+        let log = LogFile::new("invalid");
+        drop(Recorder::create(&log.0, b"module").unwrap());
+        let error = Replay::load(&log.0, b"other").err().unwrap().to_string();
+        assert!(error.contains("module does not match"), "{}", error);
+
+        let load = |text: &str| {
+            fs::write(&log.0, text).unwrap();
+            Replay::load(&log.0, b"module").err().unwrap().to_string()
+        };
+        assert!(load("").contains("missing session header"));
+        assert!(load("{\"kind\": \"clock\", \"clock\": 0, \"value\": 1}\n")
+            .contains(":1: invalid session log: missing session header"));
+        let header = format!(
+            "{{\"kind\": \"session\", \"version\": 1, \"module_sha256\": \"{}\"}}\n",
+            sha256_hex(b"module")
+        );
+        assert!(
+            load(&(header.clone() + "{\"kind\": \"random\", \"data\": \"x\"}\n"))
+                .contains(":2: invalid session log: malformed hex data")
+        );
+        assert!(load(&(header.clone() + &header)).contains("repeated session header"));
+        assert!(load(&header.replace("\"version\": 1", "\"version\": 2"))
+            .contains("unsupported session log version 2"));
+    }
+}
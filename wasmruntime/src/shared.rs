@@ -0,0 +1,41 @@
+// Named shared memory segments. Segments live in a process-wide registry, so
+// every instance in the process that opens the same name sees the same bytes.
+// Guests cannot map host memory directly, so reads and writes are copies
+// mediated by the host.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock};
+
+pub type Segment = Arc<Mutex<Vec<u8>>>;
+
+fn registry() -> &'static Mutex<HashMap<String, Segment>> {
+    static REGISTRY: OnceLock<Mutex<HashMap<String, Segment>>> = OnceLock::new();
+    REGISTRY.get_or_init(Default::default)
+}
+
+// Opens the segment with the given name, creating it zero-filled with the
+// given size if it does not exist yet. An existing segment keeps its size.
+pub fn open(name: &str, size: usize) -> Segment {
+    registry()
+        .lock()
+        .unwrap()
+        .entry(name.to_string())
+        .or_insert_with(|| Arc::new(Mutex::new(vec![0; size])))
+        .clone()
+}
+
+// Copies data into the segment at offset. Returns None if it does not fit.
+pub fn write(segment: &Segment, offset: usize, data: &[u8]) -> Option<()> {
+    let mut bytes = segment.lock().unwrap();
+    bytes
+        .get_mut(offset..offset.checked_add(data.len())?)?
+        .copy_from_slice(data);
+    Some(())
+}
+
+// Copies up to len bytes starting at offset out of the segment.
+pub fn read(segment: &Segment, offset: usize, len: usize) -> Option<Vec<u8>> {
+    let bytes = segment.lock().unwrap();
+    let end = offset.checked_add(len)?.min(bytes.len());
+    Some(bytes.get(offset..end)?.to_vec())
+}
@@ -0,0 +1,86 @@
+// Graceful handling of SIGINT/SIGTERM, such as Ctrl-C. A background thread
+// records the signal and bumps the engine's epoch; the next epoch check in
+// the guest then stops execution with a SignalInterrupt error, and the
+// runtime calls the guest's registered handler, if any, and finishes the run
+// as usual before exiting. Host calls that wait, such as reading stdin or a
+// socket and sleep_ms, watch for the signal and stop the guest the same way.
+// A guest blocked in any other host call makes no epoch checks, so a second
+// signal before the first has stopped the guest exits the process at once.
+
+use std::fmt;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::Arc;
+use wasmtime::{Engine, Result};
+
+#[cfg(unix)]
+pub use signal_hook::consts::{SIGINT, SIGTERM};
+#[cfg(not(unix))]
+pub const SIGINT: i32 = 2;
+#[cfg(not(unix))]
+pub const SIGTERM: i32 = 15;
+
+// The most recent unhandled signal, or 0 if none.
+#[derive(Clone, Default)]
+pub struct PendingSignal(Arc<AtomicI32>);
+
+impl PendingSignal {
+    pub fn is_pending(&self) -> bool {
+        self.0.load(Ordering::SeqCst) != 0
+    }
+
+    // The pending signal, left for the main thread to take.
+    pub fn peek(&self) -> Option<i32> {
+        match self.0.load(Ordering::SeqCst) {
+            0 => None,
+            signal => Some(signal),
+        }
+    }
+
+    pub fn take(&self) -> Option<i32> {
+        match self.0.swap(0, Ordering::SeqCst) {
+            0 => None,
+            signal => Some(signal),
+        }
+    }
+}
+
+// Error that stops the guest when a signal arrives.
+#[derive(Debug)]
+pub struct SignalInterrupt(pub i32);
+
+impl fmt::Display for SignalInterrupt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "interrupted by signal {}", self.0)
+    }
+}
+
+impl std::error::Error for SignalInterrupt {}
+
+// Starts listening for SIGINT and SIGTERM. From now on these signals no
+// longer kill the process directly.
+#[cfg(unix)]
+pub fn install(engine: &Engine) -> Result<PendingSignal> {
+    let pending = PendingSignal::default();
+    let mut signals = signal_hook::iterator::Signals::new([SIGINT, SIGTERM])?;
+    let engine = engine.clone();
+    let thread_pending = pending.clone();
+    std::thread::spawn(move || {
+        for signal in signals.forever() {
+            if thread_pending.0.swap(signal, Ordering::SeqCst) != 0 {
+                let _ = std::io::Write::flush(&mut std::io::stdout());
+                crate::tty::restore();
+                crate::bundle::remove_extracted();
+                std::process::exit(128 + signal);
+            }
+            engine.increment_epoch();
+        }
+    });
+    Ok(pending)
+}
+
+#[cfg(not(unix))]
+pub fn install(_engine: &Engine) -> Result<PendingSignal> {
+    Err(wasmtime::Error::msg(
+        "signal handlers are not supported on this platform",
+    ))
+}
@@ -0,0 +1,98 @@
+// Module signature verification for --verify-signature. A module is signed
+// with Ed25519, either by a detached "<module>.sig" file next to it or by a
+// "zong.signature" custom section at the very end of the module, which then
+// signs every byte before it. Signatures are the raw 64 bytes, as produced
+// by e.g. `openssl pkeyutl -sign -rawin`.
+
+use ed25519_dalek::pkcs8::DecodePublicKey;
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+use std::fs;
+use std::path::Path;
+use wasmtime::{Error, Result};
+
+const SIGNATURE_SECTION: &str = "zong.signature";
+
+// Reads a public key, either PEM-encoded ("BEGIN PUBLIC KEY", as written by
+// `openssl pkey -pubout`) or the raw 32 bytes.
+pub fn load_key(path: &Path) -> Result<VerifyingKey> {
+    let bytes = fs::read(path)?;
+    let key = match std::str::from_utf8(&bytes) {
+        Ok(pem) if pem.contains("-----BEGIN") => {
+            VerifyingKey::from_public_key_pem(pem).map_err(|error| error.to_string())
+        }
+        _ => <[u8; 32]>::try_from(bytes.as_slice())
+            .map_err(|_| String::from("expected PEM or 32 raw bytes"))
+            .and_then(|bytes| VerifyingKey::from_bytes(&bytes).map_err(|error| error.to_string())),
+    };
+    key.map_err(|error| Error::msg(format!("{}: invalid public key: {}", path.display(), error)))
+}
+
+// Fails unless the module is signed by key. module_path locates a detached
+// signature; an embedded signature takes precedence.
+pub fn verify(key: &VerifyingKey, module_path: &Path, wasm_bytes: &[u8]) -> Result<()> {
+    let (signed, signature) = match embedded_signature(wasm_bytes) {
+        Some((signed, signature)) => (signed, signature.to_vec()),
+        None => {
+            let mut sig_path = module_path.as_os_str().to_owned();
+            sig_path.push(".sig");
+            let signature = fs::read(&sig_path).map_err(|_| {
+                Error::msg(format!(
+                    "{}: module is not signed (no {} section or {} file)",
+                    module_path.display(),
+                    SIGNATURE_SECTION,
+                    Path::new(&sig_path).display()
+                ))
+            })?;
+            (wasm_bytes, signature)
+        }
+    };
+    let signature = Signature::from_slice(&signature)
+        .map_err(|_| Error::msg(format!("{}: malformed signature", module_path.display())))?;
+    key.verify(signed, &signature).map_err(|_| {
+        Error::msg(format!(
+            "{}: signature does not match the module",
+            module_path.display()
+        ))
+    })
+}
+
+// If the module's last section is the signature section, returns the bytes
+// before it and the signature.
+fn embedded_signature(wasm_bytes: &[u8]) -> Option<(&[u8], &[u8])> {
+    if !wasm_bytes.starts_with(b"\0asm") {
+        return None;
+    }
+    let mut offset = 8;
+    let mut last = None;
+    while offset < wasm_bytes.len() {
+        let start = offset;
+        let id = wasm_bytes[offset];
+        let (size, size_len) = read_leb128(wasm_bytes.get(offset + 1..)?)?;
+        let contents = wasm_bytes.get(offset + 1 + size_len..offset + 1 + size_len + size)?;
+        offset += 1 + size_len + size;
+        last = Some((start, id, contents));
+    }
+    let (start, id, contents) = last?;
+    // Custom section: [name length, name, data]
+    if id != 0 {
+        return None;
+    }
+    let (name_len, name_len_len) = read_leb128(contents)?;
+    let name = contents.get(name_len_len..name_len_len + name_len)?;
+    if name != SIGNATURE_SECTION.as_bytes() {
+        return None;
+    }
+    Some((&wasm_bytes[..start], &contents[name_len_len + name_len..]))
+}
+
+// Decodes an unsigned LEB128 number, returning it and its encoded length.
+pub fn read_leb128(bytes: &[u8]) -> Option<(usize, usize)> {
+    let mut value = 0usize;
+    for (i, &byte) in bytes.iter().enumerate().take(5) {
+        value |= ((byte & 0x7f) as usize) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Some((value, i + 1));
+        }
+    }
+    None
+}
@@ -0,0 +1,397 @@
+// The slice structure host functions use for strings and byte arrays:
+// [items_ptr: i32 at offset 0, length: i64 at offset 8], 16 bytes in all.
+// Host functions take slices by the structure's address, and return them by
+// writing a structure to an address the guest passes, with the items copied
+// onto the tstack.
+//
+// In a module with a 64-bit memory, items_ptr is an i64, filling the
+// padding before length, and addresses are passed as i64s.
+//
+// Modules for version 2 of the Zong ABI pass slices to host functions as two
+// parameters instead, (items_ptr: i32, length: i32), so that the compiler
+// need not build a structure for each call, or (i64, i64) with a 64-bit
+// memory. Host functions that take slices
+// are defined with define, which gives them the parameters of the module's
+// version; for version 2, it writes the structures just above the tstack
+// before calling the function, so that the function is the same for both.
+// Host functions return slices as structures in both versions.
+
+use crate::abi::Version;
+use crate::guest::{GuestData, GuestDataMut};
+use crate::{guest, HostState};
+use std::borrow::Cow;
+use wasmtime::{Caller, Error, FuncType, Linker, Result, Val, ValType};
+
+pub const SLICE_SIZE: usize = 16;
+
+// How read_slice reads the module's structures.
+#[derive(Clone, Copy, Default)]
+pub struct Layout {
+    // items_ptr is an i64, for a 64-bit memory.
+    pub memory64: bool,
+    // Set by --strict-abi.
+    pub strict: bool,
+}
+
+// The parameters of Zong host functions that are slices the guest passes in,
+// and those that are addresses the function writes a slice to, by their
+// index in version 1 of the ABI.
+pub fn slice_params(module: &str, name: &str) -> (&'static [usize], &'static [usize]) {
+    if module != "env" {
+        return (&[], &[]);
+    }
+    match name {
+        "print_bytes" | "eprint_bytes" | "print_fmt" | "chdir" | "file_open" | "watch_path"
+        | "shared_open" | "load_module" | "progress_begin" | "parse_i64" | "panic"
+        | "tcp_connect" | "host_intern_string" | "const_str_register" => (&[0], &[]),
+        "on_signal" | "module_call" | "file_write" | "tcp_write" | "log" => (&[1], &[]),
+        "shared_write" => (&[2], &[]),
+        "read_line" | "read_line_utf8" | "read_bytes" | "read_all" | "getcwd" | "random_bytes"
+        | "last_error_message" => (&[], &[0]),
+        "args_get" | "file_read" | "tcp_read" | "i64_to_string" | "host_string_get" => (&[], &[1]),
+        "get_embedded" | "env_get" | "http_get" | "exec" => (&[0], &[1]),
+        "write_file" => (&[0, 1], &[]),
+        _ => (&[], &[]),
+    }
+}
+
+// The index that a parameter at index in version 1 has in the module's
+// version, where each slice before it may take two.
+pub fn param_index(abi: Version, slices: &[usize], index: usize) -> usize {
+    match abi {
+        Version::V1 => index,
+        Version::V2 => index + slices.iter().filter(|&&slice| slice < index).count(),
+    }
+}
+
+// What a host function defined with define returns.
+pub trait HostResult {
+    fn types() -> Vec<ValType>;
+    fn write(self, results: &mut [Val]);
+}
+
+impl HostResult for () {
+    fn types() -> Vec<ValType> {
+        Vec::new()
+    }
+
+    fn write(self, _results: &mut [Val]) {}
+}
+
+impl HostResult for i32 {
+    fn types() -> Vec<ValType> {
+        vec![ValType::I32]
+    }
+
+    fn write(self, results: &mut [Val]) {
+        results[0] = Val::I32(self);
+    }
+}
+
+impl HostResult for i64 {
+    fn types() -> Vec<ValType> {
+        vec![ValType::I64]
+    }
+
+    fn write(self, results: &mut [Val]) {
+        results[0] = Val::I64(self);
+    }
+}
+
+// Defines env.name, a host function taking slices at the indexes slice_params
+// gives, with its version 1 parameter types. func gets the arguments as for
+// version 1, with each slice as the address of a structure.
+pub fn define<R: HostResult>(
+    linker: &mut Linker<HostState>,
+    abi: Version,
+    name: &'static str,
+    params: &[ValType],
+    func: impl Fn(Caller<'_, HostState>, &[Val]) -> Result<R> + Send + Sync + 'static,
+) -> Result<()> {
+    let (slices, _) = slice_params("env", name);
+    let wasm_params: Vec<ValType> = match abi {
+        Version::V1 => params.to_vec(),
+        Version::V2 => params
+            .iter()
+            .enumerate()
+            .flat_map(|(index, ty)| {
+                // A slice is passed as two of its pointer type
+                if slices.contains(&index) {
+                    vec![ty.clone(), ty.clone()]
+                } else {
+                    vec![ty.clone()]
+                }
+            })
+            .collect(),
+    };
+    let ty = FuncType::new(linker.engine(), wasm_params, R::types());
+    linker.func_new("env", name, ty, move |mut caller, args, results| {
+        let result = match abi {
+            Version::V2 if !slices.is_empty() => {
+                let args = stage_slices(&mut caller, name, slices, args)?;
+                func(caller, &args)?
+            }
+            // Without slices the parameters are the same in both versions
+            _ => func(caller, args)?,
+        };
+        result.write(results);
+        Ok(())
+    })?;
+    Ok(())
+}
+
+// Writes a structure for each (items_ptr, length) pair among a version 2
+// host function's arguments just above the tstack, and returns the
+// arguments with each pair replaced by its structure's address.
+fn stage_slices(
+    caller: &mut Caller<'_, HostState>,
+    function: &str,
+    slices: &[usize],
+    args: &[Val],
+) -> Result<Vec<Val>> {
+    let limit = caller.data().tstack_limit();
+    let mut dest_addr = guest::tstack_scratch(caller, function, slices.len() * SLICE_SIZE, limit)?;
+    let memory = guest::memory(caller, function)?;
+    let mut data = memory.data_mut(&mut *caller);
+    let mut staged = Vec::with_capacity(args.len());
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if !slices.contains(&staged.len()) {
+            staged.push(*arg);
+            continue;
+        }
+        let items_ptr = guest::pointer(arg);
+        // The wasm type has a length after each items_ptr
+        let length = guest::pointer(args.next().unwrap());
+        write_slice(&mut data, function, dest_addr, items_ptr, length)?;
+        staged.push(match arg {
+            Val::I64(_) => Val::I64(dest_addr as u64 as i64),
+            _ => Val::I32(dest_addr as u32 as i32),
+        });
+        dest_addr += SLICE_SIZE;
+    }
+    Ok(staged)
+}
+
+// Returns the bytes referenced by the slice structure at slice_ptr.
+//
+// A slice that does not describe guest memory is an error naming the host
+// function it was passed to and the offending fields, since it can only come
+// from a compiler bug. The compiler does not align tstack allocations, so the
+// structure's alignment is only checked with --strict-abi.
+pub fn read_slice<'a>(
+    data: GuestData<'a>,
+    function: &str,
+    slice_ptr: u64,
+    layout: Layout,
+) -> Result<Cow<'a, [u8]>> {
+    let invalid = |problem: String| {
+        Err(Error::msg(format!(
+            "{}: invalid slice at {:#x}: {}",
+            function, slice_ptr, problem
+        )))
+    };
+    let Some(slice_ptr) = usize::try_from(slice_ptr)
+        .ok()
+        .filter(|&slice_ptr| slice_ptr.saturating_add(SLICE_SIZE) <= data.size())
+    else {
+        return invalid(format!(
+            "the structure extends past the end of memory ({:#x} bytes)",
+            data.size()
+        ));
+    };
+    if layout.strict && !slice_ptr.is_multiple_of(8) {
+        return invalid(String::from("the structure is not 8-byte aligned"));
+    }
+    let structure = data.get(slice_ptr..slice_ptr + SLICE_SIZE);
+    let items_ptr = if layout.memory64 {
+        let items_ptr = u64::from_le_bytes(structure[..8].try_into().unwrap());
+        match usize::try_from(items_ptr) {
+            Ok(items_ptr) => items_ptr,
+            Err(_) => {
+                return invalid(format!(
+                    "items_ptr {:#x} is past the end of memory ({:#x} bytes)",
+                    items_ptr,
+                    data.size()
+                ))
+            }
+        }
+    } else {
+        u32::from_le_bytes(structure[..4].try_into().unwrap()) as usize
+    };
+    let length = u64::from_le_bytes(structure[8..].try_into().unwrap());
+    if items_ptr == 0 && length > 0 {
+        return invalid(format!("items_ptr is null but length is {}", length));
+    }
+    match usize::try_from(length)
+        .ok()
+        .and_then(|length| items_ptr.checked_add(length))
+    {
+        Some(end) if end <= data.size() => Ok(data.get(items_ptr..end)),
+        _ => invalid(format!(
+            "items_ptr {:#x} with length {} extends past the end of memory ({:#x} bytes)",
+            items_ptr,
+            length,
+            data.size()
+        )),
+    }
+}
+
+// Reads the slice at slice_ptr as text, replacing invalid UTF-8.
+pub fn read_string(
+    caller: &mut Caller<'_, HostState>,
+    function: &str,
+    slice_ptr: u64,
+) -> Result<String> {
+    let memory = guest::memory(caller, function)?;
+    let layout = caller.data().slice_layout();
+    let bytes = read_slice(memory.data(&*caller), function, slice_ptr, layout)?;
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+// Writes a slice structure at dest_addr. items_ptr is written as an i64,
+// which with a 32-bit memory zeroes the padding after it.
+pub fn write_slice(
+    data: &mut GuestDataMut<'_>,
+    function: &str,
+    dest_addr: usize,
+    items_ptr: u64,
+    length: u64,
+) -> Result<()> {
+    let mut structure = [0; SLICE_SIZE];
+    structure[..8].copy_from_slice(&items_ptr.to_le_bytes());
+    structure[8..].copy_from_slice(&length.to_le_bytes());
+    guest::write(data, function, dest_addr, &structure)
+}
+
+// Returns bytes to the guest: copies them onto the tstack and writes a slice
+// structure referencing them at dest_addr. Empty bytes are written as a null
+// slice, without touching the tstack.
+pub fn push_slice(
+    caller: &mut Caller<'_, HostState>,
+    function: &str,
+    dest_addr: usize,
+    bytes: &[u8],
+) -> Result<()> {
+    let items_ptr = if bytes.is_empty() {
+        0
+    } else {
+        let limit = caller.data().tstack_limit();
+        guest::tstack_push(caller, function, bytes, limit)?
+    };
+    let memory = guest::memory(caller, function)?;
+    write_slice(
+        &mut memory.data_mut(&mut *caller),
+        function,
+        dest_addr,
+        items_ptr,
+        bytes.len() as u64,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Runtime;
+
+    fn structure(items_ptr: u64, length: u64) -> Vec<u8> {
+        let mut memory = vec![0; 64];
+        let mut data = GuestDataMut::Local(&mut memory);
+        write_slice(&mut data, "test", 0, items_ptr, length).unwrap();
+        memory[32..37].copy_from_slice(b"hello");
+        memory
+    }
+
+    fn read(memory: &[u8], layout: Layout) -> Result<Vec<u8>> {
+        let bytes = read_slice(GuestData::Local(memory), "test", 0, layout)?;
+        Ok(bytes.into_owned())
+    }
+
+    #[test]
+    fn reads_the_slices_it_writes() {
+        let memory = structure(32, 5);
+        assert_eq!(read(&memory, Layout::default()).unwrap(), b"hello");
+        let memory64 = Layout {
+            memory64: true,
+            strict: false,
+        };
+        assert_eq!(read(&memory, memory64).unwrap(), b"hello");
+        assert!(read(&structure(0, 0), Layout::default())
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn rejects_slices_outside_memory() {
+        let error = read(&structure(32, 33), Layout::default()).unwrap_err();
+        assert!(
+            error.to_string().contains("extends past the end"),
+            "{}",
+            error
+        );
+        let error = read(&structure(0, 1), Layout::default()).unwrap_err();
+        assert!(error.to_string().contains("null"), "{}", error);
+        let memory = structure(32, 5);
+        let error =
+            read_slice(GuestData::Local(&memory), "test", 50, Layout::default()).unwrap_err();
+        assert!(
+            error.to_string().contains("the structure extends"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn strict_layout_checks_alignment() {
+        let mut memory = vec![0; 64];
+        let mut data = GuestDataMut::Local(&mut memory);
+        write_slice(&mut data, "test", 4, 32, 0).unwrap();
+        let strict = Layout {
+            memory64: false,
+            strict: true,
+        };
+        assert!(read_slice(GuestData::Local(&memory), "test", 4, Layout::default()).is_ok());
+        assert!(read_slice(GuestData::Local(&memory), "test", 4, strict).is_err());
+    }
+
+    #[test]
+    fn v2_parameters_follow_the_slices_before_them() {
+        let (slices, _) = slice_params("env", "write_file");
+        assert_eq!(slices, [0, 1]);
+        assert_eq!(param_index(Version::V1, slices, 1), 1);
+        assert_eq!(param_index(Version::V2, slices, 1), 2);
+        assert_eq!(param_index(Version::V2, slices, 2), 4);
+        assert_eq!(
+            slice_params("wasi_snapshot_preview1", "fd_write"),
+            (&[][..], &[][..])
+        );
+    }
+
+    #[test]
+    fn push_slice_copies_onto_the_tstack() {
+        let module = r#"(module
+          (import "test" "push" (func $push (param i32)))
+          (memory (export "memory") 1)
+          (global $tstack (export "tstack") (mut i32) (i32.const 1024))
+          (func (export "push") (result i64 i64 i32)
+            (call $push (i32.const 0))
+            (i64.load (i32.const 0))
+            (i64.load (i32.const 8))
+            (global.get $tstack)))"#;
+        let mut runtime = Runtime::new(module.as_bytes()).unwrap();
+        runtime
+            .linker()
+            .func_wrap(
+                "test",
+                "push",
+                |mut caller: Caller<'_, HostState>, dest: i32| {
+                    push_slice(&mut caller, "push", dest as usize, b"hello")
+                },
+            )
+            .unwrap();
+        let results = runtime.invoke("push", &[]).unwrap();
+        assert_eq!(results[0].unwrap_i64(), 1024);
+        assert_eq!(results[1].unwrap_i64(), 5);
+        assert!(results[2].unwrap_i32() >= 1029);
+    }
+}
@@ -0,0 +1,276 @@
+// Source locations for trap reports. A module may map its code back to Zong
+// source in a "zong.srcmap" custom section, of unsigned LEB128 numbers and
+// length-prefixed UTF-8 strings:
+//
+//   version (1)
+//   file count, then each file's path
+//   entry count, then each entry's module offset, file index, line, column,
+//   and length, in increasing order of offset
+//
+// An entry covers the instructions from its offset up to the next entry's.
+// Lines and columns are numbered from 1, and the length is the number of
+// columns of source the instructions came from.
+//
+// A module without that section may instead carry DWARF debug info, whose
+// line table gives the same mapping, less the lengths.
+//
+// With --source-dir, a trap in such a module is reported with the source
+// line it happened on, the expression underlined, and the chain of calls
+// that reached it, instead of wasmtime's plain message.
+
+use crate::diagnostics;
+use crate::signature::read_leb128;
+use gimli::{ColumnType, EndianSlice, LittleEndian};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use wasmtime::{Error, Result, Trap, WasmBacktrace};
+
+pub const SECTION_NAME: &str = "zong.srcmap";
+
+const VERSION: usize = 1;
+
+struct Entry {
+    offset: usize,
+    file: usize,
+    line: usize,
+    column: usize,
+    length: usize,
+}
+
+pub struct SourceMap {
+    files: Vec<String>,
+    entries: Vec<Entry>,
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl Reader<'_> {
+    fn number(&mut self) -> Result<usize> {
+        let (value, length) = read_leb128(self.bytes).ok_or_else(truncated)?;
+        self.bytes = &self.bytes[length..];
+        Ok(value)
+    }
+
+    fn string(&mut self) -> Result<String> {
+        let length = self.number()?;
+        if length > self.bytes.len() {
+            return Err(truncated());
+        }
+        let (string, rest) = self.bytes.split_at(length);
+        self.bytes = rest;
+        Ok(String::from_utf8(string.to_vec())?)
+    }
+}
+
+fn truncated() -> Error {
+    Error::msg(format!("{} section is truncated", SECTION_NAME))
+}
+
+struct Location<'a> {
+    file: &'a str,
+    line: usize,
+    column: usize,
+    length: usize,
+}
+
+impl SourceMap {
+    // Reads the module's source map, or its DWARF line table if it has no
+    // source map. Returns None if it has neither.
+    pub fn load(wasm_bytes: &[u8]) -> Result<Option<SourceMap>> {
+        let mut sections = HashMap::new();
+        let mut code_start = 0;
+        for payload in wasmparser::Parser::new(0).parse_all(wasm_bytes) {
+            match payload? {
+                wasmparser::Payload::CustomSection(section) => {
+                    sections
+                        .entry(section.name())
+                        .or_insert_with(|| section.data());
+                }
+                wasmparser::Payload::CodeSectionStart { range, .. } => code_start = range.start,
+                _ => {}
+            }
+        }
+        if let Some(section) = sections.get(SECTION_NAME) {
+            return Ok(Some(SourceMap::parse(section)?));
+        }
+        if sections.contains_key(".debug_line") {
+            return Ok(Some(SourceMap::from_dwarf(&sections, code_start)?));
+        }
+        Ok(None)
+    }
+
+    pub fn parse(bytes: &[u8]) -> Result<SourceMap> {
+        let mut reader = Reader { bytes };
+        let version = reader.number()?;
+        if version != VERSION {
+            return Err(Error::msg(format!(
+                "{} section has unsupported version {}",
+                SECTION_NAME, version
+            )));
+        }
+        let files = (0..reader.number()?)
+            .map(|_| reader.string())
+            .collect::<Result<Vec<_>>>()?;
+        let mut entries = Vec::new();
+        for _ in 0..reader.number()? {
+            let entry = Entry {
+                offset: reader.number()?,
+                file: reader.number()?,
+                line: reader.number()?,
+                column: reader.number()?,
+                length: reader.number()?,
+            };
+            if entry.file >= files.len() {
+                return Err(Error::msg(format!(
+                    "{} section refers to missing file {}",
+                    SECTION_NAME, entry.file
+                )));
+            }
+            entries.push(entry);
+        }
+        Ok(SourceMap { files, entries })
+    }
+
+    // For inspect, e.g. "120 entries in 2 files".
+    pub fn summary(&self) -> String {
+        format!(
+            "{} entries in {} files",
+            self.entries.len(),
+            self.files.len()
+        )
+    }
+
+    // Builds entries from the rows of the DWARF line table. Addresses in wasm
+    // DWARF are offsets into the code section's contents, which starts at
+    // code_start in the module.
+    fn from_dwarf(sections: &HashMap<&str, &[u8]>, code_start: usize) -> Result<SourceMap> {
+        let dwarf = gimli::Dwarf::load(|id| -> Result<_> {
+            let section = sections.get(id.name()).copied().unwrap_or_default();
+            Ok(EndianSlice::new(section, LittleEndian))
+        })?;
+        let mut files = Vec::new();
+        let mut file_indexes = HashMap::new();
+        let mut entries = Vec::new();
+        let mut units = dwarf.units();
+        while let Some(header) = units.next()? {
+            let unit = dwarf.unit(header)?;
+            let Some(program) = unit.line_program.clone() else {
+                continue;
+            };
+            let mut rows = program.rows();
+            while let Some((header, row)) = rows.next_row()? {
+                let (Some(file), Some(line)) = (row.file(header), row.line()) else {
+                    continue;
+                };
+                let mut path = String::new();
+                if let Some(directory) = file.directory(header) {
+                    path = dwarf
+                        .attr_string(&unit, directory)?
+                        .to_string_lossy()
+                        .into_owned();
+                }
+                let name = dwarf.attr_string(&unit, file.path_name())?;
+                let name = name.to_string_lossy();
+                path = if path.is_empty() || name.starts_with('/') {
+                    name.into_owned()
+                } else {
+                    format!("{}/{}", path, name)
+                };
+                let file = *file_indexes.entry(path.clone()).or_insert_with(|| {
+                    files.push(path);
+                    files.len() - 1
+                });
+                entries.push(Entry {
+                    offset: code_start + row.address() as usize,
+                    file,
+                    line: line.get() as usize,
+                    column: match row.column() {
+                        ColumnType::LeftEdge => 1,
+                        ColumnType::Column(column) => column.get() as usize,
+                    },
+                    length: 1,
+                });
+            }
+        }
+        entries.sort_by_key(|entry| entry.offset);
+        Ok(SourceMap { files, entries })
+    }
+
+    fn locate(&self, offset: usize) -> Option<Location<'_>> {
+        let index = self.entries.partition_point(|entry| entry.offset <= offset);
+        let entry = &self.entries[index.checked_sub(1)?];
+        Some(Location {
+            file: &self.files[entry.file],
+            line: entry.line,
+            column: entry.column,
+            length: entry.length,
+        })
+    }
+
+    // The source location of the instruction at offset in the module, as
+    // file:line:column.
+    pub fn position(&self, offset: usize) -> Option<String> {
+        let location = self.locate(offset)?;
+        Some(format!(
+            "{}:{}:{}",
+            location.file, location.line, location.column
+        ))
+    }
+
+    // Prints a report of a trap with the source it came from, read from
+    // source_dir, and the trap's raw description if verbose. Returns false,
+    // printing nothing, if the error is not a trap or its location is
+    // unknown.
+    pub fn print_trap(&self, error: &Error, source_dir: &Path, verbose: bool) -> bool {
+        let (Some(_), Some(backtrace)) = (
+            error.downcast_ref::<Trap>(),
+            error.downcast_ref::<WasmBacktrace>(),
+        ) else {
+            return false;
+        };
+        let frames = backtrace.frames();
+        let Some(location) = frames
+            .first()
+            .and_then(|frame| frame.module_offset())
+            .and_then(|offset| self.locate(offset))
+        else {
+            return false;
+        };
+
+        eprintln!("error: {}", diagnostics::describe(error, verbose));
+        eprintln!(
+            " --> {}:{}:{}",
+            location.file, location.line, location.column
+        );
+        let source = fs::read_to_string(source_dir.join(location.file)).ok();
+        if let Some(line) = source
+            .as_deref()
+            .and_then(|source| source.lines().nth(location.line.checked_sub(1)?))
+        {
+            let gutter = location.line.to_string().len();
+            eprintln!("{:gutter$} |", "");
+            eprintln!("{} | {}", location.line, line);
+            eprintln!(
+                "{:gutter$} | {}{}",
+                "",
+                " ".repeat(location.column.saturating_sub(1)),
+                "^".repeat(location.length.max(1))
+            );
+        }
+        for (i, frame) in frames.iter().enumerate() {
+            let name = frame.func_name().unwrap_or("<unknown>");
+            let called = if i == 0 { "in" } else { "called from" };
+            match frame.module_offset().and_then(|offset| self.locate(offset)) {
+                Some(location) if i > 0 => eprintln!(
+                    "  = {} {} at {}:{}:{}",
+                    called, name, location.file, location.line, location.column
+                ),
+                _ => eprintln!("  = {} {}", called, name),
+            }
+        }
+        true
+    }
+}
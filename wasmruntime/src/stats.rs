@@ -0,0 +1,60 @@
+// Run statistics for --stats, printed to stderr when the run ends:
+//
+//   stats:
+//     time:          0.0012 s
+//     peak memory:   131072 bytes (2 pages)
+//     final tstack:  0x1010
+//     stdin:         6 bytes
+//     stdout:        12 bytes
+//     stderr:        0 bytes
+//     host calls:
+//       env.print      3
+//       env.read_line  1
+//
+// Peak memory is the size of the largest memory at its largest. Host calls
+// are counted at direct call sites, as for --callgraph, so calls through a
+// table are not included.
+
+use crate::callcount::Instrumented;
+use crate::{guest, HostState};
+use std::time::Duration;
+use wasmtime::{Instance, Result, Store};
+
+const PAGE_SIZE: usize = 64 << 10;
+
+pub fn print(
+    store: &mut Store<HostState>,
+    instance: &Instance,
+    instrumented: &Instrumented,
+    elapsed: Duration,
+) -> Result<()> {
+    let host_calls = instrumented.import_calls(&instrumented.counts(&mut *store, instance)?);
+    let tstack = instance
+        .get_global(&mut *store, "tstack")
+        .map(|tstack| guest::pointer(&tstack.get(&mut *store)));
+    let state = store.data();
+    let peak_memory = state.limits.peak_memory;
+
+    eprintln!("stats:");
+    eprintln!("  time:          {:.4} s", elapsed.as_secs_f64());
+    eprintln!(
+        "  peak memory:   {} bytes ({} pages)",
+        peak_memory,
+        peak_memory / PAGE_SIZE
+    );
+    match tstack {
+        Some(tstack) => eprintln!("  final tstack:  {:#x}", tstack),
+        None => eprintln!("  final tstack:  -"),
+    }
+    if let Some(stdio) = &state.stdio {
+        eprintln!("  stdin:         {} bytes", stdio.stdin);
+        eprintln!("  stdout:        {} bytes", stdio.stdout);
+        eprintln!("  stderr:        {} bytes", stdio.stderr);
+    }
+    eprintln!("  host calls:");
+    let width = host_calls.keys().map(String::len).max().unwrap_or(0);
+    for (name, calls) in &host_calls {
+        eprintln!("    {:width$}  {}", name, calls, width = width);
+    }
+    Ok(())
+}
@@ -0,0 +1,70 @@
+// Shell commands for exec, behind --allow-exec. The command runs with sh -c
+// (cmd /C on Windows) in the guest's working directory, or the runtime's if
+// that is not a host directory, with no stdin. Its stdout is captured up to
+// a limit, and its stderr is passed on to the guest's stderr.
+
+use std::io::{self, Read};
+use std::path::Path;
+use std::process::{Command, ExitStatus, Stdio};
+use std::thread;
+
+// Default for --exec-output-limit.
+pub const DEFAULT_OUTPUT_LIMIT: usize = 1 << 20;
+
+pub struct Output {
+    pub exit_code: i32,
+    // At most the output limit; the rest is discarded.
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+}
+
+// Runs command and waits for it to exit.
+pub fn run(command: &str, dir: Option<&Path>, output_limit: usize) -> io::Result<Output> {
+    let mut shell = if cfg!(windows) {
+        let mut shell = Command::new("cmd");
+        shell.arg("/C");
+        shell
+    } else {
+        let mut shell = Command::new("sh");
+        shell.arg("-c");
+        shell
+    };
+    shell
+        .arg(command)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    if let Some(dir) = dir {
+        shell.current_dir(dir);
+    }
+    let mut child = shell.spawn()?;
+    // Read stderr alongside stdout, so that neither pipe fills up and blocks
+    // the child
+    let mut child_stderr = child.stderr.take().unwrap();
+    let stderr_reader = thread::spawn(move || {
+        let mut stderr = Vec::new();
+        child_stderr.read_to_end(&mut stderr).map(|_| stderr)
+    });
+    let mut child_stdout = child.stdout.take().unwrap();
+    let mut stdout = Vec::new();
+    (&mut child_stdout)
+        .take(output_limit as u64)
+        .read_to_end(&mut stdout)?;
+    io::copy(&mut child_stdout, &mut io::sink())?;
+    let stderr = stderr_reader.join().unwrap()?;
+    Ok(Output {
+        exit_code: exit_code(child.wait()?),
+        stdout,
+        stderr,
+    })
+}
+
+// The exit code, or 128 plus the signal number for a child killed by a
+// signal, as shells report it.
+fn exit_code(status: ExitStatus) -> i32 {
+    #[cfg(unix)]
+    if let Some(signal) = std::os::unix::process::ExitStatusExt::signal(&status) {
+        return 128 + signal;
+    }
+    status.code().unwrap_or(-1)
+}
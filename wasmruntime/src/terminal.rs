@@ -0,0 +1,199 @@
+// Virtual terminal for --expect's screen assertions. Output is interpreted
+// the way a terminal would: printable characters are drawn at the cursor,
+// carriage returns, line feeds, backspaces, and tabs move it, and the common
+// CSI sequences for cursor movement and erasing are applied. Other escape
+// sequences, including colors, are parsed and ignored.
+
+enum State {
+    Normal,
+    Escape,
+    // Parameter and intermediate bytes so far.
+    Csi(String),
+    // Operating system command, ended by BEL or ESC \.
+    Osc { escape: bool },
+}
+
+pub struct Terminal {
+    rows: usize,
+    columns: usize,
+    grid: Vec<Vec<char>>,
+    row: usize,
+    column: usize,
+    state: State,
+    // Bytes of an incomplete UTF-8 character.
+    pending: Vec<u8>,
+}
+
+impl Terminal {
+    pub fn new(rows: usize, columns: usize) -> Terminal {
+        Terminal {
+            rows,
+            columns,
+            grid: vec![vec![' '; columns]; rows],
+            row: 0,
+            column: 0,
+            state: State::Normal,
+            pending: Vec::new(),
+        }
+    }
+
+    // Returns the text of a row, numbered from 1, without trailing spaces.
+    pub fn row(&self, row: usize) -> Option<String> {
+        let cells = self.grid.get(row.checked_sub(1)?)?;
+        Some(cells.iter().collect::<String>().trim_end().to_string())
+    }
+
+    pub fn dump(&self) -> String {
+        (1..=self.rows)
+            .map(|row| format!("{:>3}|{}\n", row, self.row(row).unwrap()))
+            .collect()
+    }
+
+    pub fn feed(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.feed_byte(byte);
+        }
+    }
+
+    fn feed_byte(&mut self, byte: u8) {
+        match &mut self.state {
+            State::Normal => {}
+            State::Escape => {
+                self.state = match byte {
+                    b'[' => State::Csi(String::new()),
+                    b']' => State::Osc { escape: false },
+                    _ => State::Normal,
+                };
+                return;
+            }
+            State::Csi(parameters) => {
+                if (0x40..=0x7e).contains(&byte) {
+                    let parameters = std::mem::take(parameters);
+                    self.state = State::Normal;
+                    self.control_sequence(&parameters, byte);
+                } else {
+                    parameters.push(byte as char);
+                }
+                return;
+            }
+            State::Osc { escape } => {
+                if byte == 0x07 || (*escape && byte == b'\\') {
+                    self.state = State::Normal;
+                } else {
+                    *escape = byte == 0x1b;
+                }
+                return;
+            }
+        }
+        if !self.pending.is_empty() || byte >= 0x80 {
+            self.pending.push(byte);
+            match std::str::from_utf8(&self.pending) {
+                Ok(text) => {
+                    let c = text.chars().next().unwrap();
+                    self.pending.clear();
+                    self.put(c);
+                }
+                Err(error) if error.error_len().is_some() => {
+                    self.pending.clear();
+                    self.put(char::REPLACEMENT_CHARACTER);
+                }
+                Err(_) => {}
+            }
+            return;
+        }
+        match byte {
+            0x1b => self.state = State::Escape,
+            b'\r' => self.column = 0,
+            b'\n' | 0x0b | 0x0c => self.line_feed(),
+            0x08 => self.column = self.column.saturating_sub(1),
+            b'\t' => self.column = ((self.column / 8 + 1) * 8).min(self.columns - 1),
+            0x20..=0x7e => self.put(byte as char),
+            _ => {}
+        }
+    }
+
+    fn put(&mut self, c: char) {
+        if self.column >= self.columns {
+            self.column = 0;
+            self.line_feed();
+        }
+        self.grid[self.row][self.column] = c;
+        self.column += 1;
+    }
+
+    fn line_feed(&mut self) {
+        if self.row + 1 < self.rows {
+            self.row += 1;
+        } else {
+            self.grid.remove(0);
+            self.grid.push(vec![' '; self.columns]);
+        }
+    }
+
+    fn control_sequence(&mut self, parameters: &str, command: u8) {
+        // Private sequences such as ESC [ ? 25 l only change modes
+        if parameters.starts_with(['?', '>', '=']) {
+            return;
+        }
+        let numbers: Vec<usize> = parameters
+            .split(';')
+            .map(|number| number.parse().unwrap_or(0))
+            .collect();
+        let number = |index: usize, default: usize| match numbers.get(index) {
+            Some(0) | None => default,
+            Some(&number) => number,
+        };
+        match command {
+            b'A' => self.row = self.row.saturating_sub(number(0, 1)),
+            b'B' => self.row = (self.row + number(0, 1)).min(self.rows - 1),
+            b'C' => self.column = (self.column + number(0, 1)).min(self.columns - 1),
+            b'D' => {
+                self.column = self
+                    .column
+                    .min(self.columns - 1)
+                    .saturating_sub(number(0, 1))
+            }
+            b'G' => self.column = number(0, 1).min(self.columns) - 1,
+            b'd' => self.row = number(0, 1).min(self.rows) - 1,
+            b'H' | b'f' => {
+                self.row = number(0, 1).min(self.rows) - 1;
+                self.column = number(1, 1).min(self.columns) - 1;
+            }
+            b'J' => {
+                let (row, column) = (self.row, self.column.min(self.columns));
+                match numbers[0] {
+                    0 => {
+                        self.erase(row, column..self.columns);
+                        for row in row + 1..self.rows {
+                            self.erase(row, 0..self.columns);
+                        }
+                    }
+                    1 => {
+                        for row in 0..row {
+                            self.erase(row, 0..self.columns);
+                        }
+                        self.erase(row, 0..(column + 1).min(self.columns));
+                    }
+                    _ => {
+                        for row in 0..self.rows {
+                            self.erase(row, 0..self.columns);
+                        }
+                    }
+                }
+            }
+            b'K' => {
+                let (row, column) = (self.row, self.column.min(self.columns));
+                match numbers[0] {
+                    0 => self.erase(row, column..self.columns),
+                    1 => self.erase(row, 0..(column + 1).min(self.columns)),
+                    _ => self.erase(row, 0..self.columns),
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn erase(&mut self, row: usize, columns: std::ops::Range<usize>) {
+        self.grid[row][columns].fill(' ');
+    }
+}
@@ -0,0 +1,477 @@
+// `wasmruntime test`: runs a directory of Zong programs as a test suite. Each
+// NAME.wasm is run with NAME.stdin as its stdin, or no input if there is none,
+// and passes if it exits with code 0 and its stdout matches NAME.expected
+// byte for byte. Without a NAME.expected, only the exit code is checked.
+// Tests run in parallel, on --jobs threads or else one per core, each in its
+// own store with its stdout captured, and share one engine and linker.
+// Results are printed in name order with each test's time and the first
+// difference for each failure, followed by a summary.
+//
+// With --output json or junit, the results are instead printed as a report
+// that also gives each test's host calls, by function, the bytes it wrote
+// to stdout and stderr, and the fuel it consumed, so that CI can check
+// performance budgets. The JSON report is one object:
+//
+//   {"tests":[{"name":"hello","passed":true,"problems":[],"time_secs":0.004,
+//     "exit_code":0,"host_calls":{"env.print_bytes":1},"stdout_bytes":6,
+//     "stderr_bytes":0,"fuel_consumed":93}],"passed":1,"failed":0,
+//    "time_secs":0.006}
+//
+// exit_code is null, and the statistics are left out, for a test that could
+// not be run.
+// The JUnit report has a testcase for each test, with the statistics as its
+// properties, and host calls as properties named host_calls.MODULE.NAME.
+// Host calls are counted at direct call sites, as for --stats.
+
+use crate::hostio::HostIo;
+use crate::report::{self, StdioCounts};
+use crate::sandbox::Profile;
+use crate::{
+    call_entry, callcount, check_resolved, diagnostics, exitcode, read_module, Host, HostState,
+};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt::{Display, Write as _};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use wasmtime::{Error, Module, Result, Store};
+use wasmtime_wasi::I32Exit;
+
+#[derive(Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ReportFormat {
+    /// A line for each test and a summary
+    Text,
+    /// A JSON object with each test's result and statistics
+    Json,
+    /// JUnit XML, with each test's statistics as properties
+    Junit,
+}
+
+struct Test {
+    name: String,
+    wasm_path: PathBuf,
+    stdin: Vec<u8>,
+    expected: Option<Vec<u8>>,
+}
+
+// How a test program's run ended.
+struct Outcome {
+    exit_code: i32,
+    stdout: Vec<u8>,
+    // The error that stopped the program, if any.
+    error: Option<String>,
+    // Set for --output json and junit.
+    stats: Option<Stats>,
+}
+
+#[derive(Serialize)]
+struct Stats {
+    // Calls to each host function, by "module.name".
+    host_calls: BTreeMap<String, i64>,
+    stdout_bytes: u64,
+    stderr_bytes: u64,
+    fuel_consumed: u64,
+}
+
+// A test's entry in the JSON report.
+#[derive(Serialize)]
+struct TestReport<'a> {
+    name: &'a str,
+    passed: bool,
+    problems: &'a [String],
+    time_secs: f64,
+    exit_code: Option<i32>,
+    #[serde(flatten)]
+    stats: Option<&'a Stats>,
+}
+
+#[derive(Serialize)]
+struct SuiteReport<'a> {
+    tests: Vec<TestReport<'a>>,
+    passed: usize,
+    failed: usize,
+    time_secs: f64,
+}
+
+// A test's outcome, or why it could not run, and how long it took.
+type TimedOutcome = (Result<Outcome>, Duration);
+
+// Runs the tests in dir on jobs threads, or one per core, and returns the
+// exit code: 0 if all passed, 1 otherwise.
+pub fn run(dir: &Path, jobs: Option<usize>, format: ReportFormat) -> Result<i32> {
+    let started = Instant::now();
+    let tests = find_tests(dir)?;
+    // Only the reports give the fuel, which metering slows tests down for
+    let with_stats = format != ReportFormat::Text;
+    let host = if with_stats {
+        Host::with_fuel_metering()?
+    } else {
+        Host::new()?
+    };
+    let next = AtomicUsize::new(0);
+    let outcomes: Mutex<Vec<Option<TimedOutcome>>> =
+        Mutex::new(tests.iter().map(|_| None).collect());
+    let threads = jobs
+        .unwrap_or_else(|| {
+            std::thread::available_parallelism()
+                .map(|count| count.get())
+                .unwrap_or(1)
+        })
+        .min(tests.len());
+    std::thread::scope(|scope| {
+        for _ in 0..threads {
+            scope.spawn(|| loop {
+                let index = next.fetch_add(1, Ordering::SeqCst);
+                let Some(test) = tests.get(index) else {
+                    break;
+                };
+                let test_started = Instant::now();
+                let outcome = run_test(&host, test, with_stats);
+                outcomes.lock().unwrap()[index] = Some((outcome, test_started.elapsed()));
+            });
+        }
+    });
+
+    let results: Vec<(&Test, Option<Outcome>, Vec<String>, Duration)> = tests
+        .iter()
+        .zip(outcomes.into_inner().unwrap())
+        .map(|(test, outcome)| {
+            let (outcome, elapsed) = outcome.expect("every test is run");
+            match outcome {
+                Ok(outcome) => {
+                    let problems = problems(test, &outcome);
+                    (test, Some(outcome), problems, elapsed)
+                }
+                Err(error) => (test, None, vec![format!("Error: {:#}", error)], elapsed),
+            }
+        })
+        .collect();
+    let failed = results
+        .iter()
+        .filter(|(_, _, problems, _)| !problems.is_empty())
+        .count();
+    let elapsed = started.elapsed();
+    match format {
+        ReportFormat::Text => print_text(&results, elapsed),
+        ReportFormat::Json => print_json(&results, elapsed),
+        ReportFormat::Junit => print_junit(dir, &results, elapsed),
+    }
+    Ok(if failed == 0 { 0 } else { 1 })
+}
+
+// A test, how its run ended if it could be run, and what it did wrong.
+type TestResult<'a> = (&'a Test, Option<Outcome>, Vec<String>, Duration);
+
+fn print_text(results: &[TestResult<'_>], elapsed: Duration) {
+    let mut failed = 0;
+    for (test, _, problems, test_elapsed) in results {
+        let time = test_elapsed.as_secs_f64();
+        if problems.is_empty() {
+            println!("ok    {} ({:.3} s)", test.name, time);
+        } else {
+            failed += 1;
+            println!("FAIL  {} ({:.3} s)", test.name, time);
+            for problem in problems {
+                println!("      {}", problem);
+            }
+        }
+    }
+    println!(
+        "{} passed, {} failed in {:.3} s",
+        results.len() - failed,
+        failed,
+        elapsed.as_secs_f64()
+    );
+}
+
+fn print_json(results: &[TestResult<'_>], elapsed: Duration) {
+    let tests: Vec<TestReport> = results
+        .iter()
+        .map(|(test, outcome, problems, test_elapsed)| TestReport {
+            name: &test.name,
+            passed: problems.is_empty(),
+            problems,
+            time_secs: test_elapsed.as_secs_f64(),
+            exit_code: outcome.as_ref().map(|outcome| outcome.exit_code),
+            stats: outcome.as_ref().and_then(|outcome| outcome.stats.as_ref()),
+        })
+        .collect();
+    let passed = tests.iter().filter(|test| test.passed).count();
+    let report = SuiteReport {
+        failed: tests.len() - passed,
+        tests,
+        passed,
+        time_secs: elapsed.as_secs_f64(),
+    };
+    println!(
+        "{}",
+        serde_json::to_string(&report).expect("reports serialize to JSON")
+    );
+}
+
+fn print_junit(dir: &Path, results: &[TestResult<'_>], elapsed: Duration) {
+    let failed = results
+        .iter()
+        .filter(|(_, _, problems, _)| !problems.is_empty())
+        .count();
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    let _ = writeln!(
+        xml,
+        "<testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" time=\"{:.3}\">",
+        escape_xml(&dir.display().to_string()),
+        results.len(),
+        failed,
+        elapsed.as_secs_f64()
+    );
+    for (test, outcome, problems, test_elapsed) in results {
+        let _ = writeln!(
+            xml,
+            "  <testcase name=\"{}\" time=\"{:.3}\">",
+            escape_xml(&test.name),
+            test_elapsed.as_secs_f64()
+        );
+        if let Some(stats) = outcome.as_ref().and_then(|outcome| outcome.stats.as_ref()) {
+            xml.push_str("    <properties>\n");
+            let mut property = |name: &str, value: &dyn Display| {
+                let name = escape_xml(name);
+                let _ = writeln!(
+                    xml,
+                    "      <property name=\"{}\" value=\"{}\"/>",
+                    name, value
+                );
+            };
+            property("fuel_consumed", &stats.fuel_consumed);
+            property("stdout_bytes", &stats.stdout_bytes);
+            property("stderr_bytes", &stats.stderr_bytes);
+            for (name, calls) in &stats.host_calls {
+                property(&format!("host_calls.{}", name), calls);
+            }
+            xml.push_str("    </properties>\n");
+        }
+        if let Some(first) = problems.first() {
+            let _ = writeln!(
+                xml,
+                "    <failure message=\"{}\">{}</failure>",
+                escape_xml(first),
+                escape_xml(&problems.join("\n"))
+            );
+        }
+        xml.push_str("  </testcase>\n");
+    }
+    xml.push_str("</testsuite>");
+    println!("{}", xml);
+}
+
+pub fn escape_xml(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '&' => escaped.push_str("&amp;"),
+            '"' => escaped.push_str("&quot;"),
+            // XML 1.0 cannot represent most control characters at all
+            c if c.is_control() && !matches!(c, '\n' | '\t') => escaped.push('\u{fffd}'),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// Returns the tests in dir, sorted by name.
+fn find_tests(dir: &Path) -> Result<Vec<Test>> {
+    let context = |error| Error::new(error).context(dir.display().to_string());
+    let mut wasm_paths = Vec::new();
+    for entry in fs::read_dir(dir).map_err(context)? {
+        let path = entry.map_err(context)?.path();
+        if path
+            .extension()
+            .is_some_and(|extension| extension == "wasm")
+        {
+            wasm_paths.push(path);
+        }
+    }
+    if wasm_paths.is_empty() {
+        return Err(Error::msg(format!("{}: no .wasm files", dir.display())));
+    }
+    wasm_paths.sort();
+    wasm_paths
+        .into_iter()
+        .map(|wasm_path| {
+            let read_sibling = |extension: &str| {
+                let path = wasm_path.with_extension(extension);
+                match fs::read(&path) {
+                    Ok(bytes) => Ok(Some(bytes)),
+                    Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(None),
+                    Err(error) => Err(Error::new(error).context(path.display().to_string())),
+                }
+            };
+            Ok(Test {
+                name: wasm_path
+                    .file_stem()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .into_owned(),
+                stdin: read_sibling("stdin")?.unwrap_or_default(),
+                expected: read_sibling("expected")?,
+                wasm_path,
+            })
+        })
+        .collect()
+}
+
+// Runs a test program the way `wasmruntime run` would with default options,
+// but with its stdin and stdout in memory. With with_stats, the host must
+// meter fuel.
+fn run_test(host: &Host, test: &Test, with_stats: bool) -> Result<Outcome> {
+    let wasm_bytes = read_module(&test.wasm_path)?;
+    // Host calls are counted by an instrumented copy of the module, as for
+    // --stats
+    let instrumented = if with_stats {
+        Some(callcount::instrument(&wasm_bytes)?)
+    } else {
+        None
+    };
+    let run_bytes = instrumented
+        .as_ref()
+        .map_or(&wasm_bytes, |instrumented| &instrumented.wasm_bytes);
+    let module = Module::new(host.engine(), run_bytes)?;
+    host.check_imports(&module)?;
+    let argv = [test.wasm_path.display().to_string()];
+    let mut io = HostIo::default();
+    io.set_stdin(test.stdin.clone());
+    let stdout = io.capture_stdout();
+    let profile = Profile::default();
+    let mut state = HostState::for_module(&module, &wasm_bytes, None, &[], &argv, io, profile)?;
+    let mut linker = host.linker().clone();
+    if with_stats {
+        state.stdio = Some(StdioCounts::default());
+        if state.wasi.is_some() {
+            report::count_wasi_stdio(&mut linker)?;
+        }
+    }
+    let mut store = Store::new(host.engine(), state);
+    store.limiter(|state| &mut state.limits);
+    store.set_epoch_deadline(1);
+    if with_stats {
+        store.set_fuel(u64::MAX)?;
+    }
+    check_resolved(&linker, &mut store, &module, &argv[0])?;
+
+    let mut instance = None;
+    let result = linker
+        .instantiate(&mut store, &module)
+        .and_then(|new_instance| {
+            let instance = instance.insert(new_instance);
+            let entry = match instance.get_func(&mut store, "main") {
+                Some(main) => Some((main, "main")),
+                None => instance
+                    .get_func(&mut store, "_start")
+                    .map(|start| (start, "_start")),
+            };
+            let (entry_func, entry) =
+                entry.ok_or_else(|| Error::msg("entry export 'main' not found"))?;
+            call_entry(&mut store, entry_func, entry)
+        });
+    let (exit_code, error) = match result {
+        Ok(exit_code) => (exit_code.unwrap_or(0), None),
+        Err(error) => match error.downcast_ref::<I32Exit>() {
+            Some(exit) => (exit.0, None),
+            None => (
+                exitcode::for_error(&error),
+                Some(diagnostics::describe(&error, false)),
+            ),
+        },
+    };
+    store.data_mut().flush_stdout()?;
+    let stats = match instrumented {
+        Some(instrumented) => {
+            let host_calls = match &instance {
+                Some(instance) => {
+                    instrumented.import_calls(&instrumented.counts(&mut store, instance)?)
+                }
+                None => BTreeMap::new(),
+            };
+            let stdio = store.data().stdio.as_ref();
+            Some(Stats {
+                host_calls,
+                stdout_bytes: stdio.map_or(0, |stdio| stdio.stdout),
+                stderr_bytes: stdio.map_or(0, |stdio| stdio.stderr),
+                fuel_consumed: u64::MAX - store.get_fuel()?,
+            })
+        }
+        None => None,
+    };
+    Ok(Outcome {
+        exit_code,
+        stdout: stdout.contents(),
+        error,
+        stats,
+    })
+}
+
+// Describes how the outcome differs from what the test expects.
+fn problems(test: &Test, outcome: &Outcome) -> Vec<String> {
+    let mut problems = Vec::new();
+    if let Some(error) = &outcome.error {
+        problems.push(format!("Error: {}", error));
+    }
+    if outcome.exit_code != 0 {
+        problems.push(format!("exited with code {}", outcome.exit_code));
+    }
+    if let Some(expected) = &test.expected {
+        if let Some(difference) = first_difference(expected, &outcome.stdout) {
+            problems.push(difference);
+        }
+    }
+    problems
+}
+
+// Describes the first line of stdout that differs from the expected output,
+// or returns None if they are the same.
+fn first_difference(expected: &[u8], actual: &[u8]) -> Option<String> {
+    let difference = first_different_line(expected, actual)?;
+    Some(format!(
+        "stdout line {}: expected {}, got {}",
+        difference.line,
+        show_line(difference.a),
+        show_line(difference.b)
+    ))
+}
+
+// The first line that differs between two outputs.
+pub struct LineDifference<'a> {
+    // Counting from 1.
+    pub line: usize,
+    // The line in each output, or None for the end of that output.
+    pub a: Option<&'a [u8]>,
+    pub b: Option<&'a [u8]>,
+}
+
+// Returns the first line that differs between two outputs, or None if they
+// are the same.
+pub fn first_different_line<'a>(a: &'a [u8], b: &'a [u8]) -> Option<LineDifference<'a>> {
+    if a == b {
+        return None;
+    }
+    let mut a_lines = a.split_inclusive(|&byte| byte == b'\n');
+    let mut b_lines = b.split_inclusive(|&byte| byte == b'\n');
+    let mut line = 1;
+    loop {
+        match (a_lines.next(), b_lines.next()) {
+            (Some(a), Some(b)) if a == b => line += 1,
+            (a, b) => return Some(LineDifference { line, a, b }),
+        }
+    }
+}
+
+// Shows a line returned by first_different_line.
+pub fn show_line(line: Option<&[u8]>) -> String {
+    match line {
+        Some(line) => format!("{:?}", String::from_utf8_lossy(line)),
+        None => String::from("end of output"),
+    }
+}
@@ -0,0 +1,301 @@
+// Guest threads for spawn and join, so that Zong programs can experiment with
+// parallelism. Before compiling, a module that imports env.spawn is changed
+// so that its memory is a shared memory imported as env.memory, and gets a
+// table holding every function at its function index, exported as
+// __zong_threads. spawn starts a host thread with a new instance of a copy
+// of the module without its active data segments and start function, which
+// would reinitialize the memory, and calls the function there.
+//
+// Threads share the memory, the host_alloc heap, and stdin, stdout, and
+// stderr with the program, and nothing else: each has its own globals,
+// files, timers, and WASI context, and no preopened directories. Each runs
+// on its own tstack of STACK_SIZE bytes from the heap.
+//
+// Threads are held to the program's limits, as load_module's children are:
+// growing the shared memory to --max-memory and --max-tables, and their code
+// to half of the fuel the program has left when it calls spawn, which join
+// gives back what the thread did not use of. A signal or the timeout stops
+// every thread at its next epoch check, and the main thread, which runs the
+// guest's handler for the signal. Instrumenting options only see the main
+// thread. The program exits when its entry function returns, whether or not
+// its threads have finished.
+
+use crate::allocprofile::AllocProfile;
+use crate::heap::Heap;
+use crate::limits::Limits;
+use crate::sandbox::Profile;
+use crate::{HostIo, HostState};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use walrus::{ConstExpr, ElementItems, ElementKind, RefType};
+use wasmparser::{Parser, Payload, TypeRef};
+use wasmtime::{Engine, Error, Module, Result, SharedMemory, Store, UpdateDeadline, Val};
+
+pub const TABLE: &str = "__zong_threads";
+
+// Size of each thread's tstack.
+pub const STACK_SIZE: usize = 1 << 20;
+
+// Shared memories need a maximum; this is all of wasm32's address space.
+const MAX_PAGES: u64 = 1 << 16;
+
+pub struct Threads {
+    instance: ThreadInstance,
+    // By id; None once joined.
+    threads: Vec<Option<Thread>>,
+}
+
+// What a thread needs to instantiate the module.
+#[derive(Clone)]
+struct ThreadInstance {
+    engine: Engine,
+    // The copy of the module that threads instantiate.
+    module: Module,
+    // The main module's bytes, for get_embedded.
+    wasm_bytes: Arc<[u8]>,
+    memory: SharedMemory,
+    profile: Profile,
+}
+
+// What a thread gets from the program.
+pub struct Inherited {
+    pub io: HostIo,
+    pub heap: Arc<Mutex<Heap>>,
+    pub alloc_profile: Option<Arc<Mutex<AllocProfile>>>,
+    pub limits: Limits,
+    // Stops the thread if a signal arrived or the timeout passed.
+    pub check_interrupted: Box<dyn Fn() -> Result<()> + Send + Sync>,
+}
+
+struct Thread {
+    handle: JoinHandle<Finished>,
+    stack: usize,
+}
+
+// A thread that has finished.
+pub struct Finished {
+    // Whether the thread's function returned without trapping.
+    pub ok: bool,
+    // The address of its tstack.
+    pub stack: usize,
+    // With fuel metering, what is left of the fuel it was given.
+    pub fuel: Option<u64>,
+}
+
+// Whether the module imports env.spawn, checked without walrus, which would
+// reject some modules that wasmtime runs.
+fn imports_spawn(wasm_bytes: &[u8]) -> Result<bool> {
+    for payload in Parser::new(0).parse_all(wasm_bytes) {
+        if let Payload::ImportSection(imports) = payload? {
+            for import in imports {
+                let import = import?;
+                if (import.module, import.name) == ("env", "spawn")
+                    && matches!(import.ty, TypeRef::Func(_))
+                {
+                    return Ok(true);
+                }
+            }
+        }
+    }
+    Ok(false)
+}
+
+// Returns the module to run and the copy for threads, or None if the module
+// does not import env.spawn.
+pub fn instrument(wasm_bytes: &[u8]) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+    if !imports_spawn(wasm_bytes)? {
+        return Ok(None);
+    }
+    let mut module = walrus::Module::from_buffer(wasm_bytes)?;
+    let memory = module
+        .memories
+        .iter()
+        .next()
+        .map(|memory| memory.id())
+        .ok_or_else(|| Error::msg("spawn needs the module to have a memory"))?;
+    let shared = module.memories.get_mut(memory);
+    shared.shared = true;
+    shared.maximum.get_or_insert(MAX_PAGES);
+    if shared.import.is_none() {
+        let import = module.imports.add("env", "memory", memory);
+        module.memories.get_mut(memory).import = Some(import);
+    }
+
+    let funcs: Vec<_> = module.funcs.iter().map(|func| func.id()).collect();
+    let len = funcs.len() as u64;
+    let table = module
+        .tables
+        .add_local(false, len, Some(len), RefType::FUNCREF);
+    let elements = module.elements.add(
+        ElementKind::Active {
+            table,
+            offset: ConstExpr::Value(walrus::ir::Value::I32(0)),
+        },
+        ElementItems::Functions(funcs),
+    );
+    module.tables.get_mut(table).elem_segments.insert(elements);
+    module.exports.add(TABLE, table);
+    let main_bytes = module.emit_wasm();
+
+    let active: Vec<_> = module
+        .data
+        .iter()
+        .filter(|data| matches!(data.kind, walrus::DataKind::Active { .. }))
+        .map(|data| data.id())
+        .collect();
+    for data in active {
+        module.data.delete(data);
+    }
+    module.memories.get_mut(memory).data_segments.clear();
+    module.start = None;
+    Ok(Some((main_bytes, module.emit_wasm())))
+}
+
+impl Threads {
+    pub fn new(
+        engine: &Engine,
+        module: Module,
+        wasm_bytes: &[u8],
+        memory: SharedMemory,
+        profile: Profile,
+    ) -> Threads {
+        Threads {
+            instance: ThreadInstance {
+                engine: engine.clone(),
+                module,
+                wasm_bytes: Arc::from(wasm_bytes),
+                memory,
+                profile,
+            },
+            threads: Vec::new(),
+        }
+    }
+
+    // Starts a thread calling the function at index func_index of the
+    // table with arg, with its tstack at stack and, with fuel metering, the
+    // given fuel, and returns its id.
+    pub fn spawn(
+        &mut self,
+        func_index: u32,
+        arg: i64,
+        stack: usize,
+        inherited: Inherited,
+        fuel: Option<u64>,
+    ) -> i32 {
+        let id = self.threads.len() as i32;
+        let instance = self.instance.clone();
+        let handle = std::thread::spawn(move || {
+            let mut stderr = inherited.io.share();
+            let (result, fuel) = instance.run(inherited, stack, func_index, arg, fuel);
+            let ok = match result {
+                Ok(()) => true,
+                // The main thread reports these
+                Err(error) if crate::is_interruption(&error) => false,
+                Err(error) => {
+                    let message = format!("thread {}: {:#}\n", id, error);
+                    let _ = stderr.write_stderr(message.as_bytes());
+                    false
+                }
+            };
+            Finished { ok, stack, fuel }
+        });
+        self.threads.push(Some(Thread { handle, stack }));
+        id
+    }
+
+    // Whether the thread is still running, or None if there is no such
+    // thread or it was joined.
+    pub fn is_running(&self, id: i32) -> Option<bool> {
+        let thread = self.threads.get(usize::try_from(id).ok()?)?.as_ref()?;
+        Some(!thread.handle.is_finished())
+    }
+
+    // Waits for the thread, or returns None if there is no such thread or
+    // it was joined.
+    pub fn join(&mut self, id: i32) -> Option<Finished> {
+        let thread = self.threads.get_mut(usize::try_from(id).ok()?)?.take()?;
+        Some(thread.handle.join().unwrap_or(Finished {
+            ok: false,
+            stack: thread.stack,
+            fuel: None,
+        }))
+    }
+}
+
+impl ThreadInstance {
+    // Runs the thread's function, returning its result and what is left of
+    // the fuel.
+    fn run(
+        self,
+        inherited: Inherited,
+        stack: usize,
+        func_index: u32,
+        arg: i64,
+        fuel: Option<u64>,
+    ) -> (Result<()>, Option<u64>) {
+        let mut store = match self.store(inherited, stack, fuel) {
+            Ok(store) => store,
+            Err(error) => return (Err(error), fuel),
+        };
+        let result = self.call(&mut store, stack, func_index, arg);
+        let fuel = fuel.and_then(|_| store.get_fuel().ok());
+        (result, fuel)
+    }
+
+    fn store(
+        &self,
+        inherited: Inherited,
+        stack: usize,
+        fuel: Option<u64>,
+    ) -> Result<Store<HostState>> {
+        let mut state = HostState::for_module(
+            &self.module,
+            &self.wasm_bytes,
+            None,
+            &[],
+            &[],
+            inherited.io,
+            self.profile,
+        )?;
+        state.heap = Some(inherited.heap);
+        state.alloc_profile = inherited.alloc_profile;
+        state.limits = inherited.limits;
+        state.thread_stack_end = Some(stack + STACK_SIZE);
+        let mut store = Store::new(&self.engine, state);
+        store.limiter(|state| &mut state.limits);
+        store.set_epoch_deadline(1);
+        let check_interrupted = inherited.check_interrupted;
+        store.epoch_deadline_callback(move |_| {
+            check_interrupted()?;
+            Ok(UpdateDeadline::Continue(1))
+        });
+        if let Some(fuel) = fuel {
+            store.set_fuel(fuel)?;
+        }
+        Ok(store)
+    }
+
+    fn call(
+        &self,
+        store: &mut Store<HostState>,
+        stack: usize,
+        func_index: u32,
+        arg: i64,
+    ) -> Result<()> {
+        let mut linker = crate::host_linker(&self.engine, store.data())?;
+        linker.define(&*store, "env", "memory", self.memory.clone())?;
+        let instance = linker.instantiate(&mut *store, &self.module)?;
+        // The tstack grows up from its base
+        if let Some(tstack) = instance.get_global(&mut *store, "tstack") {
+            tstack.set(&mut *store, Val::I32(stack as u32 as i32))?;
+        }
+        let func = instance
+            .get_table(&mut *store, TABLE)
+            .and_then(|table| table.get(&mut *store, func_index.into()))
+            .and_then(|func| func.as_func().flatten().copied())
+            .ok_or_else(|| Error::msg(format!("no function {}", func_index)))?;
+        let result = func.typed::<i64, ()>(&*store)?.call(&mut *store, arg);
+        store.data_mut().flush_stdout()?;
+        result
+    }
+}
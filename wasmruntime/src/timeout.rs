@@ -0,0 +1,81 @@
+// Wall-clock limit for --timeout. A background thread waits out the limit,
+// then marks it expired and bumps the engine's epoch; the next epoch check in
+// the guest stops execution with a TimedOut error, so the runtime can report
+// where the guest was before exiting. Host calls that wait, such as reading
+// stdin or a socket and sleep_ms, watch for the limit and stop the guest the
+// same way. A guest blocked in any other host call makes no epoch checks, so
+// if it has not stopped GRACE_PERIOD later, the thread reports the timeout
+// itself and exits the process.
+//
+// Embedders cannot have the process exit under them, so a Pool's runs get
+// deadlines made with from_now instead, which expire by the clock alone and
+// rely on the Pool's ticker to bump the epoch.
+
+use std::fmt;
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use wasmtime::Engine;
+
+const GRACE_PERIOD: Duration = Duration::from_secs(1);
+
+#[derive(Clone)]
+pub struct Deadline {
+    limit: Duration,
+    expired: Arc<AtomicBool>,
+    // Set for deadlines made with from_now.
+    ends: Option<Instant>,
+}
+
+impl Deadline {
+    pub fn start(engine: &Engine, limit: Duration) -> Deadline {
+        let deadline = Deadline {
+            limit,
+            expired: Arc::new(AtomicBool::new(false)),
+            ends: None,
+        };
+        let engine = engine.clone();
+        let expired = deadline.expired.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(limit);
+            expired.store(true, Ordering::SeqCst);
+            engine.increment_epoch();
+            std::thread::sleep(GRACE_PERIOD);
+            let _ = std::io::stdout().flush();
+            eprintln!("Error: {} (in a host call)", TimedOut(limit));
+            crate::tty::restore();
+            crate::bundle::remove_extracted();
+            std::process::exit(crate::exitcode::TIMEOUT);
+        });
+        deadline
+    }
+
+    // A deadline limit from now, with no thread of its own.
+    pub fn from_now(limit: Duration) -> Deadline {
+        Deadline {
+            limit,
+            expired: Arc::new(AtomicBool::new(false)),
+            ends: Instant::now().checked_add(limit),
+        }
+    }
+
+    // Returns the error to stop the guest with, if the limit has passed.
+    pub fn check(&self) -> Option<TimedOut> {
+        let expired = self.expired.load(Ordering::SeqCst)
+            || self.ends.is_some_and(|ends| Instant::now() >= ends);
+        expired.then_some(TimedOut(self.limit))
+    }
+}
+
+// Error that stops the guest when the limit passes.
+#[derive(Debug)]
+pub struct TimedOut(pub Duration);
+
+impl fmt::Display for TimedOut {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "execution timed out after {:?}", self.0)
+    }
+}
+
+impl std::error::Error for TimedOut {}
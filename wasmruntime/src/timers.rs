@@ -0,0 +1,61 @@
+// Guest timers. The guest schedules callbacks with set_timeout/set_interval,
+// then enters the event loop; the loop sleeps until the next timer is due and
+// hands its callback back to be called in the guest.
+
+use std::time::{Duration, Instant};
+
+struct Timer {
+    id: i32,
+    due: Instant,
+    // Some for set_interval timers, which are rescheduled after each call.
+    interval: Option<Duration>,
+    // Index of the callback in the guest's function table.
+    callback: u32,
+}
+
+#[derive(Default)]
+pub struct Timers {
+    timers: Vec<Timer>,
+    next_id: i32,
+}
+
+impl Timers {
+    pub fn schedule(&mut self, callback: u32, delay: Duration, repeat: bool) -> i32 {
+        self.next_id += 1;
+        self.timers.push(Timer {
+            id: self.next_id,
+            due: Instant::now() + delay,
+            interval: repeat.then_some(delay),
+            callback,
+        });
+        self.next_id
+    }
+
+    // Returns whether a timer with the given id was pending.
+    pub fn cancel(&mut self, id: i32) -> bool {
+        let count = self.timers.len();
+        self.timers.retain(|timer| timer.id != id);
+        self.timers.len() != count
+    }
+
+    pub fn next_due(&self) -> Option<Instant> {
+        self.timers.iter().map(|timer| timer.due).min()
+    }
+
+    // Removes the earliest timer if it is due, rescheduling it if it repeats,
+    // and returns its id and callback.
+    pub fn pop_due(&mut self, now: Instant) -> Option<(i32, u32)> {
+        let index = (0..self.timers.len())
+            .filter(|&i| self.timers[i].due <= now)
+            .min_by_key(|&i| self.timers[i].due)?;
+        let timer = &mut self.timers[index];
+        let due = (timer.id, timer.callback);
+        match timer.interval {
+            Some(interval) => timer.due = now + interval,
+            None => {
+                self.timers.remove(index);
+            }
+        }
+        Some(due)
+    }
+}
@@ -0,0 +1,324 @@
+// Host call tracing for --trace. Before compiling, each imported function is
+// replaced by a wrapper that passes its arguments to zong_trace.arg, calls
+// zong_trace.enter, calls the import, and then calls zong_trace.exit with the
+// result. The host logs each call to stderr with the time since the run
+// started, its raw arguments, and the contents of the slices it was passed,
+// and each return with its result and the slices it wrote. Each slice is
+// named by the memdump view that shows it, as in arg0=slice@0x1ff0 "hi", for
+// looking at it in full with --memdump or the debugger. Calls nest when a
+// host function calls back into the guest, e.g. for timers, and are indented
+// by depth.
+
+use crate::abi::Version;
+use crate::guest::{self, GuestData};
+use crate::{memdump, slice_abi, HostState};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::time::Instant;
+use walrus::ir::{Instr, InstrSeq, UnaryOp, VisitorMut};
+use walrus::{ElementItems, FunctionBuilder, FunctionId, ImportKind, ValType};
+use wasmtime::{Caller, Result};
+
+pub const IMPORT_MODULE: &str = "zong_trace";
+
+// Bytes of each slice shown in the log.
+const SLICE_LIMIT: usize = 64;
+
+struct Import {
+    module: String,
+    name: String,
+    params: Vec<ValType>,
+    result: Option<ValType>,
+}
+
+pub struct Tracer {
+    // Traced imports, by the index the wrappers pass.
+    imports: Vec<Import>,
+    start: Instant,
+    // Arguments passed to zong_trace.arg for the next enter.
+    pending: Vec<i64>,
+    // Calls in progress, innermost last: (import index, arguments).
+    calls: Vec<(usize, Vec<i64>)>,
+}
+
+// Redirects direct calls to the traced imports to their wrappers.
+struct Redirect<'a>(&'a HashMap<FunctionId, FunctionId>);
+
+impl VisitorMut for Redirect<'_> {
+    fn start_instr_seq_mut(&mut self, seq: &mut InstrSeq) {
+        for (instr, _) in &mut seq.instrs {
+            let func = match instr {
+                Instr::Call(call) => &mut call.func,
+                Instr::ReturnCall(call) => &mut call.func,
+                _ => continue,
+            };
+            if let Some(wrapper) = self.0.get(func) {
+                *func = *wrapper;
+            }
+        }
+    }
+}
+
+// Returns the instrumented module and a tracer for it.
+pub fn instrument(wasm_bytes: &[u8]) -> Result<(Vec<u8>, Tracer)> {
+    let wasm_bytes = wat::parse_bytes(wasm_bytes)?;
+    let mut module = walrus::Module::from_buffer(&wasm_bytes)?;
+    let mut imports = Vec::new();
+    let mut funcs = Vec::new();
+    for import in module.imports.iter() {
+        let ImportKind::Function(func) = import.kind else {
+            continue;
+        };
+        if import.module == crate::calltrace::IMPORT_MODULE {
+            continue;
+        }
+        let ty = module.types.get(module.funcs.get(func).ty());
+        let numeric = |ty: &ValType| {
+            matches!(
+                ty,
+                ValType::I32 | ValType::I64 | ValType::F32 | ValType::F64
+            )
+        };
+        if ty.results().len() > 1 || !ty.params().iter().chain(ty.results()).all(numeric) {
+            continue;
+        }
+        imports.push(Import {
+            module: import.module.clone(),
+            name: import.name.clone(),
+            params: ty.params().to_vec(),
+            result: ty.results().first().copied(),
+        });
+        funcs.push(func);
+    }
+
+    let arg_type = module.types.add(&[ValType::I64], &[]);
+    let (arg, _) = module.add_import_func(IMPORT_MODULE, "arg", arg_type);
+    let enter_type = module.types.add(&[ValType::I32], &[]);
+    let (enter, _) = module.add_import_func(IMPORT_MODULE, "enter", enter_type);
+    let exit_type = module.types.add(&[ValType::I32, ValType::I64], &[]);
+    let (exit, _) = module.add_import_func(IMPORT_MODULE, "exit", exit_type);
+
+    let mut wrappers = HashMap::new();
+    for (index, (import, func)) in imports.iter().zip(funcs).enumerate() {
+        let results: Vec<ValType> = import.result.into_iter().collect();
+        let mut builder = FunctionBuilder::new(&mut module.types, &import.params, &results);
+        let params: Vec<_> = import
+            .params
+            .iter()
+            .map(|ty| module.locals.add(*ty))
+            .collect();
+        let mut body = builder.func_body();
+        for (param, ty) in params.iter().zip(&import.params) {
+            body.local_get(*param);
+            widen(&mut body, *ty);
+            body.call(arg);
+        }
+        body.i32_const(index as i32).call(enter);
+        for param in &params {
+            body.local_get(*param);
+        }
+        body.call(func);
+        match import.result {
+            Some(ty) => {
+                let result = module.locals.add(ty);
+                body.local_set(result)
+                    .i32_const(index as i32)
+                    .local_get(result);
+                widen(&mut body, ty);
+                body.call(exit).local_get(result);
+            }
+            None => {
+                body.i32_const(index as i32).i64_const(0).call(exit);
+            }
+        }
+        wrappers.insert(func, builder.finish(params, &mut module.funcs));
+    }
+
+    let wrapper_ids: Vec<FunctionId> = wrappers.values().copied().collect();
+    for (id, func) in module.funcs.iter_local_mut() {
+        if !wrapper_ids.contains(&id) {
+            walrus::ir::dfs_pre_order_mut(&mut Redirect(&wrappers), func, func.entry_block());
+        }
+    }
+    for element in module.elements.iter_mut() {
+        if let ElementItems::Functions(funcs) = &mut element.items {
+            for func in funcs {
+                if let Some(wrapper) = wrappers.get(func) {
+                    *func = *wrapper;
+                }
+            }
+        }
+    }
+
+    let tracer = Tracer {
+        imports,
+        start: Instant::now(),
+        pending: Vec::new(),
+        calls: Vec::new(),
+    };
+    Ok((module.emit_wasm(), tracer))
+}
+
+// Converts the value on top of the stack to the i64 passed to the tracer.
+fn widen(body: &mut walrus::InstrSeqBuilder<'_>, ty: ValType) {
+    match ty {
+        ValType::I32 => {
+            body.unop(UnaryOp::I64ExtendUI32);
+        }
+        ValType::F32 => {
+            body.unop(UnaryOp::I32ReinterpretF32)
+                .unop(UnaryOp::I64ExtendUI32);
+        }
+        ValType::F64 => {
+            body.unop(UnaryOp::I64ReinterpretF64);
+        }
+        _ => {}
+    }
+}
+
+fn format_value(ty: ValType, bits: i64) -> String {
+    match ty {
+        ValType::I32 => (bits as i32).to_string(),
+        ValType::F32 => f32::from_bits(bits as u32).to_string(),
+        ValType::F64 => f64::from_bits(bits as u64).to_string(),
+        _ => bits.to_string(),
+    }
+}
+
+// Formats the slice at slice_ptr, truncated to SLICE_LIMIT bytes.
+fn format_slice(data: GuestData<'_>, name: &str, slice_ptr: i32) -> String {
+    let slice_ptr = guest::address(slice_ptr) as u64;
+    let bytes = slice_abi::read_slice(data, name, slice_ptr, slice_abi::Layout::default());
+    format!("{} {}", memdump::slice_view(slice_ptr), format_bytes(bytes))
+}
+
+// Formats the slice passed as arguments at index and index + 1, the way
+// version 2 of the ABI passes slices.
+fn format_pair(data: GuestData<'_>, name: &str, args: &[i64], index: usize) -> String {
+    let (&items_ptr, &length) = match (args.get(index), args.get(index + 1)) {
+        (Some(items_ptr), Some(length)) => (items_ptr, length),
+        _ => return String::from("<invalid slice>"),
+    };
+    let addr = guest::address(items_ptr as i32);
+    let range = guest::range(data, name, addr, length as u32 as usize);
+    let view = memdump::hex_view(addr as u64, length as u32 as u64);
+    format!(
+        "{} {}",
+        view,
+        format_bytes(range.map(|range| data.get(range)))
+    )
+}
+
+fn format_bytes(bytes: Result<Cow<'_, [u8]>>) -> String {
+    match bytes {
+        Ok(bytes) if bytes.len() > SLICE_LIMIT => format!(
+            "{:?}... ({} bytes)",
+            String::from_utf8_lossy(&bytes[..SLICE_LIMIT]),
+            bytes.len()
+        ),
+        Ok(bytes) => format!("{:?}", String::from_utf8_lossy(&bytes)),
+        Err(_) => String::from("<invalid slice>"),
+    }
+}
+
+impl Tracer {
+    pub fn arg(&mut self, bits: i64) {
+        self.pending.push(bits);
+    }
+
+    fn prefix(&self) -> String {
+        format!(
+            "[{:>11.6}] {}",
+            self.start.elapsed().as_secs_f64(),
+            "  ".repeat(self.calls.len())
+        )
+    }
+}
+
+fn data_and_state<'a>(caller: &'a mut Caller<'_, HostState>) -> (GuestData<'a>, &'a mut HostState) {
+    match guest::memory(caller, IMPORT_MODULE) {
+        Ok(memory) => {
+            let (data, state) = memory.data_and_store_mut(caller);
+            (data.into_data(), state)
+        }
+        Err(_) => (GuestData::Local(&[]), caller.data_mut()),
+    }
+}
+
+// Logs a call of the import at index, with the arguments passed to arg.
+pub fn enter(caller: &mut Caller<'_, HostState>, index: usize) {
+    let (data, state) = data_and_state(caller);
+    let abi = state.abi;
+    let Some(tracer) = &mut state.trace else {
+        return;
+    };
+    let args = std::mem::take(&mut tracer.pending);
+    let import = &tracer.imports[index];
+    let formatted: Vec<String> = import
+        .params
+        .iter()
+        .zip(&args)
+        .map(|(ty, bits)| format_value(*ty, *bits))
+        .collect();
+    let mut line = format!(
+        "{}{}.{}({})",
+        tracer.prefix(),
+        import.module,
+        import.name,
+        formatted.join(", ")
+    );
+    let (inputs, _) = slice_abi::slice_params(&import.module, &import.name);
+    for &param in inputs {
+        let index = slice_abi::param_index(abi, inputs, param);
+        let slice = match abi {
+            Version::V1 => match args.get(index) {
+                Some(&bits) => format_slice(data, &import.name, bits as i32),
+                None => continue,
+            },
+            Version::V2 => format_pair(data, &import.name, &args, index),
+        };
+        line += &format!(" arg{}={}", index, slice);
+    }
+    eprintln!("{}", line);
+    tracer.calls.push((index, args));
+}
+
+// Logs the return of the innermost call. Slices the call wrote are only
+// shown if it succeeded.
+pub fn exit(caller: &mut Caller<'_, HostState>, index: usize, result: i64) {
+    let (data, state) = data_and_state(caller);
+    let abi = state.abi;
+    let Some(tracer) = &mut state.trace else {
+        return;
+    };
+    let args = match tracer.calls.pop() {
+        Some((_, args)) => args,
+        None => Vec::new(),
+    };
+    let import = &tracer.imports[index];
+    let mut line = format!("{}{}.{}", tracer.prefix(), import.module, import.name);
+    match import.result {
+        Some(ty) => line += &format!(" -> {}", format_value(ty, result)),
+        None => line += " returned",
+    }
+    let (inputs, outputs) = slice_abi::slice_params(&import.module, &import.name);
+    let result = match import.result {
+        Some(ValType::I32) => result as i32 as i64,
+        _ => result,
+    };
+    let succeeded = match import.name.as_str() {
+        "env_get" => result == 1,
+        _ => result >= 0,
+    };
+    for &param in outputs.iter().filter(|_| succeeded) {
+        let index = slice_abi::param_index(abi, inputs, param);
+        if let Some(&bits) = args.get(index) {
+            line += &format!(
+                " arg{}={}",
+                index,
+                format_slice(data, &import.name, bits as i32)
+            );
+        }
+    }
+    eprintln!("{}", line);
+}
@@ -0,0 +1,158 @@
+// Terminal host functions, for interactive programs such as progress bars
+// and simple TUIs:
+//
+//   term_is_tty() -> i32 returns 1 if stdout is a terminal, or 0.
+//   term_size(dest_ptr) writes [columns: i32 at offset 0, rows: i32 at
+//     offset 4], the size of the terminal, or zeros if stdout is not one.
+//   read_key() -> i64 reads one keypress from stdin, without waiting for a
+//     newline or echoing it, and returns its bytes packed little-endian: an
+//     ASCII key is its byte, "é" is 0xa9c3, and the up arrow, ESC [ A, is
+//     0x415b1b. It returns -1 at the end of input or if reading failed.
+//
+// The first read_key from a terminal puts it in raw mode, which stays on for
+// the rest of the run. Ctrl-C still raises SIGINT. The terminal is restored
+// when the run ends, whether the program exits, traps, or is interrupted,
+// and before the runtime exits without unwinding, as on a second signal or
+// a timeout in a host call. Stdin that is not a terminal is read the same
+// way, one key's bytes at a time.
+
+use crate::{guest, HostState};
+use wasmtime::{Caller, FuncType, Linker, Result, ValType};
+
+#[cfg(unix)]
+use std::sync::Mutex;
+
+// The terminal settings before raw mode, to restore.
+#[cfg(unix)]
+static SAVED: Mutex<Option<libc::termios>> = Mutex::new(None);
+
+// Keeps the terminal in raw mode until dropped.
+pub struct RawMode(());
+
+impl RawMode {
+    // Puts stdin's terminal in raw mode, or returns None if it cannot.
+    #[cfg(unix)]
+    pub fn enter() -> Option<RawMode> {
+        // SAFETY: termios is plain data, filled in by tcgetattr
+        let mut termios: libc::termios = unsafe { std::mem::zeroed() };
+        if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut termios) } != 0 {
+            return None;
+        }
+        *SAVED.lock().unwrap() = Some(termios);
+        // Keep ISIG, so that Ctrl-C stops the program through the runtime's
+        // signal handling
+        termios.c_lflag &= !(libc::ICANON | libc::ECHO);
+        termios.c_cc[libc::VMIN] = 1;
+        termios.c_cc[libc::VTIME] = 0;
+        if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &termios) } != 0 {
+            SAVED.lock().unwrap().take();
+            return None;
+        }
+        Some(RawMode(()))
+    }
+
+    #[cfg(not(unix))]
+    pub fn enter() -> Option<RawMode> {
+        None
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        restore();
+    }
+}
+
+// Restores the terminal settings from before raw mode, if it is on.
+#[cfg(unix)]
+pub fn restore() {
+    // Exits after a panic still restore the terminal
+    let mut saved = SAVED.lock().unwrap_or_else(|error| error.into_inner());
+    if let Some(termios) = saved.take() {
+        // SAFETY: termios came from tcgetattr
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &termios) };
+    }
+}
+
+#[cfg(not(unix))]
+pub fn restore() {}
+
+// The size of stdout's terminal, as (columns, rows).
+#[cfg(unix)]
+fn window_size() -> Option<(u16, u16)> {
+    // SAFETY: winsize is plain data, filled in by the ioctl
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    if unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) } != 0 {
+        return None;
+    }
+    Some((size.ws_col, size.ws_row))
+}
+
+#[cfg(not(unix))]
+fn window_size() -> Option<(u16, u16)> {
+    None
+}
+
+// The length of the keypress at the start of bytes: an escape sequence, a
+// UTF-8 character, or a single byte, at most 8 bytes.
+fn key_len(bytes: &[u8]) -> usize {
+    let len = match bytes {
+        [] => 0,
+        [0x1b, b'[', rest @ ..] => rest
+            .iter()
+            .position(|byte| (0x40..=0x7e).contains(byte))
+            .map_or(bytes.len(), |end| end + 3),
+        [0x1b, b'O', _, ..] => 3,
+        [0xc0..=0xdf, ..] => 2,
+        [0xe0..=0xef, ..] => 3,
+        [0xf0..=0xf7, ..] => 4,
+        [_, ..] => 1,
+    };
+    len.min(bytes.len()).min(8)
+}
+
+pub fn add_to_linker(linker: &mut Linker<HostState>, pointer: &ValType) -> Result<()> {
+    linker.func_wrap(
+        "env",
+        "term_is_tty",
+        |caller: Caller<'_, HostState>| -> i32 { caller.data().io.stdout_is_terminal() as i32 },
+    )?;
+    let ty = FuncType::new(linker.engine(), [pointer.clone()], []);
+    linker.func_new("env", "term_size", ty, |mut caller, params, _results| {
+        let dest_addr = guest::pointer(&params[0]) as usize;
+        let (columns, rows) = if caller.data().io.stdout_is_terminal() {
+            window_size().unwrap_or_default()
+        } else {
+            (0, 0)
+        };
+        let mut size = [0; 8];
+        size[..4].copy_from_slice(&i32::from(columns).to_le_bytes());
+        size[4..].copy_from_slice(&i32::from(rows).to_le_bytes());
+        let memory = guest::memory(&mut caller, "term_size")?;
+        let mut data = memory.data_mut(&mut caller);
+        guest::write(&mut data, "term_size", dest_addr, &size)
+    })?;
+    linker.func_wrap(
+        "env",
+        "read_key",
+        |mut caller: Caller<'_, HostState>| -> Result<i64> {
+            let state = caller.data_mut();
+            if state.raw_mode.is_none() && state.io.stdin_is_terminal() {
+                state.raw_mode = RawMode::enter();
+            }
+            let mut key = [0; 8];
+            let result = state.read_stdin(|stdin| {
+                let buffer = stdin.fill_buf()?;
+                let len = key_len(buffer);
+                key[..len].copy_from_slice(&buffer[..len]);
+                stdin.consume(len);
+                Ok(len)
+            })?;
+            match result {
+                Ok(0) | Err(_) => Ok(-1),
+                Ok(_) => Ok(i64::from_le_bytes(key)),
+            }
+        },
+    )?;
+    Ok(())
+}
@@ -0,0 +1,261 @@
+// Wast scripts for `wasmruntime wast`, as a regression suite for the
+// runtime's engine configuration and host functions. Modules are linked
+// against the Zong host functions, as a program's would be, and can register
+// their instances for later modules to import. Supported directives are
+// module, register, invoke, assert_return, assert_trap, assert_exhaustion,
+// assert_invalid, assert_malformed, and assert_unlinkable, with integer,
+// float, and null reference values.
+
+use crate::hostio::HostIo;
+use crate::sandbox::Profile;
+use crate::{host_linker, HostState};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use wasmtime::{Config, Engine, Error, Instance, Linker, Module, Result, Store, Val};
+use wast::core::{AbstractHeapType, HeapType, NanPattern, WastArgCore, WastRetCore};
+use wast::parser::{self, ParseBuffer};
+use wast::{QuoteWat, Wast, WastArg, WastDirective, WastExecute, WastInvoke, WastRet, Wat};
+
+struct Context {
+    store: Store<HostState>,
+    linker: Linker<HostState>,
+    // The most recently instantiated module, which unnamed invokes use.
+    current: Option<Instance>,
+    instances: HashMap<String, Instance>,
+}
+
+// Runs the scripts and returns the exit code: 0 if all passed, 1 otherwise.
+pub fn run(paths: &[PathBuf]) -> Result<i32> {
+    let mut config = Config::new();
+    config.epoch_interruption(true);
+    let engine = Engine::new(&config)?;
+    let mut failed = 0;
+    for path in paths {
+        match run_file(&engine, path) {
+            Ok(count) => println!("ok    {} ({} directives)", path.display(), count),
+            Err(error) => {
+                failed += 1;
+                println!("FAIL  {:#}", error);
+            }
+        }
+    }
+    println!("{} passed, {} failed", paths.len() - failed, failed);
+    Ok(if failed == 0 { 0 } else { 1 })
+}
+
+// Runs a script in a fresh store and returns the number of directives.
+fn run_file(engine: &Engine, path: &Path) -> Result<usize> {
+    let text = fs::read_to_string(path)
+        .map_err(|error| Error::new(error).context(path.display().to_string()))?;
+    let with_location = |mut error: wast::Error| {
+        error.set_path(path);
+        error.set_text(&text);
+        error
+    };
+    let buffer = ParseBuffer::new(&text).map_err(with_location)?;
+    let wast: Wast = parser::parse(&buffer).map_err(with_location)?;
+
+    // Scripts get the Zong host functions but no WASI
+    let empty_bytes = wat::parse_str("(module)")?;
+    let empty = Module::new(engine, &empty_bytes)?;
+    let state = HostState::for_module(
+        &empty,
+        &empty_bytes,
+        None,
+        &[],
+        &[],
+        HostIo::default(),
+        Profile::default(),
+    )?;
+    let linker = host_linker(engine, &state)?;
+    let mut store = Store::new(engine, state);
+    store.set_epoch_deadline(1);
+    let mut context = Context {
+        store,
+        linker,
+        current: None,
+        instances: HashMap::new(),
+    };
+    let count = wast.directives.len();
+    for directive in wast.directives {
+        let (line, column) = directive.span().linecol_in(&text);
+        context.directive(directive).map_err(|error| {
+            error.context(format!("{}:{}:{}", path.display(), line + 1, column + 1))
+        })?;
+    }
+    Ok(count)
+}
+
+impl Context {
+    fn directive(&mut self, directive: WastDirective<'_>) -> Result<()> {
+        match directive {
+            WastDirective::Module(mut module) => {
+                let name = module_name(&module);
+                let instance = self.instantiate(&module.encode()?)?;
+                if let Some(name) = name {
+                    self.instances.insert(name.to_string(), instance);
+                }
+                self.current = Some(instance);
+            }
+            WastDirective::Register { name, module, .. } => {
+                let instance = self.instance(module.map(|id| id.name()))?;
+                self.linker.instance(&mut self.store, name, instance)?;
+            }
+            WastDirective::Invoke(invoke) => {
+                self.invoke(&invoke)?;
+            }
+            WastDirective::AssertReturn { exec, results, .. } => {
+                let actual = self.execute(exec)?;
+                let matches = actual.len() == results.len()
+                    && actual
+                        .iter()
+                        .zip(&results)
+                        .all(|(actual, expected)| matches_ret(actual, expected));
+                if !matches {
+                    return Err(Error::msg(format!(
+                        "expected {:?}, got {:?}",
+                        results, actual
+                    )));
+                }
+            }
+            WastDirective::AssertTrap { exec, message, .. } => {
+                expect_error(self.execute(exec), message)?;
+            }
+            WastDirective::AssertExhaustion { call, message, .. } => {
+                expect_error(self.invoke(&call), message)?;
+            }
+            WastDirective::AssertInvalid { mut module, .. }
+            | WastDirective::AssertMalformed { mut module, .. } => {
+                // Malformed text may already fail to encode
+                if let Ok(bytes) = module.encode() {
+                    if Module::new(self.store.engine(), &bytes).is_ok() {
+                        return Err(Error::msg("expected the module to be rejected"));
+                    }
+                }
+            }
+            WastDirective::AssertUnlinkable {
+                mut module,
+                message,
+                ..
+            } => {
+                expect_error(self.instantiate(&module.encode()?), message)?;
+            }
+            _ => return Err(Error::msg("unsupported directive")),
+        }
+        Ok(())
+    }
+
+    fn instantiate(&mut self, wasm_bytes: &[u8]) -> Result<Instance> {
+        let module = Module::new(self.store.engine(), wasm_bytes)?;
+        self.linker.instantiate(&mut self.store, &module)
+    }
+
+    fn instance(&self, name: Option<&str>) -> Result<Instance> {
+        match name {
+            Some(name) => self
+                .instances
+                .get(name)
+                .copied()
+                .ok_or_else(|| Error::msg(format!("no module named ${}", name))),
+            None => self
+                .current
+                .ok_or_else(|| Error::msg("no module has been instantiated")),
+        }
+    }
+
+    fn execute(&mut self, exec: WastExecute<'_>) -> Result<Vec<Val>> {
+        match exec {
+            WastExecute::Invoke(invoke) => self.invoke(&invoke),
+            WastExecute::Wat(mut module) => {
+                self.instantiate(&module.encode()?)?;
+                Ok(Vec::new())
+            }
+            WastExecute::Get { module, global, .. } => {
+                let instance = self.instance(module.map(|id| id.name()))?;
+                let global = instance
+                    .get_global(&mut self.store, global)
+                    .ok_or_else(|| Error::msg(format!("no global export '{}'", global)))?;
+                Ok(vec![global.get(&mut self.store)])
+            }
+        }
+    }
+
+    fn invoke(&mut self, invoke: &WastInvoke<'_>) -> Result<Vec<Val>> {
+        let instance = self.instance(invoke.module.map(|id| id.name()))?;
+        let func = instance
+            .get_func(&mut self.store, invoke.name)
+            .ok_or_else(|| Error::msg(format!("no function export '{}'", invoke.name)))?;
+        let args = invoke.args.iter().map(arg).collect::<Result<Vec<_>>>()?;
+        let mut results = vec![Val::I32(0); func.ty(&self.store).results().len()];
+        func.call(&mut self.store, &args, &mut results)?;
+        Ok(results)
+    }
+}
+
+fn module_name<'a>(module: &QuoteWat<'a>) -> Option<&'a str> {
+    match module {
+        QuoteWat::Wat(Wat::Module(module)) => module.id.map(|id| id.name()),
+        _ => None,
+    }
+}
+
+// Checks that an operation failed with an error mentioning the message.
+fn expect_error<T>(result: Result<T>, message: &str) -> Result<()> {
+    match result {
+        Ok(_) => Err(Error::msg(format!("expected an error: {}", message))),
+        Err(error) if format!("{:?}", error).contains(message) => Ok(()),
+        Err(error) => Err(error.context(format!("expected an error: {}", message))),
+    }
+}
+
+fn arg(arg: &WastArg<'_>) -> Result<Val> {
+    let WastArg::Core(arg) = arg else {
+        return Err(Error::msg("component values are not supported"));
+    };
+    Ok(match arg {
+        WastArgCore::I32(value) => Val::I32(*value),
+        WastArgCore::I64(value) => Val::I64(*value),
+        WastArgCore::F32(value) => Val::F32(value.bits),
+        WastArgCore::F64(value) => Val::F64(value.bits),
+        WastArgCore::RefNull(HeapType::Abstract {
+            ty: AbstractHeapType::Func,
+            ..
+        }) => Val::FuncRef(None),
+        WastArgCore::RefNull(HeapType::Abstract {
+            ty: AbstractHeapType::Extern,
+            ..
+        }) => Val::ExternRef(None),
+        other => return Err(Error::msg(format!("unsupported argument {:?}", other))),
+    })
+}
+
+fn matches_ret(actual: &Val, expected: &WastRet<'_>) -> bool {
+    match expected {
+        WastRet::Core(expected) => matches_core(actual, expected),
+        WastRet::Component(_) => false,
+    }
+}
+
+// Floats are compared by their bits, so that e.g. -0.0 and 0.0 differ.
+fn matches_core(actual: &Val, expected: &WastRetCore<'_>) -> bool {
+    match (actual, expected) {
+        (_, WastRetCore::Either(expected)) => expected
+            .iter()
+            .any(|expected| matches_core(actual, expected)),
+        (Val::I32(actual), WastRetCore::I32(expected)) => actual == expected,
+        (Val::I64(actual), WastRetCore::I64(expected)) => actual == expected,
+        (Val::F32(actual), WastRetCore::F32(expected)) => match expected {
+            NanPattern::CanonicalNan => actual & 0x7fff_ffff == 0x7fc0_0000,
+            NanPattern::ArithmeticNan => actual & 0x7fc0_0000 == 0x7fc0_0000,
+            NanPattern::Value(expected) => *actual == expected.bits,
+        },
+        (Val::F64(actual), WastRetCore::F64(expected)) => match expected {
+            NanPattern::CanonicalNan => actual & 0x7fff_ffff_ffff_ffff == 0x7ff8_0000_0000_0000,
+            NanPattern::ArithmeticNan => actual & 0x7ff8_0000_0000_0000 == 0x7ff8_0000_0000_0000,
+            NanPattern::Value(expected) => *actual == expected.bits,
+        },
+        (Val::FuncRef(None) | Val::ExternRef(None), WastRetCore::RefNull(_)) => true,
+        _ => false,
+    }
+}
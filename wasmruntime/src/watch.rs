@@ -0,0 +1,111 @@
+// File watching for guests and for --watch, backed by the host's filesystem
+// notification API (inotify, FSEvents, ...). Events are queued by notify's
+// background thread and handed out one at a time.
+
+use crate::errno::Errno;
+use notify::event::{EventKind, ModifyKind};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+// Event kinds as reported to the guest.
+pub const EVENT_CREATED: i64 = 1;
+pub const EVENT_MODIFIED: i64 = 2;
+pub const EVENT_REMOVED: i64 = 3;
+pub const EVENT_RENAMED: i64 = 4;
+
+pub struct WatchEvent {
+    pub kind: i64,
+    pub host_path: PathBuf,
+}
+
+pub struct FileWatcher {
+    watcher: RecommendedWatcher,
+    events: Receiver<notify::Result<notify::Event>>,
+    pending: VecDeque<WatchEvent>,
+    watch_count: usize,
+}
+
+impl FileWatcher {
+    pub fn new() -> notify::Result<FileWatcher> {
+        let (sender, events) = mpsc::channel();
+        let watcher = notify::recommended_watcher(sender)?;
+        Ok(FileWatcher {
+            watcher,
+            events,
+            pending: VecDeque::new(),
+            watch_count: 0,
+        })
+    }
+
+    // Watches a file, or a directory and everything below it.
+    pub fn watch(&mut self, host_path: &Path) -> notify::Result<()> {
+        self.watcher.watch(host_path, RecursiveMode::Recursive)?;
+        self.watch_count += 1;
+        Ok(())
+    }
+
+    // Watches the entries of a directory, but not those of its
+    // subdirectories.
+    pub fn watch_entries(&mut self, dir: &Path) -> notify::Result<()> {
+        self.watcher.watch(dir, RecursiveMode::NonRecursive)?;
+        self.watch_count += 1;
+        Ok(())
+    }
+
+    pub fn is_watching(&self) -> bool {
+        self.watch_count > 0
+    }
+
+    // Waits for the next event. A timeout of None waits forever. Returns None
+    // if the timeout passes first.
+    pub fn next_event(&mut self, timeout: Option<Duration>) -> Option<WatchEvent> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        loop {
+            if let Some(event) = self.pending.pop_front() {
+                return Some(event);
+            }
+            let result = match deadline {
+                Some(deadline) => self
+                    .events
+                    .recv_timeout(deadline.saturating_duration_since(Instant::now())),
+                None => self
+                    .events
+                    .recv()
+                    .map_err(|_| RecvTimeoutError::Disconnected),
+            };
+            match result {
+                Ok(Ok(event)) => self.queue(event),
+                // Errors from the notification backend (e.g. a watched
+                // directory vanishing) are not events; keep waiting
+                Ok(Err(_)) => {}
+                Err(_) => return None,
+            }
+        }
+    }
+
+    fn queue(&mut self, event: notify::Event) {
+        let kind = match event.kind {
+            EventKind::Create(_) => EVENT_CREATED,
+            EventKind::Modify(ModifyKind::Name(_)) => EVENT_RENAMED,
+            EventKind::Modify(_) => EVENT_MODIFIED,
+            EventKind::Remove(_) => EVENT_REMOVED,
+            // Reads and other access are not changes
+            EventKind::Access(_) | EventKind::Any | EventKind::Other => return,
+        };
+        for host_path in event.paths {
+            self.pending.push_back(WatchEvent { kind, host_path });
+        }
+    }
+}
+
+// The error code for a failure to start watching.
+pub fn errno(error: &notify::Error) -> Errno {
+    match &error.kind {
+        notify::ErrorKind::Io(error) => Errno::for_io(error),
+        notify::ErrorKind::PathNotFound => Errno::NotFound,
+        _ => Errno::Other,
+    }
+}
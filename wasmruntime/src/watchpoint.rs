@@ -0,0 +1,177 @@
+// Guest memory watchpoints for --watch-addr, for tracking down the code that
+// corrupts memory such as the tstack. The watched range is compared with its
+// last contents on each call from wasm into the host, so a change made by
+// wasm code is reported at the next host call, and on each return from the
+// host, so a change made by a host function is reported as that call's. The
+// last check, when the entry function returns, catches changes after the
+// last host call.
+//
+// With --watch-stores, every store is followed by a call to a host import
+// that does nothing, so that a change is caught right after the store that
+// made it: the innermost frame's offset is that of the call after the store.
+//
+// Each change is reported on stderr with the range's old and new bytes and
+// the wasm backtrace, and the run goes on.
+
+use crate::backtrace;
+use crate::guest::{GuestData, GuestMemory};
+use crate::HostState;
+use std::borrow::Cow;
+use walrus::ir::{Call, Instr, InstrLocId, InstrSeq, VisitorMut};
+use walrus::FunctionId;
+use wasmtime::{CallHook, Error, Result, Store, StoreContextMut, WasmBacktrace};
+
+pub const IMPORT_MODULE: &str = "zong_watch";
+pub const IMPORT_NAME: &str = "store";
+
+// The length watched when --watch-addr gives none, that of a pointer or an
+// i64.
+const DEFAULT_LEN: u64 = 8;
+
+#[derive(Clone, Copy, Debug)]
+pub struct WatchRange {
+    pub address: u64,
+    pub len: u64,
+}
+
+impl WatchRange {
+    // Parses ADDR[:LEN], each in decimal or 0x-prefixed hex.
+    pub fn parse(value: &str) -> Result<WatchRange, String> {
+        let number = |value: &str| match value.strip_prefix("0x") {
+            Some(hex) => u64::from_str_radix(hex, 16).ok(),
+            None => value.parse().ok(),
+        };
+        let (address, len) = match value.split_once(':') {
+            Some((address, len)) => (number(address), number(len)),
+            None => (number(value), Some(DEFAULT_LEN)),
+        };
+        match (address, len) {
+            (Some(_), Some(0)) => Err(String::from("the length must not be 0")),
+            (Some(address), Some(len)) if address.checked_add(len).is_some() => {
+                Ok(WatchRange { address, len })
+            }
+            _ => Err(String::from(
+                "expected ADDR[:LEN], such as 0x1000 or 4096:16",
+            )),
+        }
+    }
+
+    fn end(self) -> u64 {
+        self.address + self.len
+    }
+}
+
+pub struct Watchpoint {
+    range: WatchRange,
+    memory: GuestMemory,
+    last: Vec<u8>,
+    host_calls: u64,
+}
+
+// Inserts a call to the watch import after each store.
+struct CheckStores(FunctionId);
+
+impl VisitorMut for CheckStores {
+    fn start_instr_seq_mut(&mut self, seq: &mut InstrSeq) {
+        let instrs = std::mem::take(&mut seq.instrs);
+        for (instr, loc) in instrs {
+            let is_store = matches!(instr, Instr::Store(_));
+            seq.instrs.push((instr, loc));
+            if is_store {
+                let check = Instr::Call(Call { func: self.0 });
+                seq.instrs.push((check, InstrLocId::default()));
+            }
+        }
+    }
+}
+
+pub fn instrument(wasm_bytes: &[u8]) -> Result<Vec<u8>> {
+    let wasm_bytes = wat::parse_bytes(wasm_bytes)?;
+    let mut module = walrus::Module::from_buffer(&wasm_bytes)?;
+    let check_type = module.types.add(&[], &[]);
+    let (check, _) = module.add_import_func(IMPORT_MODULE, IMPORT_NAME, check_type);
+    let mut visitor = CheckStores(check);
+    for (_, func) in module.funcs.iter_local_mut() {
+        walrus::ir::dfs_pre_order_mut(&mut visitor, func, func.entry_block());
+    }
+    Ok(module.emit_wasm())
+}
+
+fn hex(bytes: &[u8]) -> String {
+    let hex: Vec<String> = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+    hex.join(" ")
+}
+
+impl Watchpoint {
+    fn current<'a>(&self, data: GuestData<'a>) -> Cow<'a, [u8]> {
+        data.get(self.range.address as usize..self.range.end() as usize)
+    }
+
+    fn report(&mut self, data: GuestData<'_>, by_host: bool, backtrace: &WasmBacktrace) {
+        let current = self.current(data);
+        let by = if by_host {
+            format!("by host call {}", self.host_calls)
+        } else {
+            format!("in wasm code, before host call {}", self.host_calls + 1)
+        };
+        eprintln!(
+            "watchpoint: {:#x}..{:#x} changed {}",
+            self.range.address,
+            self.range.end(),
+            by
+        );
+        eprintln!("  old: {}", hex(&self.last));
+        eprintln!("  new: {}", hex(&current));
+        if !backtrace.frames().is_empty() {
+            backtrace::print_backtrace(backtrace);
+        }
+        self.last = current.to_vec();
+    }
+}
+
+// Starts watching the range of memory, as it is now, for the rest of the
+// store's calls.
+pub fn install(store: &mut Store<HostState>, range: WatchRange, memory: GuestMemory) -> Result<()> {
+    let size = memory.data(&*store).size() as u64;
+    if range.end() > size {
+        return Err(Error::msg(format!(
+            "--watch-addr {:#x}..{:#x} is outside the memory of {} bytes",
+            range.address,
+            range.end(),
+            size
+        )));
+    }
+    let last = memory
+        .data(&*store)
+        .get(range.address as usize..range.end() as usize)
+        .to_vec();
+    store.data_mut().watchpoint = Some(Watchpoint {
+        range,
+        memory,
+        last,
+        host_calls: 0,
+    });
+    store.call_hook(|mut context: StoreContextMut<'_, HostState>, hook| {
+        let by_host = match hook {
+            CallHook::CallingHost | CallHook::ReturningFromWasm => false,
+            CallHook::ReturningFromHost => true,
+            CallHook::CallingWasm => return Ok(()),
+        };
+        let Some(mut watchpoint) = context.data_mut().watchpoint.take() else {
+            return Ok(());
+        };
+        if watchpoint.current(watchpoint.memory.data(&context)) != watchpoint.last {
+            // Keep the report after the guest's output so far
+            context.data_mut().io.flush_stdout()?;
+            let backtrace = WasmBacktrace::force_capture(&context);
+            let data = watchpoint.memory.data(&context);
+            watchpoint.report(data, by_host, &backtrace);
+        }
+        if matches!(hook, CallHook::CallingHost) {
+            watchpoint.host_calls += 1;
+        }
+        context.data_mut().watchpoint = Some(watchpoint);
+        Ok(())
+    });
+    Ok(())
+}
@@ -0,0 +1,554 @@
+// End-to-end checks of the wasmruntime command, for what the wast scripts
+// cannot do, such as running files the runtime writes.
+
+use std::fs;
+use std::io::Write;
+use std::net::TcpListener;
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+
+fn wasmruntime(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_wasmruntime"))
+        .args(args)
+        .env("RUST_BACKTRACE", "0")
+        .output()
+        .unwrap()
+}
+
+// An empty directory for a test's files, removed when it is dropped.
+struct TestDir(PathBuf);
+
+impl TestDir {
+    fn new(name: &str) -> TestDir {
+        let dir =
+            std::env::temp_dir().join(format!("wasmruntime-test-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        TestDir(dir)
+    }
+
+    // Writes a file in the directory and returns its path.
+    fn write(&self, name: &str, contents: &str) -> String {
+        let path = self.0.join(name);
+        fs::write(&path, contents).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    fn path(&self, name: &str) -> String {
+        self.0.join(name).to_str().unwrap().to_string()
+    }
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+#[test]
+fn host_function_wast_scripts_pass() {
+    let script = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/host.wast");
+    let output = wasmruntime(&["wast", script]);
+    assert!(output.status.success(), "{:?}", output);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("1 passed, 0 failed"));
+}
+
+#[test]
+fn runs_precompiled_module() {
+    let dir = TestDir::new("precompiled");
+    let wat = dir.write(
+        "answer.wat",
+        r#"(module
+             (import "env" "print" (func $print (param i64)))
+             (memory (export "memory") 1)
+             (func (export "main") (call $print (i64.const 42))))"#,
+    );
+    let cwasm = dir.path("answer.cwasm");
+    let compiled = wasmruntime(&["compile", &wat, "-o", &cwasm]);
+    assert!(compiled.status.success(), "{:?}", compiled);
+
+    let output = wasmruntime(&["run", "--allow-precompiled", &cwasm]);
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "42\n");
+
+    // Precompiled code is only run on request
+    let output = wasmruntime(&["run", &cwasm]);
+    assert_eq!(output.status.code(), Some(2), "{:?}", output);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--allow-precompiled"));
+}
+
+#[test]
+fn tcp_read_with_huge_max_len() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let server = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        stream.write_all(b"hello").unwrap();
+    });
+    let dir = TestDir::new("tcp-read");
+    let wat = dir.write(
+        "read.wat",
+        &format!(
+            r#"(module
+                 (import "env" "print" (func $print (param i64)))
+                 (import "env" "print_bytes" (func $print_bytes (param i32)))
+                 (import "env" "tcp_connect" (func $connect (param i32 i32) (result i32)))
+                 (import "env" "tcp_read" (func $read (param i32 i32 i64) (result i64)))
+                 (memory (export "memory") 1)
+                 (global (export "tstack") (mut i32) (i32.const 1024))
+                 ;; The slice "127.0.0.1" at address 0
+                 (data (i32.const 0) "\20\00\00\00\00\00\00\00\09\00\00\00\00\00\00\00")
+                 (data (i32.const 32) "127.0.0.1")
+                 (func (export "main") (local $fd i32)
+                   (local.set $fd (call $connect (i32.const 0) (i32.const {port})))
+                   (call $print (call $read (local.get $fd) (i32.const 64)
+                                            (i64.const 0x7fffffffffffffff)))
+                   (call $print_bytes (i32.const 64))))"#
+        ),
+    );
+    let output = wasmruntime(&["run", "--allow-net", &wat]);
+    server.join().unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "5\nhello");
+}
+
+#[test]
+fn restore_rejects_partial_page() {
+    let dir = TestDir::new("restore");
+    let wat = dir.write(
+        "snapshot.wat",
+        r#"(module
+             (memory (export "memory") 1)
+             (func (export "init"))
+             (func (export "main")))"#,
+    );
+    let snapshot = dir.path("memory.snapshot");
+    let output = wasmruntime(&[
+        "run",
+        "--snapshot-at",
+        "init",
+        "--snapshot-out",
+        &snapshot,
+        &wat,
+    ]);
+    assert!(output.status.success(), "{:?}", output);
+
+    // A page and a byte of memory, which no module can have
+    let mut dump = fs::read(&snapshot).unwrap();
+    let header = b"memory 65536\n";
+    let at = dump
+        .windows(header.len())
+        .position(|w| w == header)
+        .unwrap();
+    dump.splice(at..at + header.len(), b"memory 65537\n".iter().copied());
+    dump.push(0);
+    fs::write(&snapshot, dump).unwrap();
+
+    let output = wasmruntime(&["run", "--restore", &snapshot, &wat]);
+    assert_eq!(output.status.code(), Some(1), "{:?}", output);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("whole number"));
+}
+
+// A module whose main spawns a thread running worker, joins it, and prints
+// what join returned.
+fn spawn_module(dir: &TestDir, worker: &str) -> String {
+    dir.write(
+        "spawn.wat",
+        &format!(
+            r#"(module
+                 (import "env" "spawn" (func $spawn (param i32 i64) (result i32)))
+                 (import "env" "join" (func $join (param i32) (result i32)))
+                 (import "env" "print" (func $print (param i64)))
+                 (memory (export "memory") 1)
+                 (global (export "tstack") (mut i32) (i32.const 1024))
+                 (func $worker (param i64) {worker})
+                 (func (export "main")
+                   (call $print (i64.extend_i32_s
+                     (call $join (call $spawn (i32.const 3) (i64.const 0)))))))"#
+        ),
+    )
+}
+
+#[test]
+fn spawned_threads_share_the_fuel_limit() {
+    let dir = TestDir::new("thread-fuel");
+    let wat = spawn_module(&dir, "(loop $spin (br $spin))");
+    let output = wasmruntime(&["run", "--fuel", "100000", &wat]);
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "1\n");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("all fuel consumed"));
+}
+
+#[test]
+fn spawned_threads_share_the_memory_limit() {
+    let dir = TestDir::new("thread-memory");
+    let wat = spawn_module(&dir, "(drop (memory.grow (i32.const 100)))");
+    let output = wasmruntime(&["run", "--max-memory", "1M", &wat]);
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "1\n");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("memory limit exceeded"));
+}
+
+#[test]
+fn loaded_modules_share_the_memory_limit() {
+    let dir = TestDir::new("dynload-memory");
+    // Loads a child with two pages of memory three times, printing each
+    // handle, with room for only four pages
+    let wat = dir.write(
+        "dynload.wat",
+        r#"(module
+             (import "env" "load_module" (func $load_module (param i32 i32) (result i32)))
+             (import "env" "print" (func $print (param i64)))
+             (memory (export "memory") 1)
+             (global (export "tstack") (mut i32) (i32.const 1024))
+             (data (i32.const 0) "\40\00\00\00\00\00\00\00\30\00\00\00\00\00\00\00")
+             ;; (module (memory 2) (func (export "double") ...))
+             (data (i32.const 64)
+               "\00asm\01\00\00\00"
+               "\01\06\01\60\01\7e\01\7e"
+               "\03\02\01\00"
+               "\05\03\01\00\02"
+               "\07\0a\01\06double\00\00"
+               "\0a\09\01\07\00\20\00\42\02\7e\0b")
+             (func $load (call $print (i64.extend_i32_s
+               (call $load_module (i32.const 0) (i32.const 0)))))
+             (func (export "main") (call $load) (call $load) (call $load)))"#,
+    );
+    let output = wasmruntime(&["run", "--max-memory", "256K", &wat]);
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "0\n1\n-7\n");
+}
+
+#[test]
+fn memory64_module_passes_i64_addresses() {
+    let dir = TestDir::new("memory64");
+    let wat = dir.write(
+        "memory64.wat",
+        r#"(module
+             (import "env" "print_bytes" (func $print_bytes (param i64)))
+             (import "env" "i64_to_string" (func $i64_to_string (param i64 i64)))
+             (import "env" "host_alloc" (func $host_alloc (param i64) (result i64)))
+             (import "env" "host_free" (func $host_free (param i64)))
+             (import "env" "print_fmt" (func $print_fmt (param i64 i64 i64)))
+             (memory (export "memory") i64 1)
+             (global (export "tstack") (mut i64) (i64.const 1024))
+             ;; The slice "n={}\n" at address 0, and an i64 argument at 32
+             (data (i64.const 0) "\c8\00\00\00\00\00\00\00\05\00\00\00\00\00\00\00")
+             (data (i64.const 200) "n={}\n")
+             (func (export "main") (local $block i64)
+               (call $i64_to_string (i64.const -42) (i64.const 64))
+               (call $print_bytes (i64.const 64))
+               (local.set $block (call $host_alloc (i64.const 16)))
+               (call $host_free (local.get $block))
+               (i64.store (i64.const 40) (local.get $block))
+               (call $print_fmt (i64.const 0) (i64.const 32) (i64.const 1))))"#,
+    );
+    let output = wasmruntime(&["run", &wat]);
+    assert!(output.status.success(), "{:?}", output);
+    // The heap starts where memory ended, after its one page
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "-42n=65536\n");
+}
+
+#[test]
+fn test_report_gives_host_calls_output_and_fuel() {
+    let dir = TestDir::new("test-report");
+    dir.write(
+        "two.wasm",
+        r#"(module
+             (import "env" "print" (func $print (param i64)))
+             (memory (export "memory") 1)
+             (func (export "main") (call $print (i64.const 1)) (call $print (i64.const 2))))"#,
+    );
+    dir.write("two.expected", "1\n2\n");
+    let output = wasmruntime(&["test", &dir.path(""), "--output", "json"]);
+    assert!(output.status.success(), "{:?}", output);
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let test = &report["tests"][0];
+    assert_eq!(test["name"], "two");
+    assert_eq!(test["passed"], true);
+    assert_eq!(test["host_calls"]["env.print"], 2);
+    assert_eq!(test["stdout_bytes"], 4);
+    assert!(test["fuel_consumed"].as_u64().unwrap() > 0);
+
+    let output = wasmruntime(&["test", &dir.path(""), "--output", "junit"]);
+    assert!(output.status.success(), "{:?}", output);
+    let xml = String::from_utf8_lossy(&output.stdout);
+    assert!(xml.contains(r#"<property name="host_calls.env.print" value="2"/>"#));
+}
+
+#[test]
+fn json_report_records_the_run_environment() {
+    let dir = TestDir::new("run-environment");
+    let wat = dir.write(
+        "quiet.wat",
+        r#"(module
+             (memory (export "memory") 1)
+             (func (export "main")))"#,
+    );
+    let stdin = dir.write("input.txt", "abc");
+    let output = wasmruntime(&[
+        "run", "--output", "json", "--seed", "7", "--stdin", &stdin, &wat,
+    ]);
+    assert!(output.status.success(), "{:?}", output);
+    let report: serde_json::Value = serde_json::from_slice(&output.stderr).unwrap();
+    let environment = &report["environment"];
+    assert_eq!(environment["runtime_version"], env!("CARGO_PKG_VERSION"));
+    assert_eq!(environment["seed"], 7);
+    assert_eq!(
+        environment["stdin_sha256"],
+        "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+    );
+    assert_eq!(environment["engine"]["backend"], "cranelift");
+}
+
+#[test]
+fn coverage_merge_sums_calls_and_exports_lcov() {
+    let dir = TestDir::new("coverage");
+    let a = dir.write("a.json", r#"{"main": 1, "parse": 3, "unused": 0}"#);
+    let b = dir.write("b.json", r#"{"main": 1, "parse": 0, "report": 2}"#);
+    let total = dir.path("total.json");
+    let output = wasmruntime(&["coverage", "merge", &a, &b, "-o", &total]);
+    assert!(output.status.success(), "{:?}", output);
+    let merged: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&total).unwrap()).unwrap();
+    assert_eq!(
+        merged,
+        serde_json::json!({"main": 2, "parse": 3, "report": 2, "unused": 0})
+    );
+
+    let output = wasmruntime(&[
+        "coverage",
+        "export",
+        &total,
+        "--format",
+        "lcov",
+        "--source",
+        "prog.zong",
+    ]);
+    assert!(output.status.success(), "{:?}", output);
+    let lcov = String::from_utf8_lossy(&output.stdout);
+    assert!(lcov.starts_with("TN:\nSF:prog.zong\n"), "{}", lcov);
+    assert!(lcov.contains("FNDA:3,parse\n"), "{}", lcov);
+    assert!(lcov.ends_with("FNF:4\nFNH:3\nend_of_record\n"), "{}", lcov);
+
+    let output = wasmruntime(&["coverage", "export", &total, "--format", "html"]);
+    assert!(output.status.success(), "{:?}", output);
+    let html = String::from_utf8_lossy(&output.stdout);
+    assert!(html.contains("3 of 4 functions called (75.0%)"), "{}", html);
+}
+
+#[test]
+fn alloc_profile_gives_bytes_by_call_site() {
+    let dir = TestDir::new("alloc-profile");
+    let wat = dir.write(
+        "alloc.wat",
+        r#"(module
+             (import "env" "host_alloc" (func $alloc (param i64) (result i32)))
+             (import "env" "host_free" (func $free (param i32)))
+             (memory (export "memory") 1)
+             (func $small (result i32) (call $alloc (i64.const 24)))
+             (func $main (export "main")
+               (call $free (call $small))
+               (drop (call $small))
+               (drop (call $alloc (i64.const 1000)))))"#,
+    );
+    let output = wasmruntime(&["run", "--alloc-profile", &wat]);
+    assert!(output.status.success(), "{:?}", output);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let lines: Vec<&str> = stderr.lines().collect();
+    assert_eq!(
+        lines[0],
+        "allocations: 1048 bytes in 3 blocks, 1024 bytes live"
+    );
+    // The largest first
+    assert!(
+        lines[2].starts_with("        1000       1        1000  main at"),
+        "{}",
+        stderr
+    );
+    assert!(
+        lines[3].starts_with("          48       2          24  small at"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn debugger_diffs_heap_snapshots() {
+    let dir = TestDir::new("heap-diff");
+    let wat = dir.write(
+        "leak.wat",
+        r#"(module
+             (import "env" "host_alloc" (func $alloc (param i64) (result i32)))
+             (import "env" "host_free" (func $free (param i32)))
+             (memory (export "memory") 1)
+             (func $checkpoint)
+             (func $leaky (drop (call $alloc (i64.const 40))))
+             (func $tidy (call $free (call $alloc (i64.const 8))))
+             (func $main (export "main")
+               (drop (call $alloc (i64.const 100)))
+               (call $checkpoint)
+               (call $leaky)
+               (call $tidy)
+               (call $checkpoint)))"#,
+    );
+    let mut child = Command::new(env!("CARGO_BIN_EXE_wasmruntime"))
+        .args(["debug", "--alloc-profile", "--break", "checkpoint", &wat])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let commands = "snap\nc\nsnap\ndiff\nc\n";
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(commands.as_bytes())
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    // Only the block leaky allocated is new; tidy's was freed
+    assert!(
+        stdout.contains("snapshot 2: 2 blocks, 160 bytes"),
+        "{}",
+        stdout
+    );
+    assert!(
+        stdout.contains("1 blocks, 48 bytes, in snapshot 2 and not 1\n"),
+        "{}",
+        stdout
+    );
+    assert!(stdout.contains(": 48 bytes from leaky at"), "{}", stdout);
+}
+
+fn repl(args: &[&str], input: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_wasmruntime"))
+        .arg("repl")
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+// A stand-in for the Zong compiler that builds `print(1 + 2);` into a
+// module printing 3, and fails on anything else.
+#[cfg(unix)]
+fn fake_compiler(dir: &TestDir) -> String {
+    use std::os::unix::fs::PermissionsExt;
+    let module = r#"(module
+      (import "env" "print" (func $print (param i64)))
+      (memory (export "memory") 1)
+      (func (export "main") (call $print (i64.const 3))))"#;
+    let script = dir.write(
+        "zong",
+        &format!(
+            "#!/bin/sh\n\
+             [ \"$1 $2\" = \"build -o\" ] || exit 2\n\
+             grep -q 'print(1 + 2);' \"$4\" || {{ echo 'Compilation failed: bad' >&2; exit 1; }}\n\
+             cat > \"$3\" <<'EOF'\n{}\nEOF\n",
+            module
+        ),
+    );
+    fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+    script
+}
+
+#[cfg(unix)]
+#[test]
+fn repl_evaluates_zong_expressions() {
+    let dir = TestDir::new("repl-eval");
+    let compiler = fake_compiler(&dir);
+    let wat = dir.write(
+        "session.wat",
+        r#"(module
+             (memory (export "memory") 1)
+             (func (export "double") (param i64) (result i64)
+               (i64.mul (local.get 0) (i64.const 2))))"#,
+    );
+    let output = repl(
+        &["--compiler", &compiler, &wat],
+        "double 4\n1 + 2\n1 +\ndouble 5\n",
+    );
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "8\n3\n10\n");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Compilation failed: bad"), "{}", stderr);
+}
+
+#[test]
+fn repl_keeps_or_restarts_the_instance_after_a_trap() {
+    let dir = TestDir::new("repl-trap");
+    let wat = dir.write(
+        "counter.wat",
+        r#"(module
+             (memory (export "memory") 1)
+             (global $n (mut i64) (i64.const 0))
+             (func (export "bump") (result i64)
+               (global.set $n (i64.add (global.get $n) (i64.const 1)))
+               (global.get $n))
+             (func (export "boom")
+               (global.set $n (i64.const 100))
+               unreachable))"#,
+    );
+    let input = "bump\nboom\nbump\n";
+    let output = repl(&[&wat], input);
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "1\n101\n");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("unreachable"));
+
+    let output = repl(&["--on-trap", "restart", &wat], input);
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "1\n1\n");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("restarted with a fresh instance"),
+        "{}",
+        stderr
+    );
+}
+
+// Writes a bundle of the given entries, stored uncompressed.
+fn write_bundle(path: &str, entries: &[(&str, &[u8])]) {
+    let mut zip = zip::ZipWriter::new(fs::File::create(path).unwrap());
+    let options =
+        zip::write::SimpleFileOptions::default().compression_method(zip::CompressionMethod::Stored);
+    for (name, contents) in entries {
+        zip.start_file(*name, options).unwrap();
+        zip.write_all(contents).unwrap();
+    }
+    zip.finish().unwrap();
+}
+
+#[test]
+fn bundle_assets_must_be_a_directory() {
+    let dir = TestDir::new("bundle-assets");
+    // (module (memory (export "memory") 1) (func (export "main")))
+    let wasm: &[u8] = b"\0asm\x01\0\0\0\
+        \x01\x04\x01\x60\0\0\
+        \x03\x02\x01\0\
+        \x05\x03\x01\0\x01\
+        \x07\x11\x02\x06memory\x02\0\x04main\0\0\
+        \x0a\x04\x01\x02\0\x0b";
+    let good = dir.path("good.zip");
+    write_bundle(&good, &[("prog.wasm", wasm), ("assets/data.txt", b"data")]);
+    let output = wasmruntime(&["run", &good]);
+    assert!(output.status.success(), "{:?}", output);
+
+    let bad = dir.path("bad.zip");
+    write_bundle(&bad, &[("prog.wasm", wasm), ("assets", b"not a directory")]);
+    let output = wasmruntime(&["run", &bad]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("is a file, not a directory"), "{}", stderr);
+}
@@ -0,0 +1,152 @@
+;; Regression checks for the engine configuration and host functions. Run
+;; with: wasmruntime wast tests/*.wast
+
+;; Instances can be registered and imported by later modules
+(module $lib
+  (func (export "double") (param i64) (result i64)
+    (i64.mul (local.get 0) (i64.const 2))))
+(register "lib")
+
+(module
+  (import "env" "print" (func $print (param i64)))
+  (import "lib" "double" (func $double (param i64) (result i64)))
+  (memory (export "memory") 1)
+  (global (export "tstack") (mut i32) (i32.const 16))
+  (func (export "print_double") (param i64)
+    (call $print (call $double (local.get 0))))
+  (func (export "div") (param i32 i32) (result i32)
+    (i32.div_s (local.get 0) (local.get 1)))
+  (func (export "load") (param i32) (result i32)
+    (i32.load (local.get 0)))
+  (func $recurse (export "recurse")
+    (call $recurse)))
+
+(invoke "print_double" (i64.const 21))
+(assert_return (invoke $lib "double" (i64.const 4)) (i64.const 8))
+(assert_return (invoke "div" (i32.const 7) (i32.const 2)) (i32.const 3))
+(assert_trap (invoke "div" (i32.const 1) (i32.const 0)) "integer divide by zero")
+(assert_trap (invoke "load" (i32.const 65536)) "out of bounds memory access")
+(assert_exhaustion (invoke "recurse") "call stack exhausted")
+
+;; Host functions are checked by name and type when linking
+(assert_unlinkable
+  (module (import "env" "no_such_function" (func)))
+  "unknown import")
+(assert_unlinkable
+  (module (import "env" "print" (func (param i32))))
+  "incompatible import type")
+
+;; shared_open refuses sizes the host should not allocate
+(module
+  (import "env" "shared_open" (func $shared_open (param i32 i64) (result i32)))
+  (memory (export "memory") 1)
+  (global (export "tstack") (mut i32) (i32.const 1024))
+  ;; The slice "segment" at address 0
+  (data (i32.const 0) "\20\00\00\00\00\00\00\00\07\00\00\00\00\00\00\00")
+  (data (i32.const 32) "segment")
+  (func (export "shared_open") (param i64) (result i32)
+    (call $shared_open (i32.const 0) (local.get 0))))
+
+(assert_return (invoke "shared_open" (i64.const 64)) (i32.const 0))
+(assert_return (invoke "shared_open" (i64.const 0x200000000)) (i32.const -7))
+(assert_return (invoke "shared_open" (i64.const -1)) (i32.const -7))
+
+;; The shared_ functions return BAD_FD for a handle that is not open, and
+;; INVALID_ARGUMENT for a range outside the segment
+(module
+  (import "env" "shared_open" (func $shared_open (param i32 i64) (result i32)))
+  (import "env" "shared_size" (func $shared_size (param i32) (result i64)))
+  (import "env" "shared_read" (func $shared_read (param i32 i64 i32 i64) (result i64)))
+  (memory (export "memory") 1)
+  (global (export "tstack") (mut i32) (i32.const 1024))
+  ;; The slice "segment" at address 0
+  (data (i32.const 0) "\20\00\00\00\00\00\00\00\07\00\00\00\00\00\00\00")
+  (data (i32.const 32) "segment")
+  ;; Reads 8 bytes at the offset of a new 64-byte segment
+  (func (export "read_new") (param i64) (result i64)
+    (call $shared_read
+      (call $shared_open (i32.const 0) (i64.const 64))
+      (local.get 0) (i32.const 64) (i64.const 8)))
+  (func (export "shared_size") (param i32) (result i64)
+    (call $shared_size (local.get 0)))
+  (func (export "shared_read") (param i32) (result i64)
+    (call $shared_read (local.get 0) (i64.const 0) (i32.const 64) (i64.const 8))))
+
+(assert_return (invoke "read_new" (i64.const 56)) (i64.const 8))
+(assert_return (invoke "read_new" (i64.const 65)) (i64.const -7))
+(assert_return (invoke "shared_size" (i32.const 1000)) (i64.const -8))
+(assert_return (invoke "shared_read" (i32.const 1000)) (i64.const -8))
+
+;; on_signal returns INVALID_ARGUMENT for a signal it cannot handle
+(module
+  (import "env" "on_signal" (func $on_signal (param i32 i32) (result i32)))
+  (memory (export "memory") 1)
+  (global (export "tstack") (mut i32) (i32.const 1024))
+  ;; The slice "handler" at address 0
+  (data (i32.const 0) "\20\00\00\00\00\00\00\00\07\00\00\00\00\00\00\00")
+  (data (i32.const 32) "handler")
+  (func (export "on_signal") (param i32) (result i32)
+    (call $on_signal (local.get 0) (i32.const 0))))
+
+(assert_return (invoke "on_signal" (i32.const 9)) (i32.const -7))
+
+;; random_bytes refuses lengths that do not fit on the tstack before
+;; allocating them
+(module
+  (import "env" "random_bytes" (func $random_bytes (param i32 i64) (result i32)))
+  (memory (export "memory") 1)
+  (global (export "tstack") (mut i32) (i32.const 1024))
+  (func (export "random_bytes") (param i64) (result i32)
+    (call $random_bytes (i32.const 0) (local.get 0))))
+
+(assert_return (invoke "random_bytes" (i64.const 16)) (i32.const 0))
+(assert_return (invoke "random_bytes" (i64.const 0x100000000)) (i32.const -7))
+(assert_return (invoke "random_bytes" (i64.const -1)) (i32.const -7))
+
+;; load_module runs a child module in its own store, module_call calls its
+;; exports, and a module may load at most 64 children
+(module
+  (import "env" "load_module" (func $load_module (param i32 i32) (result i32)))
+  (import "env" "module_call" (func $module_call (param i32 i32 i64 i32) (result i32)))
+  (memory (export "memory") 1)
+  (global (export "tstack") (mut i32) (i32.const 1024))
+  ;; The slice of the child module at address 0, and "double" at 16
+  (data (i32.const 0) "\40\00\00\00\00\00\00\00\2b\00\00\00\00\00\00\00")
+  (data (i32.const 16) "\30\00\00\00\00\00\00\00\06\00\00\00\00\00\00\00")
+  (data (i32.const 48) "double")
+  ;; (module (func (export "double") (param i64) (result i64)
+  ;;   (i64.mul (local.get 0) (i64.const 2))))
+  (data (i32.const 64)
+    "\00asm\01\00\00\00"
+    "\01\06\01\60\01\7e\01\7e"
+    "\03\02\01\00"
+    "\07\0a\01\06double\00\00"
+    "\0a\09\01\07\00\20\00\42\02\7e\0b")
+  ;; The slice at 256 of the child's bytes after its header, which are not a
+  ;; module
+  (data (i32.const 256) "\48\00\00\00\00\00\00\00\08\00\00\00\00\00\00\00")
+  (func (export "load") (result i32)
+    (call $load_module (i32.const 0) (i32.const 0)))
+  (func (export "load_invalid") (result i32)
+    (call $load_module (i32.const 256) (i32.const 0)))
+  (func (export "call_double") (param i32 i64) (result i64)
+    (if (call $module_call (local.get 0) (i32.const 16) (local.get 1) (i32.const 128))
+      (then (return (i64.const -1))))
+    (i64.load (i32.const 128)))
+  (func (export "call_missing") (param i32) (result i32)
+    (call $module_call (local.get 0) (i32.const 0) (i64.const 0) (i32.const 128)))
+  ;; Loads the child n times and returns the last handle
+  (func (export "load_many") (param $n i32) (result i32)
+    (local $handle i32)
+    (loop $again
+      (local.set $handle (call $load_module (i32.const 0) (i32.const 0)))
+      (br_if $again (local.tee $n (i32.sub (local.get $n) (i32.const 1)))))
+    (local.get $handle)))
+
+(assert_return (invoke "load") (i32.const 0))
+(assert_return (invoke "call_double" (i32.const 0) (i64.const 21)) (i64.const 42))
+(assert_return (invoke "call_double" (i32.const 1) (i64.const 21)) (i64.const -1))
+(assert_return (invoke "call_missing" (i32.const 0)) (i32.const -1))
+(assert_return (invoke "load_invalid") (i32.const -7))
+(assert_return (invoke "load_many" (i32.const 63)) (i32.const 63))
+(assert_return (invoke "load") (i32.const -7))
@@ -0,0 +1,199 @@
+// Checks of the embedding API: Host, Runtime, CompiledProgram, and
+// ProgramInstance.
+
+use std::collections::HashMap;
+use std::num::NonZeroUsize;
+use std::time::Duration;
+use zong_runtime::wasmtime::Val;
+use zong_runtime::{CompiledProgram, Host, HostIo, Job, Pool, RunLimits, Runtime};
+
+const EXEC: &str = r#"(module
+  (import "env" "exec" (func (param i32 i32) (result i32)))
+  (memory (export "memory") 1)
+  (func (export "main")))"#;
+
+#[test]
+fn exec_needs_allow_exec() {
+    let error = format!("{:#}", Runtime::new(EXEC.as_bytes()).err().unwrap());
+    assert!(error.contains("'subprocess'"), "{}", error);
+
+    let mut host = Host::new().unwrap();
+    host.allow_exec();
+    let mut runtime = Runtime::with_host(&host, EXEC.as_bytes()).unwrap();
+    assert_eq!(runtime.run_main().unwrap(), 0);
+}
+
+// Adds count's argument to a global, printing the total, and returns it.
+const COUNTER: &str = r#"(module
+  (import "env" "print" (func $print (param i64)))
+  (memory (export "memory") 1)
+  (global $count (mut i64) (i64.const 0))
+  (func (export "count") (param i64) (result i64)
+    (global.set $count (i64.add (global.get $count) (local.get 0)))
+    (call $print (global.get $count))
+    (global.get $count))
+  (func (export "main") (result i32) (i32.const 42)))"#;
+
+// Echoes a line of stdin.
+const ECHO: &str = r#"(module
+  (import "env" "read_line" (func $read_line (param i32) (result i32)))
+  (import "env" "print_bytes" (func $print_bytes (param i32)))
+  (memory (export "memory") 1)
+  (global (export "tstack") (mut i32) (i32.const 1024))
+  (func (export "main")
+    (drop (call $read_line (i32.const 0)))
+    (call $print_bytes (i32.const 0))))"#;
+
+#[test]
+fn runtime_runs_main_and_invokes_exports_on_one_instance() {
+    let host = Host::new().unwrap();
+    let mut io = HostIo::default();
+    let stdout = io.capture_stdout();
+    let mut runtime = Runtime::with_io(&host, COUNTER.as_bytes(), io).unwrap();
+    assert_eq!(runtime.run_main().unwrap(), 42);
+    for expected in 1..=3 {
+        let results = runtime.invoke("count", &[Val::I64(1)]).unwrap();
+        assert_eq!(results[0].unwrap_i64(), expected);
+    }
+    assert_eq!(String::from_utf8(stdout.contents()).unwrap(), "1\n2\n3\n");
+
+    let error = format!("{:#}", runtime.invoke("missing", &[]).unwrap_err());
+    assert!(error.contains("no function export 'missing'"), "{}", error);
+}
+
+#[test]
+fn runtime_reads_the_given_stdin() {
+    let host = Host::new().unwrap();
+    let mut io = HostIo::default();
+    io.set_stdin(b"hello\nworld\n".to_vec());
+    let stdout = io.capture_stdout();
+    let mut runtime = Runtime::with_io(&host, ECHO.as_bytes(), io).unwrap();
+    assert_eq!(runtime.run_main().unwrap(), 0);
+    assert_eq!(stdout.contents(), b"hello");
+}
+
+#[test]
+fn program_instances_are_isolated() {
+    let host = Host::new().unwrap();
+    let counter = CompiledProgram::new(&host, COUNTER.as_bytes()).unwrap();
+    let mut first = counter.instantiate().unwrap();
+    let mut second = counter.instantiate().unwrap();
+    first.invoke("count", &[Val::I64(1)]).unwrap();
+    let results = first.invoke("count", &[Val::I64(1)]).unwrap();
+    assert_eq!(results[0].unwrap_i64(), 2);
+    let results = second.invoke("count", &[Val::I64(5)]).unwrap();
+    assert_eq!(results[0].unwrap_i64(), 5);
+    assert_eq!(first.stdout(), b"1\n2\n");
+    assert_eq!(second.stdout(), b"5\n");
+    assert_eq!(first.run_main().unwrap(), 42);
+
+    let echo = CompiledProgram::new(&host, ECHO.as_bytes()).unwrap();
+    let mut one = echo.instantiate_with(b"one\n", &[]).unwrap();
+    let mut two = echo.instantiate_with(b"two\n", &[]).unwrap();
+    assert_eq!(two.run_main().unwrap(), 0);
+    assert_eq!(one.run_main().unwrap(), 0);
+    assert_eq!(one.stdout(), b"one");
+    assert_eq!(two.stdout(), b"two");
+    assert!(one.stderr().is_empty());
+}
+
+const ARGS_COUNT: &str = r#"(module
+  (import "env" "print" (func $print (param i64)))
+  (import "env" "args_count" (func $args_count (result i64)))
+  (memory (export "memory") 1)
+  (func (export "main") (call $print (call $args_count))))"#;
+
+const SPIN: &str = r#"(module
+  (memory (export "memory") 1)
+  (func (export "main") (loop $forever (br $forever))))"#;
+
+const GROW: &str = r#"(module
+  (memory (export "memory") 1)
+  (func (export "main") (drop (memory.grow (i32.const 16)))))"#;
+
+fn job(program: &CompiledProgram, args: usize, limits: RunLimits) -> Job {
+    Job {
+        program: program.clone(),
+        stdin: Vec::new(),
+        args: vec![String::from("arg"); args],
+        limits,
+    }
+}
+
+#[test]
+fn pool_runs_jobs_in_their_own_instances() {
+    let host = Host::new().unwrap();
+    let program = CompiledProgram::new(&host, ARGS_COUNT.as_bytes()).unwrap();
+    let mut pool = Pool::new(&host, NonZeroUsize::new(4).unwrap());
+    let mut expected = HashMap::new();
+    for args in 0..20 {
+        let id = pool
+            .submit(job(&program, args, RunLimits::default()))
+            .unwrap();
+        expected.insert(id, format!("{}\n", args));
+    }
+    let mut finished = 0;
+    for result in &mut pool {
+        assert_eq!(result.exit_code.unwrap(), 0);
+        assert_eq!(
+            String::from_utf8(result.stdout).unwrap(),
+            expected[&result.id]
+        );
+        finished += 1;
+    }
+    assert_eq!(finished, 20);
+}
+
+#[test]
+fn pool_enforces_each_jobs_limits() {
+    let host = Host::with_fuel_metering().unwrap();
+    let spin = CompiledProgram::new(&host, SPIN.as_bytes()).unwrap();
+    let grow = CompiledProgram::new(&host, GROW.as_bytes()).unwrap();
+    let mut pool = Pool::new(&host, NonZeroUsize::new(3).unwrap());
+    let timed_out = RunLimits {
+        timeout: Some(Duration::from_millis(50)),
+        ..RunLimits::default()
+    };
+    let out_of_fuel = RunLimits {
+        fuel: Some(10_000),
+        ..RunLimits::default()
+    };
+    let small_memory = RunLimits {
+        max_memory: Some(4 << 16),
+        ..RunLimits::default()
+    };
+    let timed_out = pool.submit(job(&spin, 0, timed_out)).unwrap();
+    let out_of_fuel = pool.submit(job(&spin, 0, out_of_fuel)).unwrap();
+    let small_memory = pool.submit(job(&grow, 0, small_memory)).unwrap();
+    let mut errors = HashMap::new();
+    for result in &mut pool {
+        let error = result.exit_code.err().unwrap();
+        if result.id == out_of_fuel {
+            assert_eq!(result.fuel_consumed, Some(10_000));
+        }
+        errors.insert(result.id, format!("{:#}", error));
+    }
+    assert!(
+        errors[&timed_out].contains("timed out"),
+        "{}",
+        errors[&timed_out]
+    );
+    assert!(
+        errors[&out_of_fuel].contains("fuel"),
+        "{}",
+        errors[&out_of_fuel]
+    );
+    assert!(
+        errors[&small_memory].contains("memory"),
+        "{}",
+        errors[&small_memory]
+    );
+}
+
+#[test]
+fn pool_rejects_programs_from_another_host() {
+    let host = Host::new().unwrap();
+    let other = CompiledProgram::new(&Host::new().unwrap(), ARGS_COUNT.as_bytes()).unwrap();
+    let mut pool = Pool::new(&host, NonZeroUsize::new(1).unwrap());
+    assert!(pool.submit(job(&other, 0, RunLimits::default())).is_err());
+}